@@ -0,0 +1,15 @@
+//! Any document at a `LOCATION` must never panic the description parser
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use sonos_discovery::upnp::Description;
+
+fuzz_target!(|data: &[u8]| {
+    let xml = String::from_utf8_lossy(data);
+    if let Ok(description) = Description::parse(&xml, Some("http://192.168.1.20:1400/xml/device_description.xml")) {
+        for device in description.devices() {
+            for service in &device.services {
+                let _ = service.control_url.as_deref().map(|url| description.url(url));
+            }
+        }
+    }
+});
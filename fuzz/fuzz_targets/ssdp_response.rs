@@ -0,0 +1,18 @@
+//! Random packets on the multicast group must never panic the parsing of a response
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use sonos_discovery::ssdp::SsdpResponse;
+
+fuzz_target!(|data: &[u8]| {
+    let response = SsdpResponse::parse(data);
+    let _ = response.is_search_response();
+    let _ = response.is_notify();
+    let _ = response.location();
+    let _ = response.st();
+    let _ = response.uuid();
+    let _ = response.max_age();
+
+    if let Ok(strict) = SsdpResponse::try_parse(data) {
+        assert_eq!(strict, response);
+    }
+});
@@ -0,0 +1,47 @@
+#ifndef SONOS_DISCOVERY_H
+#define SONOS_DISCOVERY_H
+
+/* Generated with cbindgen from src/ffi.rs, don't edit it by hand */
+
+#include <stddef.h>
+#include <stdint.h>
+
+// The discovery succeeded, it found no devices if the length is 0
+#define SONOS_OK 0
+
+// `out_array` or `out_len` is null
+#define SONOS_ERROR_INVALID_ARGUMENT -1
+
+// The discovery failed, e.g. the socket couldn't be created or the search couldn't be sent
+#define SONOS_ERROR_DISCOVERY -2
+
+// The discovery panicked, this is a bug of the library
+#define SONOS_ERROR_PANIC -3
+
+// A discovered device, owned by the array of `sonos_discover`
+typedef struct {
+  // Address of the device (e.g. `192.168.1.20`), nul terminated
+  char *ip;
+  // Unique id of the device (`RINCON_...`), nul terminated, null if its response had none
+  char *uuid;
+} SonosDevice;
+
+// Discovers the sonos devices for `timeout_ms` milliseconds.
+//
+// On success the devices are written to `*out_array` (null if none were found) and their number to `*out_len`,
+// the array has to be released with `sonos_free_results`. Returns `SONOS_OK` or one of the `SONOS_ERROR_*` codes,
+// the outputs are only written on success.
+//
+// # Safety
+//
+// `out_array` and `out_len` have to be null or valid for writes.
+int sonos_discover(uint32_t timeout_ms, SonosDevice **out_array, size_t *out_len);
+
+// Releases the devices of `sonos_discover`, does nothing if `array` is null.
+//
+// # Safety
+//
+// `array` and `len` have to be the outputs of a successful `sonos_discover`, every array can only be released once.
+void sonos_free_results(SonosDevice *array, size_t len);
+
+#endif /* SONOS_DISCOVERY_H */
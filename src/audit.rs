@@ -0,0 +1,73 @@
+use std::fmt::{self, Debug};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Direction of an audited packet
+pub enum Direction {
+    /// Sent by this host (the search message)
+    Sent,
+    /// Received from another device
+    Received
+}
+
+#[derive(Debug, Clone, Copy)]
+/// A single ssdp packet sent or received by `Discover`
+pub struct AuditRecord<'a> {
+    /// Time the packet was sent/received
+    pub timestamp: SystemTime,
+    pub direction: Direction,
+    /// Destination of a sent packet, source of a received packet
+    pub peer: SocketAddr,
+    /// Raw payload of the packet
+    pub data: &'a [u8]
+}
+
+/// Receives every packet sent and received during a discovery.
+///
+/// Installed with `Discover::set_audit_sink`.
+/// Recording can't fail the discovery, sinks have to handle their errors themselves.
+pub trait AuditSink: Debug + Send + Sync {
+    fn record(&self, record: &AuditRecord);
+}
+
+/// `AuditSink` writing one line per packet to a `Write` (e.g. a `File`)
+///
+/// Line format: `<unix time> <sent|received> <peer> <length> <payload>`, the payload is quoted and escaped.
+pub struct WriteAuditSink<W: Write + Send> {
+    writer: Mutex<W>
+}
+
+impl<W: Write + Send> WriteAuditSink<W> {
+    pub fn new(writer: W) -> Self {
+        WriteAuditSink {
+            writer: Mutex::new(writer)
+        }
+    }
+}
+
+impl<W: Write + Send> Debug for WriteAuditSink<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WriteAuditSink").finish()
+    }
+}
+
+impl<W: Write + Send> AuditSink for WriteAuditSink<W> {
+    fn record(&self, record: &AuditRecord) {
+        let time = record.timestamp.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let direction = match record.direction {
+            Direction::Sent => "sent",
+            Direction::Received => "received"
+        };
+
+        if let Ok(mut writer) = self.writer.lock() {
+            // An audit log which can't be written must not break the discovery
+            let _ = writeln!(writer, "{}.{:09} {} {} {} {:?}",
+                             time.as_secs(), time.subsec_nanos(), direction, record.peer, record.data.len(),
+                             String::from_utf8_lossy(record.data))
+                .and_then(|_| writer.flush());
+        }
+    }
+}
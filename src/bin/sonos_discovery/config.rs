@@ -0,0 +1,310 @@
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// `sonos_discovery/config.toml` in the config directory of the platform: `$XDG_CONFIG_HOME` or `~/.config`,
+/// `%APPDATA%` on windows. `None` if the directory isn't known.
+pub fn default_path() -> Option<PathBuf> {
+    let directory = if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+    };
+
+    directory.map(|directory| directory.join("sonos_discovery").join("config.toml"))
+}
+
+/// Defaults of the options from a config file, see `parse` for its format
+#[derive(Debug, Default)]
+pub struct Defaults {
+    /// File they're read from, `None` without a file
+    path: Option<PathBuf>,
+    settings: Vec<Setting>
+}
+
+/// Key of the config file as command line arguments
+#[derive(Debug, PartialEq)]
+pub struct Setting {
+    /// Line of the key
+    line: usize,
+    /// `["--timeout", "2500ms"]` for `timeout = "2500ms"`, `["--no-verify"]` for `verify = false`
+    pub arguments: Vec<String>
+}
+
+impl Defaults {
+    /// Defaults of the file at `path` or the default path, a missing file at the default path has no defaults
+    pub fn load(path: Option<&Path>) -> Result<Self, String> {
+        let explicit = path.is_some();
+        let path = match path.map(Path::to_path_buf).or_else(default_path) {
+            Some(path) => path,
+            None => return Ok(Defaults::default())
+        };
+
+        let settings = match fs::read_to_string(&path) {
+            Ok(content) => parse(&content).map_err(|e| format!("Invalid config file {}: {}", path.display(), e))?,
+            Err(ref e) if e.kind() == ErrorKind::NotFound && !explicit => Vec::new(),
+            Err(e) => return Err(format!("Couldn't read the config file {}: {}", path.display(), e))
+        };
+
+        Ok(Defaults {
+            path: Some(path),
+            settings
+        })
+    }
+
+    pub fn settings(&self) -> &[Setting] {
+        &self.settings
+    }
+
+    /// Error `message` of the key of `setting`, e.g. an unknown option or an invalid value
+    pub fn error(&self, setting: &Setting, message: &str) -> String {
+        let path = self.path.as_ref().map_or_else(String::new, |path| path.display().to_string());
+        format!("Invalid config file {}: line {}: {}", path, setting.line, message)
+    }
+}
+
+/// Settings of a config file with `key = value` lines (a subset of toml without tables), the key is the name of the
+/// command line option without the leading dashes, words are separated by `-` or `_`:
+///
+/// ```toml
+/// timeout = "2500ms"
+/// interface = "eth0"
+/// format = "json"
+/// count = 10
+/// verify = true
+/// cache_file = "/var/cache/sonos.json"
+/// webhook = ["http://localhost:8080/a", "http://localhost:8080/b"]  # Both get every event
+/// ```
+///
+/// `true` is the flag alone, `false` the flag with `--no-`, an array repeats the option for every value.
+fn parse(content: &str) -> Result<Vec<Setting>, String> {
+    let mut settings = Vec::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |message: &str| format!("line {}: {}", number + 1, message);
+
+        let mut parts = line.splitn(2, '=');
+        let (key, value) = match (parts.next(), parts.next()) {
+            (Some(key), Some(value)) => (key.trim(), value.trim()),
+            _ => return Err(error("expected `key = value`"))
+        };
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            return Err(error(&format!("invalid key `{}`", key)));
+        }
+        if key == "config" {
+            return Err(error("`config` can only be passed on the command line"));
+        }
+        let option = key.replace('_', "-");
+
+        let (value, rest) = parse_value(value).map_err(|e| error(&e))?;
+        let rest = rest.trim();
+        if !rest.is_empty() && !rest.starts_with('#') {
+            return Err(error(&format!("unexpected `{}` after the value", rest)));
+        }
+        let arguments = match value {
+            Value::Flag(true) => vec![format!("--{}", option)],
+            Value::Flag(false) => vec![format!("--no-{}", option)],
+            Value::Values(values) => values.into_iter()
+                .flat_map(|value| vec![format!("--{}", option), value])
+                .collect()
+        };
+        settings.push(Setting {
+            line: number + 1,
+            arguments
+        });
+    }
+
+    Ok(settings)
+}
+
+/// Value of a key
+#[derive(Debug, PartialEq)]
+enum Value {
+    Flag(bool),
+    /// A single value or the elements of an array
+    Values(Vec<String>)
+}
+
+/// Value at the start of `input`, a boolean, a string, a number or a single line array of strings and numbers.
+/// Returns it with the rest of `input`.
+fn parse_value(input: &str) -> Result<(Value, &str), String> {
+    let word_end = input.find(|c: char| c.is_whitespace() || c == '#').unwrap_or(input.len());
+    match &input[..word_end] {
+        "true" => return Ok((Value::Flag(true), &input[word_end..])),
+        "false" => return Ok((Value::Flag(false), &input[word_end..])),
+        _ => {}
+    }
+
+    let mut rest = match input.strip_prefix('[') {
+        Some(rest) => rest,
+        None => return scalar(input).map(|(value, rest)| (Value::Values(vec![value]), rest))
+    };
+    let mut values = Vec::new();
+    loop {
+        rest = rest.trim_start();
+        // A trailing comma is allowed
+        if let Some(after) = rest.strip_prefix(']') {
+            return Ok((Value::Values(values), after));
+        }
+        if rest.is_empty() || rest.starts_with('#') {
+            return Err("arrays have to be on a single line".to_string());
+        }
+        let (value, after) = scalar(rest)?;
+        values.push(value);
+        rest = after.trim_start();
+        if let Some(after) = rest.strip_prefix(',') {
+            rest = after;
+        } else if !rest.starts_with(']') {
+            return Err(match rest {
+                "" => "arrays have to be on a single line".to_string(),
+                _ => format!("expected `,` or `]` in the array, got `{}`", rest)
+            });
+        }
+    }
+}
+
+/// String in double quotes (with the escapes `\"`, `\\`, `\t` and `\n`) or plain number at the start of `input`,
+/// returns it with the rest of `input`
+fn scalar(input: &str) -> Result<(String, &str), String> {
+    let rest = match input.strip_prefix('"') {
+        Some(rest) => rest,
+        None => {
+            let end = input.find(|c: char| !c.is_ascii_digit()).unwrap_or(input.len());
+            if end == 0 {
+                return Err(format!("expected a quoted string, a number or a boolean, got `{}`", input));
+            }
+            return Ok((input[..end].to_string(), &input[end..]));
+        }
+    };
+
+    let mut string = String::new();
+    let mut chars = rest.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' => return Ok((string, &rest[index + 1..])),
+            '\\' => match chars.next() {
+                Some((_, '"')) => string.push('"'),
+                Some((_, '\\')) => string.push('\\'),
+                Some((_, 't')) => string.push('\t'),
+                Some((_, 'n')) => string.push('\n'),
+                _ => return Err("invalid escape sequence".to_string())
+            },
+            c => string.push(c)
+        }
+    }
+
+    Err("unterminated string".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Arguments of every setting in `content`
+    fn arguments(content: &str) -> Result<Vec<Vec<String>>, String> {
+        parse(content).map(|settings| settings.into_iter().map(|setting| setting.arguments).collect())
+    }
+
+    #[test]
+    fn values() {
+        let content = "timeout = \"2500ms\"\ncount = 10\nverify = true\nreuse_port = false\ncache-file = \"a b\"\n";
+        assert_eq!(arguments(content).unwrap(), vec![
+            vec!["--timeout", "2500ms"],
+            vec!["--count", "10"],
+            vec!["--verify"],
+            vec!["--no-reuse-port"],
+            vec!["--cache-file", "a b"]
+        ]);
+        assert_eq!(parse(content).unwrap().iter().map(|setting| setting.line).collect::<Vec<_>>(), [1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn arrays_repeat_the_option() {
+        assert_eq!(arguments("multicast_group = [\"239.255.255.251\", \"239.255.255.252:1901\"]").unwrap(),
+                   vec![vec!["--multicast-group", "239.255.255.251", "--multicast-group", "239.255.255.252:1901"]]);
+        assert_eq!(arguments("webhook = [\"a\",]").unwrap(), vec![vec!["--webhook", "a"]]);
+        assert_eq!(arguments("webhook = [ ]").unwrap(), vec![Vec::<String>::new()]);
+        assert_eq!(arguments("webhook = [\"a,b\", \"c]\"]").unwrap(),
+                   vec![vec!["--webhook", "a,b", "--webhook", "c]"]]);
+        assert_eq!(arguments("resends = [1, 2]").unwrap(), vec![vec!["--resends", "1", "--resends", "2"]]);
+    }
+
+    #[test]
+    fn comments() {
+        let content = "# Defaults\n\n  # indented\ntimeout = \"1s\" # one second\ncount = 3# three\nverify = true #\n\
+                       webhook = [\"a\"] # [not] part of it]\nhousehold = \"Sonos_#1\"\n";
+        assert_eq!(arguments(content).unwrap(), vec![
+            vec!["--timeout", "1s"],
+            vec!["--count", "3"],
+            vec!["--verify"],
+            vec!["--webhook", "a"],
+            vec!["--household", "Sonos_#1"]
+        ]);
+    }
+
+    #[test]
+    fn quoting() {
+        assert_eq!(arguments(r#"on_found = "echo \"$SONOS_IP\"\t\\ \n""#).unwrap(),
+                   vec![vec!["--on-found", "echo \"$SONOS_IP\"\t\\ \n"]]);
+        assert_eq!(arguments("room = \"Küche = Wohnzimmer\"").unwrap(), vec![vec!["--room", "Küche = Wohnzimmer"]]);
+        assert_eq!(arguments("room = \"\"").unwrap(), vec![vec!["--room", ""]]);
+
+        assert_eq!(arguments(r#"room = "a\x""#), Err("line 1: invalid escape sequence".to_string()));
+        assert_eq!(arguments("room = \"Kitchen"), Err("line 1: unterminated string".to_string()));
+        assert_eq!(arguments("room = Kitchen"),
+                   Err("line 1: expected a quoted string, a number or a boolean, got `Kitchen`".to_string()));
+        assert_eq!(arguments("room = 'Kitchen'"),
+                   Err("line 1: expected a quoted string, a number or a boolean, got `'Kitchen'`".to_string()));
+    }
+
+    #[test]
+    fn invalid_lines() {
+        assert_eq!(arguments("\ntimeout"), Err("line 2: expected `key = value`".to_string()));
+        assert_eq!(arguments("= 1"), Err("line 1: invalid key ``".to_string()));
+        assert_eq!(arguments("[discovery]"), Err("line 1: expected `key = value`".to_string()));
+        assert_eq!(arguments("time out = 1"), Err("line 1: invalid key `time out`".to_string()));
+        assert_eq!(arguments("config = \"other.toml\""),
+                   Err("line 1: `config` can only be passed on the command line".to_string()));
+        assert_eq!(arguments("timeout = \"1s\" \"2s\""),
+                   Err("line 1: unexpected `\"2s\"` after the value".to_string()));
+        assert_eq!(arguments("count = 3.5"), Err("line 1: unexpected `.5` after the value".to_string()));
+        assert_eq!(arguments("verify = truely"),
+                   Err("line 1: expected a quoted string, a number or a boolean, got `truely`".to_string()));
+        assert_eq!(arguments("webhook = [\"a\""), Err("line 1: arrays have to be on a single line".to_string()));
+        assert_eq!(arguments("webhook = [\"a\", # b\n\"c\"]"),
+                   Err("line 1: arrays have to be on a single line".to_string()));
+        assert_eq!(arguments("webhook = [\"a\" \"b\"]"),
+                   Err("line 1: expected `,` or `]` in the array, got `\"b\"]`".to_string()));
+        assert_eq!(arguments("webhook = [true]"),
+                   Err("line 1: expected a quoted string, a number or a boolean, got `true]`".to_string()));
+    }
+
+    #[test]
+    fn unknown_keys_are_options() {
+        // The options are only known when the arguments are parsed
+        assert_eq!(arguments("colour = \"red\"").unwrap(), vec![vec!["--colour", "red"]]);
+    }
+
+    #[test]
+    fn files() {
+        let path = env::temp_dir().join(format!("sonos_discovery_config_{}.toml", std::process::id()));
+        fs::write(&path, "timeout = \"1s\"\n\ncount = x\n").unwrap();
+        let error = Defaults::load(Some(&path)).unwrap_err();
+        assert_eq!(error, format!("Invalid config file {}: line 3: expected a quoted string, a number or a boolean, \
+                                   got `x`", path.display()));
+
+        fs::write(&path, "timeout = \"1s\"\n\ncount = 3\n").unwrap();
+        let defaults = Defaults::load(Some(&path)).unwrap();
+        assert_eq!(defaults.settings().len(), 2);
+        assert_eq!(defaults.error(&defaults.settings()[1], "Invalid device count"),
+                   format!("Invalid config file {}: line 3: Invalid device count", path.display()));
+
+        fs::remove_file(&path).unwrap();
+        assert!(Defaults::load(Some(&path)).unwrap_err().starts_with("Couldn't read the config file"));
+    }
+}
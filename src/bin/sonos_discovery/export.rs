@@ -0,0 +1,159 @@
+use output::json_string;
+use sonos_discovery::InventoryRecord;
+use std::collections::HashSet;
+
+/// Configuration for the `sonos` integration of Home Assistant with static hosts, each commented with its room and
+/// model. Static hosts skip the integration's own discovery, which doesn't work across subnets/vlans.
+pub fn home_assistant_config(records: &[InventoryRecord]) -> String {
+    let mut config = String::from("sonos:\n  media_player:\n    hosts:\n");
+    for record in records {
+        let ip = match record.ips.first() {
+            Some(ip) => ip,
+            None => continue
+        };
+        let room = comment(record.room_name.as_deref().filter(|room| !room.trim().is_empty()).unwrap_or(&record.uuid));
+        match record.model_name {
+            Some(ref model) => config.push_str(&format!("      # {} ({})\n", room, comment(model))),
+            None => config.push_str(&format!("      # {}\n", room))
+        }
+        config.push_str(&format!("      - {}\n", ip));
+    }
+
+    config
+}
+
+/// `text` on a single line, control characters would end the yaml comment
+fn comment(text: &str) -> String {
+    text.chars().map(|c| if c.is_control() { ' ' } else { c }).collect()
+}
+
+/// Ansible inventory in the ini format.
+/// Hosts are named after their room (e.g. `living_room`, `living_room_2` for the second speaker of a stereo pair) with
+/// the `ansible_host`, `model` and `uuid` variables.
+pub fn ini_inventory(records: &[InventoryRecord]) -> String {
+    let mut inventory = String::from("[sonos]\n");
+    for (name, record, ip) in hosts(records) {
+        inventory.push_str(&format!("{} ansible_host={}", name, ip));
+        if let Some(ref model) = record.model_name {
+            inventory.push_str(&format!(" model={}", json_string(model)));
+        }
+        inventory.push_str(&format!(" uuid={}\n", record.uuid));
+    }
+
+    inventory
+}
+
+/// Ansible inventory in the yaml format, see `ini_inventory`
+pub fn yaml_inventory(records: &[InventoryRecord]) -> String {
+    let mut inventory = String::from("all:\n  children:\n    sonos:\n      hosts:\n");
+    for (name, record, ip) in hosts(records) {
+        inventory.push_str(&format!("        {}:\n          ansible_host: {}\n", name, ip));
+        if let Some(ref model) = record.model_name {
+            inventory.push_str(&format!("          model: {}\n", json_string(model)));
+        }
+        inventory.push_str(&format!("          uuid: {}\n", record.uuid));
+    }
+
+    inventory
+}
+
+/// Unique host name, record and address of every record with an address.
+/// The name is the room in lower case with `_` for everything but letters and digits, or the address without a room
+/// (or a blank one). Duplicates get the next free number, e.g. `living_room_2`.
+fn hosts(records: &[InventoryRecord]) -> Vec<(String, &InventoryRecord, String)> {
+    let mut names = HashSet::new();
+    records.iter()
+        .filter_map(|record| record.ips.first().map(|ip| (record, ip.to_string())))
+        .map(|(record, ip)| {
+            let base = match record.room_name {
+                Some(ref room) if !room.trim().is_empty() => room.trim().chars()
+                    .flat_map(char::to_lowercase)
+                    .map(|c| if c.is_alphanumeric() { c } else { '_' })
+                    .collect(),
+                _ => ip.clone()
+            };
+            let mut name = base.clone();
+            let mut number = 2;
+            while !names.insert(name.clone()) {
+                name = format!("{}_{}", base, number);
+                number += 1;
+            }
+            (name, record, ip)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Record of the device `number` at `192.168.1.<20 + number>`
+    fn record(number: u8, room: Option<&str>, model: Option<&str>) -> InventoryRecord {
+        InventoryRecord {
+            uuid: format!("RINCON_000E58A0000{:02}1400", number),
+            serial_number: None,
+            model_name: model.map(str::to_string),
+            model_number: None,
+            software_version: None,
+            room_name: room.map(str::to_string),
+            ips: vec![format!("192.168.1.{}", 20 + number).parse().unwrap()],
+            household: None
+        }
+    }
+
+    /// Speakers with duplicate, unicode, blank and missing rooms
+    fn records() -> Vec<InventoryRecord> {
+        let mut pair = record(2, Some("Living Room"), Some("Sonos One SL"));
+        pair.ips.push("10.0.0.22".parse().unwrap());
+        let mut offline = record(10, Some("Garage"), Some("Sonos Move"));
+        offline.ips.clear();
+
+        vec![record(1, Some("Living Room"), Some("Sonos Arc")),
+             pair,
+             record(3, Some("living-room"), None),
+             record(4, Some("Küche"), Some("Sonos One")),
+             record(5, Some("Büro Ü2"), Some("Sonos Era 100")),
+             record(6, Some("Tom & Jerry's Room"), Some("Sonos Five")),
+             record(7, None, Some("Sonos Port")),
+             record(8, Some("  "), Some("Sonos Amp")),
+             record(9, Some("Bath\nroom"), Some("Sonos Roam")),
+             offline,
+             record(11, Some("Living Room 2"), Some("Sonos Sub"))]
+    }
+
+    #[test]
+    fn home_assistant_configs() {
+        assert_eq!(home_assistant_config(&records()), include_str!("../../../tests/fixtures/home_assistant.yaml"));
+        assert_eq!(home_assistant_config(&[]), "sonos:\n  media_player:\n    hosts:\n");
+    }
+
+    #[test]
+    fn ini_inventories() {
+        assert_eq!(ini_inventory(&records()), include_str!("../../../tests/fixtures/inventory.ini"));
+        assert_eq!(ini_inventory(&[]), "[sonos]\n");
+    }
+
+    #[test]
+    fn yaml_inventories() {
+        assert_eq!(yaml_inventory(&records()), include_str!("../../../tests/fixtures/inventory.yaml"));
+        assert_eq!(yaml_inventory(&[]), "all:\n  children:\n    sonos:\n      hosts:\n");
+    }
+
+    #[test]
+    fn host_names() {
+        let cases = [("Living Room", "living_room"),
+                     (" Living Room ", "living_room"),
+                     ("LIVING_ROOM", "living_room"),
+                     ("Kinderzimmer (Lea)", "kinderzimmer__lea_"),
+                     ("ÄRGER", "ärger"),
+                     ("Σπίτι", "σπίτι"),
+                     ("リビング", "リビング"),
+                     ("Room 🎵", "room__"),
+                     ("", "192.168.1.21"),
+                     ("\t", "192.168.1.21")];
+        for &(room, name) in &cases {
+            let hosts = hosts(&[record(1, Some(room), None)]).into_iter().map(|(name, _, _)| name).collect::<Vec<_>>();
+            assert_eq!(hosts, vec![name], "{}", room);
+        }
+    }
+}
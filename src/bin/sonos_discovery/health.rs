@@ -0,0 +1,166 @@
+use output::{format_unix_time, json_string, JSON_SCHEMA_VERSION};
+use serve::{handle_connections, CONNECTION_TIMEOUT};
+use sonos_discovery::{DiscoveryConfig, Monitor};
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Time the verification and topology requests after the search get from the library
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// State of the interval mode reported by the health endpoints
+pub struct Health {
+    /// Start of the interval mode
+    pub started: Instant,
+    /// Time of the last successful scan (monotonic and formatted)
+    pub last_scan: Option<(Instant, String)>,
+    /// Number of devices found by the last successful scan
+    pub devices: usize
+}
+
+/// Longest time without a successful scan before `/healthz` fails: twice the `interval` plus the time a scan with
+/// `config` may take, the budget or the timeout of the search and the verification and topology requests after it
+pub fn max_scan_age(config: &DiscoveryConfig, interval: Duration) -> Duration {
+    let requests = [config.verify, config.topology].iter().filter(|&&enabled| enabled).count() as u32;
+    let scan = config.budget.unwrap_or(config.timeout + REQUEST_TIMEOUT * requests);
+
+    interval * 2 + scan
+}
+
+/// Serves the health endpoints of the interval mode and `--serve` on a background thread.
+///
+/// `/healthz` fails if no scan succeeded for `max_age` (the discovery is wedged, see `max_scan_age`), `/readyz` fails
+/// until the first scan succeeded.
+pub fn serve_health(address: SocketAddr, health: Arc<Mutex<Health>>, max_age: Duration) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+
+    thread::spawn(move || handle_connections(&listener, move |stream| respond_health(stream, &health, max_age)));
+
+    Ok(())
+}
+
+/// Copies the successful scans of `monitor` into `health` (polled every second), for the health endpoints of `--serve`
+pub fn track_monitor(monitor: &Monitor, health: &Mutex<Health>) {
+    let mut scans = 0;
+    loop {
+        let snapshot = monitor.snapshot();
+        if snapshot.scans != scans {
+            scans = snapshot.scans;
+            if let (Some(finished), None) = (snapshot.last_scan, snapshot.last_error) {
+                let age = SystemTime::now().duration_since(finished).unwrap_or_default();
+                let seconds = finished.duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or(0);
+                let mut health = health.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                health.last_scan = Some((Instant::now().checked_sub(age).unwrap_or_else(Instant::now),
+                                         format_unix_time(seconds)));
+                health.devices = snapshot.devices.len();
+            }
+        }
+        thread::sleep(Duration::from_secs(1));
+    }
+}
+
+fn respond_health(mut stream: TcpStream, health: &Mutex<Health>, max_age: Duration) -> io::Result<()> {
+    stream.set_read_timeout(Some(CONNECTION_TIMEOUT))?;
+    stream.set_write_timeout(Some(CONNECTION_TIMEOUT))?;
+    let mut request = [0u8; 1024];
+    let read = stream.read(&mut request)?;
+    let request = String::from_utf8_lossy(&request[..read]);
+    let path = request.split_whitespace().nth(1).unwrap_or("");
+
+    let (ok, body) = {
+        // The state is only ever replaced as a whole, a panic while holding the lock can't leave it half-updated
+        let health = health.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let last_scan = health.last_scan.as_ref()
+            .map_or("null".to_string(), |(_, time)| json_string(time));
+        let body = format!("{{\"schema_version\":{},\"last_scan\":{},\"devices\":{}}}",
+                           JSON_SCHEMA_VERSION, last_scan, health.devices);
+
+        let ok = match path {
+            "/healthz" => {
+                let last_activity = health.last_scan.as_ref().map_or(health.started, |&(instant, _)| instant);
+                Some(last_activity.elapsed() <= max_age)
+            }
+            "/readyz" => Some(health.last_scan.is_some()),
+            _ => None
+        };
+
+        (ok, body)
+    };
+
+    let (status, body) = match ok {
+        Some(true) => ("200 OK", body),
+        Some(false) => ("503 Service Unavailable", body),
+        None => ("404 Not Found", "{}".to_string())
+    };
+
+    write!(stream, "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+           status, body.len(), body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Address of the health endpoints for `health`, answered on background threads
+    fn endpoints(health: Health) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let health = Arc::new(Mutex::new(health));
+        thread::spawn(move || {
+            handle_connections(&listener, move |stream| respond_health(stream, &health, Duration::from_secs(60)))
+        });
+
+        address
+    }
+
+    /// Status line of the response to `GET path`
+    fn get(address: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(address).unwrap();
+        // In one piece, the endpoints answer the first read
+        stream.write_all(format!("GET {} HTTP/1.1\r\nHost: {}\r\n\r\n", path, address).as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        response.lines().next().unwrap_or_default().to_string()
+    }
+
+    #[test]
+    fn endpoints_answer_the_state() {
+        let started = endpoints(Health {
+            started: Instant::now(),
+            last_scan: None,
+            devices: 0
+        });
+        let wedged = endpoints(Health {
+            started: Instant::now() - Duration::from_secs(120),
+            last_scan: Some((Instant::now() - Duration::from_secs(61), "2018-03-01T12:00:00Z".to_string())),
+            devices: 3
+        });
+
+        let cases = [(started, "/healthz", "HTTP/1.1 200 OK"),
+                     (started, "/readyz", "HTTP/1.1 503 Service Unavailable"),
+                     (started, "/", "HTTP/1.1 404 Not Found"),
+                     (wedged, "/healthz", "HTTP/1.1 503 Service Unavailable"),
+                     (wedged, "/readyz", "HTTP/1.1 200 OK")];
+        for &(address, path, status) in &cases {
+            assert_eq!(get(address, path), status, "{}", path);
+        }
+    }
+
+    #[test]
+    fn a_stalled_connection_blocks_only_itself() {
+        let address = endpoints(Health {
+            started: Instant::now(),
+            last_scan: None,
+            devices: 0
+        });
+
+        // Connected, but the request never comes
+        let _stalled = TcpStream::connect(address).unwrap();
+        let start = Instant::now();
+        assert_eq!(get(address, "/healthz"), "HTTP/1.1 200 OK");
+        assert!(start.elapsed() < CONNECTION_TIMEOUT, "{:?}", start.elapsed());
+    }
+}
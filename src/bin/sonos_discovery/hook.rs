@@ -0,0 +1,40 @@
+use output::timestamp;
+use std::net::IpAddr;
+use std::process::Command;
+use std::thread;
+
+/// Runs `command` with the shell (`sh -c`, `cmd /C` on windows) without waiting for it.
+///
+/// The device is passed in environment variables:
+/// `SONOS_EVENT` (`found` or `lost`), `SONOS_IP`, `SONOS_ID` (uuid, the address for devices without one),
+/// `SONOS_NAME` (room of the zone groups, empty if it's unknown) and `SONOS_TIME`.
+pub fn run_hook(command: &str, event: &str, id: &str, name: Option<&str>, ip: &IpAddr, time: &str) {
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    shell.arg(command)
+        .env("SONOS_EVENT", event)
+        .env("SONOS_IP", ip.to_string())
+        .env("SONOS_ID", id)
+        .env("SONOS_NAME", name.unwrap_or_default())
+        .env("SONOS_TIME", time);
+
+    match shell.spawn() {
+        // Waits in the background, so a slow hook doesn't delay the next scan
+        Ok(mut child) => {
+            let command = command.to_string();
+            thread::spawn(move || match child.wait() {
+                Ok(status) if !status.success() => eprintln!("{} hook `{}` failed: {}", timestamp(), command, status),
+                Ok(_) => {}
+                Err(e) => eprintln!("{} hook `{}` failed: {}", timestamp(), command, e)
+            });
+        }
+        Err(e) => eprintln!("{} hook `{}` couldn't be started: {}", time, command, e)
+    }
+}
@@ -0,0 +1,1537 @@
+#[cfg(feature = "notify")]
+extern crate notify_rust;
+#[cfg(feature = "webhook")]
+extern crate hmac;
+extern crate log;
+#[cfg(feature = "webhook")]
+extern crate sha2;
+extern crate sonos_discovery;
+#[cfg(feature = "webhook")]
+extern crate ureq;
+
+mod config;
+mod export;
+mod health;
+mod hook;
+mod output;
+mod serve;
+mod verbose;
+#[cfg(feature = "webhook")]
+mod webhook;
+
+use export::{home_assistant_config, ini_inventory, yaml_inventory};
+use health::{max_scan_age, serve_health, track_monitor, Health};
+use hook::run_hook;
+#[cfg(feature = "snapshot")]
+use output::format_unix_time;
+use output::{device_kinds, device_table, discovery_report, json_devices, prometheus_metrics, sort_devices, timestamp,
+             topology_dot, topology_tree, Field, SortKey};
+use config::Defaults;
+use serve::serve_devices;
+use sonos_discovery::{DeviceKind, DeviceSet, DeviceSetDiff, Discover, DiscoveryError, DiscoveryResult, Monitor,
+                      ProxyFetcher, SweepNetwork, MAX_MX};
+#[cfg(feature = "device-cache")]
+use sonos_discovery::DeviceCache;
+#[cfg(all(target_os = "linux", feature = "netns"))]
+use sonos_discovery::enter_netns;
+#[cfg(feature = "snapshot")]
+use sonos_discovery::{load_history, Change, Snapshot};
+use std::cmp;
+use std::collections::{BTreeSet, HashMap};
+use std::env;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Exit status of a failed discovery or setup (e.g. the network namespace, the health endpoints or `--serve`)
+const EXIT_FAILURE: i32 = 1;
+/// Exit status of invalid arguments
+const EXIT_USAGE: i32 = 2;
+/// Exit status of a single discovery which found fewer devices than `--min-devices` (1 by default)
+const EXIT_TOO_FEW_DEVICES: i32 = 3;
+/// Time between two scans of `--serve` without `--interval`
+const SERVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Subcommands of the binary
+enum Command {
+    /// Print the discovered devices
+    Discover,
+    /// Print the discovered devices as configuration for another tool
+    Export(ExportFormat),
+    /// Print the change history of every device from the snapshots in a directory
+    History(PathBuf)
+}
+
+/// Targets of `export`
+enum ExportFormat {
+    /// `sonos` integration configuration of Home Assistant
+    HomeAssistant,
+    /// Ansible inventory with all speakers in the `sonos` group
+    Inventory(InventoryFormat)
+}
+
+/// File formats of an Ansible inventory
+enum InventoryFormat {
+    Ini,
+    Yaml
+}
+
+/// Output formats of a single discovery
+enum OutputFormat {
+    /// One address per line followed by the elapsed time
+    Text,
+    /// Metrics for the textfile collector of the prometheus node exporter
+    Prom,
+    /// Object with the devices and the headers of their responses
+    Json,
+    /// Table of the devices with their room, model and firmware from the device descriptions
+    Table,
+    /// Tree of the zone groups with their coordinators, rooms and bonded speakers
+    Topology,
+    /// Zone groups as a Graphviz digraph
+    Dot
+}
+
+/// Command line options of the binary
+struct Options {
+    command: Command,
+    /// Config file with the defaults of the options instead of the one in the platform config directory
+    config: Option<PathBuf>,
+    format: OutputFormat,
+    /// Local address of the interface used for the discovery
+    interface: Option<Ipv4Addr>,
+    /// Name of the interface used for the discovery
+    interface_name: Option<String>,
+    /// Local address the socket is bound to
+    bind: Option<SocketAddr>,
+    /// Interface the socket is bound to with `SO_BINDTODEVICE` (linux only)
+    bind_device: Option<String>,
+    /// Local port the search is sent from
+    source_port: Option<u16>,
+    /// Share the ssdp port with other listeners
+    reuse_port: bool,
+    /// Local address of the interface the multicast search leaves from
+    multicast_if: Option<Ipv4Addr>,
+    /// Deliver the multicast search to the sockets of the host as well
+    multicast_loop: bool,
+    /// Send the search out of every interface
+    all_interfaces: bool,
+    /// Choose the interface of a private network instead of the default route
+    auto_interface: bool,
+    /// Only devices of this household are discovered
+    household: Option<String>,
+    /// Only devices in this room are listed (`--format table`)
+    room: Option<String>,
+    /// Only devices of this model are listed (`--format table`)
+    model: Option<String>,
+    /// Columns of `--format table`
+    fields: Vec<Field>,
+    /// Order of the rows of `--format table`, by the address if `None`
+    sort: Option<SortKey>,
+    /// The search is sent to these multicast groups as well
+    multicast_groups: Vec<SocketAddr>,
+    /// The search is sent to this address if sending to the multicast address fails
+    broadcast_fallback: Option<SocketAddr>,
+    /// Timeout of each discovery, the library default (5 seconds) if `None`
+    timeout: Option<Duration>,
+    /// A single discovery stops after this many devices, `None` waits for the whole timeout
+    count: Option<usize>,
+    /// A single discovery fails unless it found this many devices, 1 (0 for `--format prom`) if `None`
+    min_devices: Option<usize>,
+    /// Stop at the first speaker and list its household from the zone group topology
+    fast: bool,
+    /// Probe the speakers of the last run before discovering all
+    cache: bool,
+    /// Device cache of `--cache` instead of the one in the platform cache directory
+    cache_file: Option<PathBuf>,
+    /// Print the packets and the reasons for rejected responses to stderr
+    verbose: bool,
+    /// Print the timing of the discovery and the latency of every device to stderr
+    report: bool,
+    /// Search target (`ST` header) instead of sonos speakers
+    search_target: Option<String>,
+    /// List every upnp device with its kind instead of the sonos speakers
+    all_devices: bool,
+    /// Search `ssdp:all` and keep the sonos speakers among the answers
+    ssdp_all: bool,
+    /// Seconds devices may wait before answering the search
+    mx: Option<u32>,
+    /// Time late answers get on top of MX
+    mx_slack: Option<Duration>,
+    /// TTL of the search
+    ttl: Option<u32>,
+    /// The search is sent this many more times, once per second
+    resends: Option<u32>,
+    /// Stop each discovery once the devices went quiet
+    adaptive: bool,
+    /// Stop each discovery after this long without a response
+    quiet: Option<Duration>,
+    /// Drop responders whose `LOCATION` isn't the description of a sonos device
+    verify: bool,
+    /// Sweep the network for devices on port 1400 if nobody answers the search
+    sweep: bool,
+    /// Network of `--sweep` instead of the /24 of the interface
+    sweep_network: Option<SweepNetwork>,
+    /// Look up the hostname of every device for the json and table output
+    resolve_hostnames: bool,
+    /// Recreate the socket when it keeps failing during a discovery
+    recreate_socket: bool,
+    /// Accept every response mentioning Sonos
+    lenient: bool,
+    /// Only accept responses from the local subnets with a `LOCATION` on the responding host
+    source_check: bool,
+    /// Overall time of a discovery including the description requests
+    budget: Option<Duration>,
+    /// Device descriptions are cached for this long
+    description_ttl: Option<Duration>,
+    /// Http proxy for the description requests (`--verify`, snapshots)
+    proxy: Option<ProxyFetcher>,
+    /// Network namespace the binary switches to before anything else
+    netns: Option<String>,
+    /// Rerun the discovery every `interval`, runs once if `None`
+    interval: Option<Duration>,
+    /// Shorthand for `--interval` (default 10s) with `--diff`
+    watch: bool,
+    /// Only print devices which joined/left since the previous scan
+    diff: bool,
+    /// Responses of a device are only printed once within this window, `None` prints every device once per scan
+    dedup_window: Option<Duration>,
+    /// Raise desktop notifications for devices which joined/left since the previous scan
+    notify: bool,
+    /// Address of the `/healthz` and `/readyz` endpoints
+    health: Option<SocketAddr>,
+    /// Address of the `/devices` endpoint of the daemon mode, which keeps a monitor running
+    serve: Option<SocketAddr>,
+    /// Directory for periodic inventory snapshots
+    snapshot_dir: Option<PathBuf>,
+    /// Minimum time between two inventory snapshots
+    snapshot_interval: Duration,
+    /// Urls receiving a POST for every device which was found, lost or changed its address
+    webhooks: Vec<String>,
+    /// Key for the HMAC-SHA256 signature of the webhook payloads
+    webhook_secret: Option<String>,
+    /// Shell command run for every device which joined since the previous scan (`--on-found` or `--on-join`)
+    on_found: Option<String>,
+    /// Shell command run for every device which left since the previous scan (`--on-lost` or `--on-leave`)
+    on_lost: Option<String>
+}
+
+impl Default for Options {
+    /// Options without any argument
+    fn default() -> Self {
+        Options {
+            command: Command::Discover,
+            config: None,
+            format: OutputFormat::Text,
+            interface: None,
+            interface_name: None,
+            all_interfaces: false,
+            auto_interface: false,
+            bind: None,
+            bind_device: None,
+            source_port: None,
+            reuse_port: false,
+            multicast_if: None,
+            multicast_loop: true,
+            household: None,
+            room: None,
+            model: None,
+            fields: Field::DEFAULT.to_vec(),
+            sort: None,
+            netns: None,
+            multicast_groups: Vec::new(),
+            broadcast_fallback: None,
+            timeout: None,
+            count: None,
+            min_devices: None,
+            fast: false,
+            cache: false,
+            cache_file: None,
+            verbose: false,
+            report: false,
+            search_target: None,
+            all_devices: false,
+            ssdp_all: false,
+            mx: None,
+            mx_slack: None,
+            ttl: None,
+            resends: None,
+            adaptive: false,
+            quiet: None,
+            verify: false,
+            sweep: false,
+            sweep_network: None,
+            resolve_hostnames: false,
+            recreate_socket: false,
+            lenient: false,
+            source_check: true,
+            proxy: None,
+            budget: None,
+            description_ttl: None,
+            interval: None,
+            watch: false,
+            diff: false,
+            dedup_window: None,
+            notify: false,
+            health: None,
+            serve: None,
+            snapshot_dir: None,
+            snapshot_interval: Duration::from_secs(60 * 60),
+            webhooks: Vec::new(),
+            webhook_secret: None,
+            on_found: None,
+            on_lost: None
+        }
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = match options(&args) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}", message);
+            eprintln!("Usage: sonos_discovery [--timeout <duration>] [--count <number>|all | --fast | --cache]");
+            eprintln!("                       [--cache-file <path>] [--config <path>]");
+            eprintln!("                       [--min-devices <number>] [--verbose|-v] [--report]");
+            eprintln!("                       [--search-target <st> | --all-devices | --ssdp-all]");
+            eprintln!("                       [--watch] [--interval <duration>] [--diff | --dedup-window <duration>]");
+            eprintln!("                       [--notify] [--interface <address|name>] [--all-interfaces] [--bind <address>]");
+            eprintln!("                       [--auto-interface] [--bind-device <name>] [--source-port <port>]");
+            eprintln!("                       [--broadcast-fallback <address>] [--mx <seconds>] [--ttl <hops>]");
+            eprintln!("                       [--mx-slack <duration>]");
+            eprintln!("                       [--resends <count>] [--adaptive] [--quiet <duration>] [--verify] [--lenient]");
+            eprintln!("                       [--no-source-check] [--sweep] [--sweep-network <network>]");
+            eprintln!("                       [--resolve-hostnames] [--recreate-socket]");
+            eprintln!("                       [--description-ttl <duration>] [--household <household id>] [--netns <name>]");
+            eprintln!("                       [--room <name>] [--model <name>] [--reuse-port]");
+            eprintln!("                       [--multicast-if <address>] [--no-multicast-loop]");
+            eprintln!("                       [--multicast-group <address[:port]>]...");
+            eprintln!("                       [--proxy <host:port>|env] [--budget <duration>] [--health <address>]");
+            eprintln!("                       [--serve <address>]");
+            eprintln!("                       [--snapshot-dir <directory>] [--snapshot-interval <duration>]");
+            eprintln!("                       [--webhook <url>]... [--webhook-secret <secret>]");
+            eprintln!("                       [--on-found|--on-join <command>] [--on-lost|--on-leave <command>]");
+            eprintln!("                       [--format text|prom|json|table|topology|dot] [--json]");
+            eprintln!("                       [--fields <field>,...] [--sort ip|name|model]");
+            eprintln!("       sonos_discovery export home-assistant");
+            eprintln!("       sonos_discovery export inventory [ini|yaml]");
+            eprintln!("       sonos_discovery history <snapshot directory>");
+            eprintln!("Defaults from the environment (the options take precedence): SONOS_DISCOVERY_TIMEOUT,");
+            eprintln!("SONOS_DISCOVERY_INTERFACE and SONOS_DISCOVERY_TARGET");
+            eprintln!("Every flag can be turned off with --no-<flag>, e.g. a default of the config file");
+            process::exit(EXIT_USAGE)
+        }
+    };
+
+    // Before any other thread is started, so everything (health endpoints, webhooks) runs inside the namespace
+    if let Some(ref namespace) = options.netns {
+        if let Err(e) = enter_netns(namespace) {
+            eprintln!("Couldn't enter the network namespace {}: {}", namespace, e);
+            process::exit(EXIT_FAILURE)
+        }
+    }
+
+    let mut discovery = Discover::default();
+    if let Some(interface) = options.interface {
+        discovery.set_interface(interface);
+    }
+    if let Some(ref name) = options.interface_name {
+        discovery.set_interface_name(name);
+    }
+    discovery.set_all_interfaces(options.all_interfaces);
+    discovery.set_auto_interface(options.auto_interface);
+    if let Some(address) = options.bind {
+        discovery.set_bind_addr(address);
+    }
+    if let Some(ref device) = options.bind_device {
+        set_bind_device(&mut discovery, device);
+    }
+    if let Some(port) = options.source_port {
+        discovery.set_source_port(port);
+    }
+    discovery.set_reuse_port(options.reuse_port);
+    if let Some(interface) = options.multicast_if {
+        discovery.set_multicast_if(interface);
+    }
+    discovery.set_multicast_loop(options.multicast_loop);
+    if !options.multicast_groups.is_empty() {
+        discovery.set_additional_multicast_addrs(&options.multicast_groups);
+    }
+    if let Some(mx) = options.mx {
+        discovery.set_mx(mx);
+    }
+    if let Some(slack) = options.mx_slack {
+        discovery.set_mx_slack(slack);
+    }
+    if let Some(ttl) = options.ttl {
+        discovery.set_multicast_ttl(ttl);
+    }
+    // After the MX, which raises the timeout to at least MX + 1 second
+    if let Some(timeout) = options.timeout {
+        discovery.set_timeout(timeout);
+    }
+    if let Some(ref search_target) = options.search_target {
+        discovery.set_search_target(search_target);
+    }
+    discovery.set_ssdp_all(options.ssdp_all);
+    if let Some(resends) = options.resends {
+        discovery.set_resends(resends, Duration::from_secs(1));
+    }
+    discovery.set_adaptive(options.adaptive);
+    if let Some(quiet) = options.quiet {
+        discovery.set_quiet_period(quiet);
+    }
+    discovery.set_verify(options.verify);
+    discovery.set_sweep_fallback(options.sweep);
+    if let Some(network) = options.sweep_network {
+        discovery.set_sweep_network(network);
+    }
+    discovery.set_resolve_hostnames(options.resolve_hostnames);
+    discovery.set_recreate_socket(options.recreate_socket);
+    // The rooms of the hooks (`SONOS_NAME`) are only known from the zone groups
+    if options.on_found.is_some() || options.on_lost.is_some() {
+        discovery.set_topology(true);
+    }
+    if let OutputFormat::Topology | OutputFormat::Dot = options.format {
+        discovery.set_topology(true);
+        discovery.set_include_invisible(true);
+    }
+    if options.verbose {
+        verbose::enable(&mut discovery);
+    }
+    discovery.set_lenient_match(options.lenient);
+    discovery.set_validate_source(options.source_check);
+    if let Some(ttl) = options.description_ttl {
+        discovery.set_description_ttl(ttl);
+    }
+    if let Some(budget) = options.budget {
+        discovery.set_budget(budget);
+    }
+    if let Some(ref proxy) = options.proxy {
+        discovery.set_http_fetcher(Arc::new(proxy.clone()));
+    }
+    if let Some(address) = options.broadcast_fallback {
+        discovery.set_broadcast_fallback(address);
+    }
+    if let Some(ref household) = options.household {
+        discovery.set_household(household);
+    }
+    if let Some(ref room) = options.room {
+        discovery.set_room_filter(room);
+    }
+    if let Some(ref model) = options.model {
+        discovery.set_model_filter(model);
+    }
+
+    if let Command::History(ref directory) = options.command {
+        if let Err(e) = print_history(directory) {
+            eprintln!("Couldn't read the snapshots in {}: {}", directory.display(), e);
+            process::exit(EXIT_FAILURE)
+        }
+        return;
+    }
+
+    if let Command::Export(format) = options.command {
+        // The rooms and models of the device descriptions, sorted by the room
+        let mut records = discovery.inventory().unwrap_or_else(|e| discovery_failed(e));
+        records.sort_by(|a, b| a.room_name.cmp(&b.room_name).then_with(|| a.uuid.cmp(&b.uuid)));
+        match format {
+            ExportFormat::HomeAssistant => print!("{}", home_assistant_config(&records)),
+            ExportFormat::Inventory(InventoryFormat::Ini) => print!("{}", ini_inventory(&records)),
+            ExportFormat::Inventory(InventoryFormat::Yaml) => print!("{}", yaml_inventory(&records))
+        }
+        return;
+    }
+
+    if let Some(address) = options.serve {
+        return serve(discovery, address, options.interval.unwrap_or(SERVE_INTERVAL), options.health);
+    }
+
+    if let Some(interval) = options.interval {
+        return watch(&discovery, interval, &options);
+    }
+
+    // The prometheus metrics are written without devices as well, they report the count themselves
+    let min_devices = options.min_devices.unwrap_or(match options.format {
+        OutputFormat::Prom => 0,
+        _ => 1
+    });
+    // A smaller device count would stop the discovery before it could succeed
+    let count = options.count.map(|count| cmp::max(count, min_devices));
+    let found = match options.format {
+        OutputFormat::Text if options.all_devices => {
+            let start_time = Instant::now();
+
+            let devices = discovery.start_all(None).unwrap_or_else(|e| discovery_failed(e));
+            print!("{}", device_kinds(&devices));
+
+            println!("\nTime: {:?}", start_time.elapsed());
+            devices.iter().filter(|device| device.kind == DeviceKind::SonosZonePlayer).count()
+        }
+        OutputFormat::Text => {
+            let start_time = Instant::now();
+
+            let ips = if options.fast {
+                discovery.start_fast(None)
+            } else if options.cache {
+                start_cached(&discovery, options.cache_file.as_deref(), count)
+            } else {
+                discovery.start(None, count)
+            }.unwrap_or_else(|e| discovery_failed(e));
+            let found = ips.unique_ips().len();
+            if options.report {
+                eprint!("{}", discovery_report(&ips.report()));
+            }
+            for ip in ips {
+                println!("{:?}", ip)
+            }
+
+            println!("\nTime: {:?}", start_time.elapsed());
+            found
+        }
+        OutputFormat::Prom => {
+            // Wait for the whole timeout, a device count limit would hide additional speakers
+            let start_time = Instant::now();
+            let ips: BTreeSet<IpAddr> = discovery.start(None, None).unwrap_or_else(|e| discovery_failed(e))
+                .into_iter().collect();
+
+            print!("{}", prometheus_metrics(&ips, start_time.elapsed()));
+            ips.len()
+        }
+        OutputFormat::Json => {
+            let devices = if options.all_devices {
+                discovery.start_all(None)
+            } else {
+                discovery.start_detailed(None, count)
+            }.unwrap_or_else(|e| discovery_failed(e));
+
+            print!("{}", json_devices(&devices));
+            devices.iter().filter(|device| device.kind == DeviceKind::SonosZonePlayer).count()
+        }
+        OutputFormat::Table => {
+            let mut devices = discovery.start_with_description(None, count).unwrap_or_else(|e| discovery_failed(e));
+            if let Some(key) = options.sort {
+                sort_devices(&mut devices, key);
+            }
+
+            print!("{}", device_table(&devices, &options.fields));
+            devices.len()
+        }
+        OutputFormat::Topology | OutputFormat::Dot => {
+            let result = discovery.start(None, count).unwrap_or_else(|e| discovery_failed(e));
+            let groups = result.grouped();
+            match options.format {
+                OutputFormat::Dot => print!("{}", topology_dot(&groups)),
+                _ => print!("{}", topology_tree(&groups))
+            }
+            result.unique_ips().len()
+        }
+    };
+
+    if found < min_devices {
+        eprintln!("Found {} devices, expected at least {}", found, min_devices);
+        process::exit(EXIT_TOO_FEW_DEVICES)
+    }
+}
+
+/// Exits with `EXIT_FAILURE` after a failed single discovery
+fn discovery_failed(error: DiscoveryError) -> ! {
+    eprintln!("The discovery failed: {}", error);
+    process::exit(EXIT_FAILURE)
+}
+
+/// Daemon mode: keeps a `Monitor` scanning every `interval` and serves its devices as json on `address`, with the
+/// health endpoints on `health_address`
+fn serve(discovery: Discover, address: SocketAddr, interval: Duration, health_address: Option<SocketAddr>) {
+    let listener = TcpListener::bind(address).unwrap_or_else(|e| {
+        eprintln!("Couldn't serve the devices on {}: {}", address, e);
+        process::exit(EXIT_FAILURE)
+    });
+    eprintln!("Serving the devices on http://{}/devices", address);
+
+    let max_age = max_scan_age(discovery.config(), interval);
+    let monitor = Arc::new(Monitor::start(discovery, interval));
+    if let Some(health_address) = health_address {
+        let health = Arc::new(Mutex::new(Health {
+            started: Instant::now(),
+            last_scan: None,
+            devices: 0
+        }));
+        if let Err(e) = serve_health(health_address, Arc::clone(&health), max_age) {
+            eprintln!("Couldn't start the health endpoints on {}: {}", health_address, e);
+            process::exit(EXIT_FAILURE)
+        }
+        let monitor = Arc::clone(&monitor);
+        thread::spawn(move || track_monitor(&monitor, &health));
+    }
+    serve_devices(&listener, monitor);
+}
+
+/// Reruns the discovery every `interval`.
+/// With `options.diff` only the changes to the previous scan are printed (the first scan counts every device as joined):
+/// `joined <ip>`, `left <ip>` and `address-changed <uuid> <old ip> -> <new ip>`.
+fn watch(discovery: &Discover, interval: Duration, options: &Options) {
+    let mut known: Option<DeviceSet> = None;
+    let mut last_snapshot: Option<Instant> = None;
+    let mut last_printed: HashMap<String, Instant> = HashMap::new();
+    // Rooms of the devices by their uuid, from the zone groups of the hooks
+    let mut rooms: HashMap<String, String> = HashMap::new();
+
+    let health = Arc::new(Mutex::new(Health {
+        started: Instant::now(),
+        last_scan: None,
+        devices: 0
+    }));
+    if let Some(address) = options.health {
+        if let Err(e) = serve_health(address, Arc::clone(&health), max_scan_age(discovery.config(), interval)) {
+            eprintln!("Couldn't start the health endpoints on {}: {}", address, e);
+            process::exit(EXIT_FAILURE)
+        }
+    }
+
+    loop {
+        let scan_start = Instant::now();
+
+        match discovery.start(None, None) {
+            Ok(result) => {
+                // Devices are identified by their uuid, so a new address (dhcp) isn't reported as left + joined
+                let current = result.device_set();
+                let first_scan = known.is_none();
+                let diff = known.take().unwrap_or_default().diff(&current);
+                let now = timestamp();
+
+                if options.diff {
+                    for (_, ip) in &diff.joined {
+                        println!("{} joined {}", now, ip);
+                    }
+                    for (_, ip) in &diff.left {
+                        println!("{} left {}", now, ip);
+                    }
+                    for (uuid, old, new) in &diff.changed {
+                        println!("{} address-changed {} {} -> {}", now, uuid, old, new);
+                    }
+                } else if let Some(window) = options.dedup_window {
+                    // Every response (several per device are possible), collapsed within the window
+                    for ip in &result {
+                        let identity = result.uuid(ip).map_or_else(|| ip.to_string(), str::to_string);
+                        let due = match last_printed.get(&identity) {
+                            Some(printed) => printed.elapsed() >= window,
+                            None => true
+                        };
+                        if due {
+                            println!("{} {}", now, ip);
+                            last_printed.insert(identity, Instant::now());
+                        }
+                    }
+                } else {
+                    for (_, ip) in &current {
+                        println!("{} {}", now, ip);
+                    }
+                }
+
+                // Every device joins on the first scan, that's not worth a notification
+                if options.notify && !first_scan {
+                    notify_changes(&diff);
+                }
+
+                send_webhooks(options, &now, &diff);
+
+                // Kept across the scans, a device which left can't tell its room anymore
+                for member in result.grouped().into_iter().flat_map(|group| group.members) {
+                    if let Some(name) = member.zone_name {
+                        rooms.insert(member.uuid, name);
+                    }
+                }
+                if let Some(ref command) = options.on_found {
+                    for (identity, ip) in &diff.joined {
+                        run_hook(command, "found", identity, rooms.get(identity).map(String::as_str), ip, &now);
+                    }
+                }
+                if let Some(ref command) = options.on_lost {
+                    for (identity, ip) in &diff.left {
+                        run_hook(command, "lost", identity, rooms.get(identity).map(String::as_str), ip, &now);
+                    }
+                }
+
+                if let Ok(mut health) = health.lock() {
+                    health.last_scan = Some((Instant::now(), now));
+                    health.devices = current.len();
+                }
+
+                known = Some(current);
+            }
+            // A failed scan shouldn't end the observation, the next one might succeed
+            Err(e) => eprintln!("{} scan failed: {}", timestamp(), e)
+        }
+
+        if let Some(ref directory) = options.snapshot_dir {
+            let due = match last_snapshot {
+                Some(last) => last.elapsed() >= options.snapshot_interval,
+                None => true
+            };
+            if due {
+                last_snapshot = Some(Instant::now());
+                write_snapshot(discovery, directory);
+            }
+        }
+
+        if let Some(remaining) = interval.checked_sub(scan_start.elapsed()) {
+            thread::sleep(remaining);
+        }
+    }
+}
+
+/// Writes an inventory snapshot to `directory`, failures are only reported
+#[cfg(feature = "snapshot")]
+fn write_snapshot(discovery: &Discover, directory: &Path) {
+    let written = discovery.inventory()
+        .map_err(io::Error::from)
+        .and_then(|records| Snapshot::new(records).write(directory));
+
+    match written {
+        Ok(path) => eprintln!("{} snapshot written to {}", timestamp(), path.display()),
+        Err(e) => eprintln!("{} snapshot failed: {}", timestamp(), e)
+    }
+}
+
+/// Prints the change history of every device from the snapshots in `directory`
+#[cfg(feature = "snapshot")]
+fn print_history(directory: &Path) -> io::Result<()> {
+    for history in load_history(directory)? {
+        println!("{} ({}) first seen {}, last seen {}",
+                 history.uuid, history.room_name.as_ref().map_or("unknown room", String::as_str),
+                 format_unix_time(history.first_seen), format_unix_time(history.last_seen));
+
+        for event in history.events {
+            let change = match event.change {
+                Change::AddressChanged { old, new } => format!("address {} -> {}", join(&old), join(&new)),
+                Change::FirmwareChanged { old, new } => format!("firmware {} -> {}",
+                                                                old.unwrap_or_else(|| "unknown".to_string()),
+                                                                new.unwrap_or_else(|| "unknown".to_string())),
+                Change::Disappeared => "disappeared".to_string(),
+                Change::Reappeared => "reappeared".to_string()
+            };
+            println!("  {} {}", format_unix_time(event.time), change);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "snapshot")]
+fn join(ips: &[IpAddr]) -> String {
+    ips.iter().map(IpAddr::to_string).collect::<Vec<String>>().join(", ")
+}
+
+/// `history` is rejected while parsing the arguments without the `snapshot` feature
+#[cfg(not(feature = "snapshot"))]
+fn print_history(_directory: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// `--snapshot-dir` is rejected while parsing the arguments without the `snapshot` feature
+#[cfg(not(feature = "snapshot"))]
+fn write_snapshot(_discovery: &Discover, _directory: &Path) {}
+
+/// Discovery which probes the speakers of the cache at `path` (or in the platform cache directory) first
+#[cfg(feature = "device-cache")]
+fn start_cached(discovery: &Discover, path: Option<&Path>, count: Option<usize>) -> Result<DiscoveryResult, DiscoveryError> {
+    match path.map(Path::to_path_buf).or_else(DeviceCache::default_path) {
+        Some(path) => discovery.start_cached(&path, None),
+        None => {
+            eprintln!("No cache directory found, discovering all speakers");
+            discovery.start(None, count)
+        }
+    }
+}
+
+/// `--cache` is rejected while parsing the arguments without the `device-cache` feature
+#[cfg(not(feature = "device-cache"))]
+fn start_cached(discovery: &Discover, _path: Option<&Path>, count: Option<usize>) -> Result<DiscoveryResult, DiscoveryError> {
+    discovery.start(None, count)
+}
+
+#[cfg(target_os = "linux")]
+fn set_bind_device(discovery: &mut Discover, name: &str) {
+    discovery.set_bind_device(name);
+}
+
+/// `--bind-device` is rejected while parsing the arguments outside of linux
+#[cfg(not(target_os = "linux"))]
+fn set_bind_device(_discovery: &mut Discover, _name: &str) {}
+
+/// `--netns` is rejected while parsing the arguments without the `netns` feature
+#[cfg(not(all(target_os = "linux", feature = "netns")))]
+fn enter_netns(_namespace: &str) -> io::Result<()> {
+    Ok(())
+}
+
+/// Raises a desktop notification for every device which joined or left the network
+#[cfg(feature = "notify")]
+fn notify_changes(diff: &DeviceSetDiff) {
+    let changes = diff.joined.iter().map(|(_, ip)| ("Sonos speaker joined", ip))
+        .chain(diff.left.iter().map(|(_, ip)| ("Sonos speaker left", ip)));
+
+    for (summary, ip) in changes {
+        let result = notify_rust::Notification::new()
+            .appname("sonos_discovery")
+            .summary(summary)
+            .body(&ip.to_string())
+            .show();
+        if let Err(e) = result {
+            eprintln!("{} notification failed: {}", timestamp(), e);
+        }
+    }
+}
+
+/// `--notify` is rejected while parsing the arguments without the `notify` feature
+#[cfg(not(feature = "notify"))]
+fn notify_changes(_diff: &DeviceSetDiff) {}
+
+/// Posts one event per found, lost and changed device to every `--webhook`.
+/// Unlike the notifications the first scan is sent too, receivers learn about every device.
+#[cfg(feature = "webhook")]
+fn send_webhooks(options: &Options, now: &str, diff: &DeviceSetDiff) {
+    use webhook::{payload, send, Event};
+
+    let events = diff.joined.iter().map(|(_, ip)| Event::Found(ip))
+        .chain(diff.left.iter().map(|(_, ip)| Event::Lost(ip)))
+        .chain(diff.changed.iter().map(|(uuid, old, new)| Event::Changed { uuid, old, new }));
+    let payloads = events.map(|event| payload(&event, now)).collect();
+
+    send(&options.webhooks, options.webhook_secret.as_deref(), payloads);
+}
+
+/// `--webhook` is rejected while parsing the arguments without the `webhook` feature
+#[cfg(not(feature = "webhook"))]
+fn send_webhooks(_options: &Options, _now: &str, _diff: &DeviceSetDiff) {}
+
+/// Field of a flag in the options
+type FlagField = fn(&mut Options) -> &mut bool;
+
+/// Flags of the command line with their option, `--no-<flag>` turns one off again (e.g. one of the config file)
+const FLAGS: &[(&str, FlagField)] = &[
+    ("--diff", |options| &mut options.diff),
+    ("--watch", |options| &mut options.watch),
+    ("--notify", |options| &mut options.notify),
+    ("--reuse-port", |options| &mut options.reuse_port),
+    ("--multicast-loop", |options| &mut options.multicast_loop),
+    ("--all-interfaces", |options| &mut options.all_interfaces),
+    ("--auto-interface", |options| &mut options.auto_interface),
+    ("--fast", |options| &mut options.fast),
+    ("--cache", |options| &mut options.cache),
+    ("--all-devices", |options| &mut options.all_devices),
+    ("--ssdp-all", |options| &mut options.ssdp_all),
+    ("--verbose", |options| &mut options.verbose),
+    ("--report", |options| &mut options.report),
+    ("--adaptive", |options| &mut options.adaptive),
+    ("--lenient", |options| &mut options.lenient),
+    ("--source-check", |options| &mut options.source_check),
+    ("--verify", |options| &mut options.verify),
+    ("--sweep", |options| &mut options.sweep),
+    ("--resolve-hostnames", |options| &mut options.resolve_hostnames),
+    ("--recreate-socket", |options| &mut options.recreate_socket)
+];
+
+/// Options of the command line `args` on top of the defaults of the config file (`--config` or the default path)
+fn options(args: &[String]) -> Result<Options, String> {
+    let mut command_line = Options::default();
+    apply(&mut command_line, args.iter().cloned())?;
+
+    merge(&Defaults::load(command_line.config.as_deref())?, args)
+}
+
+/// Options of the command line `args` on top of `defaults`. A default of an option on the command line is replaced
+/// (all values of a repeatable option like `--webhook`), one which can't be combined with the command line (e.g.
+/// `format = "json"` with `--interval`) is left out.
+fn merge(defaults: &Defaults, args: &[String]) -> Result<Options, String> {
+    let given = apply(&mut Options::default(), args.iter().cloned())?;
+    let mut settings = Vec::new();
+    for setting in defaults.settings() {
+        let names = apply(&mut Options::default(), setting.arguments.iter().cloned())
+            .map_err(|e| defaults.error(setting, &e))?;
+        if names.is_disjoint(&given) {
+            settings.push(setting);
+        }
+    }
+
+    // A default can depend on a later one (`diff = true` on `interval`), until no other one fits
+    let mut arguments: Vec<String> = Vec::new();
+    loop {
+        let before = settings.len();
+        settings.retain(|setting| {
+            let combined = arguments.iter().chain(&setting.arguments).chain(args).cloned();
+            if parse_args(combined).is_err() {
+                return true;
+            }
+            arguments.extend(setting.arguments.iter().cloned());
+            false
+        });
+        if settings.len() == before {
+            break;
+        }
+    }
+
+    parse_args(arguments.into_iter().chain(args.iter().cloned()))
+}
+
+/// Options of the command line `args` alone
+fn parse_args<I: Iterator<Item = String>>(args: I) -> Result<Options, String> {
+    let mut options = Options::default();
+    apply(&mut options, args)?;
+    validate(&mut options)?;
+
+    Ok(options)
+}
+
+/// Option an alias stands for
+fn canonical(arg: &str) -> &str {
+    match arg {
+        "-v" => "--verbose",
+        "--json" => "--format",
+        "--on-join" => "--on-found",
+        "--on-leave" => "--on-lost",
+        _ => arg
+    }
+}
+
+/// Sets the options of `args`, without checking whether they can be combined. Returns the names of the given options,
+/// flags without `--no-` and aliases by the option they stand for (`canonical`).
+fn apply<I: Iterator<Item = String>>(options: &mut Options, args: I) -> Result<BTreeSet<String>, String> {
+    let mut args = args.peekable();
+    let mut given = BTreeSet::new();
+
+    while let Some(arg) = args.next() {
+        let name = canonical(&arg);
+        let (flag, enabled) = match name.strip_prefix("--no-") {
+            Some(flag) => (format!("--{}", flag), false),
+            None => (name.to_string(), true)
+        };
+        if let Some(&(_, field)) = FLAGS.iter().find(|&&(name, _)| name == flag) {
+            *field(options) = enabled;
+            given.insert(flag);
+            continue;
+        }
+        given.insert(name.to_string());
+
+        match arg.as_str() {
+            "--interval" => {
+                let value = args.next().ok_or("--interval needs a value")?;
+                options.interval = Some(parse_duration(&value)?);
+            }
+            "--dedup-window" => {
+                let value = args.next().ok_or("--dedup-window needs a value")?;
+                // `0` disables the collapsing, every single response is printed
+                options.dedup_window = Some(if value == "0" { Duration::from_secs(0) } else { parse_duration(&value)? });
+            }
+            "--snapshot-dir" if cfg!(feature = "snapshot") => {
+                options.snapshot_dir = Some(args.next().ok_or("--snapshot-dir needs a value")?.into());
+            }
+            "--snapshot-dir" => return Err("--snapshot-dir needs the `snapshot` feature".to_string()),
+            "--snapshot-interval" => {
+                let value = args.next().ok_or("--snapshot-interval needs a value")?;
+                options.snapshot_interval = parse_duration(&value)?;
+            }
+            "--webhook" if cfg!(feature = "webhook") => {
+                options.webhooks.push(args.next().ok_or("--webhook needs a value")?);
+            }
+            "--webhook" => return Err("--webhook needs the `webhook` feature".to_string()),
+            "--webhook-secret" => {
+                options.webhook_secret = Some(args.next().ok_or("--webhook-secret needs a value")?);
+            }
+            "--on-found" | "--on-join" => options.on_found = Some(args.next().ok_or(format!("{} needs a value", arg))?),
+            "--on-lost" | "--on-leave" => options.on_lost = Some(args.next().ok_or(format!("{} needs a value", arg))?),
+            "--interface" => {
+                let value = args.next().ok_or("--interface needs a value")?;
+                // Anything which isn't an address is the name of the interface
+                match value.parse() {
+                    Ok(address) => options.interface = Some(address),
+                    Err(_) => options.interface_name = Some(value)
+                }
+            }
+            "--multicast-if" => {
+                let value = args.next().ok_or("--multicast-if needs a value")?;
+                options.multicast_if = Some(value.parse().map_err(|_| format!("Invalid interface address: {}", value))?);
+            }
+            "--bind" => {
+                let value = args.next().ok_or("--bind needs a value")?;
+                // Without a port the os chooses one
+                let address = value.parse()
+                    .or_else(|_| value.parse().map(|ip: IpAddr| SocketAddr::new(ip, 0)))
+                    .map_err(|_| format!("Invalid bind address: {}", value))?;
+                options.bind = Some(address);
+            }
+            "--bind-device" if cfg!(target_os = "linux") => {
+                options.bind_device = Some(args.next().ok_or("--bind-device needs a value")?);
+            }
+            "--bind-device" => return Err("--bind-device is linux only".to_string()),
+            "--source-port" => {
+                let value = args.next().ok_or("--source-port needs a value")?;
+                options.source_port = Some(value.parse().map_err(|_| format!("Invalid port: {}", value))?);
+            }
+            "--multicast-group" => {
+                let value = args.next().ok_or("--multicast-group needs a value")?;
+                // Without a port the group is on the ssdp port
+                let address = value.parse()
+                    .or_else(|_| value.parse().map(|ip: IpAddr| SocketAddr::new(ip, 1900)))
+                    .map_err(|_| format!("Invalid multicast group: {}", value))?;
+                options.multicast_groups.push(address);
+            }
+            "--broadcast-fallback" => {
+                let value = args.next().ok_or("--broadcast-fallback needs a value")?;
+                // The port is optional, ssdp always uses 1900
+                let address = value.parse()
+                    .or_else(|_| value.parse().map(|ip: Ipv4Addr| SocketAddr::new(IpAddr::V4(ip), 1900)))
+                    .map_err(|_| format!("Invalid broadcast address: {}", value))?;
+                options.broadcast_fallback = Some(address);
+            }
+            "--mx-slack" => {
+                let value = args.next().ok_or("--mx-slack needs a value")?;
+                options.mx_slack = Some(parse_duration(&value)?);
+            }
+            "--mx" => {
+                let value = args.next().ok_or("--mx needs a value")?;
+                let mx = value.parse().map_err(|_| format!("Invalid MX: {}", value))?;
+                if !(1..=MAX_MX).contains(&mx) {
+                    return Err(format!("--mx has to be between 1 and {}", MAX_MX));
+                }
+                options.mx = Some(mx);
+            }
+            "--ttl" => {
+                let value = args.next().ok_or("--ttl needs a value")?;
+                let ttl = value.parse().map_err(|_| format!("Invalid TTL: {}", value))?;
+                if !(1..=255).contains(&ttl) {
+                    return Err("--ttl has to be between 1 and 255".to_string());
+                }
+                options.ttl = Some(ttl);
+            }
+            "--timeout" => {
+                let value = args.next().ok_or("--timeout needs a value")?;
+                options.timeout = Some(parse_duration(&value)?);
+            }
+            "--count" => {
+                let value = args.next().ok_or("--count needs a value")?;
+                options.count = if value == "all" {
+                    None
+                } else {
+                    Some(value.parse().map_err(|_| format!("Invalid device count: {}", value))?)
+                };
+            }
+            "--min-devices" => {
+                let value = args.next().ok_or("--min-devices needs a value")?;
+                options.min_devices = Some(value.parse().map_err(|_| format!("Invalid device count: {}", value))?);
+            }
+            "--cache-file" if cfg!(feature = "device-cache") => {
+                options.cache_file = Some(args.next().ok_or("--cache-file needs a value")?.into());
+                options.cache = true;
+            }
+            "--cache-file" => return Err("--cache-file needs the `device-cache` feature".to_string()),
+            "--config" => options.config = Some(args.next().ok_or("--config needs a value")?.into()),
+            "--search-target" => options.search_target = Some(args.next().ok_or("--search-target needs a value")?),
+            "--resends" => {
+                let value = args.next().ok_or("--resends needs a value")?;
+                options.resends = Some(value.parse().map_err(|_| format!("Invalid number of resends: {}", value))?);
+            }
+            "--sweep-network" => {
+                let value = args.next().ok_or("--sweep-network needs a value")?;
+                options.sweep_network = Some(value.parse().map_err(|e: DiscoveryError| e.to_string())?);
+                options.sweep = true;
+            }
+            "--description-ttl" => {
+                let value = args.next().ok_or("--description-ttl needs a value")?;
+                options.description_ttl = Some(parse_duration(&value)?);
+            }
+            "--quiet" => {
+                let value = args.next().ok_or("--quiet needs a value")?;
+                options.quiet = Some(parse_duration(&value)?);
+            }
+            "--budget" => {
+                let value = args.next().ok_or("--budget needs a value")?;
+                options.budget = Some(parse_duration(&value)?);
+            }
+            "--proxy" => {
+                let value = args.next().ok_or("--proxy needs a value")?;
+                options.proxy = if value == "env" {
+                    Some(ProxyFetcher::from_env().ok_or("--proxy env needs the HTTP_PROXY environment variable")?)
+                } else {
+                    Some(ProxyFetcher {
+                        proxy: value,
+                        no_proxy: Vec::new()
+                    })
+                };
+            }
+            "--household" => options.household = Some(args.next().ok_or("--household needs a value")?),
+            "--room" => options.room = Some(args.next().ok_or("--room needs a value")?),
+            "--model" => options.model = Some(args.next().ok_or("--model needs a value")?),
+            "--netns" if cfg!(all(target_os = "linux", feature = "netns")) => {
+                options.netns = Some(args.next().ok_or("--netns needs a value")?);
+            }
+            "--netns" => return Err("--netns needs the `netns` feature (linux only)".to_string()),
+            "--health" => {
+                let value = args.next().ok_or("--health needs a value")?;
+                options.health = Some(value.parse()
+                    .map_err(|_| format!("Invalid health address: {}", value))?);
+            }
+            "--serve" => {
+                let value = args.next().ok_or("--serve needs a value")?;
+                options.serve = Some(value.parse()
+                    .map_err(|_| format!("Invalid serve address: {}", value))?);
+            }
+            "--format" => {
+                options.format = match args.next().as_deref() {
+                    Some("text") => OutputFormat::Text,
+                    Some("prom") => OutputFormat::Prom,
+                    Some("json") => OutputFormat::Json,
+                    Some("table") => OutputFormat::Table,
+                    Some("topology") => OutputFormat::Topology,
+                    Some("dot") => OutputFormat::Dot,
+                    Some(format) => return Err(format!("Unknown output format: {}", format)),
+                    None => return Err("--format needs a value".to_string())
+                };
+            }
+            "--json" => options.format = OutputFormat::Json,
+            "--fields" => {
+                let value = args.next().ok_or("--fields needs a value")?;
+                options.fields = value.split(',')
+                    .map(|name| Field::parse(name.trim()).ok_or_else(|| format!("Unknown field: {}", name)))
+                    .collect::<Result<_, _>>()?;
+            }
+            "--sort" => {
+                let value = args.next().ok_or("--sort needs a value")?;
+                options.sort = Some(SortKey::parse(&value).ok_or_else(|| format!("Unknown sort key: {}", value))?);
+            }
+            "history" if cfg!(feature = "snapshot") => {
+                options.command = Command::History(args.next().ok_or("history needs a snapshot directory")?.into());
+            }
+            "history" => return Err("history needs the `snapshot` feature".to_string()),
+            "export" => {
+                let format = match args.next().as_deref() {
+                    Some("home-assistant") => ExportFormat::HomeAssistant,
+                    Some("inventory") => {
+                        // The inventory format is optional, anything else is left for the next argument
+                        let format = match args.peek().map(String::as_str) {
+                            Some("yaml") => Some(InventoryFormat::Yaml),
+                            Some("ini") => Some(InventoryFormat::Ini),
+                            _ => None
+                        };
+                        if format.is_some() {
+                            args.next();
+                        }
+                        ExportFormat::Inventory(format.unwrap_or(InventoryFormat::Ini))
+                    }
+                    Some(format) => return Err(format!("Unknown export format: {}", format)),
+                    None => return Err("export needs a format".to_string())
+                };
+                options.command = Command::Export(format);
+            }
+            _ => return Err(format!("Unknown argument: {}", arg))
+        }
+    }
+
+    Ok(given)
+}
+
+/// Checks whether the options can be combined, `--watch` sets the interval
+fn validate(options: &mut Options) -> Result<(), String> {
+    if options.notify && !cfg!(feature = "notify") {
+        return Err("--notify needs the `notify` feature".to_string());
+    }
+    if options.cache && !cfg!(feature = "device-cache") {
+        return Err("--cache needs the `device-cache` feature".to_string());
+    }
+
+    if options.watch {
+        if options.dedup_window.is_some() {
+            return Err("--watch can't be used together with --dedup-window".to_string());
+        }
+        // Frequent enough to catch a speaker dropping off the wifi, the diff keeps the output quiet otherwise
+        options.interval = options.interval.or_else(|| Some(Duration::from_secs(10)));
+        options.diff = true;
+    }
+
+    if let Command::Export(_) = options.command {
+        if options.interval.is_some() {
+            return Err("export can't be used together with --interval".to_string());
+        }
+    }
+
+    let single_format = match options.format {
+        OutputFormat::Text => None,
+        OutputFormat::Prom => Some("prom"),
+        OutputFormat::Json => Some("json"),
+        OutputFormat::Table => Some("table"),
+        OutputFormat::Topology => Some("topology"),
+        OutputFormat::Dot => Some("dot")
+    };
+    if let (Some(format), Some(_)) = (single_format, options.interval) {
+        return Err(format!("--format {} is a single discovery and can't be used with --interval", format));
+    }
+
+    if options.serve.is_some() {
+        if let Command::Export(_) = options.command {
+            return Err("export can't be used together with --serve".to_string());
+        }
+        if single_format.is_some() || options.all_devices || options.fast || options.cache || options.report {
+            return Err("--serve only serves the json of the sonos devices and can't be combined with --format, \
+                        --all-devices, --fast, --cache or --report".to_string());
+        }
+        let watch_options = options.watch || options.diff || options.dedup_window.is_some() || options.notify
+            || options.snapshot_dir.is_some() || !options.webhooks.is_empty()
+            || options.on_found.is_some() || options.on_lost.is_some();
+        if watch_options {
+            return Err("--serve only accepts --interval of the interval mode, not its outputs".to_string());
+        }
+    }
+
+    if options.fast && (options.interval.is_some() || single_format.is_some()) {
+        return Err("--fast is a single discovery with the text output".to_string());
+    }
+    if options.cache && (options.fast || options.interval.is_some() || single_format.is_some()) {
+        return Err("--cache is a single discovery with the text output".to_string());
+    }
+    if options.ssdp_all && (options.search_target.is_some() || options.all_devices) {
+        return Err("--ssdp-all can't be combined with --search-target or --all-devices".to_string());
+    }
+    if options.report && (options.all_devices || options.interval.is_some() || single_format.is_some()) {
+        return Err("--report is a single discovery with the text output".to_string());
+    }
+
+    if (options.room.is_some() || options.model.is_some()) && single_format != Some("table") {
+        return Err("--room and --model need the descriptions of --format table".to_string());
+    }
+    if (options.fields != Field::DEFAULT || options.sort.is_some()) && single_format != Some("table") {
+        return Err("--fields and --sort can only be used together with --format table".to_string());
+    }
+
+    if options.notify && options.interval.is_none() {
+        return Err("--notify can only be used together with --interval".to_string());
+    }
+
+    if options.snapshot_dir.is_some() && options.interval.is_none() {
+        return Err("--snapshot-dir can only be used together with --interval".to_string());
+    }
+
+    if !options.webhooks.is_empty() && options.interval.is_none() {
+        return Err("--webhook can only be used together with --interval".to_string());
+    }
+
+    if options.webhook_secret.is_some() && options.webhooks.is_empty() {
+        return Err("--webhook-secret can only be used together with --webhook".to_string());
+    }
+
+    if (options.on_found.is_some() || options.on_lost.is_some()) && options.interval.is_none() {
+        return Err("--on-found (--on-join) and --on-lost (--on-leave) can only be used together with --interval"
+            .to_string());
+    }
+
+    if options.health.is_some() && options.interval.is_none() && options.serve.is_none() {
+        return Err("--health can only be used together with --interval or --serve".to_string());
+    }
+
+    if options.dedup_window.is_some() {
+        if options.interval.is_none() {
+            return Err("--dedup-window can only be used together with --interval".to_string());
+        }
+        if options.diff {
+            return Err("--dedup-window can't be used together with --diff".to_string());
+        }
+    }
+
+    if options.diff && options.interval.is_none() {
+        return Err("--diff can only be used together with --interval".to_string());
+    }
+
+    Ok(())
+}
+
+/// Parses durations like `500ms`, `60s`, `5m`, `1h`. Plain numbers are seconds.
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let split = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+    let amount: u64 = amount.parse()
+        .map_err(|_| format!("Invalid duration: {}", value))?;
+
+    // Minutes and hours beyond the seconds of a `u64` are invalid
+    let duration = match unit {
+        "ms" => Some(Duration::from_millis(amount)),
+        "" | "s" => Some(Duration::from_secs(amount)),
+        "m" => amount.checked_mul(60).map(Duration::from_secs),
+        "h" => amount.checked_mul(60 * 60).map(Duration::from_secs),
+        _ => return Err(format!("Invalid duration unit: {}", unit))
+    }.ok_or_else(|| format!("Invalid duration: {}", value))?;
+
+    if duration == Duration::from_secs(0) {
+        return Err("Duration has to be greater than 0".to_string());
+    }
+
+    Ok(duration)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Options of the command line `args`
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    /// Error of the invalid command line `args`
+    fn error(args: &[&str]) -> String {
+        match parse(args) {
+            Ok(_) => panic!("{:?} are valid arguments", args),
+            Err(e) => e
+        }
+    }
+
+    /// Options of the command line `args` with the config file `content`, named `name` in the temporary directory
+    fn merged(name: &str, content: &str, args: &[&str]) -> Result<Options, String> {
+        let path = env::temp_dir().join(format!("sonos_discovery_{}_{}.toml", name, process::id()));
+        std::fs::write(&path, content).unwrap();
+        let args: Vec<String> = ["--config", path.to_str().unwrap()].iter().chain(args).map(|arg| arg.to_string())
+            .collect();
+        let options = options(&args);
+        std::fs::remove_file(&path).unwrap();
+
+        options
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("60"), Ok(Duration::from_secs(60)));
+        assert_eq!(parse_duration("60s"), Ok(Duration::from_secs(60)));
+        assert_eq!(parse_duration("5m"), Ok(Duration::from_secs(5 * 60)));
+        assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(2 * 60 * 60)));
+        assert_eq!(parse_duration("18446744073709551615"), Ok(Duration::from_secs(u64::MAX)));
+    }
+
+    #[test]
+    fn invalid_durations() {
+        for value in &["", "s", "-1s", " 5s", "99999999999999999999"] {
+            assert_eq!(parse_duration(value), Err(format!("Invalid duration: {}", value)));
+        }
+        for &(value, unit) in &[("5d", "d"), ("5S", "S"), ("1.5s", ".5s"), ("5 s", " s")] {
+            assert_eq!(parse_duration(value), Err(format!("Invalid duration unit: {}", unit)));
+        }
+    }
+
+    #[test]
+    fn zero_durations_are_invalid() {
+        for value in &["0", "0ms", "0s", "0m", "0h"] {
+            assert_eq!(parse_duration(value), Err("Duration has to be greater than 0".to_string()));
+        }
+    }
+
+    #[test]
+    fn overflowing_durations_are_invalid() {
+        assert_eq!(parse_duration("99999999999999999h"), Err("Invalid duration: 99999999999999999h".to_string()));
+        assert_eq!(parse_duration("307445734561825861m"),
+                   Err("Invalid duration: 307445734561825861m".to_string()));
+        assert_eq!(parse_duration("307445734561825860m"), Ok(Duration::from_secs(307445734561825860 * 60)));
+        assert_eq!(error(&["--interval", "99999999999999999h"]), "Invalid duration: 99999999999999999h");
+    }
+
+    #[test]
+    fn defaults() {
+        let options = parse(&[]).unwrap();
+        assert!(matches!(options.command, Command::Discover));
+        assert!(matches!(options.format, OutputFormat::Text));
+        assert_eq!(options.timeout, None);
+        assert_eq!(options.count, None);
+        assert_eq!(options.min_devices, None);
+        assert_eq!(options.interval, None);
+        assert!(options.multicast_loop);
+        assert_eq!(options.fields, Field::DEFAULT);
+        assert_eq!(options.snapshot_interval, Duration::from_secs(60 * 60));
+    }
+
+    #[test]
+    fn values() {
+        let options = parse(&["--timeout", "2500ms", "--count", "3", "--min-devices", "2", "--mx", "2", "--ttl", "4",
+                              "--interface", "192.168.1.2", "--bind", "192.168.1.2", "--multicast-group",
+                              "239.255.255.251", "--format", "table", "--sort", "name"]).unwrap();
+        assert_eq!(options.timeout, Some(Duration::from_millis(2500)));
+        assert_eq!(options.count, Some(3));
+        assert_eq!(options.min_devices, Some(2));
+        assert_eq!(options.mx, Some(2));
+        assert_eq!(options.ttl, Some(4));
+        assert_eq!(options.interface, Some(Ipv4Addr::new(192, 168, 1, 2)));
+        assert_eq!(options.interface_name, None);
+        assert_eq!(options.bind, Some("192.168.1.2:0".parse().unwrap()));
+        assert_eq!(options.multicast_groups, vec!["239.255.255.251:1900".parse().unwrap()]);
+        assert!(matches!(options.format, OutputFormat::Table));
+        assert_eq!(options.sort, Some(SortKey::Name));
+
+        let options = parse(&["--interface", "eth0", "--count", "all", "--json"]).unwrap();
+        assert_eq!(options.interface, None);
+        assert_eq!(options.interface_name.as_deref(), Some("eth0"));
+        assert_eq!(options.count, None);
+        assert!(matches!(options.format, OutputFormat::Json));
+    }
+
+    #[test]
+    fn watch_is_an_interval_with_the_diff() {
+        let options = parse(&["--watch"]).unwrap();
+        assert_eq!(options.interval, Some(Duration::from_secs(10)));
+        assert!(options.diff);
+
+        let options = parse(&["--watch", "--interval", "1m"]).unwrap();
+        assert_eq!(options.interval, Some(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn commands() {
+        assert!(matches!(parse(&["export", "home-assistant"]).unwrap().command,
+                         Command::Export(ExportFormat::HomeAssistant)));
+        assert!(matches!(parse(&["export", "inventory"]).unwrap().command,
+                         Command::Export(ExportFormat::Inventory(InventoryFormat::Ini))));
+        // The inventory format is optional, the next option follows right away
+        let options = parse(&["export", "inventory", "yaml", "--timeout", "1s"]).unwrap();
+        assert!(matches!(options.command, Command::Export(ExportFormat::Inventory(InventoryFormat::Yaml))));
+        assert_eq!(options.timeout, Some(Duration::from_secs(1)));
+        assert!(matches!(parse(&["export", "inventory", "--timeout", "1s"]).unwrap().command,
+                         Command::Export(ExportFormat::Inventory(InventoryFormat::Ini))));
+
+        assert_eq!(error(&["export"]), "export needs a format");
+        assert_eq!(error(&["export", "csv"]), "Unknown export format: csv");
+    }
+
+    #[test]
+    fn missing_values() {
+        for option in &["--interval", "--dedup-window", "--snapshot-interval", "--webhook-secret", "--on-found",
+                        "--on-leave", "--interface", "--multicast-if", "--bind", "--source-port", "--multicast-group",
+                        "--broadcast-fallback", "--mx-slack", "--mx", "--ttl", "--timeout", "--count", "--min-devices",
+                        "--search-target", "--resends", "--sweep-network", "--description-ttl", "--quiet",
+                        "--budget", "--proxy", "--household", "--room", "--model", "--health", "--serve", "--format",
+                        "--fields", "--sort"] {
+            assert_eq!(error(&[option]), format!("{} needs a value", option));
+        }
+    }
+
+    #[test]
+    fn invalid_values() {
+        assert_eq!(error(&["--bogus"]), "Unknown argument: --bogus");
+        assert_eq!(error(&["discover"]), "Unknown argument: discover");
+        assert_eq!(error(&["--count", "some"]), "Invalid device count: some");
+        assert_eq!(error(&["--count", "-1"]), "Invalid device count: -1");
+        assert_eq!(error(&["--mx", "0"]), format!("--mx has to be between 1 and {}", MAX_MX));
+        assert_eq!(error(&["--ttl", "256"]), "--ttl has to be between 1 and 255");
+        assert_eq!(error(&["--source-port", "65536"]), "Invalid port: 65536");
+        assert_eq!(error(&["--bind", "localhost"]), "Invalid bind address: localhost");
+        assert_eq!(error(&["--format", "xml"]), "Unknown output format: xml");
+        assert_eq!(error(&["--format", "table", "--fields", "ip,colour"]), "Unknown field: colour");
+        assert_eq!(error(&["--format", "table", "--sort", "colour"]), "Unknown sort key: colour");
+        assert_eq!(error(&["--timeout", "0"]), "Duration has to be greater than 0");
+    }
+
+    #[test]
+    fn conflicting_options() {
+        assert_eq!(error(&["--watch", "--dedup-window", "5s"]), "--watch can't be used together with --dedup-window");
+        assert_eq!(error(&["--format", "json", "--interval", "5s"]),
+                   "--format json is a single discovery and can't be used with --interval");
+        assert_eq!(error(&["export", "home-assistant", "--interval", "5s"]),
+                   "export can't be used together with --interval");
+        assert_eq!(error(&["--fast", "--interval", "5s"]), "--fast is a single discovery with the text output");
+        assert_eq!(error(&["--ssdp-all", "--all-devices"]),
+                   "--ssdp-all can't be combined with --search-target or --all-devices");
+        assert_eq!(error(&["--report", "--format", "json"]), "--report is a single discovery with the text output");
+        assert_eq!(error(&["--room", "Kitchen"]), "--room and --model need the descriptions of --format table");
+        assert_eq!(error(&["--sort", "name"]), "--fields and --sort can only be used together with --format table");
+        assert_eq!(error(&["--snapshot-interval", "1h", "--webhook-secret", "secret"]),
+                   "--webhook-secret can only be used together with --webhook");
+        assert_eq!(error(&["--on-found", "echo"]),
+                   "--on-found (--on-join) and --on-lost (--on-leave) can only be used together with --interval");
+        assert_eq!(error(&["--health", "127.0.0.1:9000"]),
+                   "--health can only be used together with --interval or --serve");
+        assert_eq!(error(&["--interval", "5s", "--diff", "--dedup-window", "5s"]),
+                   "--dedup-window can't be used together with --diff");
+        assert_eq!(error(&["--dedup-window", "5s"]), "--dedup-window can only be used together with --interval");
+        assert_eq!(error(&["--diff"]), "--diff can only be used together with --interval");
+        assert!(error(&["--serve", "127.0.0.1:8080", "--format", "json"]).starts_with("--serve only serves the json"));
+        assert_eq!(error(&["--serve", "127.0.0.1:8080", "--diff"]),
+                   "--serve only accepts --interval of the interval mode, not its outputs");
+
+        assert!(parse(&["--serve", "127.0.0.1:8080", "--health", "127.0.0.1:9000", "--interval", "5m"]).is_ok());
+        assert!(parse(&["--interval", "5s", "--health", "127.0.0.1:9000", "--diff"]).is_ok());
+    }
+
+    #[test]
+    fn flags_can_be_turned_off() {
+        let options = parse(&["--verify", "--no-verify", "--no-multicast-loop", "--no-source-check", "-v"]).unwrap();
+        assert!(!options.verify);
+        assert!(!options.multicast_loop);
+        assert!(!options.source_check);
+        assert!(options.verbose);
+        assert_eq!(error(&["--no-timeout"]), "Unknown argument: --no-timeout");
+    }
+
+    #[test]
+    fn the_config_option_is_only_an_option() {
+        let options = parse(&["--search-target", "--config"]).unwrap();
+        assert_eq!(options.search_target.as_deref(), Some("--config"));
+        assert_eq!(options.config, None);
+        assert_eq!(error(&["--config"]), "--config needs a value");
+    }
+
+    #[test]
+    fn the_command_line_replaces_the_defaults() {
+        let content = "timeout = \"2s\"\nverify = true\nmulticast_group = [\"239.255.255.251\", \"239.255.255.252\"]\n\
+                       format = \"table\"\n";
+        let defaults = merged("defaults", content, &[]).unwrap();
+        assert_eq!(defaults.timeout, Some(Duration::from_secs(2)));
+        assert!(defaults.verify);
+        assert_eq!(defaults.multicast_groups.len(), 2);
+        assert!(matches!(defaults.format, OutputFormat::Table));
+
+        let options = merged("replaced", content, &["--timeout", "1s", "--no-verify", "--multicast-group",
+                                                     "239.255.255.253", "--json"]).unwrap();
+        assert_eq!(options.timeout, Some(Duration::from_secs(1)));
+        assert!(!options.verify);
+        assert_eq!(options.multicast_groups, vec!["239.255.255.253:1900".parse().unwrap()]);
+        assert!(matches!(options.format, OutputFormat::Json));
+    }
+
+    #[test]
+    fn conflicting_defaults_are_left_out() {
+        let options = merged("conflict", "format = \"json\"\nfast = true\ntimeout = \"2s\"\n",
+                             &["--interval", "5s"]).unwrap();
+        assert!(matches!(options.format, OutputFormat::Text));
+        assert!(!options.fast);
+        assert_eq!(options.interval, Some(Duration::from_secs(5)));
+        assert_eq!(options.timeout, Some(Duration::from_secs(2)));
+
+        // Defaults of the interval mode are used once it's on the command line
+        let content = "diff = true\nnotify = false\nhealth = \"127.0.0.1:9000\"\n";
+        let single = merged("single", content, &[]).unwrap();
+        assert!(!single.diff);
+        assert_eq!(single.health, None);
+        let interval = merged("interval", content, &["--interval", "5s"]).unwrap();
+        assert!(interval.diff);
+        assert_eq!(interval.health, Some("127.0.0.1:9000".parse().unwrap()));
+
+        // A default depending on a later one
+        let options = merged("order", "diff = true\ninterval = \"1m\"\n", &[]).unwrap();
+        assert!(options.diff);
+        assert_eq!(options.interval, Some(Duration::from_secs(60)));
+
+        // The command line itself still has to be valid
+        assert_eq!(merged("invalid", "timeout = \"2s\"\n", &["--format", "json", "--interval", "5s"]).err(),
+                   Some("--format json is a single discovery and can't be used with --interval".to_string()));
+    }
+
+    #[test]
+    fn invalid_defaults_fail() {
+        let error = merged("unknown", "timeout = \"2s\"\ncolour = \"red\"\n", &[]).err().unwrap();
+        assert!(error.ends_with(".toml: line 2: Unknown argument: --colour"), "{}", error);
+        let error = merged("value", "count = \"some\"\n", &["--count", "3"]).err().unwrap();
+        assert!(error.ends_with(".toml: line 1: Invalid device count: some"), "{}", error);
+        let error = merged("syntax", "count = some\n", &[]).err().unwrap();
+        assert!(error.starts_with("Invalid config file "), "{}", error);
+    }
+}
@@ -0,0 +1,406 @@
+use sonos_discovery::{DeviceInfo, DeviceKind, DiscoveryReport, MemberRole, ZoneGroup, ZoneGroupMember};
+use std::cmp;
+use std::collections::BTreeSet;
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Version of the JSON outputs, see "JSON output" in the README.
+/// Only incremented for breaking changes, new fields are added without a version change.
+pub const JSON_SCHEMA_VERSION: u32 = 1;
+
+/// `value` as quoted and escaped json string
+pub fn json_string(value: &str) -> String {
+    let mut json = String::with_capacity(value.len() + 2);
+    json.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c)
+        }
+    }
+    json.push('"');
+
+    json
+}
+
+/// `value` as json string, `null` if it's `None`
+fn json_option(value: Option<&str>) -> String {
+    value.map_or_else(|| "null".to_string(), json_string)
+}
+
+/// Devices of a single discovery (`--format json`) with the headers of their responses
+pub fn json_devices(devices: &[DeviceInfo]) -> String {
+    let devices: Vec<String> = devices.iter()
+        .map(|device| format!(
+            "{{\"ip\":{},\"uuid\":{},\"mac\":{},\"household\":{},\"location\":{},\"usn\":{},\"server\":{},\"max_age\":{},\"kind\":{},\"system\":{},\"hostname\":{}}}",
+            json_string(&device.ip.to_string()),
+            json_option(device.uuid.as_deref()),
+            json_option(device.mac.as_deref()),
+            json_option(device.household.as_deref()),
+            json_option(device.location.as_deref()),
+            json_option(device.usn.as_deref()),
+            json_option(device.server.as_deref()),
+            device.max_age().map_or_else(|| "null".to_string(), |max_age| max_age.as_secs().to_string()),
+            json_string(&device.kind.to_string()),
+            json_option(device.system().map(|system| system.to_string()).as_deref()),
+            json_option(device.hostname.as_deref())))
+        .collect();
+
+    format!("{{\"schema_version\":{},\"devices\":[{}]}}\n", JSON_SCHEMA_VERSION, devices.join(","))
+}
+
+/// Columns of `--format table` (`--fields`), the values of the description are `-` if it couldn't be fetched
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Ip,
+    Room,
+    Model,
+    Version,
+    Uuid,
+    Mac,
+    Serial,
+    Household,
+    Product,
+    System,
+    Kind,
+    Server,
+    Location,
+    Hostname
+}
+
+impl Field {
+    /// Columns without `--fields`
+    pub const DEFAULT: &'static [Field] = &[Field::Ip, Field::Room, Field::Model, Field::Version];
+
+    /// Column of its name in `--fields` (e.g. `room`)
+    pub fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "ip" => Field::Ip,
+            "room" | "name" => Field::Room,
+            "model" => Field::Model,
+            "version" => Field::Version,
+            "uuid" => Field::Uuid,
+            "mac" => Field::Mac,
+            "serial" => Field::Serial,
+            "household" => Field::Household,
+            "product" => Field::Product,
+            "system" => Field::System,
+            "kind" => Field::Kind,
+            "server" => Field::Server,
+            "location" => Field::Location,
+            "hostname" => Field::Hostname,
+            _ => return None
+        })
+    }
+
+    fn header(self) -> &'static str {
+        match self {
+            Field::Ip => "IP",
+            Field::Room => "ROOM",
+            Field::Model => "MODEL",
+            Field::Version => "VERSION",
+            Field::Uuid => "UUID",
+            Field::Mac => "MAC",
+            Field::Serial => "SERIAL",
+            Field::Household => "HOUSEHOLD",
+            Field::Product => "PRODUCT",
+            Field::System => "SYSTEM",
+            Field::Kind => "KIND",
+            Field::Server => "SERVER",
+            Field::Location => "LOCATION",
+            Field::Hostname => "HOSTNAME"
+        }
+    }
+
+    /// Value of the column for `device`, `-` if it's unknown
+    fn value(self, device: &DeviceInfo) -> String {
+        let description = device.description.as_ref();
+        let value = match self {
+            Field::Ip => Some(device.ip.to_string()),
+            Field::Room => description.and_then(|description| description.room_name.clone()),
+            Field::Model => description.and_then(|description| description.model_name.clone()),
+            Field::Version => description.and_then(|description| description.software_version.clone()),
+            Field::Uuid => device.uuid.clone(),
+            Field::Mac => device.mac.clone(),
+            Field::Serial => device.serial_number.clone(),
+            Field::Household => device.household.clone(),
+            Field::Product => device.product().map(|product| product.name.to_string()),
+            Field::System => device.system().map(|system| system.to_string()),
+            Field::Kind => Some(device.kind.to_string()),
+            Field::Server => device.server.clone(),
+            Field::Location => device.location.clone(),
+            Field::Hostname => device.hostname.clone()
+        };
+
+        value.unwrap_or_else(|| "-".to_string())
+    }
+}
+
+/// Order of the rows of `--format table` (`--sort`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    Ip,
+    /// Room name, ignoring case
+    Name,
+    /// Model name, ignoring case
+    Model
+}
+
+impl SortKey {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "ip" => Some(SortKey::Ip),
+            "name" => Some(SortKey::Name),
+            "model" => Some(SortKey::Model),
+            _ => None
+        }
+    }
+}
+
+/// Sorts `devices` by `key`, devices without the value come last and ties are ordered by the address
+pub fn sort_devices(devices: &mut [DeviceInfo], key: SortKey) {
+    devices.sort_by_cached_key(|device| {
+        let description = device.description.as_ref();
+        let value = match key {
+            SortKey::Ip => None,
+            SortKey::Name => description.and_then(|description| description.room_name.as_ref()),
+            SortKey::Model => description.and_then(|description| description.model_name.as_ref())
+        };
+
+        (value.is_none(), value.map(|value| value.to_lowercase()), device.ip)
+    });
+}
+
+/// Devices with the `fields` as columns (`--format table`, by default their room, model and firmware)
+pub fn device_table(devices: &[DeviceInfo], fields: &[Field]) -> String {
+    let mut rows = vec![fields.iter().map(|field| field.header().to_string()).collect::<Vec<String>>()];
+    for device in devices {
+        rows.push(fields.iter().map(|field| field.value(device)).collect());
+    }
+
+    let mut widths = vec![0; fields.len()];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = cmp::max(*width, cell.chars().count());
+        }
+    }
+    let mut table = String::new();
+    for row in &rows {
+        let line = row.iter().zip(&widths)
+            .map(|(cell, &width)| format!("{:width$}", cell, width = width))
+            .collect::<Vec<String>>()
+            .join("  ");
+        table.push_str(line.trim_end());
+        table.push('\n');
+    }
+
+    table
+}
+
+/// Every upnp device with its kind and `SERVER` header (`--all-devices`), the sonos speakers are marked with `*`
+pub fn device_kinds(devices: &[DeviceInfo]) -> String {
+    let width = devices.iter().map(|device| device.ip.to_string().len()).max().unwrap_or(0);
+    devices.iter()
+        .map(|device| {
+            let marker = if device.kind == DeviceKind::SonosZonePlayer { '*' } else { ' ' };
+            let line = format!("{} {:width$}  {:14}  {}", marker, device.ip.to_string(), device.kind.to_string(),
+                               device.server.as_deref().unwrap_or("-"), width = width);
+            format!("{}\n", line.trim_end())
+        })
+        .collect()
+}
+
+/// Zone groups as a tree (`--format topology`): the coordinator of every group at the root, the other rooms
+/// indented below it and the bonded speakers (subs, surrounds, stereo pairs) below the speaker they belong to
+pub fn topology_tree(groups: &[ZoneGroup]) -> String {
+    let mut tree = String::new();
+    for group in groups {
+        let coordinator = group.coordinator();
+        tree.push_str(&format!("{}\n", member_label(coordinator)));
+        push_bonded(&mut tree, group, coordinator, 1);
+        for member in group.members.iter().skip(1).filter(|member| member.role == MemberRole::Zone) {
+            tree.push_str(&format!("  {}\n", member_label(member)));
+            push_bonded(&mut tree, group, member, 2);
+        }
+        // Bonded to a speaker which isn't in the group
+        let orphaned = group.members.iter().skip(1).filter(|member| {
+            member.role != MemberRole::Zone && !group.members.iter().any(|main| {
+                main.role == MemberRole::Zone && member.bonded_to.as_ref() == Some(&main.uuid)
+            })
+        });
+        for member in orphaned {
+            tree.push_str(&format!("  {}\n", member_label(member)));
+        }
+    }
+
+    tree
+}
+
+/// Adds the speakers bonded to `main` to the `tree`, indented by `depth`
+fn push_bonded(tree: &mut String, group: &ZoneGroup, main: &ZoneGroupMember, depth: usize) {
+    let bonded = group.members.iter()
+        .filter(|member| member.role != MemberRole::Zone && member.bonded_to.as_ref() == Some(&main.uuid));
+    for member in bonded {
+        tree.push_str(&format!("{}{}\n", "  ".repeat(depth), member_label(member)));
+    }
+}
+
+/// Room (or uuid) and address of a member, bonded speakers are marked with their role
+fn member_label(member: &ZoneGroupMember) -> String {
+    let name = member.zone_name.as_deref().unwrap_or(&member.uuid);
+    let ip = member.ip.map_or_else(|| "unknown address".to_string(), |ip| ip.to_string());
+    match role_marker(member.role) {
+        Some(role) => format!("{} ({}) [{}]", name, ip, role),
+        None => format!("{} ({})", name, ip)
+    }
+}
+
+/// Marker of a bonded speaker, `None` for a room
+fn role_marker(role: MemberRole) -> Option<&'static str> {
+    match role {
+        MemberRole::Zone => None,
+        MemberRole::Subwoofer => Some("sub"),
+        MemberRole::Surround => Some("surround"),
+        MemberRole::StereoPair => Some("stereo pair"),
+        _ => Some("satellite")
+    }
+}
+
+/// Zone groups as a Graphviz digraph (`--format dot`, e.g. `| dot -Tsvg`): a cluster per group with edges from the
+/// coordinator to the other rooms and dashed edges to the bonded speakers
+pub fn topology_dot(groups: &[ZoneGroup]) -> String {
+    let mut dot = String::from("digraph sonos {\n");
+    for (index, group) in groups.iter().enumerate() {
+        let coordinator = group.coordinator();
+        dot.push_str(&format!("  subgraph cluster_{} {{\n", index));
+        dot.push_str(&format!("    label={};\n", dot_string(&group.id)));
+        for member in &group.members {
+            let shape = if member.uuid == coordinator.uuid { "box" } else { "ellipse" };
+            dot.push_str(&format!("    {} [label={}, shape={}];\n", dot_string(&member.uuid),
+                                  dot_string(&member_label(member)), shape));
+        }
+        for member in group.members.iter().skip(1) {
+            match member.bonded_to {
+                Some(ref main) if member.role != MemberRole::Zone => {
+                    dot.push_str(&format!("    {} -> {} [style=dashed];\n", dot_string(main),
+                                          dot_string(&member.uuid)));
+                }
+                _ => dot.push_str(&format!("    {} -> {};\n", dot_string(&coordinator.uuid),
+                                           dot_string(&member.uuid)))
+            }
+        }
+        dot.push_str("  }\n");
+    }
+    dot.push_str("}\n");
+
+    dot
+}
+
+/// Quoted id of the dot language
+fn dot_string(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Timing of `--report`: the search, every device with its latency, the counts and the total time.
+/// Latencies within the last fifth of the timeout are marked, those devices are likely missed by a shorter one.
+pub fn discovery_report(report: &DiscoveryReport) -> String {
+    let mut text = match report.sent_at.and_then(|sent_at| sent_at.duration_since(UNIX_EPOCH).ok()) {
+        Some(sent_at) => format!("Search sent at {}\n", format_unix_time(sent_at.as_secs())),
+        None => String::new()
+    };
+    let width = report.latencies.iter().map(|(ip, _)| ip.to_string().len()).max().unwrap_or(0);
+    for (ip, latency) in &report.latencies {
+        let late = if *latency * 5 >= report.timeout * 4 { "  (close to the timeout)" } else { "" };
+        text.push_str(&format!("  {:width$}  {:>8.3}s{}\n", ip.to_string(), latency.as_secs_f64(), late,
+                               width = width));
+    }
+    text.push_str(&format!("{} responses, {} duplicates, {} rejected\n", report.responses, report.duplicates,
+                           report.rejected));
+    text.push_str(&format!("Search {:.3}s of {:.3}s, total {:.3}s\n", report.elapsed.as_secs_f64(),
+                           report.timeout.as_secs_f64(), report.total.as_secs_f64()));
+
+    text
+}
+
+/// Metrics in the prometheus text exposition format.
+/// Written in one shot for the textfile collector, e.g. `sonos_discovery --format prom > sonos.prom.$$ && mv sonos.prom.$$ sonos.prom`
+pub fn prometheus_metrics(ips: &BTreeSet<IpAddr>, duration: Duration) -> String {
+    let finished = SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+
+    metrics(ips, duration, finished)
+}
+
+/// Metrics of a discovery which took `duration` and finished at the unix time `finished`
+fn metrics(ips: &BTreeSet<IpAddr>, duration: Duration, finished: u64) -> String {
+    let duration = duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1e9;
+
+    let mut metrics = String::new();
+    metrics.push_str("# HELP sonos_discovery_devices Number of discovered Sonos devices.\n");
+    metrics.push_str("# TYPE sonos_discovery_devices gauge\n");
+    metrics.push_str(&format!("sonos_discovery_devices {}\n", ips.len()));
+    metrics.push_str("# HELP sonos_discovery_device_up Sonos device which answered the discovery.\n");
+    metrics.push_str("# TYPE sonos_discovery_device_up gauge\n");
+    for ip in ips {
+        metrics.push_str(&format!("sonos_discovery_device_up{{ip=\"{}\"}} 1\n", ip));
+    }
+    metrics.push_str("# HELP sonos_discovery_duration_seconds Duration of the discovery.\n");
+    metrics.push_str("# TYPE sonos_discovery_duration_seconds gauge\n");
+    metrics.push_str(&format!("sonos_discovery_duration_seconds {}\n", duration));
+    metrics.push_str("# HELP sonos_discovery_last_run_timestamp_seconds Unix time the discovery finished.\n");
+    metrics.push_str("# TYPE sonos_discovery_last_run_timestamp_seconds gauge\n");
+    metrics.push_str(&format!("sonos_discovery_last_run_timestamp_seconds {}\n", finished));
+
+    metrics
+}
+
+/// Current UTC time formatted as RFC 3339 (`2018-03-01T12:00:00Z`)
+pub fn timestamp() -> String {
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+
+    format_unix_time(seconds)
+}
+
+/// Unix time `seconds` formatted as RFC 3339 (`2018-03-01T12:00:00Z`)
+pub fn format_unix_time(seconds: u64) -> String {
+    let days = (seconds / 86_400) as i64;
+    let time = seconds % 86_400;
+
+    // Converts days since the epoch to a civil date (http://howardhinnant.github.io/date_algorithms.html)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, time / 3600, time % 3600 / 60, time % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prometheus_metrics_of_discoveries() {
+        let ips = ["fe80::1", "192.168.1.21", "10.0.0.5"].iter().map(|ip| ip.parse().unwrap()).collect();
+        assert_eq!(metrics(&ips, Duration::from_millis(1250), 1_700_000_000),
+                   include_str!("../../../tests/fixtures/metrics.prom"));
+
+        let empty = metrics(&BTreeSet::new(), Duration::from_secs(3), 0);
+        assert!(empty.contains("\nsonos_discovery_devices 0\n# HELP sonos_discovery_device_up"));
+        assert!(empty.contains("\nsonos_discovery_duration_seconds 3\n"));
+        assert!(!empty.contains("{ip="));
+    }
+}
@@ -0,0 +1,122 @@
+use output::json_devices;
+use sonos_discovery::Monitor;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Connections answered at the same time, further ones are closed right away
+const MAX_CONNECTIONS: usize = 16;
+
+/// Time a client has for sending its request and receiving the response
+pub const CONNECTION_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Serves the current devices of `monitor` on `listener` until the process is stopped.
+///
+/// `GET /devices` returns them like `--format json`, with `503 Service Unavailable` until the first scan finished.
+pub fn serve_devices(listener: &TcpListener, monitor: Arc<Monitor>) {
+    handle_connections(listener, move |stream| respond_devices(stream, &monitor));
+}
+
+/// Answers every connection of `listener` with `respond` on a thread of its own, a client which is slow to send its
+/// request only holds up itself. At most `MAX_CONNECTIONS` are answered at a time.
+pub fn handle_connections<F>(listener: &TcpListener, respond: F)
+    where F: Fn(TcpStream) -> io::Result<()> + Send + Sync + 'static
+{
+    let respond = Arc::new(respond);
+    let active = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming().filter_map(|stream| stream.ok()) {
+        // Dropping the stream closes the connection
+        if active.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+            active.fetch_sub(1, Ordering::SeqCst);
+            continue;
+        }
+        let slot = Slot(Arc::clone(&active));
+
+        let respond = Arc::clone(&respond);
+        let spawned = thread::Builder::new().name("connection".to_string()).spawn(move || {
+            let _slot = slot;
+            // A broken connection only affects that client
+            let _ = respond(stream);
+        });
+        if let Err(e) = spawned {
+            eprintln!("Couldn't answer a connection: {}", e);
+        }
+    }
+}
+
+/// Connection counted in `handle_connections`, released when it's dropped (the connection was answered)
+struct Slot(Arc<AtomicUsize>);
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn respond_devices(mut stream: TcpStream, monitor: &Monitor) -> io::Result<()> {
+    stream.set_read_timeout(Some(CONNECTION_TIMEOUT))?;
+    stream.set_write_timeout(Some(CONNECTION_TIMEOUT))?;
+    let mut request = [0u8; 1024];
+    let read = stream.read(&mut request)?;
+    let request = String::from_utf8_lossy(&request[..read]);
+    let mut parts = request.split_whitespace();
+    let (method, path) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+
+    let (status, body) = match (method, path) {
+        ("GET", "/devices") => {
+            let snapshot = monitor.snapshot();
+            if snapshot.last_scan.is_some() {
+                let mut devices = snapshot.devices;
+                devices.sort();
+                ("200 OK", json_devices(&devices))
+            } else {
+                ("503 Service Unavailable", "{}".to_string())
+            }
+        }
+        (_, "/devices") => ("405 Method Not Allowed", "{}".to_string()),
+        _ => ("404 Not Found", "{}".to_string())
+    };
+
+    write!(stream, "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+           status, body.len(), body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn connections_beyond_the_limit_are_closed() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            handle_connections(&listener, |mut stream| {
+                stream.set_read_timeout(Some(CONNECTION_TIMEOUT))?;
+                let mut request = [0u8; 1024];
+                let read = stream.read(&mut request)?;
+                stream.write_all(&request[..read])
+            })
+        });
+
+        let stalled: Vec<TcpStream> = (0..MAX_CONNECTIONS).map(|_| TcpStream::connect(address).unwrap()).collect();
+        let mut closed = TcpStream::connect(address).unwrap();
+        closed.set_read_timeout(Some(CONNECTION_TIMEOUT * 2)).unwrap();
+        let start = Instant::now();
+        // Closed without an answer, a reset is just as good
+        let mut response = Vec::new();
+        assert!(closed.read_to_end(&mut response).map_or(true, |read| read == 0));
+        assert!(start.elapsed() < CONNECTION_TIMEOUT, "{:?}", start.elapsed());
+
+        // The stalled connections are still answered
+        for mut stream in stalled {
+            stream.write_all(b"ping").unwrap();
+            let mut echo = [0u8; 4];
+            stream.read_exact(&mut echo).unwrap();
+            assert_eq!(&echo, b"ping");
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use log::{self, Level, LevelFilter, Log, Metadata, Record};
+use sonos_discovery::{AuditRecord, AuditSink, Direction, Discover};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Diagnostics of `--verbose` on stderr, so the output on stdout stays parseable:
+/// every packet sent and received (`Discover::set_audit_sink`) and the debug log of the library, which names the
+/// reason of every rejected response. Each line starts with the time since the start of the binary.
+pub fn enable(discovery: &mut Discover) {
+    let started = Instant::now();
+    discovery.set_audit_sink(Arc::new(PacketPrinter { started }));
+
+    // Only fails if the logger is already set, which the binary doesn't do anywhere else
+    if log::set_logger(Box::leak(Box::new(StderrLogger { started }))).is_ok() {
+        log::set_max_level(LevelFilter::Trace);
+    }
+}
+
+/// Prints every packet with its peer and payload
+#[derive(Debug)]
+struct PacketPrinter {
+    started: Instant
+}
+
+impl AuditSink for PacketPrinter {
+    fn record(&self, record: &AuditRecord) {
+        let direction = match record.direction {
+            Direction::Sent => "sent to",
+            Direction::Received => "received from"
+        };
+        let payload: String = String::from_utf8_lossy(record.data).lines()
+            .map(|line| format!("\n    {}", line))
+            .collect();
+
+        eprintln!("[+{:.3}s] {} {} ({} bytes){}", self.started.elapsed().as_secs_f64(), direction, record.peer,
+                  record.data.len(), payload);
+    }
+}
+
+/// Prints the log messages of the library
+struct StderrLogger {
+    started: Instant
+}
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.target().starts_with("sonos_discovery")
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let level = if record.level() <= Level::Warn { format!("{} ", record.level()) } else { String::new() };
+        eprintln!("[+{:.3}s] {}{}", self.started.elapsed().as_secs_f64(), level, record.args());
+    }
+
+    fn flush(&self) {}
+}
@@ -0,0 +1,189 @@
+use hmac::{Hmac, Mac};
+use output::{json_string, JSON_SCHEMA_VERSION};
+use sha2::Sha256;
+use sonos_discovery::{ExponentialBackoff, RetryPolicy};
+use std::net::IpAddr;
+use std::thread;
+use std::time::Duration;
+use ureq;
+
+/// Header with the hex encoded HMAC-SHA256 of the body (`sha256=<hex>`), only sent with a secret
+const SIGNATURE_HEADER: &str = "X-Sonos-Discovery-Signature";
+
+/// Device change reported to the webhooks
+pub enum Event<'a> {
+    Found(&'a IpAddr),
+    Lost(&'a IpAddr),
+    Changed {
+        uuid: &'a str,
+        old: &'a IpAddr,
+        new: &'a IpAddr
+    }
+}
+
+/// Json payload of an event, see "JSON output" in the README
+pub fn payload(event: &Event, time: &str) -> String {
+    let (name, fields) = match *event {
+        Event::Found(ip) => ("found", format!("\"ip\":{}", json_string(&ip.to_string()))),
+        Event::Lost(ip) => ("lost", format!("\"ip\":{}", json_string(&ip.to_string()))),
+        Event::Changed { uuid, old, new } => ("changed", format!("\"uuid\":{},\"old_ip\":{},\"ip\":{}",
+                                                               json_string(uuid),
+                                                               json_string(&old.to_string()),
+                                                               json_string(&new.to_string())))
+    };
+
+    format!("{{\"schema_version\":{},\"event\":\"{}\",\"time\":{},{}}}",
+            JSON_SCHEMA_VERSION, name, json_string(time), fields)
+}
+
+/// Posts every payload to every url on a background thread, failed requests are retried with a backoff
+pub fn send(urls: &[String], secret: Option<&str>, payloads: Vec<String>) {
+    if urls.is_empty() || payloads.is_empty() {
+        return;
+    }
+
+    let urls = urls.to_vec();
+    let secret = secret.map(str::to_string);
+    thread::spawn(move || {
+        for payload in &payloads {
+            let signature = secret.as_ref().map(|secret| sign(secret, payload));
+            for url in &urls {
+                if let Err(e) = post(url, payload, signature.as_deref()) {
+                    eprintln!("Webhook {} failed: {}", url, e);
+                }
+            }
+        }
+    });
+}
+
+/// Posts `payload` to `url`, retries unreachable webhooks and server errors. A rejected request (4xx) fails right
+/// away, another attempt wouldn't change the url or the signature.
+fn post(url: &str, payload: &str, signature: Option<&str>) -> Result<(), String> {
+    let policy = ExponentialBackoff {
+        retries: 3,
+        initial: Duration::from_secs(1),
+        max: Duration::from_secs(10),
+        jitter: true,
+        seed: None
+    };
+
+    let mut attempt = 0;
+    loop {
+        let mut request = ureq::post(url)
+            .timeout(Duration::from_secs(10))
+            .set("Content-Type", "application/json");
+        if let Some(signature) = signature {
+            request = request.set(SIGNATURE_HEADER, signature);
+        }
+
+        match request.send_string(payload) {
+            Ok(_) => return Ok(()),
+            Err(e) => {
+                attempt += 1;
+                match policy.retry_delay(attempt).filter(|_| is_temporary(&e)) {
+                    Some(delay) => thread::sleep(delay),
+                    None => return Err(e.to_string())
+                }
+            }
+        }
+    }
+}
+
+/// Whether another attempt could succeed, for unreachable webhooks and server errors
+fn is_temporary(error: &ureq::Error) -> bool {
+    match *error {
+        ureq::Error::Status(status, _) => status >= 500,
+        ureq::Error::Transport(_) => true
+    }
+}
+
+/// `sha256=<hex encoded HMAC-SHA256 of the payload>`
+fn sign(secret: &str, payload: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(payload.as_bytes());
+
+    let hex: String = mac.finalize().into_bytes().iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("sha256={}", hex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    /// Webhook on a local port answering every request with `status`, returns its url and the received requests
+    fn webhook(status: u16) -> (String, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut head = Vec::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim_end().is_empty() {
+                    break;
+                }
+                head.push(line.trim_end().to_string());
+            }
+            write!(reader.get_mut(), "HTTP/1.1 {} Status\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status)
+                .unwrap();
+
+            head
+        });
+
+        (url, server)
+    }
+
+    #[test]
+    fn signatures_are_the_hmac_of_the_payload() {
+        // Example of GitHub's webhook documentation, which uses the same scheme
+        assert_eq!(sign("It's a Secret to Everybody", "Hello, World!"),
+                   "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17");
+        assert_eq!(sign("", ""), "sha256=b613679a0814d9ec772f95d778c35fc5ff1697c493715653c6c712144292c5ad");
+    }
+
+    #[test]
+    fn requests_carry_the_signature() {
+        let (url, server) = webhook(204);
+        let payload = "{\"event\":\"found\"}";
+        let signature = sign("secret", payload);
+
+        post(&url, payload, Some(&signature)).unwrap();
+        let head = server.join().unwrap();
+        assert_eq!(head[0], "POST /hook HTTP/1.1");
+        assert!(head.contains(&format!("{}: {}", SIGNATURE_HEADER, signature)), "{:?}", head);
+    }
+
+    #[test]
+    fn rejected_requests_are_not_retried() {
+        // The webhook only accepts a single connection, a retry would fail to connect and report that instead
+        let (url, server) = webhook(401);
+
+        let error = post(&url, "{}", None).unwrap_err();
+        assert!(error.contains("401"), "{}", error);
+        assert_eq!(server.join().unwrap()[0], "POST /hook HTTP/1.1");
+    }
+
+    #[test]
+    fn server_errors_and_unreachable_webhooks_are_temporary() {
+        assert!(is_temporary(&ureq::Error::Status(503, ureq::Response::new(503, "Unavailable", "").unwrap())));
+        assert!(!is_temporary(&ureq::Error::Status(404, ureq::Response::new(404, "Not Found", "").unwrap())));
+        assert!(is_temporary(&ureq::get("http://127.0.0.1:0/").call().unwrap_err()));
+    }
+
+    #[test]
+    fn payloads() {
+        let ip: IpAddr = "192.168.1.10".parse().unwrap();
+        let new: IpAddr = "192.168.1.11".parse().unwrap();
+        assert_eq!(payload(&Event::Found(&ip), "2024-01-01T00:00:00Z"),
+                   format!("{{\"schema_version\":{},\"event\":\"found\",\"time\":\"2024-01-01T00:00:00Z\",\
+                            \"ip\":\"192.168.1.10\"}}", JSON_SCHEMA_VERSION));
+        assert_eq!(payload(&Event::Changed { uuid: "RINCON_A", old: &ip, new: &new }, "t"),
+                   format!("{{\"schema_version\":{},\"event\":\"changed\",\"time\":\"t\",\"uuid\":\"RINCON_A\",\
+                            \"old_ip\":\"192.168.1.10\",\"ip\":\"192.168.1.11\"}}", JSON_SCHEMA_VERSION));
+    }
+}
@@ -0,0 +1,214 @@
+use config::DiscoveryConfig;
+use filter::{ResponseFilter, SsdpHeaders};
+use std::cmp;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Duration;
+use Discover;
+
+/// Builds a configured `Discover`
+///
+/// Only the common settings, everything else can be changed with the setters of `Discover` afterwards.
+///
+/// # Examples
+///
+/// ```
+/// use sonos_discovery::DiscoverBuilder;
+/// use std::time::Duration;
+///
+/// let discovery = DiscoverBuilder::new()
+///     .mx(2)
+///     .device_count(3)
+///     .build();
+/// assert_eq!(discovery.config().timeout, Duration::from_secs(5));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct DiscoverBuilder {
+    config: DiscoveryConfig,
+    /// Explicitly set timeout, otherwise it's raised to at least `mx + MX_GRACE`
+    timeout: Option<Duration>
+}
+
+impl DiscoverBuilder {
+    /// Builder with the default configuration (see `DiscoveryConfig`)
+    pub fn new() -> Self {
+        DiscoverBuilder::default()
+    }
+
+    /// Multicast address the search is sent to (default: 239.255.255.250:1900)
+    pub fn multicast_addr(mut self, address: SocketAddr) -> Self {
+        self.config.multicast_addr = address;
+        self
+    }
+
+    /// Further multicast groups the search is sent to, see `Discover::set_additional_multicast_addrs`
+    pub fn additional_multicast_addrs(mut self, addresses: &[SocketAddr]) -> Self {
+        self.config.additional_multicast_addrs = addresses.to_vec();
+        self
+    }
+
+    /// Timeout of the search (default: 5 seconds, or `mx + MX_GRACE` if that's longer)
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Time late answers get on top of MX (default: `MX_GRACE` seconds), see `Discover::set_mx_slack`
+    pub fn mx_slack(mut self, slack: Duration) -> Self {
+        self.config.mx_slack = slack;
+        self
+    }
+
+    /// Maximum time in seconds devices wait before answering (default: 1, 1 to `MAX_MX`)
+    pub fn mx(mut self, mx: u32) -> Self {
+        self.config.mx = mx;
+        self
+    }
+
+    /// The discovery stops after this many devices (default: waits for the whole timeout)
+    pub fn device_count(mut self, device_count: usize) -> Self {
+        self.config.device_count = Some(device_count);
+        self
+    }
+
+    /// TTL (hop limit for ipv6) of the search (default: 4)
+    pub fn multicast_ttl(mut self, ttl: u32) -> Self {
+        self.config.multicast_ttl = ttl;
+        self
+    }
+
+    /// Outgoing interface of the multicast search (`IP_MULTICAST_IF`), see `Discover::set_multicast_if`
+    pub fn multicast_if(mut self, interface: Ipv4Addr) -> Self {
+        self.config.multicast_if = Some(interface);
+        self
+    }
+
+    /// Deliver the multicast search to the sockets of the host as well (`IP_MULTICAST_LOOP`, default: true)
+    pub fn multicast_loop(mut self, enabled: bool) -> Self {
+        self.config.multicast_loop = enabled;
+        self
+    }
+
+    /// Maximum size of a received packet in bytes (default: 1024)
+    pub fn receive_buffer_size(mut self, size: usize) -> Self {
+        self.config.receive_buffer_size = size;
+        self
+    }
+
+    /// Local address the socket is bound to (default: chosen by the os)
+    pub fn bind_addr(mut self, address: SocketAddr) -> Self {
+        self.config.bind_addr = Some(address);
+        self
+    }
+
+    /// Bind the socket to the interface named `name` with `SO_BINDTODEVICE`, see `Discover::set_bind_device`
+    #[cfg(target_os = "linux")]
+    pub fn bind_device(mut self, name: &str) -> Self {
+        self.config.bind_device = Some(name.to_string());
+        self
+    }
+
+    /// Fixed local port of the search (default: chosen by the os), see `Discover::set_source_port`
+    pub fn source_port(mut self, port: u16) -> Self {
+        self.config.source_port = Some(port);
+        self
+    }
+
+    /// Share the ssdp port with other listeners of the host, see `Discover::set_reuse_port`
+    pub fn reuse_port(mut self, enabled: bool) -> Self {
+        self.config.reuse_port = enabled;
+        self
+    }
+
+    /// Send the search out of every ipv4 interface (default: only the one of the default route)
+    pub fn all_interfaces(mut self, all_interfaces: bool) -> Self {
+        self.config.all_interfaces = all_interfaces;
+        self
+    }
+
+    /// Only send the search out of the interface named `name`, see `Discover::set_interface_name`
+    pub fn interface_name(mut self, name: &str) -> Self {
+        self.config.interface_name = Some(name.to_string());
+        self
+    }
+
+    /// Search target (`ST` header, default: `ZONE_PLAYER`), see `Discover::set_search_target`
+    pub fn search_target(mut self, search_target: &str) -> Self {
+        self.config.search_target = search_target.to_string();
+        self
+    }
+
+    /// Keep the payload of every response, see `Discover::set_keep_raw`
+    pub fn keep_raw(mut self, enabled: bool) -> Self {
+        self.config.keep_raw = enabled;
+        self
+    }
+
+    /// Decides with `accept` which responses are devices, see `Discover::set_response_filter`
+    pub fn response_filter<F>(mut self, accept: F) -> Self
+        where F: Fn(&SsdpHeaders) -> bool + Send + Sync + 'static
+    {
+        self.config.response_filter = Some(ResponseFilter::new(accept));
+        self
+    }
+
+    /// Stop once no device answered for this long, see `Discover::set_quiet_period`
+    pub fn quiet_period(mut self, quiet_period: Duration) -> Self {
+        self.config.quiet_period = Some(quiet_period);
+        self
+    }
+
+    /// Drop the responders whose description can't be fetched or isn't from a sonos device, see
+    /// `Discover::set_verify` (default: false)
+    pub fn verify(mut self, enabled: bool) -> Self {
+        self.config.verify = enabled;
+        self
+    }
+
+    /// Reject responses from outside the local subnets or with a foreign `LOCATION`, see
+    /// `Discover::set_validate_source` (default: true)
+    pub fn validate_source(mut self, enabled: bool) -> Self {
+        self.config.validate_source = enabled;
+        self
+    }
+
+    /// Only return the devices in this room, see `Discover::set_room_filter`
+    pub fn filter_room(mut self, room: &str) -> Self {
+        self.config.room_filter = Some(room.to_string());
+        self
+    }
+
+    /// Only return the devices of this model, see `Discover::set_model_filter`
+    pub fn filter_model(mut self, model: &str) -> Self {
+        self.config.model_filter = Some(model.to_string());
+        self
+    }
+
+    /// Recreate the socket when it keeps failing, see `Discover::set_recreate_socket` (default: false)
+    pub fn recreate_socket(mut self, enabled: bool) -> Self {
+        self.config.recreate_socket = enabled;
+        self
+    }
+
+    /// Look up the hostname of every device, see `Discover::set_resolve_hostnames` (default: false)
+    pub fn resolve_hostnames(mut self, enabled: bool) -> Self {
+        self.config.resolve_hostnames = enabled;
+        self
+    }
+
+    /// Time each hostname lookup gets, see `Discover::set_hostname_timeout` (default: 1s)
+    pub fn hostname_timeout(mut self, timeout: Duration) -> Self {
+        self.config.hostname_timeout = timeout;
+        self
+    }
+
+    /// Creates the `Discover` with the configured settings
+    pub fn build(self) -> Discover {
+        let mut config = self.config;
+        config.timeout = match self.timeout {
+            Some(timeout) => timeout,
+            None => cmp::max(config.timeout, config.response_window())
+        };
+
+        Discover::with_config(config)
+    }
+}
@@ -0,0 +1,57 @@
+use result::DiscoveryResult;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use Discover;
+
+/// Result of the last `Discover::start`, shared by the clones of a `Discover` (`Discover::set_result_ttl`)
+#[derive(Debug)]
+pub(crate) struct ResultCache {
+    /// Results younger than this are returned without a search, after half of it they're refreshed in the background
+    ttl: Duration,
+    state: Mutex<State>
+}
+
+#[derive(Debug, Default)]
+struct State {
+    result: Option<(DiscoveryResult, Instant)>,
+    /// A background discovery is running, at most one at a time
+    refreshing: bool
+}
+
+impl ResultCache {
+    pub(crate) fn new(ttl: Duration) -> Self {
+        ResultCache {
+            ttl,
+            state: Mutex::new(State::default())
+        }
+    }
+
+    /// Cached result if it's younger than the ttl, starts a refresh with `discovery` once it's half way there
+    pub(crate) fn get(self: &Arc<Self>, discovery: &Discover) -> Option<DiscoveryResult> {
+        let mut state = self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let (result, age) = match state.result {
+            Some((ref result, stored)) if stored.elapsed() < self.ttl => (result.clone(), stored.elapsed()),
+            _ => return None
+        };
+
+        if age >= self.ttl / 2 && !state.refreshing {
+            state.refreshing = true;
+            let cache = Arc::clone(self);
+            let discovery = discovery.clone();
+            thread::spawn(move || {
+                match discovery.discover() {
+                    Ok(result) => cache.store(result),
+                    Err(e) => warn!("Refreshing the cached discovery failed: {}", e)
+                }
+                cache.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).refreshing = false;
+            });
+        }
+
+        Some(result)
+    }
+
+    pub(crate) fn store(&self, result: DiscoveryResult) {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).result = Some((result, Instant::now()));
+    }
+}
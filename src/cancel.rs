@@ -0,0 +1,44 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Stops running discoveries from another thread, installed with `Discover::set_cancellation_token`
+///
+/// Clones share the state, cancelling one cancels all. A cancelled token stays cancelled,
+/// every later discovery with it stops right after sending the search.
+///
+/// # Examples
+///
+/// ```no_run
+/// use sonos_discovery::{CancellationToken, Discover};
+/// use std::thread;
+///
+/// let token = CancellationToken::new();
+/// let mut discovery = Discover::new().unwrap();
+/// discovery.set_cancellation_token(token.clone());
+/// let search = thread::spawn(move || discovery.start(None, None));
+///
+/// token.cancel();
+/// let result = search.join().unwrap().unwrap();
+/// assert!(result.stats().cancelled);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>
+}
+
+impl CancellationToken {
+    /// Token which isn't cancelled yet
+    pub fn new() -> Self {
+        CancellationToken::default()
+    }
+
+    /// Stops every discovery using this token within one receive (at most 500ms)
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether `cancel` was called on this token or one of its clones
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
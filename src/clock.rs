@@ -0,0 +1,70 @@
+use std::fmt::Debug;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Source of time for the discovery's timeouts and delays.
+///
+/// `SystemClock` is used by default, `ManualClock` makes the timing deterministic (e.g. in tests).
+pub trait Clock: Debug + Send + Sync {
+    /// Current point in time
+    fn now(&self) -> Instant;
+    /// Blocks the current thread for `duration`
+    fn sleep(&self, duration: Duration);
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+/// `Instant::now` and `thread::sleep`
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration)
+    }
+}
+
+#[derive(Debug)]
+/// Clock which only moves forward when told so.
+///
+/// `sleep` returns immediately and advances the clock by the slept duration,
+/// another thread can move the clock forward with `advance`.
+pub struct ManualClock {
+    start: Instant,
+    elapsed: Mutex<Duration>
+}
+
+impl ManualClock {
+    /// Creates a clock standing at the current point in time
+    pub fn new() -> Self {
+        ManualClock {
+            start: Instant::now(),
+            elapsed: Mutex::new(Duration::from_secs(0))
+        }
+    }
+
+    /// Moves the clock forward by `duration`
+    pub fn advance(&self, duration: Duration) {
+        let mut elapsed = self.elapsed.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        *elapsed += duration;
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        ManualClock::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.start + *self.elapsed.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration)
+    }
+}
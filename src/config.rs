@@ -0,0 +1,321 @@
+use audit::AuditSink;
+use cancel::CancellationToken;
+use clock::{Clock, SystemClock};
+use fetcher::{HttpFetcher, TcpFetcher};
+use filter::ResponseFilter;
+use listener::DeviceListener;
+use metrics::MetricsSink;
+use retry::{NoRetry, RetryPolicy};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4};
+use sweep::SweepNetwork;
+use transport::{TransportFactory, UdpTransport};
+use upnp::{SSDP_MULTICAST_V4, SSDP_PORT, ZONE_PLAYER};
+use std::cmp;
+use std::env;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Default time in seconds devices get on top of MX (`DiscoveryConfig::mx_slack`), their answers still have to
+/// travel through the network
+pub const MX_GRACE: u32 = 1;
+
+/// Largest MX devices honor (UPnP 1.1), larger values are sent as 5
+pub const MAX_MX: u32 = 5;
+
+/// Environment variable with the default timeout, e.g. `2500ms`, `10s` or `1m` (a plain number is seconds)
+const ENV_TIMEOUT: &str = "SONOS_DISCOVERY_TIMEOUT";
+/// Environment variable with the default interface, its local ipv4 address or its name (e.g. `eth0`)
+const ENV_INTERFACE: &str = "SONOS_DISCOVERY_INTERFACE";
+/// Environment variable with the default search target
+const ENV_TARGET: &str = "SONOS_DISCOVERY_TARGET";
+
+#[derive(Debug, Clone)]
+/// Configuration of a discovery
+///
+/// A `Discover` holds its configuration and can be reused, all state of a single search (the socket, received responses)
+/// is created by `Discover::start` and dropped afterwards.
+/// The result of every search carries the effective configuration (defaults and arguments of `start` applied).
+///
+/// `default` (and so `Discover::new`) takes the timeout, the interface and the search target from the environment
+/// variables `SONOS_DISCOVERY_TIMEOUT` (e.g. `2500ms`), `SONOS_DISCOVERY_INTERFACE` (address or name) and
+/// `SONOS_DISCOVERY_TARGET` if they are set, e.g. to tune a containerized deployment. Invalid values are ignored
+/// with a warning, `with_address` doesn't read the environment.
+pub struct DiscoveryConfig {
+    /// Multicast address the search is sent to (default: 239.255.255.250:1900)
+    pub multicast_addr: SocketAddr,
+    /// Further multicast groups of the same family the search is sent to, e.g. an ssdp relay or a group on another
+    /// port. One socket receives all answers, a device answering several groups is only found once (default: none)
+    pub additional_multicast_addrs: Vec<SocketAddr>,
+    /// Timeout of the search (default: 5 seconds, at least `mx + MX_GRACE` with `with_mx`)
+    pub timeout: Duration,
+    /// Overall time for the search and all following requests (verification, descriptions), `None` only limits
+    /// the search with `timeout` (default). The search ends at the budget at the latest, the rest is left for the requests.
+    pub budget: Option<Duration>,
+    /// Maximum time in seconds devices wait before answering (`MX` header, default: 1).
+    /// UPnP 1.1 allows 1 to 5 (`MAX_MX`), other values are clamped. A timeout shorter than MX misses slow devices.
+    pub mx: u32,
+    /// Time late answers get on top of MX, devices answer at a random time within MX and the answers still have to
+    /// travel through the network (default: `MX_GRACE` seconds)
+    pub mx_slack: Duration,
+    /// The discovery stops early after this many devices, `None` waits for the whole timeout (default)
+    pub device_count: Option<usize>,
+    /// Local ipv4 address of the interface the search is sent from and answered to, `None` uses the default route
+    pub interface: Option<Ipv4Addr>,
+    /// Send the ipv4 search out of every interface which isn't a loopback, the answers of all of them are merged.
+    /// Ignored if `interface` is set (default: false)
+    pub all_interfaces: bool,
+    /// Only send the search out of the interfaces with this name (e.g. `eth0`), implies `all_interfaces`
+    pub interface_name: Option<String>,
+    /// Choose `interface` with `best_interface` if neither it nor another interface option or `bind_addr` is set,
+    /// e.g. when the default route leads into a VPN (default: false)
+    pub auto_interface: bool,
+    /// Local address the socket is bound to, `None` binds to `interface` or lets the os choose (default)
+    pub bind_addr: Option<SocketAddr>,
+    /// Name of the interface the socket is bound to with `SO_BINDTODEVICE` (e.g. `eth0` or a VRF), the search and
+    /// its answers only take this interface regardless of the routing (default: `None`)
+    #[cfg(target_os = "linux")]
+    pub bind_device: Option<String>,
+    /// Local port the search is sent from and answered to instead of an ephemeral one, e.g. for firewalls which only
+    /// allow replies to a pre-opened port. Replaces the port of `bind_addr` (default: `None`)
+    pub source_port: Option<u16>,
+    /// Set `SO_REUSEADDR` and `SO_REUSEPORT` (unix) on the sockets, so the ssdp port can be shared with other
+    /// listeners of the host, e.g. a media server or a second discovery (default: false)
+    pub reuse_port: bool,
+    /// TTL (hop limit for ipv6) of the search (default: 4, UPnP 1.0 needs at least 4)
+    pub multicast_ttl: u32,
+    /// Local ipv4 address of the interface the multicast search leaves from (`IP_MULTICAST_IF`) without binding the
+    /// socket to it like `interface`, takes precedence over `interface` and `all_interfaces` (default: `None`)
+    pub multicast_if: Option<Ipv4Addr>,
+    /// Deliver the multicast search to the sockets of the host as well (`IP_MULTICAST_LOOP`, default: true)
+    pub multicast_loop: bool,
+    /// Maximum size of a received packet in bytes, longer packets are truncated (default: 1024)
+    pub receive_buffer_size: usize,
+    /// Search target (`ST` header, default: `urn:schemas-upnp-org:device:ZonePlayer:1`).
+    /// Other targets accept every device answering with this `ST`, see `Discover::set_search_target`
+    pub search_target: String,
+    /// Address the search is sent to if sending to `multicast_addr` fails (e.g. `192.168.1.255:1900`, bridges and
+    /// containers without multicast routing), `None` returns the error (default)
+    pub broadcast_fallback: Option<SocketAddr>,
+    /// Reject responses from outside the subnets of the search's interface and responses whose `LOCATION` host isn't
+    /// their source address, guards against answers spoofed from elsewhere (default: true). Unicast searches
+    /// (`Discover::probe`) only check the `LOCATION`.
+    pub validate_source: bool,
+    /// Only accept devices of this household (`X-RINCON-HOUSEHOLD` header, e.g. `Sonos_abc123`), `None` accepts all
+    pub household: Option<String>,
+    /// Only return devices in this room (`DeviceDescription::room_name`, ignoring case), applied after the descriptions
+    /// were fetched (`Discover::start_with_description`), `None` returns all rooms
+    pub room_filter: Option<String>,
+    /// Only return devices whose model name contains this (`DeviceDescription::model_name`, ignoring case, e.g. `One`
+    /// matches `Sonos One` and `Sonos One SL`), applied like `room_filter`
+    pub model_filter: Option<String>,
+    /// Stop early once the devices went quiet, the idle time is derived from the latency of the first response.
+    /// Never stops before MX + `MX_GRACE` elapsed, the timeout stays the upper bound (default: false)
+    pub adaptive: bool,
+    /// Stop once no device answered for this long after the last response, without waiting for MX.
+    /// The timeout stays the upper bound and the discovery never stops before the first response (default: `None`)
+    pub quiet_period: Option<Duration>,
+    /// Fetch the `LOCATION` of every responder and drop those whose description isn't from a sonos device
+    /// (default: false)
+    pub verify: bool,
+    /// Accept every response mentioning "Sonos" like previous versions, instead of only those whose `ST` or `USN`
+    /// header contains `ZONE_PLAYER` (default: false)
+    pub lenient_match: bool,
+    /// Search `ssdp:all` instead of `search_target` and only keep the entries of sonos devices (root device,
+    /// ZonePlayer, MediaRenderer, MediaServer), one device each. For networks where the ZonePlayer search goes
+    /// unanswered (default: false)
+    pub ssdp_all: bool,
+    /// Decides which responses are devices instead of the search target (and `lenient_match`), see
+    /// `Discover::set_response_filter` (default: `None`)
+    pub response_filter: Option<ResponseFilter>,
+    /// Log a hexdump of every received packet
+    pub hexdump: bool,
+    /// Keep the payload of every response in the result, including the rejected ones
+    /// (`DiscoveryResult::raw_responses`, default: false)
+    pub keep_raw: bool,
+    /// Number of times the search is sent again after the first one, UDP can drop any single search (default: 0)
+    pub resends: u32,
+    /// Time between two sends of the search (default: 1 second, the resends should fall into the MX window)
+    pub resend_interval: Duration,
+    /// Retries of failed sends (default: `NoRetry`)
+    pub retry_policy: Arc<dyn RetryPolicy>,
+    /// Source of time for the timeout and retry delays (default: `SystemClock`)
+    pub clock: Arc<dyn Clock>,
+    /// Fetch the zone groups after the search, see `DiscoveryResult::grouped` (default: false)
+    pub topology: bool,
+    /// List the satellites of the members (subs, surrounds) in the zone groups as well, with their `MemberRole` and
+    /// the member they're bonded to (default: false)
+    pub include_invisible: bool,
+    /// Devices `Discover::start_fast` asks for the zone groups while the search is still running, e.g. the speakers
+    /// of the last run (default: none)
+    pub known_devices: Vec<IpAddr>,
+    /// Maximum number of concurrent http requests, each device only gets one at a time (default: 4).
+    /// Embedded web servers of the speakers drop requests when they're hammered in parallel.
+    pub max_requests: usize,
+    /// Sweep `sweep_network` for devices on port 1400 if nobody answered the search of `Discover::start`, for
+    /// networks which drop multicast (default: false)
+    pub sweep_fallback: bool,
+    /// Network of the sweep fallback, `None` sweeps the /24 of the search's interface (default)
+    pub sweep_network: Option<SweepNetwork>,
+    /// Maximum number of concurrent connections of the sweep fallback (default: 64)
+    pub sweep_concurrency: usize,
+    /// Look up the hostname of every device (PTR record) for `DeviceInfo::hostname` (default: false)
+    pub resolve_hostnames: bool,
+    /// Time each hostname lookup gets, a slow resolver leaves the hostname `None` (default: 1s)
+    pub hostname_timeout: Duration,
+    /// Recreate the socket and send the search again if receiving keeps failing during a discovery, e.g. after an
+    /// interface flap (default: false)
+    pub recreate_socket: bool,
+    /// Http client for device descriptions and the verification (default: `TcpFetcher`)
+    pub http_fetcher: Arc<dyn HttpFetcher>,
+    /// Creates the socket of every discovery (default: `UdpTransport`), see `Discover::set_transport`
+    pub transport: Arc<dyn TransportFactory>,
+    /// Receives every sent and received packet
+    pub audit_sink: Option<Arc<dyn AuditSink>>,
+    /// Counts the packets, responses and durations of the discoveries
+    pub metrics_sink: Option<Arc<dyn MetricsSink>>,
+    /// Notified about every found device
+    pub listener: Option<Arc<dyn DeviceListener>>,
+    /// Stops the discovery once cancelled, the result contains the devices found until then (default: `None`)
+    pub cancellation: Option<CancellationToken>
+}
+
+impl DiscoveryConfig {
+    /// Default configuration with a custom multicast address
+    pub fn with_address(multicast_addr: SocketAddr) -> Self {
+        DiscoveryConfig {
+            multicast_addr,
+            additional_multicast_addrs: Vec::new(),
+            timeout: Duration::from_secs(5),
+            budget: None,
+            mx: 1,
+            mx_slack: Duration::from_secs(u64::from(MX_GRACE)),
+            device_count: None,
+            interface: None,
+            all_interfaces: false,
+            interface_name: None,
+            auto_interface: false,
+            bind_addr: None,
+            #[cfg(target_os = "linux")]
+            bind_device: None,
+            source_port: None,
+            reuse_port: false,
+            multicast_ttl: 4,
+            multicast_if: None,
+            multicast_loop: true,
+            receive_buffer_size: 1024,
+            search_target: ZONE_PLAYER.to_string(),
+            validate_source: true,
+            household: None,
+            room_filter: None,
+            model_filter: None,
+            broadcast_fallback: None,
+            adaptive: false,
+            quiet_period: None,
+            verify: false,
+            lenient_match: false,
+            ssdp_all: false,
+            response_filter: None,
+            hexdump: false,
+            keep_raw: false,
+            resends: 0,
+            resend_interval: Duration::from_secs(1),
+            retry_policy: Arc::new(NoRetry),
+            clock: Arc::new(SystemClock),
+            topology: false,
+            include_invisible: false,
+            known_devices: Vec::new(),
+            max_requests: 4,
+            sweep_fallback: false,
+            sweep_network: None,
+            sweep_concurrency: 64,
+            resolve_hostnames: false,
+            hostname_timeout: Duration::from_secs(1),
+            recreate_socket: false,
+            http_fetcher: Arc::new(TcpFetcher),
+            transport: Arc::new(UdpTransport),
+            audit_sink: None,
+            metrics_sink: None,
+            listener: None,
+            cancellation: None
+        }
+    }
+
+    /// Search target which is sent in the search, `ssdp:all` with `ssdp_all`
+    pub fn effective_search_target(&self) -> &str {
+        if self.ssdp_all { "ssdp:all" } else { &self.search_target }
+    }
+
+    /// MX which is sent in the search, `mx` clamped to the 1 to 5 seconds of UPnP 1.1
+    pub fn effective_mx(&self) -> u32 {
+        self.mx.clamp(1, MAX_MX)
+    }
+
+    /// Time devices need to answer: the effective MX and `mx_slack` for the network
+    pub fn response_window(&self) -> Duration {
+        Duration::from_secs(u64::from(self.effective_mx())) + self.mx_slack
+    }
+
+    /// Default configuration with the MX `mx`, the timeout listens for at least `mx + MX_GRACE` seconds
+    pub fn with_mx(mx: u32) -> Self {
+        let mut config = DiscoveryConfig::default();
+        config.mx = mx;
+        config.timeout = cmp::max(config.timeout, config.response_window());
+
+        config
+    }
+
+    /// Overrides the timeout, the interface and the search target with their environment variables
+    fn apply_env(&mut self) {
+        if let Ok(value) = env::var(ENV_TIMEOUT) {
+            match parse_duration(&value) {
+                Some(timeout) => self.timeout = timeout,
+                None => warn!("Ignoring {}, invalid duration: {}", ENV_TIMEOUT, value)
+            }
+        }
+        if let Ok(value) = env::var(ENV_INTERFACE) {
+            // Anything which isn't an address is the name of the interface, like `--interface` of the binary
+            match value.parse() {
+                Ok(address) => self.interface = Some(address),
+                Err(_) if !value.trim().is_empty() => self.interface_name = Some(value),
+                Err(_) => warn!("Ignoring {}, it's empty", ENV_INTERFACE)
+            }
+        }
+        if let Ok(value) = env::var(ENV_TARGET) {
+            match value.trim() {
+                "" => warn!("Ignoring {}, it's empty", ENV_TARGET),
+                search_target => self.search_target = search_target.to_string()
+            }
+        }
+    }
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        let mut config = DiscoveryConfig::with_address(SocketAddr::V4(SocketAddrV4::new(SSDP_MULTICAST_V4, SSDP_PORT)));
+        config.apply_env();
+
+        config
+    }
+}
+
+/// Duration like `2500ms`, `10s`, `1m` or `1h`, `None` if it's invalid or zero
+fn parse_duration(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let split = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+    let amount: u64 = amount.parse().ok()?;
+
+    let duration = match unit {
+        "ms" => Duration::from_millis(amount),
+        "" | "s" => Duration::from_secs(amount),
+        "m" => Duration::from_secs(amount.checked_mul(60)?),
+        "h" => Duration::from_secs(amount.checked_mul(60 * 60)?),
+        _ => return None
+    };
+
+    if duration == Duration::from_secs(0) {
+        None
+    } else {
+        Some(duration)
+    }
+}
@@ -0,0 +1,389 @@
+use fetcher::{parse_http_url, Conditional, HttpFetcher};
+use product::{self, SonosSystem};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Port of the http server of sonos devices
+pub(crate) const DEVICE_PORT: u16 = 1400;
+
+/// Path of the upnp device description on sonos devices
+const DESCRIPTION_PATH: &str = "/xml/device_description.xml";
+
+/// Fields of the upnp device description (`/xml/device_description.xml`) of a sonos device,
+/// see `Discover::start_with_description`
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DeviceDescription {
+    /// `uuid:RINCON_...`
+    pub udn: Option<String>,
+    /// e.g. "192.168.1.10 - Sonos One - RINCON_..."
+    pub friendly_name: Option<String>,
+    /// `Sonos, Inc.` for sonos devices
+    pub manufacturer: Option<String>,
+    pub serial_number: Option<String>,
+    /// Product name (e.g. "Sonos One")
+    pub model_name: Option<String>,
+    /// Model identifier (e.g. "S18")
+    pub model_number: Option<String>,
+    /// Firmware version
+    pub software_version: Option<String>,
+    /// Firmware version shown in the apps (e.g. "16.1")
+    pub display_version: Option<String>,
+    /// `swGen`, system of the firmware (`1` for S1, `2` for S2), missing on firmware older than the split
+    pub software_generation: Option<String>,
+    pub room_name: Option<String>
+}
+
+/// Cached description of a single device
+#[derive(Debug)]
+struct CachedDescription {
+    description: DeviceDescription,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    /// Last time the description was downloaded or revalidated
+    validated: Instant
+}
+
+/// Parsed descriptions by device uuid, shared by all discoveries of a `Discover` (`Discover::set_description_ttl`)
+#[derive(Debug)]
+pub(crate) struct DescriptionCache {
+    /// Descriptions younger than this are used without any request,
+    /// older ones are revalidated (`ETag`/`Last-Modified`) or downloaded again
+    ttl: Duration,
+    entries: Mutex<HashMap<String, CachedDescription>>
+}
+
+impl DescriptionCache {
+    pub(crate) fn new(ttl: Duration) -> Self {
+        DescriptionCache {
+            ttl,
+            entries: Mutex::new(HashMap::new())
+        }
+    }
+
+    fn get(&self, fetcher: &dyn HttpFetcher, uuid: &str, url: &str, timeout: Duration) -> Result<DeviceDescription> {
+        let cached = {
+            let entries = self.entries.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            match entries.get(uuid) {
+                Some(entry) if entry.validated.elapsed() < self.ttl => return Ok(entry.description.clone()),
+                Some(entry) => Some((entry.etag.clone(), entry.last_modified.clone())),
+                None => None
+            }
+        };
+        let (etag, last_modified) = cached.unwrap_or_default();
+
+        let description = match fetcher.get_conditional(url, timeout, etag.as_deref(), last_modified.as_deref())? {
+            Conditional::Modified { body, etag, last_modified } => CachedDescription {
+                description: parse(&body),
+                etag,
+                last_modified,
+                validated: Instant::now()
+            },
+            Conditional::NotModified => {
+                let mut entries = self.entries.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                return match entries.get_mut(uuid) {
+                    Some(entry) => {
+                        entry.validated = Instant::now();
+                        Ok(entry.description.clone())
+                    }
+                    // Dropped by a concurrent download in the meantime
+                    None => Err(Error::new(ErrorKind::InvalidData, "Unexpected http status: 304"))
+                };
+            }
+        };
+
+        let result = description.description.clone();
+        self.entries.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).insert(uuid.to_string(), description);
+
+        Ok(result)
+    }
+}
+
+/// Downloads and parses the device description of the sonos device at `ip`.
+/// With a `cache` and the device's `uuid` the cached description is used or revalidated.
+pub(crate) fn fetch(fetcher: &dyn HttpFetcher, cache: Option<&DescriptionCache>, uuid: Option<&str>, ip: IpAddr,
+                    timeout: Duration) -> Result<DeviceDescription> {
+    fetch_url(fetcher, cache, uuid, &url(ip), timeout)
+}
+
+/// Url of the device description of the sonos device at `ip`
+pub(crate) fn url(ip: IpAddr) -> String {
+    format!("http://{}{}", SocketAddr::new(ip, DEVICE_PORT), DESCRIPTION_PATH)
+}
+
+fn fetch_url(fetcher: &dyn HttpFetcher, cache: Option<&DescriptionCache>, uuid: Option<&str>, url: &str,
+             timeout: Duration) -> Result<DeviceDescription> {
+    check_budget(timeout)?;
+
+    match (cache, uuid) {
+        (Some(cache), Some(uuid)) => cache.get(fetcher, uuid, url, timeout),
+        _ => fetcher.get(url, timeout).map(|body| parse(&body))
+    }
+}
+
+/// Downloads and parses the device description at `location` (the `LOCATION` header of a discovery response).
+///
+/// Only locations on the `responder` itself are fetched, a forged response could otherwise make the crate request
+/// arbitrary (internal) urls.
+pub(crate) fn fetch_location(fetcher: &dyn HttpFetcher, cache: Option<&DescriptionCache>, uuid: Option<&str>,
+                             location: &str, responder: IpAddr, timeout: Duration) -> Result<DeviceDescription> {
+    let (address, path) = parse_http_url(location)
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("Unsupported location: {}", location)))?;
+    if address.ip() != responder {
+        return Err(Error::new(ErrorKind::PermissionDenied,
+                              format!("Location {} doesn't point to the responding device {}", location, responder)));
+    }
+    // Rebuilt from the validated parts, so the fetcher can't interpret the raw header differently
+    fetch_url(fetcher, cache, uuid, &format!("http://{}{}", address, path), timeout)
+}
+
+/// A zero timeout is left when the time budget of the discovery is used up
+fn check_budget(timeout: Duration) -> Result<()> {
+    if timeout == Duration::from_secs(0) {
+        return Err(Error::new(ErrorKind::TimedOut, "Time budget of the discovery is used up"));
+    }
+
+    Ok(())
+}
+
+impl DeviceDescription {
+    /// System of the firmware by `swGen`, or by the major display version (S2 starts at 12) without it
+    pub fn system(&self) -> Option<SonosSystem> {
+        match self.software_generation.as_deref().map(str::trim) {
+            Some("1") => Some(SonosSystem::S1),
+            Some("2") => Some(SonosSystem::S2),
+            _ => product::system_of_display_version(self.display_version.as_ref()?)
+        }
+    }
+
+    /// Whether the description was written by a sonos device and not just any device mentioning "Sonos"
+    pub(crate) fn is_sonos(&self) -> bool {
+        match self.manufacturer {
+            Some(ref manufacturer) => manufacturer.starts_with("Sonos"),
+            None => false
+        }
+    }
+}
+
+/// Parses the root device of a device description, missing fields are `None`
+pub(crate) fn parse(xml: &str) -> DeviceDescription {
+    DeviceDescription {
+        udn: element(xml, "UDN"),
+        friendly_name: element(xml, "friendlyName"),
+        manufacturer: element(xml, "manufacturer"),
+        serial_number: element(xml, "serialNum"),
+        model_name: element(xml, "modelName"),
+        model_number: element(xml, "modelNumber"),
+        software_version: element(xml, "softwareVersion"),
+        display_version: element(xml, "displayVersion"),
+        software_generation: element(xml, "swGen"),
+        room_name: element(xml, "roomName")
+    }
+}
+
+/// Text of the first `<name>` element, the root device's fields come before the embedded devices
+pub(crate) fn element(xml: &str, name: &str) -> Option<String> {
+    let start_tag = format!("<{}>", name);
+    let end_tag = format!("</{}>", name);
+
+    let start = xml.find(&start_tag)? + start_tag.len();
+    let end = start + xml[start..].find(&end_tag)?;
+    let text = unescape(xml[start..end].trim());
+
+    if text.is_empty() {
+        None
+    } else {
+        Some(text)
+    }
+}
+
+/// Replaces the predefined xml entities
+pub(crate) fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fake_fetcher::FakeFetcher;
+
+    const XML: &str = include_str!("../tests/fixtures/device_description.xml");
+    const URL: &str = "http://192.168.1.20:1400/xml/device_description.xml";
+    const UUID: &str = "RINCON_000E58A0000001400";
+
+    fn ip() -> IpAddr {
+        "192.168.1.20".parse().unwrap()
+    }
+
+    /// Description of `xml` with the validators `etag` and `last_modified`
+    fn modified(xml: &str, etag: Option<&str>, last_modified: Option<&str>) -> Conditional {
+        Conditional::Modified {
+            body: xml.to_string(),
+            etag: etag.map(str::to_string),
+            last_modified: last_modified.map(str::to_string)
+        }
+    }
+
+    /// Validators sent with the request `index` of `fetcher`
+    fn validators(fetcher: &FakeFetcher, index: usize) -> Vec<(String, String)> {
+        fetcher.requests()[index].headers.clone()
+    }
+
+    #[test]
+    fn descriptions_have_the_fields_of_the_root_device() {
+        let description = parse(XML);
+        assert_eq!(description, DeviceDescription {
+            udn: Some("uuid:RINCON_000E58A0000001400".to_string()),
+            friendly_name: Some("192.168.1.20 - Sonos One - RINCON_000E58A0000001400".to_string()),
+            manufacturer: Some("Sonos, Inc.".to_string()),
+            serial_number: Some("00-0E-58-A0-00-00:E".to_string()),
+            model_name: Some("Sonos One".to_string()),
+            model_number: Some("S18".to_string()),
+            software_version: Some("79.1-56030".to_string()),
+            display_version: Some("16.1".to_string()),
+            software_generation: Some("2".to_string()),
+            room_name: Some("Tom & Jerry's Room".to_string())
+        });
+        assert!(description.is_sonos());
+        assert_eq!(description.system(), Some(SonosSystem::S2));
+
+        let other = parse("<root><device><manufacturer>Sonos-Fan Club</manufacturer></device></root>");
+        assert!(other.is_sonos());
+        assert!(!parse("<manufacturer>ACME Sonos</manufacturer>").is_sonos());
+        assert_eq!(parse(""), parse("<root><device/></root>"));
+        assert!(!parse("").is_sonos());
+    }
+
+    #[test]
+    fn systems() {
+        let cases = [(Some("1"), Some("16.1"), Some(SonosSystem::S1)),
+                     (Some(" 2 "), Some("11.2"), Some(SonosSystem::S2)),
+                     (Some("3"), Some("11.2"), Some(SonosSystem::S1)),
+                     (None, Some("12.0"), Some(SonosSystem::S2)),
+                     (None, Some("11.12"), Some(SonosSystem::S1)),
+                     (None, Some("beta"), None),
+                     (None, None, None)];
+        for &(generation, display_version, system) in &cases {
+            let description = DeviceDescription {
+                software_generation: generation.map(str::to_string),
+                display_version: display_version.map(str::to_string),
+                ..parse("")
+            };
+            assert_eq!(description.system(), system, "{:?} {:?}", generation, display_version);
+        }
+    }
+
+    #[test]
+    fn elements() {
+        assert_eq!(element("<a> x &lt;y&gt; </a>", "a").as_deref(), Some("x <y>"));
+        assert_eq!(element("<a></a><a>second</a>", "a"), None);
+        assert_eq!(element("<a>unterminated", "a"), None);
+        assert_eq!(element("<ab>x</ab>", "a"), None);
+        assert_eq!(unescape("&amp;lt; &quot;&apos;&amp;"), "&lt; \"'&");
+    }
+
+    #[test]
+    fn descriptions_are_fetched_from_the_device() {
+        let fetcher = FakeFetcher::default();
+        fetcher.answer(URL, XML);
+        assert_eq!(url(ip()), URL);
+        assert_eq!(fetch(&fetcher, None, Some(UUID), ip(), Duration::from_secs(1)).unwrap(), parse(XML));
+        assert_eq!(fetch(&fetcher, None, Some(UUID), ip(), Duration::from_secs(0)).unwrap_err().kind(),
+                   ErrorKind::TimedOut);
+        assert_eq!(fetcher.requests().len(), 1);
+    }
+
+    #[test]
+    fn only_locations_on_the_responder_are_fetched() {
+        let fetcher = FakeFetcher::default();
+        fetcher.answer("http://192.168.1.20:1400/xml/other.xml", XML);
+        let timeout = Duration::from_secs(1);
+
+        let location = " http://192.168.1.20:1400/xml/other.xml";
+        assert_eq!(fetch_location(&fetcher, None, None, location, ip(), timeout).unwrap(), parse(XML));
+        assert_eq!(fetcher.requests()[0].url, "http://192.168.1.20:1400/xml/other.xml");
+
+        let forged = fetch_location(&fetcher, None, None, "http://10.0.0.1:1400/xml/other.xml", ip(), timeout);
+        assert_eq!(forged.unwrap_err().kind(), ErrorKind::PermissionDenied);
+        for location in &["https://192.168.1.20:1400/", "http://sonos.local:1400/", "file:///etc/passwd", ""] {
+            let error = fetch_location(&fetcher, None, None, location, ip(), timeout).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidInput, "{}", location);
+        }
+        assert_eq!(fetcher.requests().len(), 1);
+    }
+
+    #[test]
+    fn fresh_descriptions_are_cached() {
+        let fetcher = FakeFetcher::default();
+        fetcher.answer(URL, XML);
+        let cache = DescriptionCache::new(Duration::from_secs(3600));
+        let timeout = Duration::from_secs(1);
+
+        for _ in 0..3 {
+            assert_eq!(fetch(&fetcher, Some(&cache), Some(UUID), ip(), timeout).unwrap(), parse(XML));
+        }
+        assert_eq!(fetcher.requests().len(), 1);
+
+        // Without an uuid there's nothing to cache by, other devices have entries of their own
+        fetch(&fetcher, Some(&cache), None, ip(), timeout).unwrap();
+        fetch(&fetcher, Some(&cache), Some("RINCON_B"), ip(), timeout).unwrap();
+        assert_eq!(fetcher.requests().len(), 3);
+    }
+
+    #[test]
+    fn stale_descriptions_are_revalidated() {
+        let fetcher = FakeFetcher::default();
+        fetcher.answer_conditional(URL, modified(XML, Some("\"v1\""), Some("Wed, 14 Oct 2026 07:00:00 GMT")));
+        // Every entry is stale right away
+        let cache = DescriptionCache::new(Duration::from_secs(0));
+        let timeout = Duration::from_secs(1);
+
+        assert_eq!(fetch(&fetcher, Some(&cache), Some(UUID), ip(), timeout).unwrap(), parse(XML));
+        assert!(validators(&fetcher, 0).is_empty());
+
+        // Unchanged, the cached description is used
+        fetcher.answer_conditional(URL, Conditional::NotModified);
+        assert_eq!(fetch(&fetcher, Some(&cache), Some(UUID), ip(), timeout).unwrap(), parse(XML));
+        assert_eq!(validators(&fetcher, 1), vec![
+            ("If-None-Match".to_string(), "\"v1\"".to_string()),
+            ("If-Modified-Since".to_string(), "Wed, 14 Oct 2026 07:00:00 GMT".to_string())
+        ]);
+
+        // Changed, the new description and its validators replace the cached ones
+        let renamed = XML.replace("Tom &amp; Jerry&apos;s Room", "Kitchen");
+        fetcher.answer_conditional(URL, modified(&renamed, None, Some("Wed, 14 Oct 2026 08:00:00 GMT")));
+        assert_eq!(fetch(&fetcher, Some(&cache), Some(UUID), ip(), timeout).unwrap().room_name.as_deref(),
+                   Some("Kitchen"));
+        fetcher.answer_conditional(URL, Conditional::NotModified);
+        assert_eq!(fetch(&fetcher, Some(&cache), Some(UUID), ip(), timeout).unwrap().room_name.as_deref(),
+                   Some("Kitchen"));
+        assert_eq!(validators(&fetcher, 3), vec![
+            ("If-Modified-Since".to_string(), "Wed, 14 Oct 2026 08:00:00 GMT".to_string())
+        ]);
+
+        // Failed revalidations aren't cached
+        fetcher.fail(URL, ErrorKind::ConnectionRefused);
+        assert_eq!(fetch(&fetcher, Some(&cache), Some(UUID), ip(), timeout).unwrap_err().kind(),
+                   ErrorKind::ConnectionRefused);
+        assert_eq!(fetcher.requests().len(), 5);
+    }
+
+    #[test]
+    fn unexpected_not_modified_responses_fail() {
+        let fetcher = FakeFetcher::default();
+        fetcher.answer_conditional(URL, Conditional::NotModified);
+        let cache = DescriptionCache::new(Duration::from_secs(0));
+
+        let error = fetch(&fetcher, Some(&cache), Some(UUID), ip(), Duration::from_secs(1)).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,500 @@
+use config::DiscoveryConfig;
+use description::{self, DescriptionCache, DeviceDescription};
+use dns;
+use engine::{Collector, Engine, Response};
+use fetcher::HttpFetcher;
+use parallel::run_limited;
+use product::{self, Product, SonosSystem};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+/// Headers of the search response of a sonos device, see `Discover::start_detailed`
+///
+/// Ordered by the address first.
+pub struct DeviceInfo {
+    /// Address the response was sent from
+    pub ip: IpAddr,
+    /// `LOCATION`, url of the device description (e.g. `http://192.168.1.10:1400/xml/device_description.xml`)
+    pub location: Option<String>,
+    /// `USN`, e.g. `uuid:RINCON_000E58A0123401400::urn:schemas-upnp-org:device:ZonePlayer:1`
+    pub usn: Option<String>,
+    /// Unique id (`RINCON_...`) from the `USN`
+    pub uuid: Option<String>,
+    /// MAC address embedded in the uuid (`RINCON_000E58A01234...` is `00:0E:58:A0:12:34`), e.g. to match DHCP
+    /// reservations
+    pub mac: Option<String>,
+    /// Serial number from the uuid (`00-0E-58-A0-12-34`), the one on the label of the speaker adds a suffix (`:A`)
+    pub serial_number: Option<String>,
+    /// `SERVER`, operating system and firmware of the device
+    pub server: Option<String>,
+    /// `CACHE-CONTROL`, e.g. `max-age = 1800`
+    pub cache_control: Option<String>,
+    /// `X-RINCON-HOUSEHOLD`, id of the sonos household of the device (e.g. `Sonos_abc123`)
+    pub household: Option<String>,
+    /// Device description, only fetched by `Discover::start_with_description`
+    pub description: Option<DeviceDescription>,
+    /// Kind of the device by its `ST`, `USN` and `SERVER` headers, see `Discover::start_all`
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub kind: DeviceKind,
+    /// Hostname of the address (PTR record), only looked up with `Discover::set_resolve_hostnames`
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub hostname: Option<String>,
+    /// `BOOTID.UPNP.ORG`, increased every time the device (re)joins the network, `None` for UPnP 1.0 devices
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub boot_id: Option<u32>,
+    /// `CONFIGID.UPNP.ORG`, changes with the device description, `None` for UPnP 1.0 devices
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub config_id: Option<u32>
+}
+
+/// Kind of a upnp device, classified by the headers of its search responses
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[non_exhaustive]
+pub enum DeviceKind {
+    /// A sonos speaker (`ZonePlayer` device type or "Sonos" in the `SERVER` header)
+    SonosZonePlayer,
+    /// A upnp media renderer, e.g. a tv or a network speaker of another vendor
+    MediaRenderer,
+    /// An internet gateway device, e.g. the router of the network
+    Router,
+    /// Any other upnp device (nas, printer, media server, ...)
+    #[default]
+    Other
+}
+
+impl DeviceKind {
+    /// Kind named by the search target or `USN` of a response (`value`) or its `SERVER` header
+    fn classify(value: &str, server: Option<&str>) -> Self {
+        let value = value.to_ascii_lowercase();
+        let sonos = server.is_some_and(|server| server.to_ascii_lowercase().contains("sonos"));
+        if sonos || value.contains(":device:zoneplayer:") {
+            DeviceKind::SonosZonePlayer
+        } else if value.contains(":device:mediarenderer:") {
+            DeviceKind::MediaRenderer
+        } else if [":device:internetgatewaydevice:", ":device:wandevice:", ":device:wanconnectiondevice:",
+                   ":service:wanipconnection:", ":service:wanpppconnection:", ":service:layer3forwarding:"]
+            .iter().any(|name| value.contains(name)) {
+            DeviceKind::Router
+        } else {
+            DeviceKind::Other
+        }
+    }
+
+    /// Kind of `response` by its `ST` (`NT` for announcements), `USN` and `SERVER` headers
+    pub(crate) fn of(response: &Response) -> Self {
+        let server = response.ssdp.header("SERVER");
+        [response.ssdp.st().or_else(|| response.ssdp.nt()), response.ssdp.usn()].iter()
+            .filter_map(|&value| value)
+            .map(|value| DeviceKind::classify(value, server))
+            .min()
+            .unwrap_or_else(|| DeviceKind::classify("", server))
+    }
+}
+
+impl fmt::Display for DeviceKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            DeviceKind::SonosZonePlayer => "sonos",
+            DeviceKind::MediaRenderer => "media-renderer",
+            DeviceKind::Router => "router",
+            DeviceKind::Other => "other"
+        })
+    }
+}
+
+impl DeviceInfo {
+    pub(crate) fn from_response(response: &Response) -> Self {
+        let uuid = response.uuid();
+        let mac = uuid.as_deref().and_then(mac_address);
+        let upnp = response.upnp_headers();
+
+        DeviceInfo {
+            ip: response.addr.ip(),
+            location: response.ssdp.location().map(str::to_string),
+            usn: response.ssdp.usn().map(str::to_string),
+            uuid,
+            mac: mac.map(|mac| format_mac(&mac, ":")),
+            serial_number: mac.map(|mac| format_mac(&mac, "-")),
+            server: response.ssdp.header("SERVER").map(str::to_string),
+            cache_control: response.ssdp.cache_control().map(str::to_string),
+            household: response.ssdp.header("X-RINCON-HOUSEHOLD").map(str::to_string),
+            description: None,
+            kind: DeviceKind::of(response),
+            hostname: None,
+            boot_id: upnp.boot_id,
+            config_id: upnp.config_id
+        }
+    }
+
+    /// Device at `ip` known only by its `description`, e.g. for a device which didn't answer the search
+    pub(crate) fn from_description(ip: IpAddr, description: DeviceDescription) -> Self {
+        let uuid = description.udn.as_deref().map(|udn| udn.trim_start_matches("uuid:").to_string());
+        let mac = uuid.as_deref().and_then(mac_address);
+
+        DeviceInfo {
+            ip,
+            location: Some(description::url(ip)),
+            usn: None,
+            uuid,
+            mac: mac.map(|mac| format_mac(&mac, ":")),
+            serial_number: mac.map(|mac| format_mac(&mac, "-")),
+            server: None,
+            cache_control: None,
+            household: None,
+            description: Some(description),
+            kind: DeviceKind::SonosZonePlayer,
+            hostname: None,
+            boot_id: None,
+            config_id: None
+        }
+    }
+
+    /// Devices of the first response of every address, sorted by the address.
+    /// The kind is the most specific one of all responses of the address, a device answers `ssdp:all` once for each
+    /// of its device and service types.
+    pub(crate) fn from_responses(responses: &[Response]) -> Vec<Self> {
+        let mut devices: HashMap<IpAddr, DeviceInfo> = HashMap::new();
+        for response in responses {
+            let kind = DeviceKind::of(response);
+            let device = devices.entry(response.addr.ip()).or_insert_with(|| DeviceInfo::from_response(response));
+            device.kind = device.kind.min(kind);
+        }
+        let mut devices: Vec<DeviceInfo> = devices.into_values().collect();
+        devices.sort();
+
+        devices
+    }
+
+    /// `max-age` of the `CACHE-CONTROL` header, how long the response stays valid
+    pub fn max_age(&self) -> Option<Duration> {
+        self.cache_control.as_ref()?
+            .split(',')
+            .filter_map(|directive| {
+                let mut parts = directive.splitn(2, '=');
+                match (parts.next(), parts.next()) {
+                    (Some(key), Some(value)) if key.trim().eq_ignore_ascii_case("max-age") => value.trim().parse().ok(),
+                    _ => None
+                }
+            })
+            .next()
+            .map(Duration::from_secs)
+    }
+
+    /// Product of the model identifier in the description (`DeviceDescription::model_number`),
+    /// `None` without a description (only fetched by `Discover::start_with_description`) or for an unknown model
+    pub fn product(&self) -> Option<&'static Product> {
+        product::product(self.description.as_ref()?.model_number.as_ref()?)
+    }
+
+    /// Whether the device runs S1 or S2 firmware, from the description if it was fetched
+    /// (`DeviceDescription::system`), otherwise from the build in the `SERVER` header
+    pub fn system(&self) -> Option<SonosSystem> {
+        self.description.as_ref()
+            .and_then(DeviceDescription::system)
+            .or_else(|| product::system_of_server(self.server.as_ref()?))
+    }
+}
+
+/// MAC address in the first 12 hex digits after `RINCON_`
+fn mac_address(uuid: &str) -> Option<[u8; 6]> {
+    let digits = uuid.strip_prefix("RINCON_")?.get(..12)?;
+    // `from_str_radix` would accept a sign
+    if !digits.bytes().all(|digit| digit.is_ascii_hexdigit()) {
+        return None;
+    }
+    let mut mac = [0; 6];
+    for (index, byte) in mac.iter_mut().enumerate() {
+        // `get` instead of indexing, a forged uuid with multi-byte characters must not panic
+        *byte = u8::from_str_radix(digits.get(index * 2..index * 2 + 2)?, 16).ok()?;
+    }
+
+    Some(mac)
+}
+
+/// Uppercase hex bytes of `mac` joined by `separator`
+fn format_mac(mac: &[u8; 6], separator: &str) -> String {
+    mac.iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<_>>().join(separator)
+}
+
+/// Fetches the description of every device with at most `concurrency` requests at a time,
+/// failures are logged and leave the description `None`
+pub(crate) fn describe(devices: &mut [DeviceInfo], fetcher: &Arc<dyn HttpFetcher>, cache: Option<&Arc<DescriptionCache>>,
+                       timeout: Duration, concurrency: usize) {
+    let jobs: Vec<(IpAddr, Option<String>)> = devices.iter().map(|device| (device.ip, device.uuid.clone())).collect();
+    let fetcher = Arc::clone(fetcher);
+    let cache = cache.cloned();
+    let fetches = run_limited(jobs, concurrency, move |(ip, uuid)|
+        description::fetch(&*fetcher, cache.as_deref(), uuid.as_deref(), ip, timeout));
+
+    for (device, fetch) in devices.iter_mut().zip(fetches) {
+        match fetch {
+            Ok(Ok(description)) => device.description = Some(description),
+            Ok(Err(e)) => warn!("Couldn't fetch the description of {}: {}", device.ip, e),
+            Err(_) => warn!("Fetching the description of {} panicked", device.ip)
+        }
+    }
+}
+
+/// Looks up the hostname of every device if `resolve_hostnames` is set, with at most `dns::MAX_LOOKUPS` lookups at a
+/// time. Failures (e.g. no nameserver or a timeout after `hostname_timeout`) are logged and leave the hostname `None`.
+pub(crate) fn resolve_hostnames(devices: &mut [DeviceInfo], config: &DiscoveryConfig) {
+    if !config.resolve_hostnames {
+        return;
+    }
+
+    let timeout = config.hostname_timeout;
+    let ips: Vec<IpAddr> = devices.iter().map(|device| device.ip).collect();
+    let lookups = run_limited(ips, dns::MAX_LOOKUPS, move |ip| dns::reverse_lookup(ip, timeout));
+
+    for (device, lookup) in devices.iter_mut().zip(lookups) {
+        match lookup {
+            Ok(Ok(hostname)) => device.hostname = hostname,
+            Ok(Err(e)) => debug!("Couldn't look up the hostname of {}: {}", device.ip, e),
+            Err(_) => warn!("Looking up the hostname of {} panicked", device.ip)
+        }
+    }
+}
+
+/// Removes the devices which don't match the room and model filters of `config`,
+/// devices without a description only match if no filter is set
+pub(crate) fn filter(devices: &mut Vec<DeviceInfo>, config: &DiscoveryConfig) {
+    let room = config.room_filter.as_ref().map(|room| room.to_lowercase());
+    let model = config.model_filter.as_ref().map(|model| model.to_lowercase());
+    if room.is_none() && model.is_none() {
+        return;
+    }
+
+    devices.retain(|device| {
+        let description = device.description.as_ref();
+        let field = |value: Option<&String>| value.map(|value| value.to_lowercase());
+        let room_matches = match room {
+            Some(ref room) => field(description.and_then(|description| description.room_name.as_ref())).as_ref() == Some(room),
+            None => true
+        };
+        let model_matches = match model {
+            Some(ref model) => field(description.and_then(|description| description.model_name.as_ref()))
+                .is_some_and(|name| name.contains(model.as_str())),
+            None => true
+        };
+
+        room_matches && model_matches
+    });
+}
+
+/// Iterator over the devices of a running search, see `Discover::iter`
+///
+/// Every call of `next` receives until the next new device answers, `None` once the timeout or the device count is reached.
+pub struct Devices {
+    engine: Engine,
+    collector: Collector
+}
+
+impl Devices {
+    pub(crate) fn new(engine: Engine, collector: Collector) -> Self {
+        Devices {
+            engine,
+            collector
+        }
+    }
+}
+
+impl Iterator for Devices {
+    type Item = DeviceInfo;
+
+    fn next(&mut self) -> Option<DeviceInfo> {
+        while !self.collector.finished() {
+            if let Some((addr, data)) = self.engine.next_packet(&mut self.collector) {
+                if let Some(response) = self.collector.receive(addr, data) {
+                    return Some(DeviceInfo::from_response(response));
+                }
+            }
+        }
+
+        None
+    }
+}
+
+impl fmt::Debug for Devices {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Devices").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ssdp::SsdpResponse;
+    use std::net::SocketAddr;
+    use std::time::{Instant, SystemTime};
+
+    /// Response from `ip` with the headers `headers`
+    fn response(ip: &str, headers: &str) -> Response {
+        let data = format!("HTTP/1.1 200 OK\r\n{}\r\n", headers);
+        Response {
+            addr: SocketAddr::new(ip.parse().unwrap(), 1900),
+            ssdp: SsdpResponse::parse(data.as_bytes()),
+            received: Instant::now(),
+            received_at: SystemTime::now(),
+            latency: Duration::from_millis(20)
+        }
+    }
+
+    #[test]
+    fn mac_addresses_and_serial_numbers_of_uuids() {
+        let cases = [("RINCON_000E58A0123401400", Some(("00:0E:58:A0:12:34", "00-0E-58-A0-12-34"))),
+                     ("RINCON_b8e9373c4d5e01400", Some(("B8:E9:37:3C:4D:5E", "B8-E9-37-3C-4D-5E"))),
+                     ("RINCON_000E58A01234", Some(("00:0E:58:A0:12:34", "00-0E-58-A0-12-34"))),
+                     ("RINCON_000E58A0123401400_MR", Some(("00:0E:58:A0:12:34", "00-0E-58-A0-12-34"))),
+                     ("RINCON_000E58A012", None),
+                     ("RINCON_", None),
+                     ("uuid:RINCON_000E58A0123401400", None),
+                     ("rincon_000E58A0123401400", None),
+                     ("RINCON_000E58G0123401400", None),
+                     ("RINCON_+0+E+8+0+2+401400", None),
+                     ("RINCON_000E58A012ü401400", None),
+                     ("RINCON_üüüüüü01400", None),
+                     ("", None)];
+        for &(uuid, expected) in &cases {
+            let mac = mac_address(uuid);
+            let formatted = mac.as_ref().map(|mac| (format_mac(mac, ":"), format_mac(mac, "-")));
+            assert_eq!(formatted, expected.map(|(mac, serial)| (mac.to_string(), serial.to_string())), "{}", uuid);
+        }
+    }
+
+    #[test]
+    fn kinds() {
+        let cases = [("urn:schemas-upnp-org:device:ZonePlayer:1", None, DeviceKind::SonosZonePlayer),
+                     ("uuid:RINCON_A::URN:SCHEMAS-UPNP-ORG:DEVICE:ZONEPLAYER:1", None, DeviceKind::SonosZonePlayer),
+                     ("upnp:rootdevice", Some("Linux UPnP/1.0 Sonos/79.1-56030 (ZPS18)"), DeviceKind::SonosZonePlayer),
+                     ("urn:schemas-upnp-org:device:MediaRenderer:1", None, DeviceKind::MediaRenderer),
+                     ("urn:schemas-upnp-org:device:MediaRenderer:1", Some("SONOS"), DeviceKind::SonosZonePlayer),
+                     ("urn:schemas-upnp-org:device:InternetGatewayDevice:1", None, DeviceKind::Router),
+                     ("urn:schemas-upnp-org:service:WANIPConnection:2", None, DeviceKind::Router),
+                     ("urn:schemas-upnp-org:service:Layer3Forwarding:1", None, DeviceKind::Router),
+                     ("urn:schemas-upnp-org:device:MediaServer:1", Some("Synology/DSM"), DeviceKind::Other),
+                     ("urn:schemas-upnp-org:service:ZonePlayer:1", None, DeviceKind::Other),
+                     ("", None, DeviceKind::Other)];
+        for &(value, server, kind) in &cases {
+            assert_eq!(DeviceKind::classify(value, server), kind, "{} {:?}", value, server);
+        }
+        assert_eq!(DeviceKind::default().to_string(), "other");
+    }
+
+    #[test]
+    fn devices_of_responses() {
+        let headers = "USN: uuid:RINCON_000E58A0123401400_MR::urn:schemas-upnp-org:device:MediaRenderer:1\r\n\
+                       ST: urn:schemas-upnp-org:device:MediaRenderer:1\r\nLOCATION: http://192.168.1.20:1400/x.xml\r\n\
+                       SERVER: Linux UPnP/1.0 Sonos/79.1-56030 (ZPS18)\r\nCACHE-CONTROL: max-age=1800\r\n\
+                       X-RINCON-HOUSEHOLD: Sonos_test\r\nBOOTID.UPNP.ORG: 7\r\nCONFIGID.UPNP.ORG: 12";
+        let device = DeviceInfo::from_response(&response("192.168.1.20", headers));
+        assert_eq!(device, DeviceInfo {
+            ip: "192.168.1.20".parse().unwrap(),
+            location: Some("http://192.168.1.20:1400/x.xml".to_string()),
+            usn: Some("uuid:RINCON_000E58A0123401400_MR::urn:schemas-upnp-org:device:MediaRenderer:1".to_string()),
+            // The uuid of the zone player, not of its media renderer
+            uuid: Some("RINCON_000E58A0123401400".to_string()),
+            mac: Some("00:0E:58:A0:12:34".to_string()),
+            serial_number: Some("00-0E-58-A0-12-34".to_string()),
+            server: Some("Linux UPnP/1.0 Sonos/79.1-56030 (ZPS18)".to_string()),
+            cache_control: Some("max-age=1800".to_string()),
+            household: Some("Sonos_test".to_string()),
+            description: None,
+            kind: DeviceKind::SonosZonePlayer,
+            hostname: None,
+            boot_id: Some(7),
+            config_id: Some(12)
+        });
+        assert_eq!(device.max_age(), Some(Duration::from_secs(1800)));
+        assert_eq!(device.system(), Some(SonosSystem::S2));
+
+        // One device per address with the most specific kind of its responses, sorted by the address
+        let devices = DeviceInfo::from_responses(&[
+            response("192.168.1.30", "ST: upnp:rootdevice\r\nUSN: uuid:tv::upnp:rootdevice"),
+            response("192.168.1.20", "ST: urn:schemas-upnp-org:device:MediaServer:1"),
+            response("192.168.1.30", "ST: urn:schemas-upnp-org:device:MediaRenderer:1"),
+            response("192.168.1.20", "ST: urn:schemas-upnp-org:device:ZonePlayer:1")
+        ]);
+        assert_eq!(devices.iter().map(|device| (device.ip.to_string(), device.kind)).collect::<Vec<_>>(),
+                   vec![("192.168.1.20".to_string(), DeviceKind::SonosZonePlayer),
+                        ("192.168.1.30".to_string(), DeviceKind::MediaRenderer)]);
+        assert_eq!(devices[1].usn.as_deref(), Some("uuid:tv::upnp:rootdevice"));
+        assert_eq!(devices[1].mac, None);
+    }
+
+    #[test]
+    fn devices_of_descriptions() {
+        let description = DeviceDescription {
+            udn: Some("uuid:RINCON_000E58A0123401400".to_string()),
+            model_number: Some("s18".to_string()),
+            display_version: Some("11.2".to_string()),
+            ..description::parse("")
+        };
+        let device = DeviceInfo::from_description("192.168.1.20".parse().unwrap(), description);
+        assert_eq!(device.uuid.as_deref(), Some("RINCON_000E58A0123401400"));
+        assert_eq!(device.location.as_deref(), Some("http://192.168.1.20:1400/xml/device_description.xml"));
+        assert_eq!(device.serial_number.as_deref(), Some("00-0E-58-A0-12-34"));
+        assert_eq!(device.kind, DeviceKind::SonosZonePlayer);
+        assert_eq!(device.product().map(|product| product.name), Some("One Gen 2"));
+        // The description wins over the server header
+        let device = DeviceInfo {
+            server: Some("Linux UPnP/1.0 Sonos/79.1-56030 (ZPS18)".to_string()),
+            ..device
+        };
+        assert_eq!(device.system(), Some(SonosSystem::S1));
+    }
+
+    #[test]
+    fn max_ages() {
+        let cases = [("max-age=1800", Some(1800)),
+                     ("no-cache, MAX-AGE = 60", Some(60)),
+                     ("max-age", None),
+                     ("max-age=-1", None),
+                     ("s-maxage=10", None),
+                     ("", None)];
+        for &(cache_control, max_age) in &cases {
+            let device = DeviceInfo::from_response(&response("192.168.1.20", &format!("CACHE-CONTROL: {}",
+                                                                                        cache_control)));
+            assert_eq!(device.max_age(), max_age.map(Duration::from_secs), "{}", cache_control);
+        }
+    }
+
+    #[test]
+    fn devices_are_filtered_by_room_and_model() {
+        let device = |ip: &str, room: Option<&str>, model: &str| DeviceInfo {
+            description: Some(DeviceDescription {
+                room_name: room.map(str::to_string),
+                model_name: Some(model.to_string()),
+                ..description::parse("")
+            }),
+            ..DeviceInfo::from_response(&response(ip, ""))
+        };
+        let devices = vec![device("192.168.1.20", Some("Kitchen"), "Sonos One"),
+                           device("192.168.1.21", Some("Living Room"), "Sonos Arc"),
+                           device("192.168.1.22", None, "Sonos One SL"),
+                           DeviceInfo::from_response(&response("192.168.1.23", ""))];
+        let ips = |room: Option<&str>, model: Option<&str>| {
+            let config = DiscoveryConfig {
+                room_filter: room.map(str::to_string),
+                model_filter: model.map(str::to_string),
+                ..DiscoveryConfig::default()
+            };
+            let mut devices = devices.clone();
+            filter(&mut devices, &config);
+            devices.iter().map(|device| device.ip.to_string()).collect::<Vec<_>>()
+        };
+
+        assert_eq!(ips(None, None).len(), 4);
+        assert_eq!(ips(Some("kitchen"), None), vec!["192.168.1.20"]);
+        assert_eq!(ips(Some("Living"), None), Vec::<String>::new());
+        assert_eq!(ips(None, Some("one")), vec!["192.168.1.20", "192.168.1.22"]);
+        assert_eq!(ips(Some("KITCHEN"), Some("arc")), Vec::<String>::new());
+    }
+}
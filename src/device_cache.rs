@@ -0,0 +1,108 @@
+use result::DiscoveryResult;
+use serde::{Deserialize, Serialize};
+use serde_json;
+use std::env;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Result, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+/// Version of the device cache file, only changes for breaking changes of the format
+pub const DEVICE_CACHE_SCHEMA_VERSION: u32 = 1;
+
+/// File name of the cache in the platform cache directory (`DeviceCache::default_path`)
+const FILE_NAME: &str = "devices.json";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+/// Last known speakers, stored as json file between runs, see `Discover::start_cached`
+pub struct DeviceCache {
+    pub schema_version: u32,
+    pub devices: Vec<CachedDevice>
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// Speaker of a `DeviceCache`
+pub struct CachedDevice {
+    pub ip: IpAddr,
+    /// Unique id (`RINCON_...`)
+    pub uuid: Option<String>,
+    /// Room of the speaker, only known if the topology was fetched (`Discover::set_topology`)
+    pub room_name: Option<String>
+}
+
+impl DeviceCache {
+    /// Cache of the devices of `result`
+    pub fn from_result(result: &DiscoveryResult) -> Self {
+        let rooms: Vec<(IpAddr, String)> = result.grouped().into_iter()
+            .flat_map(|group| group.members)
+            .filter_map(|member| match (member.ip, member.zone_name) {
+                (Some(ip), Some(room_name)) => Some((ip, room_name)),
+                _ => None
+            })
+            .collect();
+        let devices = result.unique_ips().into_iter()
+            .map(|ip| CachedDevice {
+                ip,
+                uuid: result.uuid(&ip).map(str::to_string),
+                room_name: rooms.iter().find(|&&(room_ip, _)| room_ip == ip).map(|(_, room_name)| room_name.clone())
+            })
+            .collect();
+
+        DeviceCache {
+            schema_version: DEVICE_CACHE_SCHEMA_VERSION,
+            devices
+        }
+    }
+
+    /// `sonos_discovery/devices.json` in the cache directory of the platform: `$XDG_CACHE_HOME` or `~/.cache` on
+    /// linux, `~/Library/Caches` on macOS, `%LOCALAPPDATA%` on windows. `None` if the directory isn't known.
+    pub fn default_path() -> Option<PathBuf> {
+        let directory = if cfg!(windows) {
+            env::var_os("LOCALAPPDATA").map(PathBuf::from)
+        } else if cfg!(target_os = "macos") {
+            env::var_os("HOME").map(|home| PathBuf::from(home).join("Library").join("Caches"))
+        } else {
+            env::var_os("XDG_CACHE_HOME").map(PathBuf::from)
+                .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        };
+
+        directory.map(|directory| directory.join("sonos_discovery").join(FILE_NAME))
+    }
+
+    /// Reads the cache file at `path`, a missing file is an empty cache
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(DeviceCache::default()),
+            Err(e) => return Err(e)
+        };
+        let cache: DeviceCache = serde_json::from_reader(BufReader::new(file))?;
+
+        if cache.schema_version > DEVICE_CACHE_SCHEMA_VERSION {
+            return Err(Error::new(ErrorKind::InvalidData,
+                                  format!("Unsupported device cache schema version {}", cache.schema_version)));
+        }
+
+        Ok(cache)
+    }
+
+    /// Writes the cache to `path`, the directory is created if missing
+    pub fn write(&self, path: &Path) -> Result<()> {
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+        // Write to a temporary file first, a concurrent run never reads a half-written cache
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+
+        let mut writer = BufWriter::new(File::create(&temporary)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.flush()?;
+        fs::rename(&temporary, path)
+    }
+
+    /// Addresses of the cached devices
+    pub fn ips(&self) -> Vec<IpAddr> {
+        self.devices.iter().map(|device| device.ip).collect()
+    }
+}
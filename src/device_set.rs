@@ -0,0 +1,261 @@
+use device::DeviceInfo;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::collections::btree_map::{self, BTreeMap};
+use std::iter::FromIterator;
+use std::net::IpAddr;
+
+/// Devices of a scan by their identity, the uuid (`RINCON_...`) or the address of a device without one
+///
+/// Ordered by the identity, a device which got a new address (e.g. from dhcp) keeps its place. Consecutive scans are
+/// compared with `diff`, `merge` combines the devices of several scans (e.g. of multiple interfaces).
+///
+/// # Examples
+///
+/// ```no_run
+/// use sonos_discovery::Discover;
+///
+/// let discovery = Discover::new().unwrap();
+/// let before = discovery.start(None, None).unwrap().device_set();
+/// let after = discovery.start(None, None).unwrap().device_set();
+/// let diff = before.diff(&after);
+/// for (uuid, ip) in &diff.joined {
+///     println!("{} joined at {}", uuid, ip);
+/// }
+/// for (uuid, old, new) in &diff.changed {
+///     println!("{} moved from {} to {}", uuid, old, new);
+/// }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DeviceSet {
+    devices: BTreeMap<String, IpAddr>
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// Changes from one `DeviceSet` to a newer one, see `DeviceSet::diff`
+pub struct DeviceSetDiff {
+    /// Devices only in the newer set (identity, address)
+    pub joined: Vec<(String, IpAddr)>,
+    /// Devices only in the older set (identity, address)
+    pub left: Vec<(String, IpAddr)>,
+    /// Devices in both sets with another address (identity, old address, new address)
+    pub changed: Vec<(String, IpAddr, IpAddr)>
+}
+
+impl DeviceSetDiff {
+    /// Whether both sets have the same devices at the same addresses
+    pub fn is_empty(&self) -> bool {
+        self.joined.is_empty() && self.left.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl DeviceSet {
+    /// Empty set
+    pub fn new() -> Self {
+        DeviceSet::default()
+    }
+
+    /// Set of `devices`, by their uuid or their address without one
+    pub fn from_devices(devices: &[DeviceInfo]) -> Self {
+        devices.iter()
+            .map(|device| (device.uuid.clone().unwrap_or_else(|| device.ip.to_string()), device.ip))
+            .collect()
+    }
+
+    /// Adds the device `identity` at `ip`, returns its previous address if it was in the set already
+    pub fn insert(&mut self, identity: String, ip: IpAddr) -> Option<IpAddr> {
+        self.devices.insert(identity, ip)
+    }
+
+    /// Whether the device with the uuid (or the address without one) `uuid` is in the set
+    pub fn contains_uuid(&self, uuid: &str) -> bool {
+        self.devices.contains_key(uuid)
+    }
+
+    /// Whether a device of the set has the address `ip`
+    pub fn contains_ip(&self, ip: &IpAddr) -> bool {
+        self.devices.values().any(|device| device == ip)
+    }
+
+    /// Address of the device with the uuid `uuid`
+    pub fn ip(&self, uuid: &str) -> Option<IpAddr> {
+        self.devices.get(uuid).cloned()
+    }
+
+    /// Addresses of the devices, sorted
+    pub fn ips(&self) -> Vec<IpAddr> {
+        let mut ips: Vec<IpAddr> = self.devices.values().cloned().collect();
+        ips.sort();
+
+        ips
+    }
+
+    /// Number of devices
+    pub fn len(&self) -> usize {
+        self.devices.len()
+    }
+
+    /// Whether the set has no devices
+    pub fn is_empty(&self) -> bool {
+        self.devices.is_empty()
+    }
+
+    /// Identities and addresses of the devices, ordered by the identity
+    pub fn iter(&self) -> btree_map::Iter<'_, String, IpAddr> {
+        self.devices.iter()
+    }
+
+    /// Changes from `self` to the newer set `newer`, each list is ordered by the identity
+    pub fn diff(&self, newer: &DeviceSet) -> DeviceSetDiff {
+        let mut diff = DeviceSetDiff::default();
+
+        for (identity, ip) in &newer.devices {
+            match self.devices.get(identity) {
+                None => diff.joined.push((identity.clone(), *ip)),
+                Some(old) if old != ip => diff.changed.push((identity.clone(), *old, *ip)),
+                Some(_) => {}
+            }
+        }
+        diff.left = self.devices.iter()
+            .filter(|&(identity, _)| !newer.devices.contains_key(identity))
+            .map(|(identity, ip)| (identity.clone(), *ip))
+            .collect();
+
+        diff
+    }
+
+    /// Adds the devices of `other`, the addresses of `other` win for devices in both sets
+    pub fn merge(&mut self, other: DeviceSet) {
+        self.devices.extend(other.devices);
+    }
+}
+
+impl FromIterator<(String, IpAddr)> for DeviceSet {
+    fn from_iter<I: IntoIterator<Item = (String, IpAddr)>>(devices: I) -> Self {
+        DeviceSet {
+            devices: devices.into_iter().collect()
+        }
+    }
+}
+
+impl Extend<(String, IpAddr)> for DeviceSet {
+    fn extend<I: IntoIterator<Item = (String, IpAddr)>>(&mut self, devices: I) {
+        self.devices.extend(devices);
+    }
+}
+
+impl IntoIterator for DeviceSet {
+    type Item = (String, IpAddr);
+    type IntoIter = btree_map::IntoIter<String, IpAddr>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.devices.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a DeviceSet {
+    type Item = (&'a String, &'a IpAddr);
+    type IntoIter = btree_map::Iter<'a, String, IpAddr>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.devices.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Set of the devices `(identity, address)`
+    fn set(devices: &[(&str, &str)]) -> DeviceSet {
+        devices.iter().map(|&(identity, ip)| (identity.to_string(), ip.parse().unwrap())).collect()
+    }
+
+    /// `(identity, address)` entries of a diff
+    fn entries(devices: &[(&str, &str)]) -> Vec<(String, IpAddr)> {
+        set(devices).into_iter().collect()
+    }
+
+    #[test]
+    fn devices_are_identified_by_their_uuid() {
+        let device = |ip: &str, uuid: Option<&str>| DeviceInfo {
+            ip: ip.parse().unwrap(),
+            location: None,
+            usn: None,
+            uuid: uuid.map(str::to_string),
+            mac: None,
+            serial_number: None,
+            server: None,
+            cache_control: None,
+            household: None,
+            description: None,
+            kind: Default::default(),
+            hostname: None,
+            boot_id: None,
+            config_id: None
+        };
+        let devices = DeviceSet::from_devices(&[device("192.168.1.20", Some("RINCON_B")),
+                                                device("192.168.1.10", None),
+                                                device("192.168.1.30", Some("RINCON_A"))]);
+        assert_eq!(devices, set(&[("RINCON_A", "192.168.1.30"), ("RINCON_B", "192.168.1.20"),
+                                  ("192.168.1.10", "192.168.1.10")]));
+        assert_eq!(devices.iter().map(|(identity, _)| identity.as_str()).collect::<Vec<_>>(),
+                   vec!["192.168.1.10", "RINCON_A", "RINCON_B"]);
+        assert_eq!(devices.ips(), vec!["192.168.1.10".parse::<IpAddr>().unwrap(), "192.168.1.20".parse().unwrap(),
+                                       "192.168.1.30".parse().unwrap()]);
+        assert!(devices.contains_uuid("RINCON_A"));
+        assert!(devices.contains_uuid("192.168.1.10"));
+        assert!(!devices.contains_uuid("RINCON_C"));
+        assert!(devices.contains_ip(&"192.168.1.20".parse().unwrap()));
+        assert!(!devices.contains_ip(&"192.168.1.40".parse().unwrap()));
+        assert_eq!(devices.ip("RINCON_B"), Some("192.168.1.20".parse().unwrap()));
+        assert_eq!((devices.len(), devices.is_empty()), (3, false));
+        assert!(DeviceSet::new().is_empty());
+    }
+
+    #[test]
+    fn diffs() {
+        let cases = [
+            // Nothing changed
+            (&[("RINCON_A", "192.168.1.20")][..], &[("RINCON_A", "192.168.1.20")][..], &[][..], &[][..], &[][..]),
+            (&[], &[], &[], &[], &[]),
+            (&[], &[("RINCON_A", "192.168.1.20")], &[("RINCON_A", "192.168.1.20")], &[], &[]),
+            (&[("RINCON_A", "192.168.1.20")], &[], &[], &[("RINCON_A", "192.168.1.20")], &[]),
+            // A new lease
+            (&[("RINCON_A", "192.168.1.20")], &[("RINCON_A", "192.168.1.21")], &[], &[],
+             &[("RINCON_A", "192.168.1.20", "192.168.1.21")]),
+            // Another device got the old address
+            (&[("RINCON_A", "192.168.1.20"), ("RINCON_C", "192.168.1.30")],
+             &[("RINCON_B", "192.168.1.20"), ("RINCON_C", "192.168.1.31")],
+             &[("RINCON_B", "192.168.1.20")], &[("RINCON_A", "192.168.1.20")],
+             &[("RINCON_C", "192.168.1.30", "192.168.1.31")])
+        ];
+        for &(older, newer, joined, left, changed) in &cases {
+            let diff = set(older).diff(&set(newer));
+            let expected = DeviceSetDiff {
+                joined: entries(joined),
+                left: entries(left),
+                changed: changed.iter()
+                    .map(|&(identity, old, new)| (identity.to_string(), old.parse().unwrap(), new.parse().unwrap()))
+                    .collect()
+            };
+            assert_eq!(diff, expected, "{:?} -> {:?}", older, newer);
+            assert_eq!(diff.is_empty(), joined.is_empty() && left.is_empty() && changed.is_empty());
+        }
+    }
+
+    #[test]
+    fn merges_keep_the_addresses_of_the_newer_set() {
+        let mut devices = set(&[("RINCON_A", "192.168.1.20"), ("RINCON_B", "192.168.1.21")]);
+        devices.merge(set(&[("RINCON_B", "10.0.0.21"), ("RINCON_C", "10.0.0.22")]));
+        assert_eq!(devices, set(&[("RINCON_A", "192.168.1.20"), ("RINCON_B", "10.0.0.21"), ("RINCON_C", "10.0.0.22")]));
+
+        assert_eq!(devices.insert("RINCON_A".to_string(), "10.0.0.20".parse().unwrap()),
+                   Some("192.168.1.20".parse().unwrap()));
+        assert_eq!(devices.insert("RINCON_D".to_string(), "10.0.0.23".parse().unwrap()), None);
+        devices.extend(entries(&[("RINCON_D", "10.0.0.24")]));
+        assert_eq!(devices.ip("RINCON_D"), Some("10.0.0.24".parse().unwrap()));
+        assert_eq!((&devices).into_iter().count(), 4);
+    }
+}
@@ -0,0 +1,278 @@
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Resolver configuration with the nameservers of unix systems
+const RESOLV_CONF: &str = "/etc/resolv.conf";
+const DNS_PORT: u16 = 53;
+const TYPE_PTR: u16 = 12;
+const CLASS_IN: u16 = 1;
+/// Response code of a name without records
+const NXDOMAIN: u8 = 3;
+/// Lookups of `device::resolve_hostnames` at a time
+pub(crate) const MAX_LOOKUPS: usize = 8;
+/// Compression pointers followed while reading a name, a forged response could otherwise loop forever
+const MAX_POINTERS: usize = 16;
+
+/// Hostname of `ip` by its PTR record, asked from the first nameserver of `/etc/resolv.conf`.
+/// `None` if the address has no record, fails if the nameserver doesn't answer within `timeout`.
+pub(crate) fn reverse_lookup(ip: IpAddr, timeout: Duration) -> io::Result<Option<String>> {
+    let deadline = Instant::now() + timeout;
+    let nameserver = nameserver()?;
+    let local: SocketAddr = match nameserver {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into()
+    };
+    let socket = UdpSocket::bind(local)?;
+    // Only the nameserver's answers are received
+    socket.connect(nameserver)?;
+
+    let id = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.subsec_nanos() as u16);
+    socket.send(&query(id, &ptr_name(ip)))?;
+
+    let mut buffer = [0; 512];
+    loop {
+        let remaining = deadline.checked_duration_since(Instant::now()).filter(|remaining| !remaining.is_zero())
+            .ok_or_else(|| io::Error::new(ErrorKind::TimedOut, "The nameserver didn't answer in time"))?;
+        socket.set_read_timeout(Some(remaining))?;
+        let length = socket.recv(&mut buffer).map_err(|e| match e.kind() {
+            ErrorKind::WouldBlock => io::Error::new(ErrorKind::TimedOut, "The nameserver didn't answer in time"),
+            _ => e
+        })?;
+        // Late answers of an earlier lookup are skipped
+        if let Some(answer) = parse_response(&buffer[..length], id) {
+            return answer;
+        }
+    }
+}
+
+/// First nameserver of `/etc/resolv.conf`
+fn nameserver() -> io::Result<SocketAddr> {
+    fs::read_to_string(RESOLV_CONF)?.lines()
+        .filter_map(|line| line.trim().strip_prefix("nameserver"))
+        // A link local ipv6 nameserver has a zone (`fe80::1%eth0`), which std can't parse
+        .filter_map(|address| address.trim().parse::<IpAddr>().ok())
+        .map(|ip| SocketAddr::new(ip, DNS_PORT))
+        .next()
+        .ok_or_else(|| io::Error::new(ErrorKind::NotFound, format!("No nameserver in {}", RESOLV_CONF)))
+}
+
+/// Name of the PTR record of `ip` (`4.3.2.1.in-addr.arpa` for `1.2.3.4`)
+fn ptr_name(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, d] = ip.octets();
+            format!("{}.{}.{}.{}.in-addr.arpa", d, c, b, a)
+        }
+        IpAddr::V6(ip) => {
+            let nibbles: Vec<String> = ip.octets().iter().rev()
+                .flat_map(|byte| vec![byte & 0xf, byte >> 4])
+                .map(|nibble| format!("{:x}", nibble))
+                .collect();
+            format!("{}.ip6.arpa", nibbles.join("."))
+        }
+    }
+}
+
+/// Recursive query for the PTR record `name`
+fn query(id: u16, name: &str) -> Vec<u8> {
+    let mut query = Vec::with_capacity(name.len() + 18);
+    query.extend_from_slice(&id.to_be_bytes());
+    // Recursion desired, one question
+    query.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.split('.') {
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&TYPE_PTR.to_be_bytes());
+    query.extend_from_slice(&CLASS_IN.to_be_bytes());
+
+    query
+}
+
+/// Hostname of the first PTR record of the answer to the query `id`, `None` if `data` isn't that answer
+fn parse_response(data: &[u8], id: u16) -> Option<io::Result<Option<String>>> {
+    let invalid = || io::Error::new(ErrorKind::InvalidData, "Invalid answer of the nameserver");
+    if data.len() < 12 || u16::from_be_bytes([data[0], data[1]]) != id || data[2] & 0x80 == 0 {
+        return None;
+    }
+
+    let answer = match data[3] & 0x0f {
+        0 => ptr_record(data).ok_or_else(invalid),
+        NXDOMAIN => Ok(None),
+        code => Err(io::Error::other(format!("The nameserver answered with the error {}", code)))
+    };
+
+    Some(answer)
+}
+
+/// Hostname of the first PTR record in the answer section of `data` (a message with a complete header),
+/// `None` if the message is malformed
+fn ptr_record(data: &[u8]) -> Option<Option<String>> {
+    let count = |offset: usize| u16::from_be_bytes([data[offset], data[offset + 1]]);
+    let (questions, answers) = (count(4), count(6));
+
+    let mut offset = 12;
+    for _ in 0..questions {
+        offset = skip_name(data, offset)? + 4;
+    }
+    for _ in 0..answers {
+        offset = skip_name(data, offset)?;
+        let header = data.get(offset..offset + 10)?;
+        let kind = u16::from_be_bytes([header[0], header[1]]);
+        let length = usize::from(u16::from_be_bytes([header[8], header[9]]));
+        offset += 10;
+        if kind == TYPE_PTR {
+            return read_name(data, offset).map(Some);
+        }
+        offset += length;
+    }
+
+    Some(None)
+}
+
+/// Offset after the name at `offset`
+fn skip_name(data: &[u8], mut offset: usize) -> Option<usize> {
+    loop {
+        let length = *data.get(offset)?;
+        match length {
+            0 => return Some(offset + 1),
+            // A compression pointer ends the name
+            _ if length & 0xc0 == 0xc0 => return Some(offset + 2),
+            _ => offset += 1 + usize::from(length)
+        }
+    }
+}
+
+/// Name at `offset` without the trailing dot, following compression pointers
+fn read_name(data: &[u8], mut offset: usize) -> Option<String> {
+    let mut labels: Vec<String> = Vec::new();
+    let mut pointers = 0;
+    loop {
+        let length = *data.get(offset)?;
+        if length == 0 {
+            break;
+        }
+        if length & 0xc0 == 0xc0 {
+            pointers += 1;
+            if pointers > MAX_POINTERS {
+                return None;
+            }
+            offset = usize::from(u16::from_be_bytes([length & 0x3f, *data.get(offset + 1)?]));
+            continue;
+        }
+
+        let label = data.get(offset + 1..offset + 1 + usize::from(length))?;
+        labels.push(String::from_utf8_lossy(label).into_owned());
+        offset += 1 + usize::from(length);
+    }
+
+    Some(labels.join("."))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: u16 = 0x1234;
+
+    /// Answer of the nameserver to the lookup of 192.168.0.1 with the response code `code`, a PTR record with `rdata`
+    /// unless it's empty
+    fn response(code: u8, rdata: &[u8]) -> Vec<u8> {
+        let mut data = query(ID, &ptr_name(IpAddr::V4(Ipv4Addr::new(192, 168, 0, 1))));
+        data[2] |= 0x80;
+        data[3] = 0x80 | code;
+        if !rdata.is_empty() {
+            data[7] = 1;
+            // The name points to the question
+            data.extend_from_slice(&[0xc0, 12]);
+            data.extend_from_slice(&TYPE_PTR.to_be_bytes());
+            data.extend_from_slice(&CLASS_IN.to_be_bytes());
+            data.extend_from_slice(&[0, 0, 0x0e, 0x10]);
+            data.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+            data.extend_from_slice(rdata);
+        }
+
+        data
+    }
+
+    /// `name` in the wire format
+    fn encode(name: &str) -> Vec<u8> {
+        let mut data = Vec::new();
+        for label in name.split('.') {
+            data.push(label.len() as u8);
+            data.extend_from_slice(label.as_bytes());
+        }
+        data.push(0);
+
+        data
+    }
+
+    /// Whether `answer` is the one of a malformed message
+    fn is_invalid(answer: Option<io::Result<Option<String>>>) -> bool {
+        matches!(answer, Some(Err(ref e)) if e.kind() == ErrorKind::InvalidData)
+    }
+
+    #[test]
+    fn ptr_names() {
+        assert_eq!(ptr_name(IpAddr::V4(Ipv4Addr::new(192, 168, 0, 1))), "1.0.168.192.in-addr.arpa");
+        assert_eq!(ptr_name("2001:db8::1".parse().unwrap()),
+                   format!("1.{}8.b.d.0.1.0.0.2.ip6.arpa", "0.".repeat(23)));
+    }
+
+    #[test]
+    fn reads_the_hostname() {
+        let answer = parse_response(&response(0, &encode("speaker.local")), ID);
+        assert_eq!(answer.unwrap().unwrap(), Some("speaker.local".to_string()));
+    }
+
+    #[test]
+    fn follows_compression_pointers() {
+        // "in-addr" starts after the labels 1, 0, 168 and 192 of the question
+        let mut rdata = encode("speaker");
+        rdata.pop();
+        rdata.extend_from_slice(&[0xc0, 24]);
+
+        let answer = parse_response(&response(0, &rdata), ID);
+        assert_eq!(answer.unwrap().unwrap(), Some("speaker.in-addr.arpa".to_string()));
+    }
+
+    #[test]
+    fn rejects_looping_pointers() {
+        let offset = response(0, &[]).len() + 12;
+        let data = response(0, &[0xc0, offset as u8]);
+        assert_eq!(data[offset..], [0xc0, offset as u8]);
+
+        assert!(is_invalid(parse_response(&data, ID)));
+    }
+
+    #[test]
+    fn rejects_truncated_answers() {
+        let data = response(0, &encode("speaker.local"));
+        for length in 12..data.len() {
+            assert!(is_invalid(parse_response(&data[..length], ID)), "length {}", length);
+        }
+        assert!(parse_response(&data[..11], ID).is_none());
+    }
+
+    #[test]
+    fn unknown_names_have_no_hostname() {
+        assert_eq!(parse_response(&response(NXDOMAIN, &[]), ID).unwrap().unwrap(), None);
+        assert_eq!(parse_response(&response(0, &[]), ID).unwrap().unwrap(), None);
+    }
+
+    #[test]
+    fn other_response_codes_fail() {
+        let answer = parse_response(&response(2, &[]), ID).unwrap();
+        assert_eq!(answer.unwrap_err().kind(), ErrorKind::Other);
+    }
+
+    #[test]
+    fn skips_other_messages() {
+        let data = response(0, &encode("speaker.local"));
+        assert!(parse_response(&data, ID + 1).is_none());
+        assert!(parse_response(&query(ID, "1.0.168.192.in-addr.arpa"), ID).is_none());
+    }
+}
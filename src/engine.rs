@@ -0,0 +1,1471 @@
+use audit::{AuditRecord, Direction};
+use cancel::CancellationToken;
+use config::{DiscoveryConfig, MAX_MX};
+use description::{self, DescriptionCache, DeviceDescription};
+use device::{DeviceInfo, DeviceKind};
+use error::{DiscoveryError, Result};
+use fetcher::{parse_http_url, HttpFetcher};
+use filter::SsdpHeaders;
+use hexdump::hexdump;
+use log::Level;
+use parallel::run_limited;
+use ratelimit::RateLimiter;
+use result::{DiscoveryResult, DiscoveryStats, RawResponse, SearchMethod, Sighting};
+use retry;
+use sweep;
+use ssdp::{self, SsdpResponse};
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::cmp;
+use std::io::{self, ErrorKind};
+#[cfg(any(feature = "tokio", feature = "async-io"))]
+use std::net::UdpSocket;
+use std::net::{IpAddr, SocketAddr};
+use std::ops::ControlFlow;
+use std::sync::{Arc, mpsc};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use topology::{self, ZoneGroup};
+use transport::{self, LocalNetwork, Transport};
+use upnp::{UpnpHeaders, SSDP_PORT, ZONE_PLAYER};
+
+/// Bounds of the idle time of an adaptive discovery
+const MIN_IDLE: Duration = Duration::from_millis(200);
+const MAX_IDLE: Duration = Duration::from_secs(2);
+
+/// Time a single receive waits for a packet until the timeout and device count are checked again
+pub(crate) const RECEIVE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Timeout for connecting and each read/write while verifying a responder
+const VERIFY_TIMEOUT: Duration = Duration::from_secs(2);
+/// Timeout for connecting and each read/write while fetching the zone groups
+const TOPOLOGY_TIMEOUT: Duration = Duration::from_secs(2);
+/// Wait after a failed receive before the next one
+const FAILURE_DELAY: Duration = Duration::from_millis(50);
+/// Transient receive errors in a row after which the socket is recreated, see `Engine::recover`
+const PERSISTENT_FAILURES: u32 = 3;
+/// Sockets a single discovery recreates at most
+const MAX_RECOVERIES: u32 = 3;
+
+/// Response of a sonos device
+pub(crate) struct Response {
+    pub(crate) addr: SocketAddr,
+    /// Payload of the response with its parsed status line and headers
+    pub(crate) ssdp: SsdpResponse,
+    /// Time of the discovery's clock the response was received
+    pub(crate) received: Instant,
+    /// Wall clock time the response was received
+    pub(crate) received_at: SystemTime,
+    /// Time from sending the search until the response was received
+    pub(crate) latency: Duration
+}
+
+impl Response {
+    /// Copy of the packet for `DiscoveryConfig::keep_raw`
+    pub(crate) fn raw(&self) -> RawResponse {
+        RawResponse {
+            addr: self.addr,
+            data: self.ssdp.as_bytes().to_vec(),
+            received_at: self.received_at
+        }
+    }
+
+    /// UPnP 1.1 headers (`BOOTID.UPNP.ORG`, `CONFIGID.UPNP.ORG`, `SEARCHPORT.UPNP.ORG`)
+    pub(crate) fn upnp_headers(&self) -> UpnpHeaders {
+        UpnpHeaders::parse(|name| self.ssdp.header(name))
+    }
+
+    /// Unique id of the device (`RINCON_...`) from the `USN` header (`uuid:RINCON_...::urn:...`).
+    /// The sonos sub-devices of an `ssdp:all` search (`RINCON_..._MR` of the MediaRenderer, `RINCON_..._MS` of the
+    /// MediaServer) have the one of their ZonePlayer.
+    pub(crate) fn uuid(&self) -> Option<String> {
+        self.ssdp.uuid().map(|uuid| {
+            let root = uuid.strip_suffix("_MR").or_else(|| uuid.strip_suffix("_MS"))
+                .filter(|_| uuid.starts_with("RINCON_"));
+            root.unwrap_or(uuid).to_string()
+        })
+    }
+}
+
+/// State of a single search, created by `Discover::start` for each run
+pub(crate) struct Engine {
+    config: DiscoveryConfig,
+    duplicate_log: Arc<RateLimiter>,
+    /// Descriptions of previous runs for the verification, `None` without caching
+    descriptions: Option<Arc<DescriptionCache>>,
+    /// Search socket, or the socket joined to the multicast group of a passive engine. Replaced by `recover`.
+    socket: RefCell<Box<dyn Transport>>,
+    /// Whether `socket` is the one of a passive engine
+    passive: bool,
+    /// Sockets recreated by `recover` so far
+    recoveries: Cell<u32>,
+    /// Failed receives in a row
+    failures: Cell<u32>,
+    /// Rejected responses of the collected searches, only with `DiscoveryConfig::keep_raw`
+    rejected: RefCell<Vec<RawResponse>>,
+    /// Receive buffer of `receive_buffer_size` bytes, reused for every packet
+    buffer: RefCell<Vec<u8>>,
+    /// Time the engine was created, the start of the discovery for the metrics sink
+    started: Instant
+}
+
+impl Engine {
+    pub(crate) fn new(mut config: DiscoveryConfig, duplicate_log: Arc<RateLimiter>,
+                      descriptions: Option<Arc<DescriptionCache>>) -> Result<Self> {
+        transport::select_interface(&mut config);
+        let started = config.clock.now();
+        let socket = config.transport.search(&config)?;
+
+        Ok(Engine {
+            config,
+            duplicate_log,
+            descriptions,
+            socket: RefCell::new(socket),
+            passive: false,
+            recoveries: Cell::new(0),
+            failures: Cell::new(0),
+            rejected: RefCell::new(Vec::new()),
+            buffer: RefCell::new(Vec::new()),
+            started
+        })
+    }
+
+    /// Engine which only listens for the announcements (`NOTIFY`) of the devices on the multicast address,
+    /// see `Discover::listen`
+    pub(crate) fn passive(mut config: DiscoveryConfig, duplicate_log: Arc<RateLimiter>,
+                          descriptions: Option<Arc<DescriptionCache>>) -> Result<Self> {
+        transport::select_interface(&mut config);
+        let started = config.clock.now();
+        let socket = config.transport.passive(&config)?;
+
+        Ok(Engine {
+            config,
+            duplicate_log,
+            descriptions,
+            socket: RefCell::new(socket),
+            passive: true,
+            recoveries: Cell::new(0),
+            failures: Cell::new(0),
+            rejected: RefCell::new(Vec::new()),
+            buffer: RefCell::new(Vec::new()),
+            started
+        })
+    }
+
+    /// Sends the search message to the defined socket, or the broadcast fallback if that fails.
+    pub(crate) fn send_search(&self) -> Result<SearchMethod> {
+        let multicast_addr = self.config.multicast_addr;
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("send_search", addr = %multicast_addr,
+                                         st = %self.config.effective_search_target()).entered();
+        let player_search = search_message(&self.config, multicast_addr);
+        let player_search = player_search.as_bytes();
+        let socket = self.socket.borrow();
+
+        let sent = transport::send_multicast(&**socket, &self.config, || {
+            retry::retry(&*self.config.retry_policy, &*self.config.clock,
+                         || socket.send_to(player_search, multicast_addr))
+        });
+        let error = match sent {
+            Ok(_) => {
+                debug!("Sent the search for {} to {}", self.config.effective_search_target(), multicast_addr);
+                record_sent(&self.config, multicast_addr, player_search);
+                send_additional_groups(&self.config, &**socket, |search, group| {
+                    retry::retry(&*self.config.retry_policy, &*self.config.clock, || socket.send_to(search, group))
+                });
+                return Ok(SearchMethod::Multicast);
+            }
+            Err(e) => e
+        };
+
+        let broadcast_addr = match self.config.broadcast_fallback {
+            Some(address) => address,
+            None => return Err(error)
+        };
+        warn!("Sending the search to {} failed ({}), falling back to {}", multicast_addr, error, broadcast_addr);
+        socket.set_broadcast(true).map_err(DiscoveryError::SocketCreate)?;
+        retry::retry(&*self.config.retry_policy, &*self.config.clock,
+                     || socket.send_to(player_search, broadcast_addr)).map_err(DiscoveryError::Send)?;
+        debug!("Sent the search for {} to {}", self.config.effective_search_target(), broadcast_addr);
+        record_sent(&self.config, broadcast_addr, player_search);
+
+        Ok(SearchMethod::Broadcast)
+    }
+
+    /// Sends the search again the same way as the first one (`method`), failures of the additional multicast groups
+    /// are only logged
+    fn resend_search(&self, method: SearchMethod) -> Result<()> {
+        let multicast_addr = self.config.multicast_addr;
+        let player_search = search_message(&self.config, multicast_addr);
+        let player_search = player_search.as_bytes();
+        let socket = self.socket.borrow();
+        let send = |address| retry::retry(&*self.config.retry_policy, &*self.config.clock,
+                                          || socket.send_to(player_search, address));
+
+        let (address, sent) = match (method, self.config.broadcast_fallback) {
+            (SearchMethod::Broadcast, Some(broadcast_addr)) =>
+                (broadcast_addr, send(broadcast_addr).map(|_| ()).map_err(DiscoveryError::Send)),
+            _ => (multicast_addr, transport::send_multicast(&**socket, &self.config, || send(multicast_addr)))
+        };
+        if method == SearchMethod::Multicast {
+            send_additional_groups(&self.config, &**socket, |search, group| socket.send_to(search, group));
+        }
+        match sent {
+            Ok(()) => {
+                debug!("Resent the search to {}", address);
+                record_sent(&self.config, address, player_search);
+                Ok(())
+            }
+            Err(e) => {
+                warn!("Resending the search to {} failed: {}", address, e);
+                Err(e)
+            }
+        }
+    }
+
+    /// Receives the next packet of the search of `collector`, resends the search when it's due.
+    ///
+    /// A failed receive doesn't end the search: transient errors (`retry::is_transient`) wait a moment, the socket
+    /// keeps working once the network is back. The socket is recreated (`recover`) after other errors, after
+    /// `PERSISTENT_FAILURES` transient ones in a row and after a failed resend.
+    pub(crate) fn next_packet(&self, collector: &mut Collector) -> Option<(SocketAddr, Box<[u8]>)> {
+        if collector.resend_due() && self.resend_search(collector.method()).is_err() {
+            self.recover(collector);
+        }
+
+        let error = match self.try_receive(collector.receive_timeout()) {
+            Ok(packet) => {
+                self.failures.set(0);
+                return packet;
+            }
+            Err(e) => e
+        };
+        // E.g. an icmp port unreachable (windows) or a truncated packet, the search goes on
+        debug!("Receiving failed: {}", error);
+        let failures = self.failures.get() + 1;
+        self.failures.set(failures);
+        if (!retry::is_transient(&error) || failures >= PERSISTENT_FAILURES) && self.recover(collector) {
+            return None;
+        }
+        // A socket which fails right away would spin until the timeout
+        self.config.clock.sleep(cmp::min(FAILURE_DELAY, collector.remaining()));
+
+        None
+    }
+
+    /// Replaces the socket by a new one of the transport (e.g. after the interface went down and came back) and sends
+    /// the search again, at most `MAX_RECOVERIES` times per discovery and only with `DiscoveryConfig::recreate_socket`.
+    /// Unicast probes aren't sent again. Returns whether the socket was replaced.
+    fn recover(&self, collector: &mut Collector) -> bool {
+        let method = collector.method();
+        if !self.config.recreate_socket || method == SearchMethod::Unicast || self.recoveries.get() >= MAX_RECOVERIES {
+            return false;
+        }
+        self.recoveries.set(self.recoveries.get() + 1);
+
+        let socket = if self.passive {
+            self.config.transport.passive(&self.config)
+        } else {
+            self.config.transport.search(&self.config)
+        };
+        let socket = match socket {
+            Ok(socket) => socket,
+            Err(e) => {
+                warn!("Couldn't recreate the socket: {}", e);
+                return false;
+            }
+        };
+        if method == SearchMethod::Broadcast {
+            if let Err(e) = socket.set_broadcast(true) {
+                warn!("Couldn't allow broadcasts on the recreated socket: {}", e);
+                return false;
+            }
+        }
+        *self.socket.borrow_mut() = socket;
+        self.failures.set(0);
+        collector.recovered();
+        warn!("Recreated the socket after it failed");
+
+        if method != SearchMethod::Passive {
+            // Already logged, the next resend or recovery tries again
+            let _ = self.resend_search(method);
+        }
+
+        true
+    }
+
+    /// Sends the search directly to port 1900 of every address in `ips`, fails only if no search could be sent
+    fn send_probes(&self, ips: &[IpAddr]) -> Result<()> {
+        let mut sent = false;
+        let mut last_error = None;
+        let socket = self.socket.borrow();
+        for &ip in ips {
+            let address = SocketAddr::new(ip, SSDP_PORT);
+            let probe = search_message(&self.config, address);
+            let probe = probe.as_bytes();
+            match retry::retry(&*self.config.retry_policy, &*self.config.clock, || socket.send_to(probe, address)) {
+                Ok(_) => {
+                    debug!("Sent the search to {}", address);
+                    record_sent(&self.config, address, probe);
+                    sent = true;
+                }
+                Err(e) => {
+                    warn!("Sending the search to {} failed: {}", address, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        match last_error {
+            Some(e) if !sent => Err(DiscoveryError::Send(e)),
+            _ => Ok(())
+        }
+    }
+
+    /// Keeps the responses of devices whose `LOCATION` is the description of a sonos device.
+    /// Each device gets one request, at most `max_requests` run at a time.
+    fn verify(&self, responses: Vec<Response>, timeout: Duration, stats: &mut DiscoveryStats) -> Vec<Response> {
+        let mut locations: Vec<(IpAddr, Option<String>, Option<String>)> = Vec::new();
+        for response in &responses {
+            if !locations.iter().any(|&(ip, _, _)| ip == response.addr.ip()) {
+                locations.push((response.addr.ip(), response.ssdp.location().map(str::to_string), response.uuid()));
+            }
+        }
+
+        let ips: Vec<IpAddr> = locations.iter().map(|&(ip, _, _)| ip).collect();
+        let fetcher: Arc<dyn HttpFetcher> = Arc::clone(&self.config.http_fetcher);
+        let cache = self.descriptions.clone();
+        let fetches = run_limited(locations, self.config.max_requests, move |(ip, location, uuid)| {
+            let location = location.ok_or_else(|| "No LOCATION header".to_string())?;
+            description::fetch_location(&*fetcher, cache.as_deref(), uuid.as_deref(), &location, ip, timeout)
+                .map_err(|e| format!("Couldn't fetch {}: {}", location, e))
+        });
+
+        let mut verified: Vec<IpAddr> = Vec::new();
+        for (ip, fetch) in ips.into_iter().zip(fetches) {
+            match fetch {
+                Ok(Ok(ref description)) if description.is_sonos() => verified.push(ip),
+                Ok(Ok(_)) => warn!("Dropping {}, its description isn't from a sonos device", ip),
+                Ok(Err(e)) => warn!("Dropping {}, it couldn't be verified: {}", ip, e),
+                Err(_) => warn!("Dropping {}, verifying it panicked", ip)
+            }
+        }
+
+        let (verified, unverified): (Vec<Response>, Vec<Response>) = responses.into_iter()
+            .partition(|response| verified.contains(&response.addr.ip()));
+        stats.unverified += unverified.len();
+
+        verified
+    }
+
+    /// `timeout` for a request after `elapsed` of the discovery, capped by the remaining time budget
+    pub(crate) fn fetch_timeout(&self, timeout: Duration, elapsed: Duration) -> Duration {
+        match self.config.budget {
+            Some(budget) => cmp::min(timeout, budget.checked_sub(elapsed).unwrap_or_default()),
+            None => timeout
+        }
+    }
+
+    /// Sends the search and collects responses until the timeout or the device count is reached
+    pub(crate) fn run(self) -> Result<DiscoveryResult> {
+        self.run_with(|_| ControlFlow::Continue(()))
+    }
+
+    /// Like `run`, calls `on_device` for every new device while collecting (see `collect`)
+    pub(crate) fn run_with<F: FnMut(&Response) -> ControlFlow<()>>(self, on_device: F) -> Result<DiscoveryResult> {
+        let collector = self.begin()?;
+        let (responses, stats) = self.collect(collector, on_device);
+
+        Ok(self.result(responses, stats))
+    }
+
+    /// Collects the announcements of the devices until the timeout or the device count is reached, without a search
+    pub(crate) fn listen(self) -> Result<DiscoveryResult> {
+        let time = self.config.clock.now();
+        let collector = Collector::new(self.config.clone(), Arc::clone(&self.duplicate_log), SearchMethod::Passive, time);
+        let (responses, stats) = self.collect(collector, |_| ControlFlow::Continue(()));
+
+        Ok(self.result(responses, stats))
+    }
+
+    /// Sends the search to every address in `ips` instead of the multicast address (see `Discover::probe`),
+    /// only answers of these addresses are accepted
+    pub(crate) fn probe(self, ips: &[IpAddr]) -> Result<DiscoveryResult> {
+        let (responses, stats) = self.probe_responses(ips)?;
+
+        Ok(self.result(responses, stats))
+    }
+
+    /// Responses to the unicast searches of `probe`, only those of `ips`
+    pub(crate) fn probe_responses(&self, ips: &[IpAddr]) -> Result<(Vec<Response>, DiscoveryStats)> {
+        let time = self.config.clock.now();
+        self.send_probes(ips)?;
+
+        let collector = Collector::new(self.config.clone(), Arc::clone(&self.duplicate_log), SearchMethod::Unicast, time);
+        let (mut responses, stats) = self.collect(collector, |_| ControlFlow::Continue(()));
+        responses.retain(|response| ips.contains(&response.addr.ip()));
+
+        Ok((responses, stats))
+    }
+
+    /// Stops the search at the first device and completes the result with the members of its household's topology,
+    /// see `Discover::start_fast`.
+    ///
+    /// The zone groups of the known devices are fetched at the same time, whichever is first ends the other.
+    pub(crate) fn household(self, known: Vec<IpAddr>) -> Result<DiscoveryResult> {
+        let mut collector = self.begin()?;
+        let known_groups = if known.is_empty() {
+            None
+        } else {
+            let stop = CancellationToken::new();
+            collector.stop_on(stop.clone());
+            Some((self.known_topology(known, stop.clone()), stop))
+        };
+        let (responses, stats) = self.collect(collector, |_| ControlFlow::Break(()));
+
+        let known_groups = known_groups.and_then(|(receiver, stop)| match receiver.try_recv() {
+            Ok(groups) => Some(groups),
+            // Nobody answered the search, the known devices are the only chance left
+            Err(_) if responses.is_empty() => receiver.recv().ok(),
+            Err(_) => {
+                // The search was first, the thread gives up before its next request instead of outliving the search
+                stop.cancel();
+                None
+            }
+        });
+        let (groups, warnings) = match known_groups {
+            Some(Some(groups)) => (groups, Vec::new()),
+            _ => {
+                let mut warnings = warnings(&self.config, &responses, &stats);
+                let groups = self.topology(&responses, stats.elapsed, &mut warnings);
+                (groups, warnings)
+            }
+        };
+
+        let rejected = self.rejected.take();
+        let elapsed = self.config.clock.now().duration_since(self.started);
+        let mut result = discovery_result(&responses, rejected, groups, stats, warnings, self.config, elapsed);
+        result.add_group_members();
+
+        Ok(result)
+    }
+
+    /// Fetches the zone groups from the first of `known` which answers on another thread, cancels `stop` on success.
+    /// Stops before the next device once `stop` is cancelled by the search.
+    fn known_topology(&self, known: Vec<IpAddr>, stop: CancellationToken) -> mpsc::Receiver<Option<Vec<ZoneGroup>>> {
+        let fetcher = Arc::clone(&self.config.http_fetcher);
+        let include_invisible = self.config.include_invisible;
+        let timeout = self.fetch_timeout(TOPOLOGY_TIMEOUT, Duration::default());
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let groups = known.into_iter()
+                .take_while(|_| !stop.is_cancelled())
+                .filter_map(|ip| match topology::fetch(&*fetcher, ip, timeout, include_invisible) {
+                    Ok(groups) => Some(groups),
+                    Err(e) => {
+                        debug!("Couldn't fetch the zone groups from the known device {}: {}", ip, e);
+                        None
+                    }
+                })
+                .next();
+            if groups.is_some() {
+                stop.cancel();
+            }
+            let _ = sender.send(groups);
+        });
+
+        receiver
+    }
+
+    /// Result of the search with the warnings and the topology, or of the sweep fallback if nobody answered
+    fn result(self, responses: Vec<Response>, stats: DiscoveryStats) -> DiscoveryResult {
+        let mut warnings = warnings(&self.config, &responses, &stats);
+        let groups = if self.config.topology && !stats.cancelled {
+            self.topology(&responses, stats.elapsed, &mut warnings)
+        } else {
+            Vec::new()
+        };
+        let swept: Vec<(IpAddr, Option<String>)> = if responses.is_empty() {
+            self.sweep(&stats, &mut warnings).into_iter()
+                .map(|(ip, description)| (ip, description.udn.map(|udn| udn.trim_start_matches("uuid:").to_string())))
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        let rejected = self.rejected.take();
+        let elapsed = self.config.clock.now().duration_since(self.started);
+        let mut result = discovery_result(&responses, rejected, groups, stats, warnings, self.config, elapsed);
+        result.add_swept(swept);
+
+        result
+    }
+
+    /// Sonos devices of the sweep fallback (`DiscoveryConfig::sweep_fallback`) after a search nobody answered
+    fn sweep(&self, stats: &DiscoveryStats, warnings: &mut Vec<String>) -> Vec<(IpAddr, DeviceDescription)> {
+        let searched = stats.method == SearchMethod::Multicast || stats.method == SearchMethod::Broadcast;
+        let timeout = self.fetch_timeout(VERIFY_TIMEOUT, stats.elapsed);
+        if !self.config.sweep_fallback || !searched || stats.cancelled || timeout == Duration::from_secs(0) {
+            return Vec::new();
+        }
+        let network = match sweep::network(&self.config) {
+            Some(network) => network,
+            None => {
+                warnings.push("No interface to derive the network of the sweep fallback from".to_string());
+                return Vec::new();
+            }
+        };
+
+        debug!("Nobody answered the search, sweeping {} for devices on port {}", network, description::DEVICE_PORT);
+        let devices = sweep::sweep(&self.config, network, self.descriptions.as_ref(), timeout);
+        if !devices.is_empty() {
+            warnings.push(format!("Found {} devices by sweeping {} for port {}, the search went unanswered",
+                                  devices.len(), network, description::DEVICE_PORT));
+        }
+
+        devices
+    }
+
+    /// `sweep` for the discoveries returning `DeviceInfo`s, which have no warnings
+    pub(crate) fn swept_devices(&self, stats: &DiscoveryStats) -> Vec<DeviceInfo> {
+        self.sweep(stats, &mut Vec::new()).into_iter()
+            .map(|(ip, description)| DeviceInfo::from_description(ip, description))
+            .collect()
+    }
+
+    /// Zone groups of every household, any device of a household can answer for all of them
+    fn topology(&self, responses: &[Response], elapsed: Duration, warnings: &mut Vec<String>) -> Vec<ZoneGroup> {
+        let mut households: Vec<(Option<String>, Vec<IpAddr>)> = Vec::new();
+        for response in responses {
+            let household = response.ssdp.header("X-RINCON-HOUSEHOLD");
+            let ip = response.addr.ip();
+            match households.iter_mut().find(|entry| entry.0.as_deref() == household) {
+                Some(entry) => if !entry.1.contains(&ip) {
+                    entry.1.push(ip)
+                },
+                None => households.push((household.map(str::to_string), vec![ip]))
+            }
+        }
+
+        let timeout = self.fetch_timeout(TOPOLOGY_TIMEOUT, elapsed);
+        let mut groups = Vec::new();
+        for (household, ips) in households {
+            let mut last_error = None;
+            // Asks the next device if one doesn't answer
+            for ip in ips {
+                match topology::fetch(&*self.config.http_fetcher, ip, timeout, self.config.include_invisible) {
+                    Ok(household_groups) => {
+                        groups.extend(household_groups);
+                        last_error = None;
+                        break;
+                    }
+                    Err(e) => last_error = Some(e)
+                }
+            }
+            if let Some(e) = last_error {
+                warnings.push(format!("Couldn't fetch the zone groups of household {}: {}",
+                                      household.as_deref().unwrap_or("unknown"), e));
+            }
+        }
+
+        groups
+    }
+
+    /// Sends the search and collects the responses of sonos devices until the timeout or the device count is reached
+    pub(crate) fn search(&self) -> Result<(Vec<Response>, DiscoveryStats)> {
+        let collector = self.begin()?;
+
+        Ok(self.collect(collector, |_| ControlFlow::Continue(())))
+    }
+
+    /// Sends the search, the returned collector receives its responses (`collect`)
+    pub(crate) fn begin(&self) -> Result<Collector> {
+        let time = self.config.clock.now();
+        let method = self.send_search()?;
+
+        Ok(Collector::new(self.config.clone(), Arc::clone(&self.duplicate_log), method, time))
+    }
+
+    /// Receives the responses of a search started with `begin`.
+    ///
+    /// `on_device` is called with the first response of every device as soon as it arrives (before the verification),
+    /// `ControlFlow::Break` stops the search.
+    pub(crate) fn collect<F: FnMut(&Response) -> ControlFlow<()>>(&self, mut collector: Collector, mut on_device: F)
+                                                                  -> (Vec<Response>, DiscoveryStats) {
+        let clock = Arc::clone(&self.config.clock);
+        let time = collector.time;
+        #[cfg(feature = "tracing")]
+        let span = tracing::debug_span!("receive", method = ?collector.method()).entered();
+
+        while !collector.finished() {
+            let (addr, data) = match self.next_packet(&mut collector) {
+                Some(packet) => packet,
+                None => continue
+            };
+            if let Some(response) = collector.receive(addr, data) {
+                if let ControlFlow::Break(()) = on_device(response) {
+                    break;
+                }
+            }
+        }
+        self.rejected.borrow_mut().append(&mut collector.take_rejected());
+        let (mut devices, mut stats) = collector.finish();
+        #[cfg(feature = "tracing")]
+        drop(span);
+        if self.config.verify && !stats.cancelled {
+            let fetch_timeout = self.fetch_timeout(VERIFY_TIMEOUT, clock.now().duration_since(time));
+            devices = self.verify(devices, fetch_timeout, &mut stats);
+        }
+        stats.elapsed = clock.now().duration_since(time);
+
+        (devices, stats)
+    }
+
+    /// Waits up to `timeout` for a packet on the calling thread (`Transport::recv_from`), `None` is the timeout
+    pub(crate) fn try_receive(&self, timeout: Duration) -> io::Result<Option<(SocketAddr, Box<[u8]>)>> {
+        // A read timeout of zero is rejected by udp sockets, the caller checks the timeout and resends anyway
+        if timeout == Duration::default() {
+            return Ok(None);
+        }
+
+        let mut buffer = self.buffer.borrow_mut();
+        buffer.resize(self.config.receive_buffer_size, 0);
+        match self.socket.borrow().recv_from(&mut buffer, timeout) {
+            // The response keeps the packet, it's the one copy out of the reused buffer
+            Ok((length, addr)) => Ok(Some((addr, buffer[..length].into()))),
+            // The timeout, unix reports it as `WouldBlock` and windows as `TimedOut`
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => Ok(None),
+            Err(e) => Err(e)
+        }
+    }
+
+    /// Sends `data` to `address` on the search socket, used by `SearchSocket::send_message`
+    pub(crate) fn send_to(&self, data: &[u8], address: SocketAddr) -> Result<()> {
+        retry::retry(&*self.config.retry_policy, &*self.config.clock, || self.socket.borrow().send_to(data, address))
+            .map_err(DiscoveryError::Send)?;
+        record_sent(&self.config, address, data);
+
+        Ok(())
+    }
+
+    pub(crate) fn config(&self) -> &DiscoveryConfig {
+        &self.config
+    }
+}
+
+/// Search message of `config` sent to `host` (`ssdp::search_request` with `effective_mx` and
+/// `effective_search_target`)
+pub(crate) fn search_message(config: &DiscoveryConfig, host: SocketAddr) -> String {
+    ssdp::search_request(host, config.effective_mx(), config.effective_search_target())
+}
+
+/// Sends the search with `send` to the `additional_multicast_addrs` of `config` after it was sent to the multicast
+/// address, failures are only logged
+fn send_additional_groups<F>(config: &DiscoveryConfig, socket: &dyn Transport, send: F)
+    where F: Fn(&[u8], SocketAddr) -> io::Result<usize>
+{
+    for &group in &config.additional_multicast_addrs {
+        let search = search_message(config, group);
+        let search = search.as_bytes();
+        match transport::send_multicast(socket, config, || send(search, group)) {
+            Ok(()) => {
+                debug!("Sent the search for {} to {}", config.effective_search_target(), group);
+                record_sent(config, group, search);
+            }
+            Err(e) => warn!("Sending the search to {} failed: {}", group, e)
+        }
+    }
+}
+
+/// Counts a sent packet for the metrics sink of `config` and passes it to the audit sink
+pub(crate) fn record_sent(config: &DiscoveryConfig, peer: SocketAddr, data: &[u8]) {
+    if let Some(ref sink) = config.metrics_sink {
+        sink.packet_sent();
+    }
+    audit(config, Direction::Sent, peer, data);
+}
+
+/// Passes a sent or received packet to the audit sink of `config`
+pub(crate) fn audit(config: &DiscoveryConfig, direction: Direction, peer: SocketAddr, data: &[u8]) {
+    if let Some(ref sink) = config.audit_sink {
+        sink.record(&AuditRecord {
+            timestamp: SystemTime::now(),
+            direction,
+            peer,
+            data
+        });
+    }
+}
+
+/// Filters the received packets of a single search and decides when the search is finished,
+/// independent of how the packets are received
+pub(crate) struct Collector {
+    config: DiscoveryConfig,
+    duplicate_log: Arc<RateLimiter>,
+    /// Every response of a sonos device, several per device are possible
+    devices: Vec<Response>,
+    /// Addresses of the devices which answered so far
+    seen: HashSet<IpAddr>,
+    stats: DiscoveryStats,
+    /// Time the search was sent
+    time: Instant,
+    /// Timeout of the search, capped by the time budget
+    timeout: Duration,
+    last_response: Instant,
+    /// Resends of the search which are still due, 0 for passive and unicast searches
+    resends: u32,
+    next_resend: Instant,
+    /// Ends the search early without counting as cancelled, see `Engine::household`
+    stop: Option<CancellationToken>,
+    /// Rejected responses, only with `DiscoveryConfig::keep_raw`
+    rejected: Vec<RawResponse>,
+    /// Subnets the responses have to come from, `None` without `DiscoveryConfig::validate_source`
+    local_networks: Option<Vec<LocalNetwork>>
+}
+
+impl Collector {
+    pub(crate) fn new(config: DiscoveryConfig, duplicate_log: Arc<RateLimiter>, method: SearchMethod, time: Instant) -> Self {
+        let timeout = match config.budget {
+            Some(budget) => cmp::min(config.timeout, budget),
+            None => config.timeout
+        };
+        let resends = match method {
+            SearchMethod::Multicast | SearchMethod::Broadcast => config.resends,
+            SearchMethod::Unicast | SearchMethod::Passive => 0
+        };
+        let next_resend = time + config.resend_interval;
+        let local_networks = if config.validate_source && method != SearchMethod::Unicast {
+            match transport::local_networks(&config) {
+                Ok(networks) => Some(networks),
+                Err(e) => {
+                    debug!("Couldn't list the interfaces, the source of the responses isn't validated: {}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        Collector {
+            config,
+            duplicate_log,
+            devices: Vec::new(),
+            seen: HashSet::new(),
+            stats: DiscoveryStats {
+                method,
+                sent_at: Some(SystemTime::now()),
+                ..DiscoveryStats::default()
+            },
+            time,
+            timeout,
+            last_response: time,
+            resends,
+            next_resend,
+            stop: None,
+            rejected: Vec::new(),
+            local_networks
+        }
+    }
+
+    /// Rejected responses so far, see `DiscoveryConfig::keep_raw`
+    pub(crate) fn take_rejected(&mut self) -> Vec<RawResponse> {
+        std::mem::take(&mut self.rejected)
+    }
+
+    /// Finishes the search once `stop` is cancelled
+    pub(crate) fn stop_on(&mut self, stop: CancellationToken) {
+        self.stop = Some(stop);
+    }
+
+    /// Time left until the timeout, on the discovery's clock
+    pub(crate) fn remaining(&self) -> Duration {
+        self.timeout.checked_sub(self.config.clock.now().duration_since(self.time)).unwrap_or_default()
+    }
+
+    /// Time the next receive waits for a packet, at most `RECEIVE_TIMEOUT` and never past the timeout, the end of the
+    /// quiet period or the next resend
+    pub(crate) fn receive_timeout(&self) -> Duration {
+        let mut timeout = cmp::min(RECEIVE_TIMEOUT, self.remaining());
+        if let (Some(quiet_period), Some(_)) = (self.config.quiet_period, self.stats.first_response) {
+            let quiet_until = self.last_response + quiet_period;
+            timeout = cmp::min(timeout, quiet_until.saturating_duration_since(self.config.clock.now()));
+        }
+        if self.resends == 0 {
+            return timeout;
+        }
+
+        cmp::min(timeout, self.next_resend.saturating_duration_since(self.config.clock.now()))
+    }
+
+    /// Whether the search has to be sent again now, counts the resend as done
+    pub(crate) fn resend_due(&mut self) -> bool {
+        if self.resends == 0 || self.config.clock.now() < self.next_resend {
+            return false;
+        }
+        self.resends -= 1;
+        self.next_resend += self.config.resend_interval;
+
+        true
+    }
+
+    /// How the search was sent, a resend has to use the same way
+    pub(crate) fn method(&self) -> SearchMethod {
+        self.stats.method
+    }
+
+    /// Counts a socket recreated by `Engine::recover`
+    pub(crate) fn recovered(&mut self) {
+        self.stats.recoveries += 1;
+    }
+
+    /// Whether the timeout or the device count is reached, the quiet period passed or an adaptive discovery became idle
+    pub(crate) fn finished(&self) -> bool {
+        if self.is_cancelled() || self.stop.as_ref().is_some_and(CancellationToken::is_cancelled) {
+            return true;
+        }
+        let now = self.config.clock.now();
+        if now.duration_since(self.time) >= self.timeout
+            || self.seen.len() >= self.config.device_count.unwrap_or(usize::MAX) {
+            return true;
+        }
+
+        if let (Some(quiet_period), Some(_)) = (self.config.quiet_period, self.stats.first_response) {
+            if now.duration_since(self.last_response) >= quiet_period {
+                return true;
+            }
+        }
+
+        if self.config.adaptive {
+            if let Some(latency) = self.stats.first_response {
+                let idle = (latency * 3).clamp(MIN_IDLE, MAX_IDLE);
+                return now.duration_since(self.time) >= self.config.response_window()
+                    && now.duration_since(self.last_response) >= idle;
+            }
+        }
+
+        false
+    }
+
+    /// Handles a packet received from `addr`, returns the response if it's the first one of a new sonos device
+    pub(crate) fn receive(&mut self, addr: SocketAddr, data: Box<[u8]>) -> Option<&Response> {
+        let clock = Arc::clone(&self.config.clock);
+
+        self.stats.responses += 1;
+        if let Some(ref sink) = self.config.metrics_sink {
+            sink.response_received();
+        }
+        audit(&self.config, Direction::Received, addr, &data);
+        if self.config.hexdump {
+            debug!("Received {} bytes from {}\n{}", data.len(), addr, hexdump(&data));
+        } else {
+            trace!("Received {} bytes from {}", data.len(), addr);
+        }
+        #[cfg(feature = "tracing")]
+        tracing::trace!(%addr, bytes = data.len(), "packet received");
+        let (ssdp, result) = SsdpResponse::from_bytes_checked(data);
+        if let Err(e) = result {
+            trace!("Malformed packet from {}: {}", addr, e);
+            if let Some(ref sink) = self.config.metrics_sink {
+                sink.parse_error(&e);
+            }
+        }
+        let response = Response {
+            addr,
+            ssdp,
+            received: clock.now(),
+            received_at: SystemTime::now(),
+            latency: clock.now().duration_since(self.time)
+        };
+        if !self.is_match(&response) {
+            trace!("Ignoring the response from {}, it doesn't answer the search", addr);
+            #[cfg(feature = "tracing")]
+            tracing::trace!(%addr, reason = "no answer to the search", "response ignored");
+            self.reject(response);
+        } else if !self.in_household(&response) {
+            trace!("Ignoring the response from {}, it's from another household", addr);
+            #[cfg(feature = "tracing")]
+            tracing::trace!(%addr, reason = "other household", "response ignored");
+            self.reject(response);
+        } else if !self.is_genuine(&response) {
+            debug!("Ignoring the response from {}, it's from outside the local subnets or its LOCATION is another host",
+                   addr);
+            #[cfg(feature = "tracing")]
+            tracing::debug!(%addr, reason = "source validation", "response ignored");
+            self.reject(response);
+        } else {
+            self.last_response = clock.now();
+            self.stats.first_response = self.stats.first_response
+                .or_else(|| Some(self.last_response.duration_since(self.time)));
+            let new = self.seen.insert(addr.ip());
+            if !new {
+                self.stats.duplicates += 1;
+                if let Some(ref sink) = self.config.metrics_sink {
+                    sink.duplicate();
+                }
+                self.log_duplicate(addr.ip());
+            } else {
+                debug!("Found {} ({})", addr.ip(), response.uuid().as_deref().unwrap_or("no uuid"));
+                #[cfg(feature = "tracing")]
+                tracing::debug!(ip = %addr.ip(), uuid = ?response.uuid(), after = ?clock.now().duration_since(self.time),
+                                "device found");
+                if let Some(ref listener) = self.config.listener {
+                    listener.found(addr.ip(), response.uuid().as_deref());
+                }
+            }
+            self.devices.push(response);
+            if new {
+                return self.devices.last();
+            }
+        }
+
+        None
+    }
+
+    /// Responses of sonos devices and the statistics of the search
+    pub(crate) fn finish(self) -> (Vec<Response>, DiscoveryStats) {
+        let mut stats = self.stats;
+        stats.elapsed = self.config.clock.now().duration_since(self.time);
+        stats.cancelled = self.is_cancelled();
+        if stats.elapsed >= self.timeout {
+            debug!("Timeout of {:?} reached", self.timeout);
+        }
+        debug!("Search finished after {:?}: {} devices, {} responses, {} rejected", stats.elapsed, self.seen.len(),
+               stats.responses, stats.rejected);
+        #[cfg(feature = "tracing")]
+        tracing::debug!(elapsed = ?stats.elapsed, devices = self.seen.len(), responses = stats.responses,
+                        rejected = stats.rejected, cancelled = stats.cancelled, "search finished");
+
+        (self.devices, stats)
+    }
+
+    /// Counts `response` as rejected and keeps it for `keep_raw`
+    fn reject(&mut self, response: Response) {
+        self.stats.rejected += 1;
+        if let Some(ref sink) = self.config.metrics_sink {
+            sink.response_rejected();
+        }
+        if self.config.keep_raw {
+            self.rejected.push(response.raw());
+        }
+    }
+
+    fn is_cancelled(&self) -> bool {
+        match self.config.cancellation {
+            Some(ref token) => token.is_cancelled(),
+            None => false
+        }
+    }
+
+    /// Whether `response` answers the search: a sonos device for the default search target,
+    /// otherwise any device whose `ST` header is the configured search target (or all devices for `ssdp:all`).
+    /// Passive discoveries only accept announcements. The response filter replaces the check of the search target.
+    fn is_match(&self, response: &Response) -> bool {
+        let search_target = &self.config.search_target;
+        if let Some(ref filter) = self.config.response_filter {
+            let announced = self.stats.method != SearchMethod::Passive || response.ssdp.is_notify();
+            return announced && filter.accepts(&SsdpHeaders::parse(response.addr.ip(), response.ssdp.as_bytes()));
+        }
+        if self.stats.method == SearchMethod::Passive {
+            return self.is_announcement(response);
+        }
+        if self.config.ssdp_all {
+            return self.is_sonos_entry(response);
+        }
+        if search_target == ZONE_PLAYER {
+            return self.is_zone_player(response);
+        }
+
+        match response.ssdp.st() {
+            Some(st) => search_target == "ssdp:all" || st.eq_ignore_ascii_case(search_target),
+            None => false
+        }
+    }
+
+    /// Whether `response` is a successful search response (`HTTP/1.1 200 OK`) whose `ST` or `USN` header names the
+    /// `ZONE_PLAYER` device type, or mentions "Sonos" anywhere for a lenient match
+    fn is_zone_player(&self, response: &Response) -> bool {
+        if self.config.lenient_match {
+            let needle: &[u8] = br"Sonos";
+            return response.ssdp.as_bytes().windows(needle.len()).any(|window| window == needle);
+        }
+
+        if !response.ssdp.is_search_response() {
+            return false;
+        }
+
+        [response.ssdp.st(), response.ssdp.usn()].iter()
+            .filter_map(|&value| value)
+            .any(|value| contains_ignore_ascii_case(value, ZONE_PLAYER))
+    }
+
+    /// Whether `response` answers an `ssdp:all` search for a sonos device: any of its entries, whose uuid is a
+    /// `RINCON_...` or whose `ST`, `USN` or `SERVER` header is of a sonos device (`DeviceKind::SonosZonePlayer`)
+    fn is_sonos_entry(&self, response: &Response) -> bool {
+        if !response.ssdp.is_search_response() {
+            return false;
+        }
+
+        response.ssdp.uuid().is_some_and(|uuid| uuid.starts_with("RINCON_"))
+            || DeviceKind::of(response) == DeviceKind::SonosZonePlayer
+    }
+
+    /// Whether `response` is a `NOTIFY ... ssdp:alive` of the configured search target (`NT` header)
+    fn is_announcement(&self, response: &Response) -> bool {
+        if !response.ssdp.is_notify() {
+            return false;
+        }
+
+        match (response.ssdp.header("NTS"), response.ssdp.nt()) {
+            (Some(nts), Some(nt)) if nts.eq_ignore_ascii_case("ssdp:alive") =>
+                self.config.search_target == "ssdp:all" || nt.eq_ignore_ascii_case(&self.config.search_target),
+            _ => false
+        }
+    }
+
+    /// Whether `response` belongs to the configured household, always true without one
+    fn in_household(&self, response: &Response) -> bool {
+        match self.config.household {
+            Some(ref household) => response.ssdp.header("X-RINCON-HOUSEHOLD") == Some(household.as_str()),
+            None => true
+        }
+    }
+
+    /// Whether `response` passes `DiscoveryConfig::validate_source`: its source is on a local subnet and its
+    /// `LOCATION` (if any) points to the source, always true without the validation
+    fn is_genuine(&self, response: &Response) -> bool {
+        if !self.config.validate_source {
+            return true;
+        }
+        let ip = response.addr.ip();
+        if let Some(ref networks) = self.local_networks {
+            if !networks.iter().any(|network| network.contains(ip)) {
+                return false;
+            }
+        }
+
+        match response.ssdp.location() {
+            Some(location) => parse_http_url(location).is_some_and(|(address, _)| address.ip() == ip),
+            None => true
+        }
+    }
+
+    /// Logs (`debug` level) a repeated response of `source`, rate limited so a chatty device doesn't flood the log
+    fn log_duplicate(&self, source: IpAddr) {
+        if !log_enabled!(Level::Debug) {
+            return;
+        }
+
+        match self.duplicate_log.allow(source, self.config.clock.now()) {
+            Some(0) => debug!("Duplicate response from {}", source),
+            Some(suppressed) => debug!("Duplicate response from {} ({} similar messages suppressed)", source, suppressed),
+            None => {}
+        }
+    }
+}
+
+/// Whether `haystack` contains `needle`, ignoring the ascii case, without lowercase copies of both
+fn contains_ignore_ascii_case(haystack: &str, needle: &str) -> bool {
+    haystack.as_bytes().windows(needle.len()).any(|window| window.eq_ignore_ascii_case(needle.as_bytes()))
+}
+
+/// Hints why the search which produced `responses` might be incomplete
+pub(crate) fn warnings(config: &DiscoveryConfig, responses: &[Response], stats: &DiscoveryStats) -> Vec<String> {
+    let device_count = config.device_count.unwrap_or(usize::MAX);
+
+    let mut warnings = Vec::new();
+    if stats.cancelled {
+        warnings.push("The discovery was cancelled, devices might be missing".to_string());
+    }
+    if stats.method == SearchMethod::Passive {
+        if stats.responses == 0 {
+            warnings.push("No announcements received, multicast traffic might be filtered".to_string());
+        }
+        return warnings;
+    }
+    if config.effective_mx() != config.mx {
+        warnings.push(format!("MX {} is outside of 1 to {}, {} was sent instead", config.mx, MAX_MX, config.effective_mx()));
+    }
+    let window = config.response_window();
+    if config.timeout < window {
+        warnings.push(format!("Timeout ({:?}) is shorter than MX + {:?} ({:?}), slow devices might not have answered yet",
+                              config.timeout, config.mx_slack, window));
+    }
+    if stats.method == SearchMethod::Broadcast {
+        warnings.push("Sending to the multicast address failed, the search was broadcast instead".to_string());
+    }
+    if stats.responses == 0 && stats.method == SearchMethod::Unicast {
+        warnings.push("No responses received, the probed addresses might not be sonos devices".to_string());
+    } else if stats.responses == 0 {
+        warnings.push("No responses received, multicast traffic might be filtered".to_string());
+    } else if config.device_count.is_some() {
+        let discovered = responses.iter().map(|response| response.addr.ip()).collect::<HashSet<IpAddr>>().len();
+        if discovered < device_count {
+            warnings.push(format!("Timeout reached after discovering {} of {} devices", discovered, device_count));
+        }
+    }
+
+    warnings
+}
+
+/// Sends the search of an async discovery on `socket`, like `Engine::send_search` but only once and without retries
+#[cfg(any(feature = "tokio", feature = "async-io"))]
+pub(crate) fn send_async_search(config: &DiscoveryConfig, socket: &UdpSocket) -> Result<SearchMethod> {
+    let multicast_addr = config.multicast_addr;
+    let player_search = search_message(config, multicast_addr);
+    let player_search = player_search.as_bytes();
+    let sent = transport::send_multicast(socket, config, || socket.send_to(player_search, multicast_addr));
+
+    match (sent, config.broadcast_fallback) {
+        (Ok(_), _) => {
+            debug!("Sent the search for {} to {}", config.effective_search_target(), multicast_addr);
+            record_sent(config, multicast_addr, player_search);
+            send_additional_groups(config, socket, |search, group| socket.send_to(search, group));
+            Ok(SearchMethod::Multicast)
+        }
+        (Err(e), Some(broadcast_addr)) => {
+            warn!("Sending the search to {} failed ({}), falling back to {}", multicast_addr, e, broadcast_addr);
+            socket.set_broadcast(true).map_err(DiscoveryError::SocketCreate)?;
+            socket.send_to(player_search, broadcast_addr).map_err(DiscoveryError::Send)?;
+            record_sent(config, broadcast_addr, player_search);
+            Ok(SearchMethod::Broadcast)
+        }
+        (Err(e), None) => Err(e)
+    }
+}
+
+/// Result of an async discovery from its collector, the http requests after the search are skipped
+#[cfg(any(feature = "tokio", feature = "async-io"))]
+pub(crate) fn async_result(mut collector: Collector) -> DiscoveryResult {
+    let started = collector.time;
+    let config = collector.config.clone();
+    let rejected = collector.take_rejected();
+    let (responses, stats) = collector.finish();
+    let mut warnings = warnings(&config, &responses, &stats);
+    if config.verify || config.topology {
+        warnings.push("The async discovery doesn't verify the devices or fetch the topology".to_string());
+    }
+
+    let elapsed = config.clock.now().duration_since(started);
+    discovery_result(&responses, rejected, Vec::new(), stats, warnings, config, elapsed)
+}
+
+/// Result of a discovery from the responses of its search, `elapsed` since it started is reported to the metrics sink
+/// and becomes the total time of the statistics
+pub(crate) fn discovery_result(responses: &[Response], rejected: Vec<RawResponse>, groups: Vec<ZoneGroup>,
+                               mut stats: DiscoveryStats, warnings: Vec<String>, config: DiscoveryConfig,
+                               elapsed: Duration) -> DiscoveryResult {
+    if let Some(ref sink) = config.metrics_sink {
+        sink.discovery_finished(elapsed);
+    }
+    stats.total = elapsed;
+    let devices: Vec<IpAddr> = responses.iter().map(|response| response.addr.ip()).collect();
+    // The first response of a device counts for its uuid, household and UPnP headers
+    let mut uuids: HashMap<IpAddr, String> = HashMap::new();
+    let mut households: HashMap<IpAddr, String> = HashMap::new();
+    let mut upnp: HashMap<IpAddr, UpnpHeaders> = HashMap::new();
+    for response in responses {
+        if let Some(uuid) = response.uuid() {
+            uuids.entry(response.addr.ip()).or_insert(uuid);
+        }
+        if let Some(household) = response.ssdp.header("X-RINCON-HOUSEHOLD") {
+            households.entry(response.addr.ip()).or_insert_with(|| household.to_string());
+        }
+        upnp.entry(response.addr.ip()).or_insert_with(|| response.upnp_headers());
+    }
+    let mut seen: HashMap<IpAddr, Sighting> = HashMap::new();
+    for response in responses {
+        let sighting = seen.entry(response.addr.ip()).or_insert(Sighting {
+            first_seen: response.received,
+            first_seen_at: response.received_at,
+            last_seen: response.received,
+            last_seen_at: response.received_at,
+            latency: response.latency
+        });
+        sighting.last_seen = response.received;
+        sighting.last_seen_at = response.received_at;
+    }
+
+    let mut raw: HashMap<IpAddr, Vec<RawResponse>> = HashMap::new();
+    if config.keep_raw {
+        for response in responses {
+            raw.entry(response.addr.ip()).or_default().push(response.raw());
+        }
+    }
+
+    DiscoveryResult::new(devices, uuids, upnp, households, seen, raw, rejected, groups, stats, warnings, config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clock::{Clock, ManualClock};
+    use fake_transport::FakeNetwork;
+
+    /// Search response of the speaker `uuid` at `ip`
+    fn response(ip: &str, uuid: &str) -> Box<[u8]> {
+        household_response(ip, uuid, "Sonos_test")
+    }
+
+    /// Search response of the speaker `uuid` at `ip` in `household`
+    fn household_response(ip: &str, uuid: &str, household: &str) -> Box<[u8]> {
+        format!("HTTP/1.1 200 OK\r\n\
+                 CACHE-CONTROL: max-age = 1800\r\n\
+                 LOCATION: http://{}:1400/xml/device_description.xml\r\n\
+                 ST: urn:schemas-upnp-org:device:ZonePlayer:1\r\n\
+                 USN: uuid:{}::urn:schemas-upnp-org:device:ZonePlayer:1\r\n\
+                 X-RINCON-HOUSEHOLD: {}\r\n\r\n", ip, uuid, household).into_bytes().into_boxed_slice()
+    }
+
+    /// Configuration on a manual clock without validating the sources
+    fn fake_config() -> DiscoveryConfig {
+        DiscoveryConfig {
+            clock: Arc::new(ManualClock::new()),
+            validate_source: false,
+            ..DiscoveryConfig::default()
+        }
+    }
+
+    /// Engine searching on a fake network with the clock of `config`
+    fn engine(mut config: DiscoveryConfig) -> (Engine, FakeNetwork) {
+        let network = FakeNetwork::new(Arc::clone(&config.clock));
+        config.transport = Arc::new(network.clone());
+        let duplicate_log = Arc::new(RateLimiter::new(3, Duration::from_secs(60)));
+
+        (Engine::new(config, duplicate_log, None).unwrap(), network)
+    }
+
+    fn addr(ip: &str) -> SocketAddr {
+        SocketAddr::new(ip.parse().unwrap(), SSDP_PORT)
+    }
+
+    /// Collector of a multicast search sent now on a manual clock, without validating the sources
+    fn collector(mut config: DiscoveryConfig) -> (Collector, Arc<ManualClock>) {
+        let clock = Arc::new(ManualClock::new());
+        config.clock = clock.clone();
+        config.validate_source = false;
+        let time = clock.now();
+        let duplicate_log = Arc::new(RateLimiter::new(3, Duration::from_secs(60)));
+
+        (Collector::new(config, duplicate_log, SearchMethod::Multicast, time), clock)
+    }
+
+    #[test]
+    fn finishes_at_the_timeout() {
+        let (collector, clock) = collector(DiscoveryConfig {
+            timeout: Duration::from_secs(2),
+            ..DiscoveryConfig::default()
+        });
+
+        clock.advance(Duration::from_millis(1999));
+        assert!(!collector.finished());
+        assert_eq!(collector.remaining(), Duration::from_millis(1));
+        assert_eq!(collector.receive_timeout(), Duration::from_millis(1));
+        clock.advance(Duration::from_millis(1));
+        assert!(collector.finished());
+        assert_eq!(collector.remaining(), Duration::from_secs(0));
+    }
+
+    #[test]
+    fn budget_caps_the_timeout() {
+        let (collector, clock) = collector(DiscoveryConfig {
+            timeout: Duration::from_secs(5),
+            budget: Some(Duration::from_secs(1)),
+            ..DiscoveryConfig::default()
+        });
+
+        clock.advance(Duration::from_secs(1));
+        assert!(collector.finished());
+    }
+
+    #[test]
+    fn quiet_period_starts_with_the_first_response() {
+        let (mut collector, clock) = collector(DiscoveryConfig {
+            timeout: Duration::from_secs(10),
+            quiet_period: Some(Duration::from_millis(500)),
+            ..DiscoveryConfig::default()
+        });
+
+        clock.advance(Duration::from_secs(3));
+        assert!(!collector.finished());
+        collector.receive(addr("192.168.1.10"), response("192.168.1.10", "RINCON_A"));
+        clock.advance(Duration::from_millis(400));
+        assert!(!collector.finished());
+        assert_eq!(collector.receive_timeout(), Duration::from_millis(100));
+
+        // Every response restarts the quiet period
+        collector.receive(addr("192.168.1.11"), response("192.168.1.11", "RINCON_B"));
+        clock.advance(Duration::from_millis(400));
+        assert!(!collector.finished());
+        clock.advance(Duration::from_millis(100));
+        assert!(collector.finished());
+    }
+
+    #[test]
+    fn resends_are_due_every_interval() {
+        let (mut collector, clock) = collector(DiscoveryConfig {
+            timeout: Duration::from_secs(10),
+            resends: 2,
+            resend_interval: Duration::from_secs(1),
+            ..DiscoveryConfig::default()
+        });
+
+        assert!(!collector.resend_due());
+        clock.advance(Duration::from_millis(700));
+        assert_eq!(collector.receive_timeout(), Duration::from_millis(300));
+        clock.advance(Duration::from_millis(300));
+        assert!(collector.resend_due());
+        assert!(!collector.resend_due());
+        clock.advance(Duration::from_secs(1));
+        assert!(collector.resend_due());
+        // Both resends are used up
+        clock.advance(Duration::from_secs(1));
+        assert!(!collector.resend_due());
+        assert_eq!(collector.receive_timeout(), RECEIVE_TIMEOUT);
+    }
+
+    #[test]
+    fn the_first_response_of_a_device_counts() {
+        let (mut collector, _clock) = collector(DiscoveryConfig::default());
+        collector.receive(addr("192.168.1.10"), response("192.168.1.10", "RINCON_A"));
+        collector.receive(addr("192.168.1.10"), response("192.168.1.10", "RINCON_B"));
+
+        let config = collector.config.clone();
+        let (responses, stats) = collector.finish();
+        let result = discovery_result(&responses, Vec::new(), Vec::new(), stats, Vec::new(), config,
+                                      Duration::from_secs(1));
+        let ip = "192.168.1.10".parse().unwrap();
+        assert_eq!(result.uuid(&ip), Some("RINCON_A"));
+        assert_eq!(result.household(&ip), Some("Sonos_test"));
+    }
+
+    #[test]
+    fn accepts_the_responses_of_sonos_devices() {
+        let (engine, network) = engine(fake_config());
+        network.answer(addr("192.168.1.11"), &response("192.168.1.11", "RINCON_B"));
+        network.answer(addr("192.168.1.10"), &response("192.168.1.10", "RINCON_A"));
+
+        let result = engine.run().unwrap();
+        let (first, second) = ("192.168.1.10".parse().unwrap(), "192.168.1.11".parse().unwrap());
+        assert_eq!(result.devices(), &[first, second]);
+        assert_eq!(result.uuid(&first), Some("RINCON_A"));
+        assert_eq!(result.household(&second), Some("Sonos_test"));
+        assert_eq!(result.stats().responses, 2);
+        assert_eq!(result.stats().rejected, 0);
+        assert_eq!(result.stats().method, SearchMethod::Multicast);
+        assert_eq!(network.sent_to(), vec![DiscoveryConfig::default().multicast_addr]);
+    }
+
+    #[test]
+    fn rejects_other_devices_and_households() {
+        let (engine, network) = engine(DiscoveryConfig {
+            household: Some("Sonos_test".to_string()),
+            ..fake_config()
+        });
+        let router = b"HTTP/1.1 200 OK\r\n\
+                       ST: urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\n\
+                       USN: uuid:router::urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\n\r\n";
+        network.answer(addr("192.168.1.1"), router);
+        network.answer(addr("192.168.1.12"), &household_response("192.168.1.12", "RINCON_C", "Sonos_other"));
+        network.answer(addr("192.168.1.13"), b"HTTP/1.1 200 OK\r\nST\r\n\xff\xfe");
+        network.answer(addr("192.168.1.10"), &response("192.168.1.10", "RINCON_A"));
+
+        let result = engine.run().unwrap();
+        assert_eq!(result.devices(), &["192.168.1.10".parse::<IpAddr>().unwrap()]);
+        assert_eq!(result.stats().responses, 4);
+        assert_eq!(result.stats().rejected, 3);
+    }
+
+    #[test]
+    fn rejects_responses_pointing_to_another_host() {
+        let (engine, network) = engine(DiscoveryConfig {
+            validate_source: true,
+            ..fake_config()
+        });
+        // Loopback is a local network on every host
+        network.answer(addr("127.0.0.1"), &response("192.168.1.10", "RINCON_A"));
+        network.answer(addr("127.0.0.1"), &response("127.0.0.1", "RINCON_B"));
+
+        let result = engine.run().unwrap();
+        let ip = "127.0.0.1".parse().unwrap();
+        assert_eq!(result.devices(), &[ip]);
+        assert_eq!(result.uuid(&ip), Some("RINCON_B"));
+        assert_eq!(result.stats().rejected, 1);
+    }
+
+    #[test]
+    fn counts_repeated_responses_as_duplicates() {
+        let (engine, network) = engine(fake_config());
+        for _ in 0..3 {
+            network.answer(addr("192.168.1.10"), &response("192.168.1.10", "RINCON_A"));
+        }
+
+        let result = engine.run().unwrap();
+        let ip: IpAddr = "192.168.1.10".parse().unwrap();
+        assert_eq!(result.devices(), &[ip]);
+        assert_eq!(result.response_addrs(), &[ip, ip, ip]);
+        assert_eq!(result.stats().duplicates, 2);
+        assert_eq!(result.stats().responses, 3);
+    }
+
+    #[test]
+    fn stops_at_the_device_count() {
+        let (engine, network) = engine(DiscoveryConfig {
+            device_count: Some(1),
+            ..fake_config()
+        });
+        network.answer(addr("192.168.1.10"), &response("192.168.1.10", "RINCON_A"));
+        network.answer(addr("192.168.1.11"), &response("192.168.1.11", "RINCON_B"));
+
+        let result = engine.run().unwrap();
+        assert_eq!(result.devices(), &["192.168.1.10".parse::<IpAddr>().unwrap()]);
+        assert_eq!(result.stats().elapsed, Duration::from_secs(0));
+    }
+
+    #[test]
+    fn falls_back_to_broadcast_without_multicast() {
+        let broadcast_addr: SocketAddr = "192.168.1.255:1900".parse().unwrap();
+        let (engine, network) = engine(DiscoveryConfig {
+            broadcast_fallback: Some(broadcast_addr),
+            resends: 1,
+            ..fake_config()
+        });
+        network.fail_multicast();
+        network.answer(addr("192.168.1.10"), &response("192.168.1.10", "RINCON_A"));
+
+        let result = engine.run().unwrap();
+        assert_eq!(result.stats().method, SearchMethod::Broadcast);
+        assert!(network.broadcast());
+        // The resend takes the same way
+        assert_eq!(network.sent_to(), vec![broadcast_addr, broadcast_addr]);
+        assert_eq!(result.devices(), &["192.168.1.10".parse::<IpAddr>().unwrap()]);
+        assert!(result.warnings().iter().any(|warning| warning.contains("broadcast instead")));
+    }
+
+    #[test]
+    fn fails_without_multicast_and_fallback() {
+        let (engine, network) = engine(fake_config());
+        network.fail_multicast();
+
+        match engine.run() {
+            Err(DiscoveryError::Send(e)) => assert_eq!(e.kind(), ErrorKind::PermissionDenied),
+            other => panic!("expected a send error, got {:?}", other.map(|result| result.ips()))
+        }
+        assert!(network.sent_to().is_empty());
+    }
+
+    #[test]
+    fn unicast_searches_are_not_resent() {
+        let clock = Arc::new(ManualClock::new());
+        let config = DiscoveryConfig {
+            resends: 2,
+            resend_interval: Duration::from_secs(1),
+            clock: clock.clone(),
+            ..DiscoveryConfig::default()
+        };
+        let duplicate_log = Arc::new(RateLimiter::new(3, Duration::from_secs(60)));
+        let mut collector = Collector::new(config, duplicate_log, SearchMethod::Unicast, clock.now());
+
+        clock.advance(Duration::from_secs(1));
+        assert!(!collector.resend_due());
+    }
+}
@@ -0,0 +1,110 @@
+use std::error;
+use std::fmt;
+use std::io::{self, ErrorKind};
+use std::net::IpAddr;
+use std::result;
+
+/// Result of the fallible operations of a `Discover`
+pub(crate) type Result<T> = result::Result<T, DiscoveryError>;
+
+/// Failure of a discovery
+///
+/// A search without answers isn't an error, it returns an empty result with a warning (`DiscoveryResult::warnings`).
+/// Responses which can't be parsed are skipped and logged.
+///
+/// # Examples
+///
+/// ```no_run
+/// use sonos_discovery::{Discover, DiscoveryError};
+/// use std::net::{IpAddr, Ipv6Addr};
+///
+/// match Discover::new().unwrap().probe(&[IpAddr::V6(Ipv6Addr::LOCALHOST)]) {
+///     Err(DiscoveryError::InvalidAddress(message)) => println!("{}", message),
+///     Err(e) => println!("discovery failed: {}", e),
+///     Ok(result) => println!("{} devices", result.len())
+/// }
+/// ```
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum DiscoveryError {
+    /// Creating, configuring or binding the socket failed
+    SocketCreate(io::Error),
+    /// Binding the search socket to the fixed source port of `DiscoveryConfig::source_port` failed
+    SourcePort(u16, io::Error),
+    /// Binding the socket to the interface of `DiscoveryConfig::bind_device` failed (linux only)
+    BindDevice(String, io::Error),
+    /// The search couldn't be sent, neither to the multicast address nor to the broadcast fallback
+    Send(io::Error),
+    /// Receiving from the socket failed
+    Receive(io::Error),
+    /// An address doesn't fit the configuration, e.g. a bind address or a probed address of the other family
+    InvalidAddress(String),
+    /// No interface matches `DiscoveryConfig::interface_name`
+    InterfaceNotFound(String),
+    /// The address checked by `Discover::verify` isn't a sonos device, it neither answered the search nor serves the
+    /// description of one
+    NotSonos(IpAddr),
+    /// Entering the network namespace of `Discover::start_in_netns` failed
+    Namespace(io::Error),
+    /// Any other io error, e.g. listing the interfaces
+    Io(io::Error)
+}
+
+impl fmt::Display for DiscoveryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DiscoveryError::SocketCreate(ref e) => write!(f, "Couldn't create the socket: {}", e),
+            DiscoveryError::SourcePort(port, ref e) => {
+                write!(f, "Couldn't bind the source port {}: {}. The port has to be free (or shared with `reuse_port`) \
+                           and the firewall has to allow the udp answers to it, e.g. `iptables -A INPUT -p udp --dport {} -j \
+                           ACCEPT`", port, e, port)
+            }
+            DiscoveryError::BindDevice(ref device, ref e) if e.kind() == ErrorKind::PermissionDenied => {
+                write!(f, "Couldn't bind the socket to the interface {}: {}. Binding to an interface needs root or the \
+                           capability CAP_NET_RAW, e.g. `setcap cap_net_raw+ep <binary>`", device, e)
+            }
+            DiscoveryError::BindDevice(ref device, ref e) => {
+                write!(f, "Couldn't bind the socket to the interface {}: {}", device, e)
+            }
+            DiscoveryError::Send(ref e) => write!(f, "Couldn't send the search: {}", e),
+            DiscoveryError::Receive(ref e) => write!(f, "Couldn't receive: {}", e),
+            DiscoveryError::InvalidAddress(ref message) => f.write_str(message),
+            DiscoveryError::InterfaceNotFound(ref message) => f.write_str(message),
+            DiscoveryError::NotSonos(ip) => write!(f, "No sonos device at {}", ip),
+            DiscoveryError::Namespace(ref e) => write!(f, "Couldn't enter the network namespace: {}", e),
+            DiscoveryError::Io(ref e) => e.fmt(f)
+        }
+    }
+}
+
+impl error::Error for DiscoveryError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            DiscoveryError::SocketCreate(ref e) | DiscoveryError::SourcePort(_, ref e)
+            | DiscoveryError::BindDevice(_, ref e) | DiscoveryError::Send(ref e) | DiscoveryError::Receive(ref e)
+            | DiscoveryError::Namespace(ref e) | DiscoveryError::Io(ref e) => Some(e),
+            DiscoveryError::InvalidAddress(_) | DiscoveryError::InterfaceNotFound(_)
+            | DiscoveryError::NotSonos(_) => None
+        }
+    }
+}
+
+impl From<io::Error> for DiscoveryError {
+    fn from(error: io::Error) -> Self {
+        DiscoveryError::Io(error)
+    }
+}
+
+/// For callers which still return `io::Result`, the io error of the failure is kept as it is
+impl From<DiscoveryError> for io::Error {
+    fn from(error: DiscoveryError) -> Self {
+        match error {
+            DiscoveryError::SocketCreate(e) | DiscoveryError::SourcePort(_, e) | DiscoveryError::BindDevice(_, e)
+            | DiscoveryError::Send(e) | DiscoveryError::Receive(e) | DiscoveryError::Namespace(e)
+            | DiscoveryError::Io(e) => e,
+            DiscoveryError::InvalidAddress(message) => io::Error::new(ErrorKind::InvalidInput, message),
+            DiscoveryError::InterfaceNotFound(message) => io::Error::new(ErrorKind::NotFound, message),
+            DiscoveryError::NotSonos(ip) => io::Error::new(ErrorKind::NotFound, format!("No sonos device at {}", ip))
+        }
+    }
+}
@@ -0,0 +1,105 @@
+//! Canned http responses for the tests of the description, topology and upnp parsing.
+//!
+//! A `FakeFetcher` answers each url with the document registered for it and records every request, so the tests
+//! can check the urls, headers and validators which were sent. Unknown urls are refused like a closed port.
+
+use fetcher::{Conditional, HttpFetcher};
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind, Result};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Request received by a `FakeFetcher`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Request {
+    pub(crate) method: &'static str,
+    pub(crate) url: String,
+    /// Additional headers of a POST, the validators (`If-None-Match`, `If-Modified-Since`) of a GET
+    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) body: Option<String>
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct FakeFetcher {
+    /// Response by url, `NotModified` is only returned for conditional requests
+    responses: Mutex<HashMap<String, std::result::Result<Conditional, ErrorKind>>>,
+    requests: Mutex<Vec<Request>>
+}
+
+impl FakeFetcher {
+    /// Answers every request of `url` with `body`
+    pub(crate) fn answer(&self, url: &str, body: &str) {
+        self.answer_conditional(url, Conditional::Modified {
+            body: body.to_string(),
+            etag: None,
+            last_modified: None
+        });
+    }
+
+    /// Answers every request of `url` with `response`
+    pub(crate) fn answer_conditional(&self, url: &str, response: Conditional) {
+        self.responses.lock().unwrap().insert(url.to_string(), Ok(response));
+    }
+
+    /// Fails every request of `url` with an error of `kind`
+    pub(crate) fn fail(&self, url: &str, kind: ErrorKind) {
+        self.responses.lock().unwrap().insert(url.to_string(), Err(kind));
+    }
+
+    /// Requests received so far, in their order
+    pub(crate) fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// Records the request and looks up the response of its url
+    fn respond(&self, request: Request) -> Result<Conditional> {
+        let conditional = request.headers.iter().any(|(name, _)| name.starts_with("If-"));
+        let response = self.responses.lock().unwrap().get(&request.url).cloned();
+        let url = request.url.clone();
+        self.requests.lock().unwrap().push(request);
+
+        match response {
+            Some(Ok(Conditional::NotModified)) if !conditional =>
+                Err(Error::new(ErrorKind::InvalidData, "Unexpected http status: 304")),
+            Some(Ok(response)) => Ok(response),
+            Some(Err(kind)) => Err(Error::new(kind, url)),
+            None => Err(Error::new(ErrorKind::ConnectionRefused, url))
+        }
+    }
+}
+
+impl HttpFetcher for FakeFetcher {
+    fn get(&self, url: &str, timeout: Duration) -> Result<String> {
+        match self.get_conditional(url, timeout, None, None)? {
+            Conditional::Modified { body, .. } => Ok(body),
+            Conditional::NotModified => unreachable!("304 of an unconditional request")
+        }
+    }
+
+    fn get_conditional(&self, url: &str, _: Duration, etag: Option<&str>, last_modified: Option<&str>)
+                       -> Result<Conditional> {
+        let validators = [("If-None-Match", etag), ("If-Modified-Since", last_modified)];
+        self.respond(Request {
+            method: "GET",
+            url: url.to_string(),
+            headers: validators.iter()
+                .filter_map(|&(name, value)| value.map(|value| (name.to_string(), value.to_string())))
+                .collect(),
+            body: None
+        })
+    }
+
+    fn post(&self, url: &str, headers: &[(&str, &str)], body: &str, _: Duration) -> Result<String> {
+        let response = self.respond(Request {
+            method: "POST",
+            url: url.to_string(),
+            headers: headers.iter().map(|&(name, value)| (name.to_string(), value.to_string())).collect(),
+            body: Some(body.to_string())
+        })?;
+
+        match response {
+            Conditional::Modified { body, .. } => Ok(body),
+            Conditional::NotModified => unreachable!("304 of an unconditional request")
+        }
+    }
+}
@@ -0,0 +1,132 @@
+//! In-memory network for the tests of the engine.
+//!
+//! A `FakeNetwork` hands canned packets to the engine once it sent its search and records everything the engine
+//! sends. Waiting for a packet advances the clock of the discovery instead of blocking, so a search on a
+//! `ManualClock` finishes right away.
+
+use clock::Clock;
+use config::DiscoveryConfig;
+use error::Result;
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use transport::{Transport, TransportFactory};
+
+/// `TransportFactory` of the fake network, every transport shares its packets
+#[derive(Debug, Clone)]
+pub(crate) struct FakeNetwork {
+    state: Arc<State>
+}
+
+#[derive(Debug)]
+struct State {
+    clock: Arc<dyn Clock>,
+    /// Packets received after the search was sent, with their source
+    packets: Mutex<VecDeque<(SocketAddr, Vec<u8>)>>,
+    /// Packets sent by the engine, with their destination
+    sent: Mutex<Vec<(SocketAddr, Vec<u8>)>>,
+    /// Sending to a multicast address fails, like without a multicast route
+    multicast_fails: AtomicBool,
+    broadcast: AtomicBool
+}
+
+/// Transport of a single discovery on the fake network
+#[derive(Debug)]
+struct FakeTransport {
+    state: Arc<State>
+}
+
+impl FakeNetwork {
+    /// Network whose waits advance `clock`
+    pub(crate) fn new(clock: Arc<dyn Clock>) -> Self {
+        FakeNetwork {
+            state: Arc::new(State {
+                clock,
+                packets: Mutex::new(VecDeque::new()),
+                sent: Mutex::new(Vec::new()),
+                multicast_fails: AtomicBool::new(false),
+                broadcast: AtomicBool::new(false)
+            })
+        }
+    }
+
+    /// Lets every following multicast send fail
+    pub(crate) fn fail_multicast(&self) {
+        self.state.multicast_fails.store(true, Ordering::SeqCst);
+    }
+
+    /// Queues `data` from `source`, it's received once a search was sent
+    pub(crate) fn answer(&self, source: SocketAddr, data: &[u8]) {
+        self.state.packets.lock().unwrap().push_back((source, data.to_vec()));
+    }
+
+    /// Destinations of the packets sent so far
+    pub(crate) fn sent_to(&self) -> Vec<SocketAddr> {
+        self.state.sent.lock().unwrap().iter().map(|&(destination, _)| destination).collect()
+    }
+
+    /// Whether a transport allowed sending to broadcast addresses
+    pub(crate) fn broadcast(&self) -> bool {
+        self.state.broadcast.load(Ordering::SeqCst)
+    }
+}
+
+impl TransportFactory for FakeNetwork {
+    fn search(&self, _config: &DiscoveryConfig) -> Result<Box<dyn Transport>> {
+        Ok(Box::new(FakeTransport { state: Arc::clone(&self.state) }))
+    }
+
+    fn passive(&self, _config: &DiscoveryConfig) -> Result<Box<dyn Transport>> {
+        Ok(Box::new(FakeTransport { state: Arc::clone(&self.state) }))
+    }
+}
+
+impl Transport for FakeTransport {
+    fn send_to(&self, data: &[u8], address: SocketAddr) -> io::Result<usize> {
+        if self.state.multicast_fails.load(Ordering::SeqCst) && address.ip().is_multicast() {
+            return Err(io::Error::new(ErrorKind::PermissionDenied, "no multicast route"));
+        }
+        // Subnet broadcasts end in 255 on the /24 networks of the tests
+        let broadcast = match address.ip() {
+            IpAddr::V4(ip) => ip.octets()[3] == 255,
+            IpAddr::V6(_) => false
+        };
+        if broadcast && !self.state.broadcast.load(Ordering::SeqCst) {
+            return Err(io::Error::new(ErrorKind::PermissionDenied, "broadcasts aren't allowed"));
+        }
+        self.state.sent.lock().unwrap().push((address, data.to_vec()));
+
+        Ok(data.len())
+    }
+
+    fn recv_from(&self, buffer: &mut [u8], timeout: Duration) -> io::Result<(usize, SocketAddr)> {
+        let packet = if self.state.sent.lock().unwrap().is_empty() {
+            None
+        } else {
+            self.state.packets.lock().unwrap().pop_front()
+        };
+        match packet {
+            Some((source, data)) => {
+                let length = data.len().min(buffer.len());
+                buffer[..length].copy_from_slice(&data[..length]);
+                Ok((length, source))
+            }
+            None => {
+                self.state.clock.sleep(timeout);
+                Err(io::Error::new(ErrorKind::WouldBlock, "no packet"))
+            }
+        }
+    }
+
+    fn set_broadcast(&self, enabled: bool) -> io::Result<()> {
+        self.state.broadcast.store(enabled, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn set_multicast_if_v4(&self, _interface: &Ipv4Addr) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,600 @@
+#[cfg(feature = "reqwest")]
+use reqwest;
+use std::env;
+use std::fmt::Debug;
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+#[cfg(feature = "ureq")]
+use ureq;
+
+/// Http client for every request after the discovery (device descriptions, `LOCATION` verification, topology).
+///
+/// Installed with `Discover::set_http_fetcher`, defaults to `TcpFetcher`.
+/// Own implementations can add a proxy, instrumentation or return canned responses in tests.
+pub trait HttpFetcher: Debug + Send + Sync {
+    /// Body of a `200` response to a GET of `url` (`http://<ip>:<port>/<path>`),
+    /// `timeout` applies to connecting and to every read/write.
+    fn get(&self, url: &str, timeout: Duration) -> Result<String>;
+
+    /// GET of `url` which is answered with `NotModified` if the document still matches `etag` or wasn't changed since
+    /// `last_modified` (`If-None-Match`/`If-Modified-Since`).
+    /// The default implementation always downloads the whole document with `get`.
+    fn get_conditional(&self, url: &str, timeout: Duration, etag: Option<&str>, last_modified: Option<&str>)
+                       -> Result<Conditional> {
+        let _ = (etag, last_modified);
+        self.get(url, timeout).map(|body| Conditional::Modified {
+            body,
+            etag: None,
+            last_modified: None
+        })
+    }
+
+    /// Body of a `200` response to a POST of `body` to `url` with the additional `headers` (e.g. a soap action).
+    /// The default implementation fails with `ErrorKind::Unsupported`, which disables e.g. the topology.
+    fn post(&self, url: &str, headers: &[(&str, &str)], body: &str, timeout: Duration) -> Result<String> {
+        let _ = (headers, body, timeout);
+        Err(Error::new(ErrorKind::Unsupported, format!("POST isn't supported by this fetcher: {}", url)))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Response of `HttpFetcher::get_conditional`
+pub enum Conditional {
+    /// Body of a `200` response with the validators for the next request
+    Modified {
+        body: String,
+        etag: Option<String>,
+        last_modified: Option<String>
+    },
+    /// `304`, the previously fetched document is still valid
+    NotModified
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+/// Minimal built-in http/1.0 client without any dependency (no chunked encoding, no https)
+pub struct TcpFetcher;
+
+impl HttpFetcher for TcpFetcher {
+    fn get(&self, url: &str, timeout: Duration) -> Result<String> {
+        let (address, path) = parse_http_url(url)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("Unsupported url: {}", url)))?;
+
+        response_body(http_get(&address, &path, &address.to_string(), timeout, None, None)?)
+    }
+
+    fn get_conditional(&self, url: &str, timeout: Duration, etag: Option<&str>, last_modified: Option<&str>)
+                       -> Result<Conditional> {
+        let (address, path) = parse_http_url(url)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("Unsupported url: {}", url)))?;
+
+        http_get(&address, &path, &address.to_string(), timeout, etag, last_modified)
+    }
+
+    fn post(&self, url: &str, headers: &[(&str, &str)], body: &str, timeout: Duration) -> Result<String> {
+        let (address, path) = parse_http_url(url)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("Unsupported url: {}", url)))?;
+
+        response_body(http_request(&address, "POST", &path, &address.to_string(), headers, Some(body), timeout)?)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Built-in http/1.0 client sending every request through a http proxy, except for the hosts in `no_proxy`
+pub struct ProxyFetcher {
+    /// `host:port` of the proxy
+    pub proxy: String,
+    /// Addresses (`192.168.1.20`), ipv4 networks (`192.168.0.0/16`) or `*` which are fetched without the proxy
+    pub no_proxy: Vec<String>
+}
+
+impl ProxyFetcher {
+    /// Proxy from `HTTP_PROXY` (`http://host:port`) and exceptions from `NO_PROXY` (comma separated),
+    /// the lowercase variables are used as well. `None` without a proxy.
+    pub fn from_env() -> Option<Self> {
+        let variable = |name: &str| env::var(name)
+            .or_else(|_| env::var(name.to_lowercase()))
+            .ok()
+            .filter(|value| !value.trim().is_empty());
+
+        let proxy = variable("HTTP_PROXY")?;
+        let proxy = proxy.trim().trim_start_matches("http://").trim_end_matches('/').to_string();
+        let no_proxy = variable("NO_PROXY")
+            .map(|value| value.split(',').map(|entry| entry.trim().to_string()).filter(|entry| !entry.is_empty()).collect())
+            .unwrap_or_default();
+
+        Some(ProxyFetcher {
+            proxy,
+            no_proxy
+        })
+    }
+
+    /// Whether `ip` is excluded by `no_proxy`
+    fn bypass(&self, ip: IpAddr) -> bool {
+        self.no_proxy.iter().any(|entry| {
+            if entry == "*" {
+                return true;
+            }
+
+            let mut parts = entry.splitn(2, '/');
+            let network = parts.next().and_then(|network| network.parse::<IpAddr>().ok());
+            let prefix = parts.next().and_then(|prefix| prefix.parse::<u32>().ok());
+            match (network, prefix, ip) {
+                (Some(IpAddr::V4(network)), Some(prefix), IpAddr::V4(ip)) if prefix <= 32 => {
+                    let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+                    u32::from(network) & mask == u32::from(ip) & mask
+                }
+                (Some(network), None, _) => network == ip,
+                _ => false
+            }
+        })
+    }
+}
+
+impl HttpFetcher for ProxyFetcher {
+    fn get(&self, url: &str, timeout: Duration) -> Result<String> {
+        response_body(self.get_conditional(url, timeout, None, None)?)
+    }
+
+    fn get_conditional(&self, url: &str, timeout: Duration, etag: Option<&str>, last_modified: Option<&str>)
+                       -> Result<Conditional> {
+        let (address, path) = parse_http_url(url)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("Unsupported url: {}", url)))?;
+        if self.bypass(address.ip()) {
+            return TcpFetcher.get_conditional(url, timeout, etag, last_modified);
+        }
+
+        let proxy = self.proxy.to_socket_addrs()?
+            .next()
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("Couldn't resolve the proxy {}", self.proxy)))?;
+
+        // Proxies expect the absolute url as request target
+        http_get(&proxy, &format!("http://{}{}", address, path), &address.to_string(), timeout, etag, last_modified)
+    }
+
+    fn post(&self, url: &str, headers: &[(&str, &str)], body: &str, timeout: Duration) -> Result<String> {
+        let (address, path) = parse_http_url(url)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("Unsupported url: {}", url)))?;
+        if self.bypass(address.ip()) {
+            return TcpFetcher.post(url, headers, body, timeout);
+        }
+
+        let proxy = self.proxy.to_socket_addrs()?
+            .next()
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("Couldn't resolve the proxy {}", self.proxy)))?;
+        let target = format!("http://{}{}", address, path);
+
+        response_body(http_request(&proxy, "POST", &target, &address.to_string(), headers, Some(body), timeout)?)
+    }
+}
+
+/// GET of `target` via `connect`, sends the validators and accepts `304` if there are any
+fn http_get(connect: &SocketAddr, target: &str, host: &str, timeout: Duration, etag: Option<&str>,
+            last_modified: Option<&str>) -> Result<Conditional> {
+    let mut headers = Vec::new();
+    if let Some(etag) = etag {
+        headers.push(("If-None-Match", etag));
+    }
+    if let Some(last_modified) = last_modified {
+        headers.push(("If-Modified-Since", last_modified));
+    }
+
+    http_request(connect, "GET", target, host, &headers, None, timeout)
+}
+
+/// Minimal http/1.0 request (no chunked encoding) of `target` via `connect`.
+/// Accepts `200`, and `304` if conditional headers (`If-...`) were sent.
+fn http_request(connect: &SocketAddr, method: &str, target: &str, host: &str, headers: &[(&str, &str)],
+                body: Option<&str>, timeout: Duration) -> Result<Conditional> {
+    let mut stream = TcpStream::connect_timeout(connect, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    let mut request = format!("{} {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n", method, target, host);
+    for &(name, value) in headers {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    if let Some(body) = body {
+        request.push_str(&format!("Content-Length: {}\r\n\r\n{}", body.len(), body));
+    } else {
+        request.push_str("\r\n");
+    }
+    stream.write_all(request.as_bytes())?;
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+    let response = String::from_utf8_lossy(&response);
+
+    let header_end = response.find("\r\n\r\n")
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Incomplete http response"))?;
+    let status = response.lines().next().unwrap_or("");
+    let conditional = headers.iter().any(|&(name, _)| name.starts_with("If-"));
+    match status.split_whitespace().nth(1) {
+        Some("200") => {}
+        Some("304") if conditional => return Ok(Conditional::NotModified),
+        _ => return Err(Error::new(ErrorKind::InvalidData, format!("Unexpected http status: {}", status)))
+    }
+
+    let header = |name: &str| response[..header_end].lines()
+        .skip(1)
+        .filter_map(|line| {
+            let mut parts = line.splitn(2, ':');
+            match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) if key.trim().eq_ignore_ascii_case(name) => Some(value.trim().to_string()),
+                _ => None
+            }
+        })
+        .next();
+
+    Ok(Conditional::Modified {
+        body: response[header_end + 4..].to_string(),
+        etag: header("ETag"),
+        last_modified: header("Last-Modified")
+    })
+}
+
+/// Body of an unconditional request
+fn response_body(response: Conditional) -> Result<String> {
+    match response {
+        Conditional::Modified { body, .. } => Ok(body),
+        Conditional::NotModified => Err(Error::new(ErrorKind::InvalidData, "Unexpected http status: 304"))
+    }
+}
+
+#[cfg(feature = "ureq")]
+#[derive(Debug, Clone)]
+/// `HttpFetcher` using `ureq`, needs the `ureq` feature
+pub struct UreqFetcher {
+    agent: ureq::Agent
+}
+
+#[cfg(feature = "ureq")]
+impl UreqFetcher {
+    /// Uses an existing agent, e.g. with a proxy. It should not follow redirects, see `Default`.
+    pub fn with_agent(agent: ureq::Agent) -> Self {
+        UreqFetcher {
+            agent
+        }
+    }
+}
+
+#[cfg(feature = "ureq")]
+impl Default for UreqFetcher {
+    /// Agent without redirects, a device must not be able to redirect the crate to another host
+    fn default() -> Self {
+        UreqFetcher::with_agent(ureq::AgentBuilder::new().redirects(0).build())
+    }
+}
+
+#[cfg(feature = "ureq")]
+impl HttpFetcher for UreqFetcher {
+    fn get(&self, url: &str, timeout: Duration) -> Result<String> {
+        match self.agent.get(url).timeout(timeout).call() {
+            Ok(response) => response.into_string(),
+            Err(ureq::Error::Status(status, _)) =>
+                Err(Error::new(ErrorKind::InvalidData, format!("Unexpected http status: {}", status))),
+            Err(e) => Err(Error::new(ErrorKind::ConnectionAborted, e.to_string()))
+        }
+    }
+
+    fn post(&self, url: &str, headers: &[(&str, &str)], body: &str, timeout: Duration) -> Result<String> {
+        let request = headers.iter()
+            .fold(self.agent.post(url).timeout(timeout), |request, &(name, value)| request.set(name, value));
+
+        match request.send_string(body) {
+            Ok(response) => response.into_string(),
+            Err(ureq::Error::Status(status, _)) =>
+                Err(Error::new(ErrorKind::InvalidData, format!("Unexpected http status: {}", status))),
+            Err(e) => Err(Error::new(ErrorKind::ConnectionAborted, e.to_string()))
+        }
+    }
+}
+
+#[cfg(feature = "reqwest")]
+#[derive(Debug, Clone)]
+/// `HttpFetcher` using the blocking client of `reqwest`, needs the `reqwest` feature.
+/// `reqwest` uses the proxy of the `HTTP_PROXY` and `NO_PROXY` environment variables by default.
+pub struct ReqwestFetcher {
+    client: reqwest::blocking::Client
+}
+
+#[cfg(feature = "reqwest")]
+impl ReqwestFetcher {
+    /// Uses an existing client, e.g. with a custom tls configuration or proxy. It should not follow redirects,
+    /// see `Default`.
+    pub fn with_client(client: reqwest::blocking::Client) -> Self {
+        ReqwestFetcher {
+            client
+        }
+    }
+}
+
+#[cfg(feature = "reqwest")]
+impl Default for ReqwestFetcher {
+    /// Client without redirects, a device must not be able to redirect the crate to another host
+    fn default() -> Self {
+        let client = reqwest::blocking::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .expect("Default reqwest client");
+
+        ReqwestFetcher::with_client(client)
+    }
+}
+
+#[cfg(feature = "reqwest")]
+impl HttpFetcher for ReqwestFetcher {
+    fn get(&self, url: &str, timeout: Duration) -> Result<String> {
+        self.client.get(url)
+            .timeout(timeout)
+            .send()
+            .and_then(|response| response.error_for_status())
+            .and_then(|response| response.text())
+            .map_err(|e| if e.is_status() {
+                Error::new(ErrorKind::InvalidData, e.to_string())
+            } else {
+                Error::new(ErrorKind::ConnectionAborted, e.to_string())
+            })
+    }
+
+    fn post(&self, url: &str, headers: &[(&str, &str)], body: &str, timeout: Duration) -> Result<String> {
+        headers.iter()
+            .fold(self.client.post(url).timeout(timeout), |request, &(name, value)| request.header(name, value))
+            .body(body.to_string())
+            .send()
+            .and_then(|response| response.error_for_status())
+            .and_then(|response| response.text())
+            .map_err(|e| if e.is_status() {
+                Error::new(ErrorKind::InvalidData, e.to_string())
+            } else {
+                Error::new(ErrorKind::ConnectionAborted, e.to_string())
+            })
+    }
+}
+
+/// Splits `http://<ip>[:<port>]/<path>` into the address and the path, host names aren't supported
+pub(crate) fn parse_http_url(url: &str) -> Option<(SocketAddr, String)> {
+    let rest = url.trim().strip_prefix("http://")?;
+    let (authority, path) = match rest.find('/') {
+        Some(index) => rest.split_at(index),
+        None => (rest, "/")
+    };
+
+    let address = authority.parse()
+        .ok()
+        .or_else(|| authority.parse().ok().map(|ip: IpAddr| SocketAddr::new(ip, 80)))?;
+
+    Some((address, path.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::thread;
+
+    const TIMEOUT: Duration = Duration::from_secs(2);
+
+    /// Http server on a local port answering the next connection with `response`, returns its address and the
+    /// head (request line and headers) and body of the request
+    fn http_server(response: &'static str) -> (SocketAddr, thread::JoinHandle<(Vec<String>, String)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut head = Vec::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim_end().is_empty() {
+                    break;
+                }
+                head.push(line.trim_end().to_string());
+            }
+            let length = head.iter()
+                .find_map(|line| line.strip_prefix("Content-Length: "))
+                .map_or(0, |length| length.parse().unwrap());
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            reader.get_mut().write_all(response.as_bytes()).unwrap();
+
+            (head, String::from_utf8(body).unwrap())
+        });
+
+        (address, server)
+    }
+
+    #[test]
+    fn documents_are_fetched() {
+        let (address, server) = http_server("HTTP/1.1 200 OK\r\nContent-Type: text/xml\r\n\r\n<root>\r\n\r\n</root>");
+        let body = TcpFetcher.get(&format!("http://{}/xml/device_description.xml", address), TIMEOUT).unwrap();
+        assert_eq!(body, "<root>\r\n\r\n</root>");
+
+        let (head, _) = server.join().unwrap();
+        assert_eq!(head, vec!["GET /xml/device_description.xml HTTP/1.0".to_string(), format!("Host: {}", address),
+                              "Connection: close".to_string()]);
+    }
+
+    #[test]
+    fn unexpected_responses_fail() {
+        let cases = [("HTTP/1.1 404 Not Found\r\n\r\n", "Unexpected http status: HTTP/1.1 404 Not Found"),
+                     ("HTTP/1.1 304 Not Modified\r\n\r\n", "Unexpected http status: HTTP/1.1 304 Not Modified"),
+                     ("HTTP/1.1 200 OK\r\nContent-Type: text/xml\r\n", "Incomplete http response"),
+                     ("", "Incomplete http response"),
+                     ("SSDP garbage\r\n\r\n", "Unexpected http status: SSDP garbage")];
+        for &(response, message) in &cases {
+            let (address, server) = http_server(response);
+            let error = TcpFetcher.get(&format!("http://{}/", address), TIMEOUT).unwrap_err();
+            assert_eq!((error.kind(), error.to_string()), (ErrorKind::InvalidData, message.to_string()));
+            server.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn unreachable_and_unsupported_urls_fail() {
+        let address = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let error = TcpFetcher.get(&format!("http://{}/", address), TIMEOUT).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::ConnectionRefused);
+
+        for url in &["https://192.168.1.20:1400/", "http://sonos.local/", "192.168.1.20:1400/xml", ""] {
+            assert_eq!(TcpFetcher.get(url, TIMEOUT).unwrap_err().kind(), ErrorKind::InvalidInput, "{}", url);
+            assert_eq!(TcpFetcher.post(url, &[], "", TIMEOUT).unwrap_err().kind(), ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn conditional_requests_send_the_validators() {
+        let (address, server) = http_server("HTTP/1.1 304 Not Modified\r\nETag: \"v1\"\r\n\r\n");
+        let url = format!("http://{}/xml/device_description.xml", address);
+        let response = TcpFetcher.get_conditional(&url, TIMEOUT, Some("\"v1\""), Some("Wed, 14 Oct 2026 07:00:00 GMT"));
+        assert_eq!(response.unwrap(), Conditional::NotModified);
+        let (head, _) = server.join().unwrap();
+        assert!(head.contains(&"If-None-Match: \"v1\"".to_string()));
+        assert!(head.contains(&"If-Modified-Since: Wed, 14 Oct 2026 07:00:00 GMT".to_string()));
+
+        // The validators of a changed document are returned for the next request, whatever their case
+        let (address, server) = http_server("HTTP/1.1 200 OK\r\netag:  \"v2\"\r\n\
+                                             LAST-MODIFIED: Wed, 14 Oct 2026 08:00:00 GMT\r\n\r\n<root/>");
+        let url = format!("http://{}/xml/device_description.xml", address);
+        assert_eq!(TcpFetcher.get_conditional(&url, TIMEOUT, Some("\"v1\""), None).unwrap(), Conditional::Modified {
+            body: "<root/>".to_string(),
+            etag: Some("\"v2\"".to_string()),
+            last_modified: Some("Wed, 14 Oct 2026 08:00:00 GMT".to_string())
+        });
+        let (head, _) = server.join().unwrap();
+        assert!(!head.iter().any(|line| line.starts_with("If-Modified-Since")));
+    }
+
+    #[test]
+    fn posts_send_their_headers_and_body() {
+        let (address, server) = http_server("HTTP/1.1 200 OK\r\n\r\n<s:Envelope/>");
+        let url = format!("http://{}/ZoneGroupTopology/Control", address);
+        let headers = [("SOAPACTION", "\"urn:a#B\"")];
+        assert_eq!(TcpFetcher.post(&url, &headers, "<s:Envelope>ü</s:Envelope>", TIMEOUT).unwrap(), "<s:Envelope/>");
+
+        let (head, body) = server.join().unwrap();
+        assert_eq!(head[0], "POST /ZoneGroupTopology/Control HTTP/1.0");
+        assert!(head.contains(&"SOAPACTION: \"urn:a#B\"".to_string()));
+        // The length in bytes
+        assert!(head.contains(&"Content-Length: 27".to_string()));
+        assert_eq!(body, "<s:Envelope>ü</s:Envelope>");
+    }
+
+    #[test]
+    fn proxies_get_the_absolute_url() {
+        let (address, server) = http_server("HTTP/1.1 200 OK\r\n\r\n<root/>");
+        let proxy = ProxyFetcher {
+            proxy: address.to_string(),
+            no_proxy: vec!["192.168.2.0/24".to_string()]
+        };
+        assert_eq!(proxy.get("http://192.168.1.20:1400/xml/device_description.xml", TIMEOUT).unwrap(), "<root/>");
+        let (head, _) = server.join().unwrap();
+        assert_eq!(head[..2], ["GET http://192.168.1.20:1400/xml/device_description.xml HTTP/1.0".to_string(),
+                               "Host: 192.168.1.20:1400".to_string()]);
+
+        let (address, server) = http_server("HTTP/1.1 304 Not Modified\r\n\r\n");
+        let proxy = ProxyFetcher {
+            proxy: address.to_string(),
+            no_proxy: Vec::new()
+        };
+        let response = proxy.get_conditional("http://192.168.1.20:1400/", TIMEOUT, Some("\"v1\""), None);
+        assert_eq!(response.unwrap(), Conditional::NotModified);
+        server.join().unwrap();
+
+        let (address, server) = http_server("HTTP/1.1 200 OK\r\n\r\n<s:Envelope/>");
+        let proxy = ProxyFetcher {
+            proxy: address.to_string(),
+            no_proxy: Vec::new()
+        };
+        proxy.post("http://192.168.1.20:1400/ZoneGroupTopology/Control", &[], "<s:Envelope/>", TIMEOUT).unwrap();
+        let (head, body) = server.join().unwrap();
+        assert_eq!(head[0], "POST http://192.168.1.20:1400/ZoneGroupTopology/Control HTTP/1.0");
+        assert_eq!(body, "<s:Envelope/>");
+    }
+
+    #[test]
+    fn excluded_hosts_are_fetched_directly() {
+        // The proxy itself isn't reachable
+        let unreachable = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let proxy = ProxyFetcher {
+            proxy: unreachable.to_string(),
+            no_proxy: vec!["127.0.0.0/8".to_string()]
+        };
+        let (address, server) = http_server("HTTP/1.1 200 OK\r\n\r\n<root/>");
+        assert_eq!(proxy.get(&format!("http://{}/xml/device_description.xml", address), TIMEOUT).unwrap(), "<root/>");
+        assert_eq!(server.join().unwrap().0[0], "GET /xml/device_description.xml HTTP/1.0");
+
+        let proxy = ProxyFetcher {
+            proxy: unreachable.to_string(),
+            no_proxy: Vec::new()
+        };
+        let error = proxy.get("http://127.0.0.1:1400/", TIMEOUT).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::ConnectionRefused);
+    }
+
+    #[test]
+    fn no_proxy_entries() {
+        let cases = [("*", "192.168.1.20", true),
+                     ("192.168.1.20", "192.168.1.20", true),
+                     ("192.168.1.20", "192.168.1.21", false),
+                     ("192.168.0.0/16", "192.168.1.20", true),
+                     ("192.168.0.0/16", "192.169.1.20", false),
+                     ("192.168.1.20/32", "192.168.1.20", true),
+                     ("10.0.0.0/0", "192.168.1.20", true),
+                     ("192.168.1.0/33", "192.168.1.20", false),
+                     ("192.168.1.0/x", "192.168.1.20", false),
+                     ("fe80::1", "fe80::1", true),
+                     ("fe80::/64", "fe80::1", false),
+                     ("sonos.local", "192.168.1.20", false),
+                     ("", "192.168.1.20", false)];
+        for &(entry, ip, bypass) in &cases {
+            let proxy = ProxyFetcher {
+                proxy: "127.0.0.1:3128".to_string(),
+                no_proxy: vec![entry.to_string()]
+            };
+            assert_eq!(proxy.bypass(ip.parse().unwrap()), bypass, "{} {}", entry, ip);
+        }
+    }
+
+    #[test]
+    fn proxies_from_the_environment() {
+        env::set_var("HTTP_PROXY", " http://10.0.0.1:3128/ ");
+        env::set_var("NO_PROXY", "192.168.1.20, ,192.168.2.0/24");
+        let proxy = ProxyFetcher::from_env();
+        env::set_var("HTTP_PROXY", " ");
+        let empty = ProxyFetcher::from_env();
+        env::remove_var("HTTP_PROXY");
+        env::remove_var("NO_PROXY");
+
+        assert_eq!(proxy, Some(ProxyFetcher {
+            proxy: "10.0.0.1:3128".to_string(),
+            no_proxy: vec!["192.168.1.20".to_string(), "192.168.2.0/24".to_string()]
+        }));
+        // An empty variable is no proxy, unless the lowercase one has one
+        if env::var("http_proxy").is_err() {
+            assert_eq!(empty, None);
+        }
+    }
+
+    #[test]
+    fn http_urls() {
+        let cases = [("http://192.168.1.20:1400/xml/device_description.xml",
+                      Some(("192.168.1.20:1400", "/xml/device_description.xml"))),
+                     ("http://192.168.1.20", Some(("192.168.1.20:80", "/"))),
+                     (" http://192.168.1.20:1400/?a=b ", Some(("192.168.1.20:1400", "/?a=b"))),
+                     ("http://192.168.1.20:1400?a=b", None),
+                     ("http://[fe80::1]:1400/a", Some(("[fe80::1]:1400", "/a"))),
+                     ("http://fe80::1/a", Some(("[fe80::1]:80", "/a"))),
+                     ("http://192.168.1.20:99999/", None),
+                     ("http://sonos.local:1400/", None),
+                     ("HTTP://192.168.1.20/", None),
+                     ("https://192.168.1.20/", None)];
+        for &(url, expected) in &cases {
+            let parsed = parse_http_url(url);
+            let expected = expected.map(|(address, path)| (address.parse().unwrap(), path.to_string()));
+            assert_eq!(parsed, expected, "{}", url);
+        }
+    }
+}
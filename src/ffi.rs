@@ -0,0 +1,238 @@
+//! C bindings of the discovery for applications which aren't written in rust.
+//!
+//! Built as a shared library with `cargo rustc --release --lib --features ffi --crate-type cdylib`, the declarations
+//! are in `include/sonos_discovery.h` (generated with `cbindgen --config cbindgen.toml --output
+//! include/sonos_discovery.h`).
+//!
+//! ```c
+//! SonosDevice *devices;
+//! size_t len;
+//! if (sonos_discover(2000, &devices, &len) == SONOS_OK) {
+//!     for (size_t i = 0; i < len; i++) {
+//!         printf("%s %s\n", devices[i].ip, devices[i].uuid ? devices[i].uuid : "-");
+//!     }
+//!     sonos_free_results(devices, len);
+//! }
+//! ```
+
+use std::ffi::CString;
+use std::os::raw::{c_char, c_int};
+use std::panic;
+use std::ptr;
+use std::time::Duration;
+use Discover;
+
+/// The discovery succeeded, it found no devices if the length is 0
+pub const SONOS_OK: c_int = 0;
+/// `out_array` or `out_len` is null
+pub const SONOS_ERROR_INVALID_ARGUMENT: c_int = -1;
+/// The discovery failed, e.g. the socket couldn't be created or the search couldn't be sent
+pub const SONOS_ERROR_DISCOVERY: c_int = -2;
+/// The discovery panicked, this is a bug of the library
+pub const SONOS_ERROR_PANIC: c_int = -3;
+
+/// A discovered device, owned by the array of `sonos_discover`
+#[repr(C)]
+pub struct SonosDevice {
+    /// Address of the device (e.g. `192.168.1.20`), nul terminated
+    pub ip: *mut c_char,
+    /// Unique id of the device (`RINCON_...`), nul terminated, null if its response had none
+    pub uuid: *mut c_char
+}
+
+/// Discovers the sonos devices for `timeout_ms` milliseconds.
+///
+/// On success the devices are written to `*out_array` (null if none were found) and their number to `*out_len`,
+/// the array has to be released with `sonos_free_results`. Returns `SONOS_OK` or one of the `SONOS_ERROR_*` codes,
+/// the outputs are only written on success.
+///
+/// # Safety
+///
+/// `out_array` and `out_len` have to be null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn sonos_discover(timeout_ms: u32, out_array: *mut *mut SonosDevice, out_len: *mut usize)
+                                        -> c_int {
+    discover_into(&Discover::default(), timeout_ms, out_array, out_len)
+}
+
+/// `sonos_discover` with `discovery` instead of the default configuration
+///
+/// # Safety
+///
+/// Same as `sonos_discover`.
+unsafe fn discover_into(discovery: &Discover, timeout_ms: u32, out_array: *mut *mut SonosDevice, out_len: *mut usize)
+                        -> c_int {
+    if out_array.is_null() || out_len.is_null() {
+        return SONOS_ERROR_INVALID_ARGUMENT;
+    }
+
+    // Unwinding into the caller's C frames is undefined behavior. The discovery isn't used after a panic, a broken
+    // state of it can't be observed.
+    let timeout = Duration::from_millis(u64::from(timeout_ms));
+    let devices = match panic::catch_unwind(panic::AssertUnwindSafe(|| discover(discovery, timeout))) {
+        Ok(Some(devices)) => devices,
+        Ok(None) => return SONOS_ERROR_DISCOVERY,
+        Err(_) => return SONOS_ERROR_PANIC
+    };
+    let len = devices.len();
+    let array = if devices.is_empty() {
+        ptr::null_mut()
+    } else {
+        Box::into_raw(devices.into_boxed_slice()) as *mut SonosDevice
+    };
+
+    // Checked for null above, valid for writes by the contract of the caller
+    *out_array = array;
+    *out_len = len;
+
+    SONOS_OK
+}
+
+/// Releases the devices of `sonos_discover`, does nothing if `array` is null.
+///
+/// # Safety
+///
+/// `array` and `len` have to be the outputs of a successful `sonos_discover`, every array can only be released once.
+#[no_mangle]
+pub unsafe extern "C" fn sonos_free_results(array: *mut SonosDevice, len: usize) {
+    if array.is_null() {
+        return;
+    }
+
+    // `sonos_discover` created the array from a boxed slice of `len` devices and each string with `CString::into_raw`
+    let devices = Box::from_raw(ptr::slice_from_raw_parts_mut(array, len));
+    for device in devices.iter() {
+        drop(CString::from_raw(device.ip));
+        if !device.uuid.is_null() {
+            drop(CString::from_raw(device.uuid));
+        }
+    }
+}
+
+/// Devices of a discovery with `discovery`, `None` if it failed
+fn discover(discovery: &Discover, timeout: Duration) -> Option<Vec<SonosDevice>> {
+    let result = match discovery.start(Some(timeout), None) {
+        Ok(result) => result,
+        Err(e) => {
+            warn!("The discovery for the c bindings failed: {}", e);
+            return None;
+        }
+    };
+
+    let devices = result.unique_ips().into_iter()
+        .map(|ip| SonosDevice {
+            ip: c_string(&ip.to_string()),
+            uuid: result.uuid(&ip).map_or(ptr::null_mut(), c_string)
+        })
+        .collect();
+
+    Some(devices)
+}
+
+/// Owned copy of `value` for c, the responses are utf-8 without nul bytes (a nul byte would end the string early)
+fn c_string(value: &str) -> *mut c_char {
+    let value = value.split('\0').next().unwrap_or_default();
+    CString::new(value).unwrap_or_default().into_raw()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clock::ManualClock;
+    use fake_transport::FakeNetwork;
+    use std::ffi::CStr;
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use DiscoveryConfig;
+
+    /// Discovery on a fake network with a manual clock, without validating the sources
+    fn discovery() -> (Discover, FakeNetwork) {
+        let clock = Arc::new(ManualClock::new());
+        let network = FakeNetwork::new(clock.clone());
+        let discovery = Discover::with_config(DiscoveryConfig {
+            clock,
+            transport: Arc::new(network.clone()),
+            validate_source: false,
+            ..DiscoveryConfig::default()
+        });
+
+        (discovery, network)
+    }
+
+    /// Search response of the speaker at `ip`, with the `uuid` in its USN
+    fn answer(network: &FakeNetwork, ip: &str, uuid: Option<&str>) {
+        let usn = uuid.map_or(String::new(), |uuid| {
+            format!("USN: uuid:{}::urn:schemas-upnp-org:device:ZonePlayer:1\r\n", uuid)
+        });
+        let response = format!("HTTP/1.1 200 OK\r\n\
+                                CACHE-CONTROL: max-age = 1800\r\n\
+                                LOCATION: http://{}:1400/xml/device_description.xml\r\n\
+                                ST: urn:schemas-upnp-org:device:ZonePlayer:1\r\n\
+                                {}\r\n", ip, usn);
+        network.answer(SocketAddr::new(ip.parse().unwrap(), 1900), response.as_bytes());
+    }
+
+    /// `(ip, uuid)` of the devices of a successful `discover_into`
+    unsafe fn devices(array: *mut SonosDevice, len: usize) -> Vec<(String, Option<String>)> {
+        let string = |value: *mut c_char| CStr::from_ptr(value).to_str().unwrap().to_string();
+        let devices = (0..len)
+            .map(|index| &*array.add(index))
+            .map(|device| (string(device.ip), if device.uuid.is_null() { None } else { Some(string(device.uuid)) }))
+            .collect();
+        sonos_free_results(array, len);
+
+        devices
+    }
+
+    #[test]
+    fn devices_are_returned_as_c_strings() {
+        let (discovery, network) = discovery();
+        answer(&network, "192.168.1.20", Some("RINCON_000E58A0000001400"));
+        answer(&network, "192.168.1.21", None);
+        // Cut at the nul byte
+        answer(&network, "192.168.1.22", Some("RINCON_000E58B0000001400\0garbage"));
+
+        let mut array = ptr::null_mut();
+        let mut len = 0;
+        unsafe {
+            assert_eq!(discover_into(&discovery, 100, &mut array, &mut len), SONOS_OK);
+            assert_eq!(devices(array, len), vec![
+                ("192.168.1.20".to_string(), Some("RINCON_000E58A0000001400".to_string())),
+                ("192.168.1.21".to_string(), None),
+                ("192.168.1.22".to_string(), Some("RINCON_000E58B0000001400".to_string()))
+            ]);
+        }
+    }
+
+    #[test]
+    fn no_devices_are_an_empty_array() {
+        let (discovery, _network) = discovery();
+
+        // Overwritten with null
+        let mut array = ptr::NonNull::<SonosDevice>::dangling().as_ptr();
+        let mut len = 1;
+        unsafe {
+            assert_eq!(discover_into(&discovery, 100, &mut array, &mut len), SONOS_OK);
+            assert!(array.is_null());
+            assert_eq!(len, 0);
+            sonos_free_results(array, len);
+        }
+    }
+
+    #[test]
+    fn errors_leave_the_outputs_alone() {
+        let (discovery, network) = discovery();
+        network.fail_multicast();
+
+        let mut array = ptr::null_mut();
+        let mut len = 7;
+        unsafe {
+            assert_eq!(discover_into(&discovery, 100, &mut array, &mut len), SONOS_ERROR_DISCOVERY);
+            assert_eq!(discover_into(&discovery, 100, ptr::null_mut(), &mut len), SONOS_ERROR_INVALID_ARGUMENT);
+            assert_eq!(sonos_discover(100, &mut array, ptr::null_mut()), SONOS_ERROR_INVALID_ARGUMENT);
+        }
+        assert!(array.is_null());
+        assert_eq!(len, 7);
+        assert!(network.sent_to().is_empty());
+    }
+}
@@ -0,0 +1,95 @@
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+/// Status line and headers of a received ssdp packet, passed to a `ResponseFilter`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SsdpHeaders {
+    ip: IpAddr,
+    status_line: String,
+    headers: Vec<(String, String)>
+}
+
+impl SsdpHeaders {
+    /// Parses the lines of `data`, lines without a `:` are skipped
+    pub(crate) fn parse(ip: IpAddr, data: &[u8]) -> Self {
+        let text = String::from_utf8_lossy(data);
+        let mut lines = text.lines();
+        let status_line = lines.next().unwrap_or_default().trim().to_string();
+        let headers = lines
+            .filter_map(|line| {
+                let mut parts = line.splitn(2, ':');
+                match (parts.next(), parts.next()) {
+                    (Some(key), Some(value)) => Some((key.trim().to_string(), value.trim().to_string())),
+                    _ => None
+                }
+            })
+            .collect();
+
+        SsdpHeaders {
+            ip,
+            status_line,
+            headers
+        }
+    }
+
+    /// Address the packet was sent from
+    pub fn ip(&self) -> IpAddr {
+        self.ip
+    }
+
+    /// First line, e.g. `HTTP/1.1 200 OK` for a search response or `NOTIFY * HTTP/1.1` for an announcement
+    pub fn status_line(&self) -> &str {
+        &self.status_line
+    }
+
+    /// Value of the first header `name` (case insensitive)
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// All headers in the order of the packet
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.headers.iter().map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+}
+
+/// Decides which responses are devices of the discovery instead of the built-in check of the search target,
+/// installed with `Discover::set_response_filter`
+///
+/// # Examples
+///
+/// ```
+/// use sonos_discovery::ResponseFilter;
+///
+/// // Sonos Boost and Bridge units next to the speakers
+/// let filter = ResponseFilter::new(|headers| headers.get("SERVER").map_or(false, |server| server.contains("Sonos")));
+/// ```
+#[derive(Clone)]
+pub struct ResponseFilter {
+    accept: Arc<dyn Fn(&SsdpHeaders) -> bool + Send + Sync>
+}
+
+impl ResponseFilter {
+    /// Filter accepting the responses for which `accept` returns true
+    pub fn new<F>(accept: F) -> Self
+        where F: Fn(&SsdpHeaders) -> bool + Send + Sync + 'static
+    {
+        ResponseFilter {
+            accept: Arc::new(accept)
+        }
+    }
+
+    /// Whether the response with `headers` is accepted
+    pub fn accepts(&self, headers: &SsdpHeaders) -> bool {
+        (self.accept)(headers)
+    }
+}
+
+impl fmt::Debug for ResponseFilter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("ResponseFilter")
+    }
+}
@@ -0,0 +1,286 @@
+//! GENA (UPnP eventing) subscription to the `ZoneGroupTopology` service of a speaker, see `Monitor::start_with_events`
+
+use description::DEVICE_PORT;
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+use std::time::{Duration, Instant};
+
+/// Event url of the `ZoneGroupTopology` service
+const EVENT_PATH: &str = "/ZoneGroupTopology/Event";
+
+/// Requested duration of a subscription, renewed after half of the granted duration
+const SUBSCRIPTION_SECONDS: u64 = 300;
+
+/// Shortest duration of a subscription taken from a response, a granted `Second-0` would be renewed over and over
+const MIN_SUBSCRIPTION_SECONDS: u64 = 30;
+
+/// Timeout for connecting and each read/write of the requests and the received events
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Largest accepted header of an event, sonos sends the zone group state in the body
+const MAX_HEADER: usize = 8 * 1024;
+
+/// Subscription to the topology events of a speaker, the events are sent to the `listener`
+#[derive(Debug)]
+pub(crate) struct Subscription {
+    device: SocketAddr,
+    listener: TcpListener,
+    /// Subscription id (`SID` header)
+    sid: String,
+    /// Time the subscription has to be renewed
+    renew_at: Instant
+}
+
+impl Subscription {
+    /// Subscribes to the topology events of the speaker at `ip`, received on a local port of the interface
+    /// which reaches the speaker
+    pub(crate) fn subscribe(ip: IpAddr) -> Result<Self> {
+        let device = SocketAddr::new(ip, DEVICE_PORT);
+        // The address the speaker can call back is the local end of a connection to it
+        let local = TcpStream::connect_timeout(&device, REQUEST_TIMEOUT)?.local_addr()?.ip();
+        let listener = TcpListener::bind(SocketAddr::new(local, 0))?;
+        listener.set_nonblocking(true)?;
+
+        let callback = format!("CALLBACK: <http://{}/>\r\nNT: upnp:event\r\n", listener.local_addr()?);
+        let response = request(device, "SUBSCRIBE", &callback)?;
+        let sid = header(&response, "SID")
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Subscription response has no SID"))?;
+        let renew_at = Instant::now() + granted(&response) / 2;
+
+        Ok(Subscription {
+            device,
+            listener,
+            sid,
+            renew_at
+        })
+    }
+
+    /// Renews the subscription if half of its duration passed, fails if the speaker dropped it
+    pub(crate) fn renew_if_due(&mut self) -> Result<()> {
+        if Instant::now() < self.renew_at {
+            return Ok(());
+        }
+
+        let response = request(self.device, "SUBSCRIBE", &format!("SID: {}\r\n", self.sid))?;
+        self.renew_at = Instant::now() + granted(&response) / 2;
+
+        Ok(())
+    }
+
+    /// Answers a pending event, returns whether it was a change of the topology.
+    /// The initial event (`SEQ: 0`) with the current state isn't a change.
+    pub(crate) fn accept_event(&self) -> Result<bool> {
+        let mut stream = match self.listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => return Ok(false),
+            Err(e) => return Err(e)
+        };
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+        stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+
+        let head = read_head(&mut stream)?;
+        stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")?;
+
+        let ours = header(&head, "SID").is_some_and(|sid| sid == self.sid);
+        let initial = header(&head, "SEQ").is_some_and(|seq| seq == "0");
+
+        Ok(head.starts_with("NOTIFY ") && ours && !initial)
+    }
+
+    /// Address of the speaker sending the events
+    pub(crate) fn device(&self) -> IpAddr {
+        self.device.ip()
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        if let Err(e) = request(self.device, "UNSUBSCRIBE", &format!("SID: {}\r\n", self.sid)) {
+            debug!("Couldn't unsubscribe from the topology events of {}: {}", self.device, e);
+        }
+    }
+}
+
+/// Sends a GENA request with `headers` to the event url and returns the head of the `200` response
+fn request(device: SocketAddr, method: &str, headers: &str) -> Result<String> {
+    let mut stream = TcpStream::connect_timeout(&device, REQUEST_TIMEOUT)?;
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+
+    let timeout = if method == "SUBSCRIBE" {
+        format!("TIMEOUT: Second-{}\r\n", SUBSCRIPTION_SECONDS)
+    } else {
+        String::new()
+    };
+    let request = format!("{} {} HTTP/1.1\r\nHOST: {}\r\n{}{}Content-Length: 0\r\nConnection: close\r\n\r\n",
+                          method, EVENT_PATH, device, headers, timeout);
+    stream.write_all(request.as_bytes())?;
+
+    let head = read_head(&mut stream)?;
+    match head.lines().next().and_then(|status| status.split_whitespace().nth(1)) {
+        Some("200") => Ok(head),
+        _ => Err(Error::new(ErrorKind::InvalidData,
+                            format!("Unexpected http status: {}", head.lines().next().unwrap_or_default())))
+    }
+}
+
+/// Reads the request or status line and the headers of `stream`, the body is left unread
+fn read_head<R: Read>(stream: &mut R) -> Result<String> {
+    let mut head = Vec::new();
+    let mut byte = [0; 1];
+    while !head.ends_with(b"\r\n\r\n") {
+        if stream.read(&mut byte)? == 0 || head.len() >= MAX_HEADER {
+            return Err(Error::new(ErrorKind::InvalidData, "Incomplete http header"));
+        }
+        head.push(byte[0]);
+    }
+
+    Ok(String::from_utf8_lossy(&head).into_owned())
+}
+
+/// Value of the header `name` (case insensitive)
+fn header(head: &str, name: &str) -> Option<String> {
+    head.lines()
+        .skip(1)
+        .filter_map(|line| {
+            let mut parts = line.splitn(2, ':');
+            match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) if key.trim().eq_ignore_ascii_case(name) => Some(value.trim().to_string()),
+                _ => None
+            }
+        })
+        .next()
+}
+
+/// Duration of the subscription granted in the `TIMEOUT` header (`Second-300`), the requested one without it (or
+/// for `Second-infinite`) and at least `MIN_SUBSCRIPTION_SECONDS`
+fn granted(head: &str) -> Duration {
+    let seconds = header(head, "TIMEOUT")
+        .and_then(|timeout| timeout.trim_start_matches("Second-").parse().ok())
+        .unwrap_or(SUBSCRIPTION_SECONDS);
+
+    Duration::from_secs(seconds.max(MIN_SUBSCRIPTION_SECONDS))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Shutdown;
+    use std::sync::mpsc;
+    use std::thread;
+
+    const NOTIFY: &str = "NOTIFY / HTTP/1.1\r\nHOST: 192.168.1.2:40000\r\nCONTENT-TYPE: text/xml\r\nNT: upnp:event\r\n\
+                          NTS: upnp:propchange\r\nSID: uuid:RINCON_000E58A0000001400_sub0000000001\r\nSEQ: 1\r\n\
+                          Content-Length: 4\r\n\r\nbody";
+
+    #[test]
+    fn headers() {
+        let cases = [("SID", Some("uuid:RINCON_000E58A0000001400_sub0000000001")),
+                     ("sid", Some("uuid:RINCON_000E58A0000001400_sub0000000001")),
+                     ("Seq", Some("1")),
+                     ("HOST", Some("192.168.1.2:40000")),
+                     ("Content-Length", Some("4")),
+                     ("NOTIFY / HTTP/1.1", None),
+                     ("NOTIFY", None),
+                     ("TIMEOUT", None),
+                     ("", None)];
+        for &(name, value) in &cases {
+            assert_eq!(header(NOTIFY, name).as_deref(), value, "{}", name);
+        }
+        assert_eq!(header("HTTP/1.1 200 OK\r\nSID:\r\nSID: second\r\n\r\n", "SID").as_deref(), Some(""));
+    }
+
+    #[test]
+    fn granted_durations() {
+        let cases = [("TIMEOUT: Second-1800", 1800),
+                     ("timeout:Second-60", 60),
+                     ("TIMEOUT: Second-30", 30),
+                     ("TIMEOUT: Second-29", MIN_SUBSCRIPTION_SECONDS),
+                     ("TIMEOUT: Second-0", MIN_SUBSCRIPTION_SECONDS),
+                     ("TIMEOUT: Second-infinite", SUBSCRIPTION_SECONDS),
+                     ("TIMEOUT: Second--1", SUBSCRIPTION_SECONDS),
+                     ("TIMEOUT: 600", 600),
+                     ("TIMEOUT:", SUBSCRIPTION_SECONDS),
+                     ("SID: uuid:RINCON_000E58A0000001400_sub0000000001", SUBSCRIPTION_SECONDS)];
+        for &(line, seconds) in &cases {
+            let head = format!("HTTP/1.1 200 OK\r\n{}\r\n\r\n", line);
+            assert_eq!(granted(&head), Duration::from_secs(seconds), "{}", line);
+        }
+    }
+
+    #[test]
+    fn heads() {
+        let (head, body) = NOTIFY.split_at(NOTIFY.len() - 4);
+        let mut stream = NOTIFY.as_bytes();
+        assert_eq!(read_head(&mut stream).unwrap(), head);
+        // The body is left for the caller
+        assert_eq!(stream, body.as_bytes());
+
+        let oversized = format!("NOTIFY / HTTP/1.1\r\nX-PADDING: {}\r\n\r\n", "x".repeat(MAX_HEADER));
+        for incomplete in &["", "NOTIFY / HTTP/1.1\r\nSID: uuid:1\r\n", "NOTIFY / HTTP/1.1\n\n", &oversized] {
+            assert_eq!(read_head(&mut incomplete.as_bytes()).unwrap_err().kind(), ErrorKind::InvalidData,
+                       "{:?}", incomplete);
+        }
+        let lossy = read_head(&mut &b"NOTIFY / HTTP/1.1\r\nSID: \xff\r\n\r\n"[..]).unwrap();
+        assert_eq!(header(&lossy, "SID").as_deref(), Some("\u{fffd}"));
+    }
+
+    /// Subscription of `sid` on a local port, its speaker is gone
+    fn subscription(sid: &str) -> Subscription {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let device = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+
+        Subscription {
+            device,
+            listener,
+            sid: sid.to_string(),
+            renew_at: Instant::now() + Duration::from_secs(SUBSCRIPTION_SECONDS)
+        }
+    }
+
+    /// Sends `event` to `subscription`, returns the result of accepting it and the response
+    fn send(subscription: &Subscription, event: &str) -> (Result<bool>, String) {
+        let address = subscription.listener.local_addr().unwrap();
+        let event = event.to_string();
+        let (sent, pending) = mpsc::channel();
+        let sender = thread::spawn(move || {
+            let mut stream = TcpStream::connect(address).unwrap();
+            stream.write_all(event.as_bytes()).unwrap();
+            stream.shutdown(Shutdown::Write).unwrap();
+            sent.send(()).unwrap();
+            let mut response = String::new();
+            let _ = stream.read_to_string(&mut response);
+            response
+        });
+        // The connection waits in the backlog of the listener
+        pending.recv().unwrap();
+        let accepted = subscription.accept_event();
+
+        (accepted, sender.join().unwrap())
+    }
+
+    #[test]
+    fn events() {
+        let sid = "uuid:RINCON_000E58A0000001400_sub0000000001";
+        let events = subscription(sid);
+        assert!(!events.accept_event().unwrap());
+
+        let (accepted, response) = send(&events, NOTIFY);
+        assert!(accepted.unwrap());
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+
+        let initial = NOTIFY.replace("SEQ: 1", "SEQ: 0");
+        let others = NOTIFY.replace(sid, "uuid:RINCON_000E58B0000001400_sub0000000007");
+        let subscribe = NOTIFY.replace("NOTIFY ", "SUBSCRIBE ");
+        for event in &[initial, others, subscribe] {
+            let (accepted, response) = send(&events, event);
+            assert!(!accepted.unwrap(), "{}", event);
+            assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        }
+
+        let (accepted, _) = send(&events, "NOTIFY / HTTP/1.1\r\nSID: ");
+        assert_eq!(accepted.unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,32 @@
+use std::fmt::Write;
+
+/// Formats `data` like `hexdump -C`: offset, 16 bytes in hex and the printable ascii characters.
+///
+/// ```text
+/// 00000000  48 54 54 50 2f 31 2e 31  20 32 30 30 20 4f 4b 0d  |HTTP/1.1 200 OK.|
+/// ```
+pub fn hexdump(data: &[u8]) -> String {
+    let mut dump = String::new();
+
+    for (line, chunk) in data.chunks(16).enumerate() {
+        let _ = write!(dump, "{:08x} ", line * 16);
+        for column in 0..16 {
+            if column == 8 {
+                dump.push(' ');
+            }
+            match chunk.get(column) {
+                Some(byte) => {
+                    let _ = write!(dump, " {:02x}", byte);
+                }
+                None => dump.push_str("   ")
+            }
+        }
+
+        dump.push_str("  |");
+        dump.extend(chunk.iter().map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' }));
+        dump.push_str("|\n");
+    }
+    let _ = write!(dump, "{:08x}", data.len());
+
+    dump
+}
@@ -0,0 +1,217 @@
+use snapshot::Snapshot;
+use std::io::Result;
+use std::net::IpAddr;
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Change of a device between two consecutive snapshots
+pub enum Change {
+    /// The device answered from different addresses
+    AddressChanged {
+        old: Vec<IpAddr>,
+        new: Vec<IpAddr>
+    },
+    /// The firmware (software version) of the device changed
+    FirmwareChanged {
+        old: Option<String>,
+        new: Option<String>
+    },
+    /// The device is missing in a snapshot
+    Disappeared,
+    /// The device is back after missing in at least one snapshot
+    Reappeared
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A `Change` with the unix time of the snapshot it was detected in
+pub struct HistoryEvent {
+    pub time: u64,
+    pub change: Change
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Audit trail of a single device over a series of snapshots
+pub struct DeviceHistory {
+    pub uuid: String,
+    /// Room name in the latest snapshot containing the device
+    pub room_name: Option<String>,
+    /// Unix time of the first snapshot containing the device
+    pub first_seen: u64,
+    /// Unix time of the last snapshot containing the device
+    pub last_seen: u64,
+    /// Changes in chronological order
+    pub events: Vec<HistoryEvent>
+}
+
+/// Builds the history of every device in `snapshots` (ordered oldest first), sorted by uuid
+pub fn history(snapshots: &[Snapshot]) -> Vec<DeviceHistory> {
+    let mut histories: Vec<DeviceHistory> = Vec::new();
+
+    for (index, snapshot) in snapshots.iter().enumerate() {
+        let previous = if index > 0 { snapshots.get(index - 1) } else { None };
+
+        for record in &snapshot.records {
+            let position = histories.iter().position(|history| history.uuid == record.uuid);
+            let history = match position {
+                Some(position) => &mut histories[position],
+                None => {
+                    histories.push(DeviceHistory {
+                        uuid: record.uuid.clone(),
+                        room_name: None,
+                        first_seen: snapshot.taken,
+                        last_seen: snapshot.taken,
+                        events: Vec::new()
+                    });
+                    let last = histories.len() - 1;
+                    &mut histories[last]
+                }
+            };
+
+            let before = previous.and_then(|previous| previous.records.iter().find(|old| old.uuid == record.uuid));
+            match before {
+                Some(old) => {
+                    if old.ips != record.ips {
+                        history.events.push(HistoryEvent {
+                            time: snapshot.taken,
+                            change: Change::AddressChanged {
+                                old: old.ips.clone(),
+                                new: record.ips.clone()
+                            }
+                        });
+                    }
+                    if old.software_version != record.software_version {
+                        history.events.push(HistoryEvent {
+                            time: snapshot.taken,
+                            change: Change::FirmwareChanged {
+                                old: old.software_version.clone(),
+                                new: record.software_version.clone()
+                            }
+                        });
+                    }
+                }
+                // Known from an older snapshot, but missing in the previous one
+                None if history.first_seen < snapshot.taken => history.events.push(HistoryEvent {
+                    time: snapshot.taken,
+                    change: Change::Reappeared
+                }),
+                None => {}
+            }
+
+            history.room_name = record.room_name.clone().or_else(|| history.room_name.take());
+            history.last_seen = snapshot.taken;
+        }
+
+        if let Some(previous) = previous {
+            for old in &previous.records {
+                if !snapshot.records.iter().any(|record| record.uuid == old.uuid) {
+                    if let Some(history) = histories.iter_mut().find(|history| history.uuid == old.uuid) {
+                        history.events.push(HistoryEvent {
+                            time: snapshot.taken,
+                            change: Change::Disappeared
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    histories.sort_by(|a, b| a.uuid.cmp(&b.uuid));
+    histories
+}
+
+/// Loads all snapshots in `directory` and builds the history of every device
+pub fn load_history(directory: &Path) -> Result<Vec<DeviceHistory>> {
+    let snapshots = Snapshot::list(directory)?.iter()
+        .map(|path| Snapshot::load(path))
+        .collect::<Result<Vec<Snapshot>>>()?;
+
+    Ok(history(&snapshots))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use snapshot::tests::{directory, record, snapshot};
+    use std::fs;
+
+    fn ips(ips: &[&str]) -> Vec<IpAddr> {
+        ips.iter().map(|ip| ip.parse().unwrap()).collect()
+    }
+
+    fn event(time: u64, change: Change) -> HistoryEvent {
+        HistoryEvent { time, change }
+    }
+
+    #[test]
+    fn histories() {
+        let mut renamed = record("RINCON_A", "80.1-55240", &["192.168.1.30"]);
+        renamed.room_name = None;
+        let snapshots = [snapshot(10, vec![record("RINCON_B", "79.1-56030", &["192.168.1.21"]),
+                                           record("RINCON_A", "79.1-56030", &["192.168.1.20"])]),
+                         snapshot(20, vec![record("RINCON_A", "79.1-56030", &["192.168.1.20"])]),
+                         snapshot(30, vec![record("RINCON_C", "79.1-56030", &["192.168.1.22"])]),
+                         snapshot(40, vec![renamed, record("RINCON_B", "79.1-56030", &["192.168.1.21", "fe80::1"])])];
+
+        let expected = vec![
+            DeviceHistory {
+                uuid: "RINCON_A".to_string(),
+                // Kept from the snapshot which still had it
+                room_name: Some("Kitchen".to_string()),
+                first_seen: 10,
+                last_seen: 40,
+                events: vec![event(30, Change::Disappeared), event(40, Change::Reappeared)]
+            },
+            DeviceHistory {
+                uuid: "RINCON_B".to_string(),
+                room_name: Some("Kitchen".to_string()),
+                first_seen: 10,
+                last_seen: 40,
+                events: vec![event(20, Change::Disappeared), event(40, Change::Reappeared)]
+            },
+            DeviceHistory {
+                uuid: "RINCON_C".to_string(),
+                room_name: Some("Kitchen".to_string()),
+                first_seen: 30,
+                last_seen: 30,
+                events: vec![event(40, Change::Disappeared)]
+            }
+        ];
+        assert_eq!(history(&snapshots), expected);
+        assert_eq!(history(&[]), vec![]);
+    }
+
+    #[test]
+    fn changes_between_consecutive_snapshots() {
+        let snapshots = [snapshot(10, vec![record("RINCON_A", "79.1-56030", &["192.168.1.20"])]),
+                         snapshot(20, vec![record("RINCON_A", "79.1-56030", &["192.168.1.20"])]),
+                         snapshot(30, vec![record("RINCON_A", "80.1-55240", &["192.168.1.30", "fe80::1"])]),
+                         snapshot(40, vec![record("RINCON_A", "80.1-55240", &["192.168.1.20"])])];
+
+        let histories = history(&snapshots);
+        assert_eq!(histories.len(), 1);
+        assert_eq!((histories[0].first_seen, histories[0].last_seen), (10, 40));
+        assert_eq!(histories[0].events, vec![
+            event(30, Change::AddressChanged { old: ips(&["192.168.1.20"]), new: ips(&["192.168.1.30", "fe80::1"]) }),
+            event(30, Change::FirmwareChanged {
+                old: Some("79.1-56030".to_string()),
+                new: Some("80.1-55240".to_string())
+            }),
+            event(40, Change::AddressChanged { old: ips(&["192.168.1.30", "fe80::1"]), new: ips(&["192.168.1.20"]) })
+        ]);
+    }
+
+    #[test]
+    fn histories_of_directories() {
+        let path = directory("history");
+        snapshot(20, vec![record("RINCON_A", "80.1-55240", &["192.168.1.20"])]).write(&path).unwrap();
+        snapshot(10, vec![record("RINCON_A", "79.1-56030", &["192.168.1.20"])]).write(&path).unwrap();
+
+        let histories = load_history(&path).unwrap();
+        assert_eq!(histories[0].events, vec![event(20, Change::FirmwareChanged {
+            old: Some("79.1-56030".to_string()),
+            new: Some("80.1-55240".to_string())
+        })]);
+        fs::remove_dir_all(&path).unwrap();
+        assert!(load_history(&path).is_err());
+    }
+}
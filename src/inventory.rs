@@ -0,0 +1,99 @@
+use description::{self, DescriptionCache};
+use engine::Response;
+use fetcher::HttpFetcher;
+use parallel::run_limited;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Timeout for connecting to a device and for each read/write while fetching its description
+pub(crate) const FETCH_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+/// Complete inventory record of a sonos device, see `Discover::inventory`
+pub struct InventoryRecord {
+    /// Unique id (`RINCON_...`)
+    pub uuid: String,
+    pub serial_number: Option<String>,
+    /// Product name (e.g. "Sonos One")
+    pub model_name: Option<String>,
+    /// Model identifier (e.g. "S18")
+    pub model_number: Option<String>,
+    /// Firmware version
+    pub software_version: Option<String>,
+    pub room_name: Option<String>,
+    /// All addresses the device answered from
+    pub ips: Vec<IpAddr>,
+    /// Id of the sonos household (`X-RINCON-HOUSEHOLD` header of the discovery response)
+    pub household: Option<String>
+}
+
+/// Fetches the description of every responding device and merges devices which answered from several addresses,
+/// with at most `concurrency` requests at a time (and one per device).
+pub(crate) fn collect(fetcher: &Arc<dyn HttpFetcher>, cache: Option<&Arc<DescriptionCache>>, responses: &[Response],
+                      timeout: Duration, concurrency: usize) -> Vec<InventoryRecord> {
+    let mut ips: Vec<IpAddr> = responses.iter().map(|response| response.addr.ip()).collect();
+    ips.sort();
+    ips.dedup();
+
+    let jobs: Vec<(IpAddr, Option<String>)> = ips.iter()
+        .map(|&ip| (ip, responses.iter()
+            .filter(|response| response.addr.ip() == ip)
+            .filter_map(Response::uuid)
+            .next()))
+        .collect();
+    let fetcher = Arc::clone(fetcher);
+    let cache = cache.cloned();
+    let fetches = run_limited(jobs, concurrency, move |(ip, uuid)|
+        description::fetch(&*fetcher, cache.as_deref(), uuid.as_deref(), ip, timeout));
+
+    let mut records: Vec<InventoryRecord> = Vec::new();
+    for (ip, fetch) in ips.into_iter().zip(fetches) {
+        let description = match fetch {
+            Ok(Ok(description)) => description,
+            Ok(Err(e)) => {
+                warn!("Couldn't fetch the description of {}: {}", ip, e);
+                continue;
+            }
+            Err(_) => {
+                warn!("Fetching the description of {} panicked", ip);
+                continue;
+            }
+        };
+
+        let uuid = match description.udn {
+            Some(ref udn) => udn.trim_start_matches("uuid:").to_string(),
+            None => {
+                warn!("Description of {} has no UDN", ip);
+                continue;
+            }
+        };
+
+        if let Some(record) = records.iter_mut().find(|record| record.uuid == uuid) {
+            record.ips.push(ip);
+            continue;
+        }
+
+        let household = responses.iter()
+            .filter(|response| response.addr.ip() == ip)
+            .filter_map(|response| response.ssdp.header("X-RINCON-HOUSEHOLD"))
+            .next()
+            .map(str::to_string);
+
+        records.push(InventoryRecord {
+            uuid,
+            serial_number: description.serial_number,
+            model_name: description.model_name,
+            model_number: description.model_number,
+            software_version: description.software_version,
+            room_name: description.room_name,
+            ips: vec![ip],
+            household
+        });
+    }
+
+    records
+}
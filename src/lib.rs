@@ -1,143 +1,1625 @@
-extern crate socket;
+#[cfg(feature = "async-io")]
+extern crate async_io as async_io_runtime;
+#[cfg(feature = "net")]
+extern crate if_addrs;
+#[cfg(all(target_os = "linux", feature = "netns"))]
+extern crate libc;
+#[cfg_attr(feature = "net", macro_use)]
+extern crate log;
+#[cfg(feature = "python")]
+extern crate pyo3;
+#[cfg(feature = "reqwest")]
+extern crate reqwest;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(any(feature = "device-cache", feature = "snapshot"))]
+extern crate serde_json;
+#[cfg(feature = "net")]
+extern crate socket2;
+#[cfg(feature = "tokio")]
+extern crate tokio as tokio_runtime;
+#[cfg(feature = "tracing")]
+extern crate tracing;
+#[cfg(feature = "ureq")]
+extern crate ureq;
 
-use socket::{AF_INET, Socket, SOCK_DGRAM, IP_MULTICAST_TTL, IPPROTO_IP};
-use std::io::{Error, ErrorKind, Result};
-use std::net::{IpAddr, SocketAddr};
-use std::str::FromStr;
-use std::sync::{Arc, mpsc};
+#[cfg(feature = "async-io")]
+pub mod async_io;
+#[cfg(feature = "net")]
+mod audit;
+#[cfg(feature = "net")]
+mod builder;
+#[cfg(feature = "net")]
+mod cache;
+#[cfg(feature = "net")]
+mod cancel;
+#[cfg(feature = "net")]
+mod clock;
+#[cfg(feature = "net")]
+mod config;
+// The fetches, caches and header parsers for the responses are only used by the discovery
+#[cfg_attr(not(feature = "net"), allow(dead_code))]
+mod description;
+#[cfg(feature = "net")]
+mod device;
+#[cfg(feature = "device-cache")]
+mod device_cache;
+#[cfg(feature = "net")]
+mod device_set;
+#[cfg(feature = "net")]
+mod dns;
+#[cfg(feature = "net")]
+mod engine;
+#[cfg(feature = "net")]
+mod error;
+#[cfg(test)]
+mod fake_fetcher;
+#[cfg(all(test, feature = "net"))]
+mod fake_transport;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod fetcher;
+#[cfg_attr(not(feature = "net"), allow(dead_code))]
+mod filter;
+#[cfg(feature = "net")]
+mod gena;
+#[cfg(feature = "net")]
+mod hexdump;
+#[cfg(feature = "snapshot")]
+mod history;
+#[cfg(feature = "net")]
+mod inventory;
+#[cfg(feature = "net")]
+mod listener;
+#[cfg(feature = "net")]
+mod metrics;
+#[cfg(feature = "net")]
+mod monitor;
+#[cfg(all(target_os = "linux", feature = "netns"))]
+mod netns;
+#[cfg(feature = "net")]
+mod parallel;
+mod product;
+#[cfg(feature = "python")]
+mod python;
+#[cfg(feature = "net")]
+mod ratelimit;
+#[cfg(feature = "net")]
+mod result;
+#[cfg(feature = "net")]
+mod retry;
+#[cfg(feature = "net")]
+mod search;
+#[cfg(feature = "snapshot")]
+mod snapshot;
+pub mod ssdp;
+#[cfg(feature = "net")]
+mod sweep;
+#[cfg(feature = "test-util")]
+pub mod test_util;
+#[cfg(feature = "tokio")]
+pub mod tokio;
+#[cfg_attr(not(feature = "net"), allow(dead_code))]
+mod topology;
+#[cfg(feature = "net")]
+mod transport;
+#[cfg_attr(not(feature = "net"), allow(dead_code))]
+pub mod upnp;
+
+#[cfg(feature = "net")]
+pub use audit::{AuditRecord, AuditSink, Direction, WriteAuditSink};
+#[cfg(feature = "net")]
+pub use builder::DiscoverBuilder;
+#[cfg(feature = "net")]
+pub use cancel::CancellationToken;
+#[cfg(feature = "net")]
+pub use clock::{Clock, ManualClock, SystemClock};
+#[cfg(feature = "net")]
+pub use config::{DiscoveryConfig, MAX_MX, MX_GRACE};
+pub use description::DeviceDescription;
+#[cfg(feature = "net")]
+pub use device::{DeviceInfo, DeviceKind, Devices};
+#[cfg(feature = "device-cache")]
+pub use device_cache::{CachedDevice, DeviceCache, DEVICE_CACHE_SCHEMA_VERSION};
+#[cfg(feature = "net")]
+pub use device_set::{DeviceSet, DeviceSetDiff};
+#[cfg(feature = "net")]
+pub use error::DiscoveryError;
+#[cfg(feature = "reqwest")]
+pub use fetcher::ReqwestFetcher;
+#[cfg(feature = "ureq")]
+pub use fetcher::UreqFetcher;
+pub use fetcher::{Conditional, HttpFetcher, ProxyFetcher, TcpFetcher};
+pub use filter::{ResponseFilter, SsdpHeaders};
+#[cfg(feature = "snapshot")]
+pub use history::{history, load_history, Change, DeviceHistory, HistoryEvent};
+#[cfg(feature = "net")]
+pub use inventory::InventoryRecord;
+#[cfg(feature = "net")]
+pub use listener::DeviceListener;
+#[cfg(feature = "net")]
+pub use metrics::{CounterMetrics, MetricsSink};
+#[cfg(feature = "net")]
+pub use monitor::{DiscoveryService, Monitor, MonitorEvent, MonitorSnapshot};
+#[cfg(all(target_os = "linux", feature = "netns"))]
+pub use netns::enter_netns;
+pub use product::{product, Product, SonosSystem};
+#[cfg(feature = "net")]
+pub use result::{DiscoveryReport, DiscoveryResult, DiscoveryStats, RawResponse, SearchMethod, Sighting};
+#[cfg(feature = "net")]
+pub use retry::{ExponentialBackoff, FixedRetry, NoRetry, RetryPolicy};
+#[cfg(feature = "net")]
+pub use search::SearchSocket;
+#[cfg(feature = "snapshot")]
+pub use snapshot::{Snapshot, SnapshotDiff, SNAPSHOT_SCHEMA_VERSION};
+#[cfg(feature = "net")]
+pub use sweep::{SweepNetwork, MIN_SWEEP_PREFIX};
+pub use topology::{MemberRole, ZoneGroup, ZoneGroupMember};
+#[cfg(feature = "net")]
+pub use transport::{best_interface, InterfaceChoice, Transport, TransportFactory, UdpTransport};
+pub use upnp::{UpnpHeaders, SSDP_LINK_LOCAL_V6, SSDP_MULTICAST_V4, SSDP_PORT, SSDP_SITE_LOCAL_V6, ZONE_PLAYER};
+
+#[cfg(feature = "net")]
+use cache::ResultCache;
+#[cfg(feature = "net")]
+use description::DescriptionCache;
+#[cfg(feature = "net")]
+use engine::Engine;
+#[cfg(feature = "net")]
+use ratelimit::RateLimiter;
+#[cfg(feature = "net")]
+use std::cmp;
+#[cfg(feature = "net")]
+use error::Result;
+#[cfg(feature = "net")]
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+#[cfg(all(target_os = "linux", feature = "netns"))]
+use std::panic;
+#[cfg(feature = "net")]
+use std::ops::ControlFlow;
+#[cfg(feature = "device-cache")]
+use std::path::Path;
+#[cfg(feature = "net")]
+use std::slice;
+#[cfg(feature = "net")]
+use std::sync::{mpsc, Arc, Mutex};
+#[cfg(feature = "net")]
 use std::thread;
-use std::time::Instant;
+#[cfg(feature = "net")]
+use std::time::{Duration, Instant};
+
+/// Time the cached devices get to answer the unicast searches of `Discover::start_cached`
+#[cfg(feature = "device-cache")]
+const CACHE_PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Discovers the sonos devices with the default configuration: waits the whole 5 seconds for every device on the
+/// ipv4 multicast address, same as `Discover::new()?.start(None, None)`.
+///
+/// # Examples
+///
+/// ```no_run
+/// for ip in sonos_discovery::discover().unwrap() {
+///     println!("{}", ip);
+/// }
+/// ```
+#[cfg(feature = "net")]
+pub fn discover() -> Result<DiscoveryResult> {
+    Discover::new()?.start(None, None)
+}
 
-#[derive(Debug)]
+#[cfg(feature = "net")]
+#[derive(Debug, Clone)]
 /// `Discover` type
 ///
 /// Used for discovering sonos devices in the local network via the simple service discovery protocol (ssdp).
 /// The ssd-protocol works via udp sockets. First a certain search-message is sent to the multicast address (239.255.255.250:1900).
 ///
-/// All answer from upnp (universal plug and play) ready devices are processed and filtered ("Sonos" is in the reply).
+/// All answer from upnp (universal plug and play) ready devices are processed and filtered (the `ST` or `USN` header
+/// names the ZonePlayer device type, see `set_lenient_match`).
+///
+/// A `Discover` only holds the configuration, every `start` creates a new socket.
+/// Several `Discover`s are fully independent of each other, e.g. one thread per interface or household
+/// (see `set_interface` and `set_household`) can discover several Sonos systems at the same time.
+///
+/// # Concurrency
+///
+/// `Discover` is `Clone`, `Send` and `Sync`. Clones share the caches (`set_description_ttl`, `set_result_ttl`) and
+/// the rate limit of the duplicate log, nothing else. Concurrent `start`s on the same `Discover` (or its clones)
+/// are safe: each one is an independent search on its own socket and sees every answer to it. Only a fixed local
+/// port (`set_bind_addr` with a port, `set_source_port`) can't be bound twice, the second search fails with
+/// `DiscoveryError::SocketCreate` or `DiscoveryError::SourcePort` unless the port is shared (`set_reuse_port`).
+///
+/// # Examples
+///
+/// ```no_run
+/// use sonos_discovery::Discover;
+/// use std::sync::Arc;
+/// use std::thread;
+///
+/// let discovery = Arc::new(Discover::new().unwrap());
+/// let handlers: Vec<_> = (0..4)
+///     .map(|_| {
+///         let discovery = Arc::clone(&discovery);
+///         thread::spawn(move || discovery.start(None, None).map(|result| result.len()))
+///     })
+///     .collect();
+/// for handler in handlers {
+///     println!("{:?}", handler.join().unwrap());
+/// }
+/// ```
 pub struct Discover {
-    /// Multicast address in the local network
-    multicast_addr: SocketAddr,
-    /// Socket implementation
-    /// INFO: The socket type will likely change in the future due to cross platform compatability
-    socket: Arc<Socket>
+    config: DiscoveryConfig,
+    /// Limits the `debug` messages about duplicate responses, shared by all runs
+    duplicate_log: Arc<RateLimiter>,
+    /// Device descriptions of previous runs, `None` without caching (`set_description_ttl`)
+    descriptions: Option<Arc<DescriptionCache>>,
+    /// First device of the last `start_fast`, asked for the zone groups by the next one
+    last_device: Arc<Mutex<Option<IpAddr>>>,
+    /// Result of the last `start`, `None` without caching (`set_result_ttl`)
+    results: Option<Arc<ResultCache>>
 }
 
+/// `Discover::new` without the `Result`, e.g. for struct fields
+///
+/// # Examples
+///
+/// ```
+/// use sonos_discovery::Discover;
+///
+/// #[derive(Default)]
+/// struct Speakers {
+///     discovery: Discover
+/// }
+///
+/// let speakers = Speakers::default();
+/// ```
+#[cfg(feature = "net")]
+impl Default for Discover {
+    fn default() -> Self {
+        Discover::with_config(DiscoveryConfig::default())
+    }
+}
+
+/// Sharing a `Discover` between threads is part of its api, a field which isn't `Send` and `Sync` fails the build here
+#[cfg(feature = "net")]
+#[allow(dead_code)]
+fn assert_send_sync() {
+    fn send_sync<T: Send + Sync>() {}
+    send_sync::<Discover>();
+}
+
+#[cfg(feature = "net")]
 impl Discover {
-    /// Creates a new `Discovery`. Uses the default socket on the default ipv4 multicast address (239.255.255.250:1900).
+    /// Creates a new `Discover`. Uses the default socket on the default ipv4 multicast address (239.255.255.250:1900).
+    ///
+    /// Never fails, the sockets are only created by `start`. `Discover::default()` is the same without the `Result`.
     ///
     /// # Examples
     ///
     /// ```
-    /// use sonos_discovery::Discovery;
+    /// use sonos_discovery::Discover;
     ///
-    /// let discovery: Discovery = Discovery::new().unwrap();
+    /// let discovery: Discover = Discover::new().unwrap();
     /// ```
     pub fn new() -> Result<Self> {
-        let multicast_address = SocketAddr::from_str("239.255.255.250:1900")
-            .map_err(|_|
-                Error::new(ErrorKind::InvalidData, "Couldn't parse socket address"))?;
+        Ok(Discover::default())
+    }
 
-        Discover::with_address(multicast_address)
+    /// Creates a new `Discover` on the link-local ipv6 multicast address (`[FF02::C]:1900`), e.g. for ipv6-only networks.
+    ///
+    /// `with_address` with `SSDP_SITE_LOCAL_V6` searches the whole site instead. `set_interface` is ipv4 only,
+    /// the search leaves on the default interface of the multicast route.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use sonos_discovery::Discover;
+    ///
+    /// for ip in Discover::new_v6().unwrap().start(None, None).unwrap() {
+    ///     println!("{}", ip);
+    /// }
+    /// ```
+    pub fn new_v6() -> Result<Self> {
+        Discover::with_address(SocketAddr::new(IpAddr::V6(SSDP_LINK_LOCAL_V6), SSDP_PORT))
     }
 
-    /// Creates a new `Discovery` with a custom multicast address.
+    /// Creates a new `Discover` with a custom multicast address (ipv4 or ipv6).
     pub fn with_address(address: SocketAddr) -> Result<Self> {
-        let socket = Discover::create_default_socket()?;
-        Ok(Discover {
-            multicast_addr: address,
-            socket
-        })
+        Ok(Discover::with_config(DiscoveryConfig::with_address(address)))
     }
 
-    /// Create a default socket
-    /// socket option: AF_INET - SOCK_DGRAM - 0 // Automatically discover the protocol (IPPROTO_UDP)
-    /// socket option: IPPROTO_IP - IP_MULTICAST_TTL - 4 // UPnP 1.0 needs a TTL of 4
-    fn create_default_socket() -> Result<Arc<Socket>> {
-        let socket_family = AF_INET;
-        let socket_level = SOCK_DGRAM;
-        let protocol = 0; // auto discover
-        let socket_options = vec![(IPPROTO_IP, IP_MULTICAST_TTL, 4)];
+    /// Creates a new `Discover` whose socket is bound to `bind_addr` (port 0 lets the os choose the port), the search
+    /// is sent from this address. Uses the default multicast address of its family (239.255.255.250:1900 or `[FF02::C]:1900`).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sonos_discovery::Discover;
+    ///
+    /// let discovery = Discover::with_bind_addr("192.168.1.2:0".parse().unwrap()).unwrap();
+    /// ```
+    pub fn with_bind_addr(bind_addr: SocketAddr) -> Result<Self> {
+        let mut discovery = match bind_addr {
+            SocketAddr::V4(_) => Discover::new()?,
+            SocketAddr::V6(_) => Discover::new_v6()?
+        };
+        discovery.set_bind_addr(bind_addr);
 
-        Discover::create_socket(socket_family, socket_level, protocol, &socket_options)
+        Ok(discovery)
     }
 
-    fn create_socket(socket_family: i32, socket_type: i32, protocol: i32, socket_options: &[(i32, i32, i32)]) -> Result<Arc<Socket>> {
-        let socket = Socket::new(socket_family, socket_type, protocol)?;
-        for socket_option in socket_options {
-            // TODO: Use result, allow to fail, panic or return a result?
-            socket.setsockopt(socket_option.0, socket_option.1, socket_option.2)?
+    /// Builder for a configured `Discover`, same as `DiscoverBuilder::new()`
+    pub fn builder() -> DiscoverBuilder {
+        DiscoverBuilder::new()
+    }
+
+    /// Creates a new `Discover` with a complete configuration.
+    pub fn with_config(config: DiscoveryConfig) -> Self {
+        Discover {
+            config,
+            // A burst of 3 messages, afterwards one per minute and source
+            duplicate_log: Arc::new(RateLimiter::new(3, Duration::from_secs(60))),
+            descriptions: None,
+            last_device: Arc::new(Mutex::new(None)),
+            results: None
         }
+    }
+
+    /// Configuration used by every `start`
+    pub fn config(&self) -> &DiscoveryConfig {
+        &self.config
+    }
+
+    /// Installs a sink which receives every packet sent and received by this `Discover`, e.g. for compliance logging.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sonos_discovery::{Discover, WriteAuditSink};
+    /// use std::io;
+    /// use std::sync::Arc;
+    ///
+    /// let mut discovery = Discover::new().unwrap();
+    /// discovery.set_audit_sink(Arc::new(WriteAuditSink::new(io::stderr())));
+    /// ```
+    pub fn set_audit_sink(&mut self, sink: Arc<dyn AuditSink>) {
+        self.config.audit_sink = Some(sink);
+    }
+
+    /// Installs a sink which counts the packets, rejected and duplicate responses and the duration of the discoveries
+    /// of this `Discover`, see `CounterMetrics` for an example
+    pub fn set_metrics_sink(&mut self, sink: Arc<dyn MetricsSink>) {
+        self.config.metrics_sink = Some(sink);
+    }
+
+    /// Shares the ssdp port with other listeners of the host (`SO_REUSEADDR`, `SO_REUSEPORT` on unix), e.g. a media
+    /// server, or a second discovery listening (`listen`) or bound to port 1900 (`set_bind_addr`).
+    ///
+    /// Without it binding fails if another process owns the port without sharing it.
+    pub fn set_reuse_port(&mut self, enabled: bool) {
+        self.config.reuse_port = enabled;
+    }
+
+    /// TTL (hop limit for ipv6) of the search, 1 keeps it on the local subnet, routed networks need more hops.
+    /// Defaults to 4, UPnP 1.0 devices expect at least 4.
+    pub fn set_multicast_ttl(&mut self, ttl: u32) {
+        self.config.multicast_ttl = ttl;
+    }
+
+    /// Sends the search to the multicast groups in `addresses` as well, e.g. the group of an ssdp relay or one on a
+    /// nonstandard port. They have to be of the family of the multicast address, the answers of every group end up in
+    /// the same result. Only sending to the multicast address itself can fail the search.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sonos_discovery::Discover;
+    ///
+    /// let mut discovery = Discover::new().unwrap();
+    /// discovery.set_additional_multicast_addrs(&["239.255.255.250:1901".parse().unwrap()]);
+    /// ```
+    pub fn set_additional_multicast_addrs(&mut self, addresses: &[SocketAddr]) {
+        self.config.additional_multicast_addrs = addresses.to_vec();
+    }
+
+    /// Sends the multicast search out of the interface with the local address `interface` (`IP_MULTICAST_IF`), the
+    /// socket stays bound like without it. For containers and WSL, whose default multicast interface is often not the
+    /// one leading to the speakers. Takes precedence over `set_interface` and `set_all_interfaces` for the search.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sonos_discovery::Discover;
+    /// use std::net::Ipv4Addr;
+    ///
+    /// let mut discovery = Discover::new().unwrap();
+    /// // The address of eth0 inside a docker container with host networking
+    /// discovery.set_multicast_if(Ipv4Addr::new(192, 168, 1, 2));
+    /// discovery.set_multicast_loop(false);
+    /// ```
+    pub fn set_multicast_if(&mut self, interface: Ipv4Addr) {
+        self.config.multicast_if = Some(interface);
+    }
+
+    /// Whether the multicast search is delivered to the sockets of the host as well (`IP_MULTICAST_LOOP`, default:
+    /// true). Disabling it keeps a speaker emulation or `listen` on the same host from seeing the own searches.
+    pub fn set_multicast_loop(&mut self, enabled: bool) {
+        self.config.multicast_loop = enabled;
+    }
+
+    /// Sets the MX (seconds devices may wait before answering) and raises the timeout to at least `mx` and the slack
+    /// for late answers (`set_mx_slack`, default: `MX_GRACE`).
+    ///
+    /// MX is clamped to 1 to 5 seconds (`MAX_MX`), devices ignore anything larger. A shorter `timeout` passed to `start`
+    /// adds a warning to the result, an adaptive discovery (`set_adaptive`) waits at least `mx + MX_GRACE`.
+    pub fn set_mx(&mut self, mx: u32) {
+        self.config.mx = mx;
+        self.config.timeout = cmp::max(self.config.timeout, self.config.response_window());
+    }
+
+    /// Time late answers get on top of MX (default: `MX_GRACE`, 1 second), raises the timeout to at least MX + `slack`
+    /// like `set_mx`. Devices answer at a random time within MX, a slow or congested network needs more slack.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sonos_discovery::Discover;
+    /// use std::time::Duration;
+    ///
+    /// let mut discovery = Discover::new().unwrap();
+    /// discovery.set_mx(3);
+    /// discovery.set_mx_slack(Duration::from_secs(4));
+    /// assert_eq!(discovery.config().timeout, Duration::from_secs(7));
+    /// ```
+    pub fn set_mx_slack(&mut self, slack: Duration) {
+        self.config.mx_slack = slack;
+        self.config.timeout = cmp::max(self.config.timeout, self.config.response_window());
+    }
+
+    /// Sends the search `resends` more times every `interval` while receiving, as UPnP recommends for the lossy UDP.
+    /// Devices answer every search, the repeated answers are only counted once.
+    ///
+    /// Applies to the multicast (or broadcast fallback) search, not to `probe` and `listen`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sonos_discovery::Discover;
+    /// use std::time::Duration;
+    ///
+    /// let mut discovery = Discover::new().unwrap();
+    /// // Three searches in total, at 0s, 0.5s and 1s
+    /// discovery.set_resends(2, Duration::from_millis(500));
+    /// ```
+    pub fn set_resends(&mut self, resends: u32, interval: Duration) {
+        self.config.resends = resends;
+        self.config.resend_interval = interval;
+    }
+
+    /// Stops every discovery (`start`, `iter`, `stream`, ...) once `token` is cancelled from another thread,
+    /// the result contains the devices found until then. See `CancellationToken`.
+    pub fn set_cancellation_token(&mut self, token: CancellationToken) {
+        self.config.cancellation = Some(token);
+    }
+
+    /// Sets the timeout used if `start` (and the other discoveries) aren't given one (default: 5 seconds)
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.config.timeout = timeout;
+    }
+
+    /// Stops the discovery early once no device answered for a while, e.g. on a fast wired network.
+    ///
+    /// The idle time is 3 times the latency of the first response (between 200ms and 2s),
+    /// so a congested wifi waits longer than a snappy lan. The discovery at least waits for MX + `MX_GRACE`.
+    pub fn set_adaptive(&mut self, enabled: bool) {
+        self.config.adaptive = enabled;
+    }
+
+    /// Stops the discovery once no device answered for `quiet_period` after the last response, e.g. 500ms lets a
+    /// typical discovery finish within 1 to 2 seconds without knowing the device count.
+    ///
+    /// Unlike `set_adaptive` the quiet period is fixed and the discovery doesn't wait for MX, slow devices answering
+    /// after a longer gap are missed. No response at all still waits for the whole timeout.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use sonos_discovery::Discover;
+    /// use std::time::Duration;
+    ///
+    /// let mut discovery = Discover::new().unwrap();
+    /// discovery.set_quiet_period(Duration::from_millis(500));
+    /// let result = discovery.start(None, None).unwrap();
+    /// println!("{} devices after {:?}", result.len(), result.stats().elapsed);
+    /// ```
+    pub fn set_quiet_period(&mut self, quiet_period: Duration) {
+        self.config.quiet_period = Some(quiet_period);
+    }
+
+    /// Confirms every responder by fetching the device description from its `LOCATION` header.
+    ///
+    /// Only locations on the responding address itself are fetched, others count as unverified.
+    /// Devices which merely answer as a ZonePlayer but don't describe themselves as a sonos device
+    /// are dropped (counted in `DiscoveryStats::unverified`). Runs after the search, so the discovery takes longer
+    /// and the listener (`set_listener`) is still called for unverified devices.
+    pub fn set_verify(&mut self, enabled: bool) {
+        self.config.verify = enabled;
+    }
+
+    /// Rejects responses from outside the subnets of the interface the search is sent out of, or whose `LOCATION`
+    /// host isn't the address they came from (default: true). Anyone on the network can send a fake answer to the
+    /// port of the search, disable it for devices behind a router or an ssdp relay.
+    pub fn set_validate_source(&mut self, enabled: bool) {
+        self.config.validate_source = enabled;
+    }
+
+    /// Decides with `filter` which responses are devices, instead of checking the search target (`set_search_target`).
+    ///
+    /// E.g. to discover Boost or Bridge units, or other UPnP renderers with `ssdp:all`. A passive discovery
+    /// (`listen`) still only passes announcements to the filter. The household (`set_household`) is checked afterwards.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use sonos_discovery::{Discover, ResponseFilter};
+    ///
+    /// let mut discovery = Discover::new().unwrap();
+    /// discovery.set_search_target("ssdp:all");
+    /// discovery.set_response_filter(ResponseFilter::new(|headers|
+    ///     headers.get("ST").map_or(false, |st| st.contains("MediaRenderer"))));
+    /// for ip in discovery.start(None, None).unwrap() {
+    ///     println!("{}", ip);
+    /// }
+    /// ```
+    pub fn set_response_filter(&mut self, filter: ResponseFilter) {
+        self.config.response_filter = Some(filter);
+    }
+
+    /// Accepts every response which mentions "Sonos" anywhere, like previous versions did, instead of only those
+    /// whose `ST` or `USN` header contains `ZONE_PLAYER`. Only for devices with unusual headers, other responses
+    /// mentioning Sonos (e.g. from a proxy or a media server) are accepted as well.
+    pub fn set_lenient_match(&mut self, enabled: bool) {
+        self.config.lenient_match = enabled;
+    }
+
+    /// Replaces the http client used for device descriptions and the verification, defaults to `TcpFetcher`.
+    ///
+    /// `ProxyFetcher` sends the requests through a http proxy (e.g. `ProxyFetcher::from_env()`),
+    /// `UreqFetcher` and `ReqwestFetcher` are available with the `ureq` and `reqwest` features.
+    pub fn set_http_fetcher(&mut self, fetcher: Arc<dyn HttpFetcher>) {
+        self.config.http_fetcher = fetcher;
+    }
+
+    /// Replaces the sockets of the discoveries, defaults to `UdpTransport`.
+    ///
+    /// E.g. an in-memory network answering with canned responses in tests. The async discovery
+    /// (`sonos_discovery::tokio`) always uses udp sockets.
+    pub fn set_transport(&mut self, transport: Arc<dyn TransportFactory>) {
+        self.config.transport = transport;
+    }
+
+    /// Limits the whole discovery including the verification and description requests to `budget`.
+    ///
+    /// The search stops at the budget at the latest, the timeouts of every request afterwards are capped by the remaining time.
+    /// Requests without any time left fail, so e.g. a `5 second` inventory can't take 30 seconds.
+    pub fn set_budget(&mut self, budget: Duration) {
+        self.config.budget = Some(budget);
+    }
+
+    /// Caches the device descriptions (of `inventory` and the verification) by device uuid.
+    ///
+    /// Descriptions younger than `ttl` are used without a request, older ones are revalidated with their `ETag`/
+    /// `Last-Modified` (`TcpFetcher` and `ProxyFetcher`) or downloaded again. Replaces any previous cache.
+    pub fn set_description_ttl(&mut self, ttl: Duration) {
+        self.descriptions = Some(Arc::new(DescriptionCache::new(ttl)));
+    }
+
+    /// Returns the result of the previous `start` instead of searching again if it's younger than `ttl`,
+    /// so repeated calls (e.g. every command of a cli) don't flood the network.
+    ///
+    /// Once the cached result is older than half the ttl it's refreshed by a discovery on a background thread,
+    /// later calls get the new result. `timeout` and `device_count` of `start` only apply to discoveries which
+    /// actually run. The cache is shared by the clones of the `Discover`, replacing it drops the cached result.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use sonos_discovery::Discover;
+    /// use std::time::Duration;
+    ///
+    /// let mut discovery = Discover::new().unwrap();
+    /// discovery.set_result_ttl(Duration::from_secs(60));
+    /// let first = discovery.start(None, None).unwrap();
+    /// // Within a minute without a search
+    /// let second = discovery.start(None, None).unwrap();
+    /// assert_eq!(first.devices(), second.devices());
+    /// ```
+    pub fn set_result_ttl(&mut self, ttl: Duration) {
+        self.results = Some(Arc::new(ResultCache::new(ttl)));
+    }
+
+    /// Limits the http requests after the search (verification, descriptions) to `max_requests` at a time (default: 4).
+    /// Every device only gets one request at a time regardless.
+    pub fn set_max_requests(&mut self, max_requests: usize) {
+        self.config.max_requests = max_requests;
+    }
+
+    /// Sweeps the local network for hosts answering on port 1400 if nobody answered the search (`start`,
+    /// `start_detailed`, `start_with_description`), for networks which drop multicast entirely. Each open host is
+    /// confirmed by its device description, the devices are returned (`DiscoveryStats::swept`) without the headers of
+    /// a response.
+    ///
+    /// The network is the /24 of the search's interface unless `set_sweep_network` is set. At most
+    /// `set_sweep_concurrency` connections (default: 64) are opened at a time, each gets 300ms.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use sonos_discovery::Discover;
+    ///
+    /// let mut discovery = Discover::new().unwrap();
+    /// discovery.set_sweep_fallback(true);
+    /// discovery.set_sweep_network("192.168.1.0/24".parse().unwrap());
+    /// let result = discovery.start(None, None).unwrap();
+    /// println!("{} devices, {} of them swept", result.unique_ips().len(), result.stats().swept);
+    /// ```
+    pub fn set_sweep_fallback(&mut self, enabled: bool) {
+        self.config.sweep_fallback = enabled;
+    }
+
+    /// Network of the sweep fallback (`set_sweep_fallback`) instead of the /24 of the search's interface
+    pub fn set_sweep_network(&mut self, network: SweepNetwork) {
+        self.config.sweep_network = Some(network);
+    }
+
+    /// Limits the sweep fallback (`set_sweep_fallback`) to `concurrency` connections at a time (default: 64)
+    pub fn set_sweep_concurrency(&mut self, concurrency: usize) {
+        self.config.sweep_concurrency = concurrency;
+    }
+
+    /// Looks up the hostname of every device (`DeviceInfo::hostname`) by its PTR record after `start_detailed`,
+    /// `start_all`, `start_with_description` and `verify`.
+    ///
+    /// The first nameserver of `/etc/resolv.conf` is asked directly (unix only), each lookup gets
+    /// `set_hostname_timeout` (default: 1s) so a slow resolver can't stall the discovery.
+    /// Addresses without a record keep no hostname.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use sonos_discovery::Discover;
+    ///
+    /// let mut discovery = Discover::new().unwrap();
+    /// discovery.set_resolve_hostnames(true);
+    /// for device in discovery.start_detailed(None, None).unwrap() {
+    ///     println!("{} {}", device.ip, device.hostname.as_deref().unwrap_or("-"));
+    /// }
+    /// ```
+    pub fn set_resolve_hostnames(&mut self, enabled: bool) {
+        self.config.resolve_hostnames = enabled;
+    }
+
+    /// Time each hostname lookup of `set_resolve_hostnames` gets (default: 1s)
+    pub fn set_hostname_timeout(&mut self, timeout: Duration) {
+        self.config.hostname_timeout = timeout;
+    }
+
+    /// Fetches the zone groups of every household after the search (`ZoneGroupTopology` service).
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use sonos_discovery::Discover;
+    ///
+    /// let mut discovery = Discover::new().unwrap();
+    /// discovery.set_topology(true);
+    /// for group in discovery.start(None, None).unwrap().grouped() {
+    ///     let rooms: Vec<_> = group.members.iter().filter_map(|member| member.zone_name.as_ref()).collect();
+    ///     println!("{}: {:?}", group.id, rooms);
+    /// }
+    /// ```
+    pub fn set_topology(&mut self, enabled: bool) {
+        self.config.topology = enabled;
+    }
+
+    /// Lists the bonded satellites (subs, surrounds) of the members in the zone groups of `set_topology` and
+    /// `start_fast` as well. They never answer as a room of their own, `ZoneGroupMember::role` tells their role and
+    /// `ZoneGroupMember::bonded_to` the speaker they belong to.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use sonos_discovery::{Discover, MemberRole};
+    ///
+    /// let mut discovery = Discover::new().unwrap();
+    /// discovery.set_topology(true);
+    /// discovery.set_include_invisible(true);
+    /// for member in discovery.start(None, None).unwrap().grouped().into_iter().flat_map(|group| group.members) {
+    ///     if member.role == MemberRole::Subwoofer {
+    ///         println!("sub {} of {:?}", member.uuid, member.bonded_to);
+    ///     }
+    /// }
+    /// ```
+    pub fn set_include_invisible(&mut self, enabled: bool) {
+        self.config.include_invisible = enabled;
+    }
+
+    /// Devices which are asked for the zone groups in parallel to the search of `start_fast`, e.g. the speakers of
+    /// a previous run. Unreachable devices only cost their request timeout on a separate thread.
+    pub fn set_known_devices(&mut self, ips: &[IpAddr]) {
+        self.config.known_devices = ips.to_vec();
+    }
+
+    /// Installs a listener which is called for every device as soon as its first response arrives.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sonos_discovery::{DeviceListener, Discover};
+    /// use std::net::IpAddr;
+    /// use std::sync::Arc;
+    ///
+    /// #[derive(Debug)]
+    /// struct PrintListener;
+    ///
+    /// impl DeviceListener for PrintListener {
+    ///     fn found(&self, ip: IpAddr, uuid: Option<&str>) {
+    ///         println!("found {} ({:?})", ip, uuid);
+    ///     }
+    /// }
+    ///
+    /// let mut discovery = Discover::new().unwrap();
+    /// discovery.set_listener(Arc::new(PrintListener));
+    /// ```
+    pub fn set_listener(&mut self, listener: Arc<dyn DeviceListener>) {
+        self.config.listener = Some(listener);
+    }
+
+    /// Sends the search from the interface with the local address `interface` and only receives answers on it.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use sonos_discovery::Discover;
+    /// use std::net::Ipv4Addr;
+    /// use std::thread;
+    ///
+    /// let handles: Vec<_> = vec![Ipv4Addr::new(192, 168, 1, 2), Ipv4Addr::new(10, 0, 0, 2)].into_iter()
+    ///     .map(|interface| thread::spawn(move || {
+    ///         let mut discovery = Discover::new().unwrap();
+    ///         discovery.set_interface(interface);
+    ///         discovery.start(None, None)
+    ///     }))
+    ///     .collect();
+    /// for handle in handles {
+    ///     println!("{:?}", handle.join().unwrap().map(|result| result.ips()));
+    /// }
+    /// ```
+    pub fn set_interface(&mut self, interface: Ipv4Addr) {
+        self.config.interface = Some(interface);
+    }
+
+    /// Binds the socket to the local address `bind_addr` instead of letting the os choose, e.g. for the NIC of one
+    /// network on a machine with several. Takes precedence over `set_interface` for the bind, which still selects
+    /// the outgoing interface of the multicast search.
+    pub fn set_bind_addr(&mut self, bind_addr: SocketAddr) {
+        self.config.bind_addr = Some(bind_addr);
+    }
+
+    /// Binds the socket to the interface named `name` (`SO_BINDTODEVICE`), for VRFs and policy routing where the
+    /// local address alone doesn't decide the interface. Needs root or CAP_NET_RAW, otherwise the discovery fails with
+    /// `DiscoveryError::BindDevice`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sonos_discovery::Discover;
+    ///
+    /// let mut discovery = Discover::new().unwrap();
+    /// discovery.set_bind_device("vrf-lan");
+    /// ```
+    #[cfg(target_os = "linux")]
+    pub fn set_bind_device(&mut self, name: &str) {
+        self.config.bind_device = Some(name.to_string());
+    }
+
+    /// Sends the search from the local port `port` instead of one chosen by the os, for firewalls which only let the
+    /// udp answers through to a fixed port. The port replaces the one of `set_bind_addr`.
+    ///
+    /// The discovery fails with `DiscoveryError::SourcePort` if the port can't be bound, its message names the
+    /// firewall rule the port needs.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sonos_discovery::Discover;
+    ///
+    /// // iptables -A INPUT -p udp --dport 50000 -j ACCEPT
+    /// let mut discovery = Discover::new().unwrap();
+    /// discovery.set_source_port(50000);
+    /// ```
+    pub fn set_source_port(&mut self, port: u16) {
+        self.config.source_port = Some(port);
+    }
+
+    /// Sends the search out of every ipv4 interface (docker bridges, VPNs, wifi and ethernet) instead of only the one
+    /// of the default route, the answers of all interfaces are merged. `set_interface` takes precedence.
+    pub fn set_all_interfaces(&mut self, all_interfaces: bool) {
+        self.config.all_interfaces = all_interfaces;
+    }
+
+    /// Chooses the interface of the search with `best_interface` (a private network which isn't a VPN or a virtual
+    /// bridge) instead of the default route, which often leads into the tunnel while a VPN is up. The choice is logged
+    /// and in `DiscoveryResult::config`. Any other interface option or `set_bind_addr` takes precedence.
+    pub fn set_auto_interface(&mut self, auto_interface: bool) {
+        self.config.auto_interface = auto_interface;
+    }
+
+    /// Sends the search out of the interface named `name` (e.g. `eth0`), an interface with several ipv4 addresses
+    /// gets one search per address. The discovery fails if no such interface exists.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sonos_discovery::Discover;
+    ///
+    /// let mut discovery = Discover::new().unwrap();
+    /// discovery.set_interface_name("eth0");
+    /// ```
+    pub fn set_interface_name(&mut self, name: &str) {
+        self.config.interface_name = Some(name.to_string());
+    }
+
+    /// Sends the search to `address` (e.g. the subnet broadcast `192.168.1.255:1900`) if sending to the multicast
+    /// address fails. `DiscoveryStats::method` tells which one was used.
+    pub fn set_broadcast_fallback(&mut self, address: SocketAddr) {
+        self.config.broadcast_fallback = Some(address);
+    }
+
+    /// Only accepts devices of `household` (the `X-RINCON-HOUSEHOLD` header of their responses, e.g. `Sonos_abc123`),
+    /// answers of other households count as rejected.
+    pub fn set_household(&mut self, household: &str) {
+        self.config.household = Some(household.to_string());
+    }
 
-        Ok(Arc::new(socket))
+    /// Only returns the devices in the room `room` (ignoring case) from `start_with_description`
+    pub fn set_room_filter(&mut self, room: &str) {
+        self.config.room_filter = Some(room.to_string());
+    }
+
+    /// Only returns the devices whose model name contains `model` (ignoring case, e.g. `One` for `Sonos One`) from
+    /// `start_with_description`
+    pub fn set_model_filter(&mut self, model: &str) {
+        self.config.model_filter = Some(model.to_string());
+    }
+
+    /// Searches for `search_target` (`ST` header) instead of sonos speakers (`ZONE_PLAYER`),
+    /// e.g. `urn:schemas-upnp-org:device:MediaRenderer:1` or `ssdp:all`.
+    ///
+    /// Every device answering with this `ST` is accepted, not only sonos devices. The sonos specific requests
+    /// (`set_verify`, `set_topology`, `inventory`) still expect sonos devices.
+    pub fn set_search_target(&mut self, search_target: &str) {
+        self.config.search_target = search_target.to_string();
+    }
+
+    /// Searches `ssdp:all` and keeps the speakers among the answers, for networks or firmwares which ignore the
+    /// search for the ZonePlayer device type.
+    ///
+    /// Sonos devices answer `ssdp:all` with one entry for the root device, each embedded device (ZonePlayer,
+    /// MediaRenderer, MediaServer) and each service. They are collapsed into one device with the uuid of the
+    /// ZonePlayer, the further entries count as duplicates (`DiscoveryStats::duplicates`). The search target is
+    /// ignored while it's enabled.
+    pub fn set_ssdp_all(&mut self, enabled: bool) {
+        self.config.ssdp_all = enabled;
     }
 
-    /// Sends the search message to the defined socket.
-    /// Message can't have leading/trailing whitespaces (\s).
+    /// Logs (`debug` level) a hexdump of every received packet, shows exactly what a device sent.
+    pub fn set_hexdump(&mut self, enabled: bool) {
+        self.config.hexdump = enabled;
+    }
+
+    /// Keeps the exact bytes of every response in the result, for troubleshooting devices or networks.
+    ///
+    /// `DiscoveryResult::raw_responses` has the responses of each device and `DiscoveryResult::rejected_responses`
+    /// those which weren't accepted (not a sonos device, other household, the response filter).
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use sonos_discovery::Discover;
+    ///
+    /// let mut discovery = Discover::new().unwrap();
+    /// discovery.set_keep_raw(true);
+    /// let result = discovery.start(None, None).unwrap();
+    /// for response in result.rejected_responses() {
+    ///     println!("{} sent\n{}", response.addr, String::from_utf8_lossy(&response.data));
+    /// }
+    /// ```
+    pub fn set_keep_raw(&mut self, enabled: bool) {
+        self.config.keep_raw = enabled;
+    }
+
+    /// Sets the policy for retrying a failed send of the search message, defaults to `NoRetry`.
+    ///
+    /// Transient errors (an interrupted call, a network which is unreachable for a moment) are retried up to 3 times
+    /// with a short backoff before the policy is asked, other errors (e.g. a denied permission) go to the policy
+    /// right away.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sonos_discovery::{Discover, ExponentialBackoff};
+    /// use std::sync::Arc;
+    /// use std::time::Duration;
     ///
-    /// # Message
+    /// let mut discovery = Discover::new().unwrap();
+    /// discovery.set_retry_policy(Arc::new(ExponentialBackoff {
+    ///     retries: 3,
+    ///     initial: Duration::from_millis(100),
+    ///     max: Duration::from_secs(1),
+    ///     jitter: true,
+    ///     seed: None
+    /// }));
     /// ```
-    /// M-SEARCH * HTTP/1.1
-    /// HOST: 239.255.255.250:1900
-    /// MAN: "ssdp:discover"
-    /// MX: 1
-    /// ST: urn:schemas-upnp-org:device:ZonePlayer:1```
-    fn send_search(&self) -> Result<usize> {
-        let player_search = br#"M-SEARCH * HTTP/1.1
-HOST: 239.255.255.250:1900
-MAN: "ssdp:discover"
-MX: 1
-ST: urn:schemas-upnp-org:device:ZonePlayer:1"#;
+    pub fn set_retry_policy(&mut self, policy: Arc<dyn RetryPolicy>) {
+        self.config.retry_policy = policy;
+    }
+
+    /// Recreates the socket when it keeps failing during a discovery (default: false), so a wifi blip or an interface
+    /// flap doesn't waste the rest of the timeout.
+    ///
+    /// A failed receive never ends the discovery: transient errors wait a moment and the socket is used again.
+    /// With this set, the socket is replaced by a new one (and the search sent again) after a fatal receive error,
+    /// 3 transient ones in a row or a failed resend (`set_resends`), at most 3 times per discovery.
+    /// `DiscoveryStats::recoveries` counts the replaced sockets.
+    pub fn set_recreate_socket(&mut self, enabled: bool) {
+        self.config.recreate_socket = enabled;
+    }
 
-        self.socket.sendto(player_search, 0, &self.multicast_addr)
+    /// Replaces the clock used for the timeout and retry delays, defaults to `SystemClock`.
+    ///
+    /// With a `ManualClock` the discovery only times out once the clock was advanced past the timeout.
+    /// Receiving still waits up to 500ms of real time per attempt.
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.config.clock = clock;
     }
 
     /// Start discovering devices.
     ///
+    /// `timeout` and `device_count` override the configured values for this run, the timeout can be shorter than a second.
+    ///
     /// # Examples
-    /// In this example the search will stop if3 devices have been discovered or the default timeout (5s) is reached.
+    /// In this example the search will stop if 3 devices have been discovered or the default timeout (5s) is reached.
     /// This is useful if you know the amount of speakers you have and want to reduce the search time.
     ///
+    /// ```no_run
+    /// use sonos_discovery::{Discover, DiscoveryResult};
+    /// use std::time::Duration;
+    ///
+    /// let result: DiscoveryResult = Discover::new().unwrap().start(None, Some(3)).unwrap();
+    /// for ip in result {
+    ///     println!("{}", ip);
+    /// }
+    ///
+    /// // A wired network answers within 800ms
+    /// let result = Discover::new().unwrap().start(Some(Duration::from_millis(800)), None).unwrap();
     /// ```
-    /// use sonos_discovery::Discovery;
+    pub fn start(&self, timeout: Option<Duration>, device_count: Option<usize>) -> Result<DiscoveryResult> {
+        if let Some(result) = self.results.as_ref().and_then(|cache| cache.get(self)) {
+            return Ok(result);
+        }
+
+        let mut config = self.config.clone();
+        config.timeout = timeout.unwrap_or(config.timeout);
+        config.device_count = device_count.or(config.device_count);
+
+        let result = Engine::new(config, Arc::clone(&self.duplicate_log), self.descriptions.clone())?.run()?;
+        if let Some(ref cache) = self.results {
+            cache.store(result.clone());
+        }
+
+        Ok(result)
+    }
+
+    /// Discovery with the configured timeout and device count, bypasses the result cache
+    pub(crate) fn discover(&self) -> Result<DiscoveryResult> {
+        Engine::new(self.config.clone(), Arc::clone(&self.duplicate_log), self.descriptions.clone())?.run()
+    }
+
+    /// Address of the first device which answers, e.g. as entry point into the household for controlling it.
+    /// `None` if no device answered within `timeout` (`None` uses the configured timeout).
     ///
-    /// let devices: Vec<IpAddr> = Discovery::new().unwrap().start(None, Some(3)).unwrap();
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use sonos_discovery::Discover;
+    ///
+    /// match Discover::new().unwrap().first(None).unwrap() {
+    ///     Some(ip) => println!("found {}", ip),
+    ///     None => println!("no speaker answered")
+    /// }
     /// ```
-    pub fn start(&self, timeout: Option<u32>, device_count: Option<usize>) -> Result<Vec<IpAddr>> {
-        let timeout = timeout.unwrap_or(5);
-        let device_count = device_count.unwrap_or(std::u32::MAX as usize);
+    pub fn first(&self, timeout: Option<Duration>) -> Result<Option<IpAddr>> {
+        let mut config = self.config.clone();
+        config.timeout = timeout.unwrap_or(config.timeout);
+        config.device_count = Some(1);
 
-        let time = Instant::now();
+        let result = Engine::new(config, Arc::clone(&self.duplicate_log), self.descriptions.clone())?.run()?;
 
-        self.send_search()?;
+        Ok(result.response_addrs().first().cloned())
+    }
 
-        let mut devices: Vec<IpAddr> = Vec::new();
-        while time.elapsed().as_secs() < u64::from(timeout) && devices.len() < device_count {
-            let socket = Arc::clone(&self.socket);
-            let (sender, receiver) = mpsc::channel();
-            thread::spawn(move ||
-                {
-                    if let Ok((_addr, _data)) = socket.recvfrom(1024, 0) {
-                        // TODO: Add logging, fail on multiple send errors?
-                        sender.send((_addr, _data)).is_ok();
-                    }
+    /// Finds all speakers of a household in a fraction of the timeout: stops the search at the first device and
+    /// asks it for the zone groups of its household (`ZoneGroupTopology`), which list every speaker.
+    ///
+    /// The result contains the answering device (the only one in `response_addrs`) and the other members. Only the
+    /// answering device has `seen` and `upnp_headers`, the members have their uuids and (`grouped`) zone groups.
+    /// Other households on the network are missed, if the topology can't be fetched the result only contains the
+    /// first device and a warning.
+    ///
+    /// The devices of `set_known_devices` and the first device of the previous `start_fast` are asked for the zone
+    /// groups while the search is running, if one of them answers first the search ends right away. The search is
+    /// the fallback if none of them is reachable anymore.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use sonos_discovery::Discover;
+    ///
+    /// for ip in Discover::new().unwrap().start_fast(None).unwrap() {
+    ///     println!("{}", ip);
+    /// }
+    /// ```
+    pub fn start_fast(&self, timeout: Option<Duration>) -> Result<DiscoveryResult> {
+        let mut config = self.config.clone();
+        config.timeout = timeout.unwrap_or(config.timeout);
+
+        let mut known = Vec::new();
+        if let Some(ip) = *self.last_device.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) {
+            known.push(ip);
+        }
+        for ip in &config.known_devices {
+            if !known.contains(ip) {
+                known.push(*ip);
+            }
+        }
+
+        let result = Engine::new(config, Arc::clone(&self.duplicate_log), self.descriptions.clone())?.household(known)?;
+        // A known device might have answered instead of the search
+        if let Some(ip) = result.response_addrs().first().or_else(|| result.first()) {
+            *self.last_device.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(*ip);
+        }
+
+        Ok(result)
+    }
+
+    /// Like `start`, but stops at the absolute `deadline` instead of after a timeout, e.g. to share one deadline between
+    /// several network probes. The deadline is a point in time of the configured clock (`set_clock`), a deadline which
+    /// already passed only sends the search.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use sonos_discovery::Discover;
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let deadline = Instant::now() + Duration::from_secs(3);
+    /// let result = Discover::new().unwrap().start_until(deadline, None).unwrap();
+    /// println!("{} devices, {:?} left", result.len(), deadline.saturating_duration_since(Instant::now()));
+    /// ```
+    pub fn start_until(&self, deadline: Instant, device_count: Option<usize>) -> Result<DiscoveryResult> {
+        let timeout = deadline.saturating_duration_since(self.config.clock.now());
+
+        self.start(Some(timeout), device_count)
+    }
+
+    /// Like `start`, but returns the headers of every device's first response instead of only its address.
+    ///
+    /// The devices are sorted by their address, the listener (`set_listener`) and the verification
+    /// (`set_verify`) apply like for `start`, the topology isn't fetched.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use sonos_discovery::Discover;
+    ///
+    /// for device in Discover::new().unwrap().start_detailed(None, None).unwrap() {
+    ///     println!("{} {:?} {:?}", device.ip, device.uuid, device.location);
+    /// }
+    /// ```
+    pub fn start_detailed(&self, timeout: Option<Duration>, device_count: Option<usize>) -> Result<Vec<DeviceInfo>> {
+        let mut config = self.config.clone();
+        config.timeout = timeout.unwrap_or(config.timeout);
+        config.device_count = device_count.or(config.device_count);
+
+        let engine = Engine::new(config, Arc::clone(&self.duplicate_log), self.descriptions.clone())?;
+        let (responses, stats) = engine.search()?;
+        let mut devices = DeviceInfo::from_responses(&responses);
+        if devices.is_empty() {
+            devices = engine.swept_devices(&stats);
+        }
+        device::resolve_hostnames(&mut devices, &self.config);
+
+        Ok(devices)
+    }
+
+    /// `start_detailed` with the configured timeout and device count, which stops early once `stop` is cancelled
+    /// (besides the configured cancellation token)
+    pub(crate) fn start_detailed_until(&self, stop: &CancellationToken) -> Result<Vec<DeviceInfo>> {
+        let engine = Engine::new(self.config.clone(), Arc::clone(&self.duplicate_log), self.descriptions.clone())?;
+        let mut collector = engine.begin()?;
+        collector.stop_on(stop.clone());
+        let (responses, _) = engine.collect(collector, |_| ControlFlow::Continue(()));
+
+        Ok(DeviceInfo::from_responses(&responses))
+    }
+
+    /// Like `start_detailed`, but searches every upnp device (`ssdp:all`) instead of only the sonos devices.
+    ///
+    /// Each device is classified by its responses (`DeviceInfo::kind`), e.g. to list the whole network with the sonos
+    /// speakers highlighted. The household filter (`set_household`) is ignored, the other devices have none.
+    /// A search target of `upnp:rootdevice` is kept, each device answers only once then but is only classified by
+    /// its `SERVER` header.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use sonos_discovery::{Discover, DeviceKind};
+    ///
+    /// for device in Discover::new().unwrap().start_all(None).unwrap() {
+    ///     let marker = if device.kind == DeviceKind::SonosZonePlayer { "*" } else { " " };
+    ///     println!("{} {} {} {:?}", marker, device.ip, device.kind, device.server);
+    /// }
+    /// ```
+    pub fn start_all(&self, timeout: Option<Duration>) -> Result<Vec<DeviceInfo>> {
+        let mut config = self.config.clone();
+        config.timeout = timeout.unwrap_or(config.timeout);
+        config.device_count = None;
+        if !config.search_target.eq_ignore_ascii_case("upnp:rootdevice") {
+            config.search_target = "ssdp:all".to_string();
+        }
+        config.household = None;
+        config.ssdp_all = false;
+
+        let (responses, _) = Engine::new(config, Arc::clone(&self.duplicate_log), self.descriptions.clone())?.search()?;
+        let mut devices = DeviceInfo::from_responses(&responses);
+        device::resolve_hostnames(&mut devices, &self.config);
+
+        Ok(devices)
+    }
+
+    /// Repeats `start_detailed` until `condition` accepts the devices of a search, e.g. once a certain speaker
+    /// answered.
+    ///
+    /// At most `max_attempts` searches are run (at least one), `backoff` gives the delay before each repetition
+    /// (`retry_delay` with the number of the repetition, `None` stops early). Returns the accepted devices, `None` if
+    /// no search satisfied the condition. A failing search returns its error right away.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use sonos_discovery::{Discover, ExponentialBackoff};
+    /// use std::time::Duration;
+    ///
+    /// let backoff = ExponentialBackoff {
+    ///     retries: 4,
+    ///     initial: Duration::from_secs(1),
+    ///     max: Duration::from_secs(30),
+    ///     jitter: true,
+    ///     seed: None
+    /// };
+    /// let kitchen = Discover::new().unwrap().discover_until(|devices| {
+    ///     devices.iter().any(|device| device.uuid.as_deref() == Some("RINCON_000E58A0123401400"))
+    /// }, 5, &backoff).unwrap();
+    /// println!("kitchen present: {}", kitchen.is_some());
+    /// ```
+    pub fn discover_until<F>(&self, mut condition: F, max_attempts: u32, backoff: &dyn RetryPolicy)
+                             -> Result<Option<Vec<DeviceInfo>>>
+        where F: FnMut(&[DeviceInfo]) -> bool {
+        for attempt in 1..=cmp::max(max_attempts, 1) {
+            if attempt > 1 {
+                match backoff.retry_delay(attempt - 1) {
+                    Some(delay) => self.config.clock.sleep(delay),
+                    None => break
                 }
-            );
-
-            // TODO: Add logging, change
-            let timeout = std::time::Duration::new(0, 500_000_000);
-            let (addr, data): (SocketAddr, Box<[u8]>) = match receiver.recv_timeout(timeout) {
-                Ok((addr, data)) => (addr, data),
-                Err(_) => continue
-            };
-
-            let needle: &[u8] = br"Sonos";
-            data.windows(needle.len()).position(|window| window == needle)
-                .map(|_|
-                    devices.push(addr.ip())
-                );
+            }
+
+            let devices = self.start_detailed(None, None)?;
+            if condition(&devices) {
+                return Ok(Some(devices));
+            }
+            debug!("Attempt {} of {} didn't satisfy the condition ({} devices)", attempt, max_attempts, devices.len());
+        }
+
+        Ok(None)
+    }
+
+    /// Like `start`, calls `callback` for every device as soon as its first response arrives.
+    ///
+    /// `ControlFlow::Break` stops the search early, the result contains the devices found until then.
+    /// The callback runs on the calling thread before the verification (`set_verify`).
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use sonos_discovery::Discover;
+    /// use std::ops::ControlFlow;
+    ///
+    /// let result = Discover::new().unwrap().start_with_callback(None, |device| {
+    ///     println!("found {}", device.ip);
+    ///     if device.uuid.as_deref() == Some("RINCON_000E58A0123401400") {
+    ///         ControlFlow::Break(())
+    ///     } else {
+    ///         ControlFlow::Continue(())
+    ///     }
+    /// }).unwrap();
+    /// println!("{} devices", result.len());
+    /// ```
+    pub fn start_with_callback<F>(&self, timeout: Option<Duration>, mut callback: F) -> Result<DiscoveryResult>
+        where F: FnMut(&DeviceInfo) -> ControlFlow<()> {
+        let mut config = self.config.clone();
+        config.timeout = timeout.unwrap_or(config.timeout);
+
+        Engine::new(config, Arc::clone(&self.duplicate_log), self.descriptions.clone())?
+            .run_with(|response| callback(&DeviceInfo::from_response(response)))
+    }
+
+    /// Sends the search and returns an iterator which yields every device as soon as its first response arrives.
+    ///
+    /// The responses are only received while iterating, the iterator ends at the timeout or the configured device count.
+    /// Failing to send the search is returned right away. Devices aren't verified (`set_verify`).
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use sonos_discovery::Discover;
+    ///
+    /// for device in Discover::new().unwrap().iter(None).unwrap() {
+    ///     println!("{}", device.ip);
+    /// }
+    /// ```
+    pub fn iter(&self, timeout: Option<Duration>) -> Result<Devices> {
+        let mut config = self.config.clone();
+        config.timeout = timeout.unwrap_or(config.timeout);
+
+        let engine = Engine::new(config, Arc::clone(&self.duplicate_log), self.descriptions.clone())?;
+        let collector = engine.begin()?;
+
+        Ok(Devices::new(engine, collector))
+    }
+
+    /// Creates the search socket of the configuration without sending anything, the building block for custom
+    /// discovery loops (see `SearchSocket`)
+    pub fn search_socket(&self) -> Result<SearchSocket> {
+        let engine = Engine::new(self.config.clone(), Arc::clone(&self.duplicate_log), self.descriptions.clone())?;
+
+        Ok(SearchSocket::new(engine))
+    }
+
+    /// Sends the search and returns a channel which receives every device as soon as its first response arrives.
+    ///
+    /// The search runs on a background thread until the timeout or the device count is reached,
+    /// afterwards the channel is closed. Dropping the receiver stops the search at the next response, the thread
+    /// (and its socket) end at the timeout at the latest.
+    /// Devices are sent before the verification (`set_verify`), the topology isn't fetched.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use sonos_discovery::Discover;
+    ///
+    /// for device in Discover::new().unwrap().stream(None, None).unwrap() {
+    ///     println!("{} {:?}", device.ip, device.uuid);
+    /// }
+    /// ```
+    pub fn stream(&self, timeout: Option<Duration>, device_count: Option<usize>) -> Result<mpsc::Receiver<DeviceInfo>> {
+        let mut config = self.config.clone();
+        config.timeout = timeout.unwrap_or(config.timeout);
+        config.device_count = device_count.or(config.device_count);
+
+        let engine = Engine::new(config, Arc::clone(&self.duplicate_log), self.descriptions.clone())?;
+        let collector = engine.begin()?;
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            engine.collect(collector, |response| match sender.send(DeviceInfo::from_response(response)) {
+                Ok(()) => ControlFlow::Continue(()),
+                // The receiver was dropped
+                Err(_) => ControlFlow::Break(())
+            });
+        });
+
+        Ok(receiver)
+    }
+
+    /// Like `start_detailed`, additionally fetches the device description (`/xml/device_description.xml`) of every device.
+    ///
+    /// `DeviceInfo::description` is `None` for devices whose description couldn't be fetched (logged as warning).
+    /// Only the devices matching `set_room_filter` and `set_model_filter` are returned, the device count still
+    /// counts every answering device.
+    /// The descriptions are cached with `set_description_ttl` and fetched with `set_max_requests` requests at a time.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use sonos_discovery::Discover;
+    ///
+    /// for device in Discover::new().unwrap().start_with_description(None, None).unwrap() {
+    ///     if let Some(description) = device.description {
+    ///         println!("{} {:?} {:?} {:?}", device.ip, description.room_name, description.model_name,
+    ///                  description.serial_number);
+    ///     }
+    /// }
+    /// ```
+    pub fn start_with_description(&self, timeout: Option<Duration>, device_count: Option<usize>) -> Result<Vec<DeviceInfo>> {
+        let mut config = self.config.clone();
+        config.timeout = timeout.unwrap_or(config.timeout);
+        config.device_count = device_count.or(config.device_count);
+
+        let engine = Engine::new(config, Arc::clone(&self.duplicate_log), self.descriptions.clone())?;
+        let (responses, stats) = engine.search()?;
+        let timeout = engine.fetch_timeout(inventory::FETCH_TIMEOUT, stats.elapsed);
+        let mut devices = DeviceInfo::from_responses(&responses);
+        device::describe(&mut devices, &self.config.http_fetcher, self.descriptions.as_ref(), timeout,
+                         self.config.max_requests);
+        if devices.is_empty() {
+            devices = engine.swept_devices(&stats);
         }
+        device::filter(&mut devices, &self.config);
+        device::resolve_hostnames(&mut devices, &self.config);
 
         Ok(devices)
     }
+
+    /// Collects the announcements (`NOTIFY ... ssdp:alive`) of the devices for `timeout` without sending a search,
+    /// e.g. on networks which don't allow sending multicast.
+    ///
+    /// Binds the ssdp port of the multicast address (1900, shared with other listeners) and joins the multicast group.
+    /// Sonos devices announce themselves when they join the network and then every few minutes,
+    /// so the timeout should be a lot longer than for `start`. The configured device count stops the discovery early.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use sonos_discovery::Discover;
+    /// use std::time::Duration;
+    ///
+    /// for ip in Discover::new().unwrap().listen(Some(Duration::from_secs(600))).unwrap() {
+    ///     println!("{}", ip);
+    /// }
+    /// ```
+    pub fn listen(&self, timeout: Option<Duration>) -> Result<DiscoveryResult> {
+        let mut config = self.config.clone();
+        config.timeout = timeout.unwrap_or(config.timeout);
+
+        Engine::passive(config, Arc::clone(&self.duplicate_log), self.descriptions.clone())?.listen()
+    }
+
+    /// Sends the search directly to port 1900 of every address in `ips` instead of the multicast address,
+    /// e.g. with the addresses of the dhcp leases on networks which filter multicast.
+    ///
+    /// The result contains the addresses which answered as sonos devices, answers of other addresses are ignored.
+    /// The discovery stops as soon as every address answered. Fails if the search couldn't be sent to any address.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use sonos_discovery::Discover;
+    /// use std::net::{IpAddr, Ipv4Addr};
+    ///
+    /// let ips = [IpAddr::V4(Ipv4Addr::new(192, 168, 1, 10)), IpAddr::V4(Ipv4Addr::new(192, 168, 1, 11))];
+    /// let result = Discover::new().unwrap().probe(&ips).unwrap();
+    /// for ip in &ips {
+    ///     println!("{}: {}", ip, result.contains(ip));
+    /// }
+    /// ```
+    pub fn probe(&self, ips: &[IpAddr]) -> Result<DiscoveryResult> {
+        let mut ips = ips.to_vec();
+        ips.sort();
+        ips.dedup();
+        if ips.iter().any(|ip| ip.is_ipv6() != self.config.multicast_addr.is_ipv6()) {
+            return Err(DiscoveryError::InvalidAddress(
+                "Probed addresses have to be of the same family as the multicast address".to_string()));
+        }
+
+        let mut config = self.config.clone();
+        config.device_count = Some(ips.len());
+
+        Engine::new(config, Arc::clone(&self.duplicate_log), self.descriptions.clone())?.probe(&ips)
+    }
+
+    /// Checks whether `ip` is a sonos device without searching the whole network, e.g. for the static addresses of a
+    /// home automation config.
+    ///
+    /// Sends the search to `ip` only (like `probe`) and fetches the description of the device if it answered. A device
+    /// which doesn't answer within `timeout` (firewalls often drop the unicast search) is still accepted if it serves
+    /// the description of a sonos device, without the headers of the search response then.
+    /// Fails with `DiscoveryError::NotSonos` if neither worked.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use sonos_discovery::{Discover, DiscoveryError};
+    /// use std::net::{IpAddr, Ipv4Addr};
+    /// use std::time::Duration;
+    ///
+    /// let ip = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 10));
+    /// match Discover::new().unwrap().verify(ip, Some(Duration::from_secs(1))) {
+    ///     Ok(device) => println!("{} is {:?}", ip, device.description.and_then(|description| description.room_name)),
+    ///     Err(DiscoveryError::NotSonos(_)) => println!("{} isn't a sonos device", ip),
+    ///     Err(e) => println!("{} couldn't be checked: {}", ip, e)
+    /// }
+    /// ```
+    pub fn verify(&self, ip: IpAddr, timeout: Option<Duration>) -> Result<DeviceInfo> {
+        if ip.is_ipv6() != self.config.multicast_addr.is_ipv6() {
+            return Err(DiscoveryError::InvalidAddress(
+                "The verified address has to be of the same family as the multicast address".to_string()));
+        }
+
+        let mut config = self.config.clone();
+        config.timeout = timeout.unwrap_or(config.timeout);
+        config.device_count = Some(1);
+
+        let engine = Engine::new(config, Arc::clone(&self.duplicate_log), self.descriptions.clone())?;
+        let (responses, stats) = engine.probe_responses(&[ip])?;
+        let timeout = engine.fetch_timeout(inventory::FETCH_TIMEOUT, stats.elapsed);
+        let mut device = match DeviceInfo::from_responses(&responses).pop() {
+            Some(mut device) => {
+                device::describe(slice::from_mut(&mut device), &self.config.http_fetcher, self.descriptions.as_ref(),
+                                 timeout, 1);
+                device
+            }
+            None => {
+                debug!("{} didn't answer the search, fetching its description", ip);
+                match description::fetch(&*self.config.http_fetcher, self.descriptions.as_deref(), None, ip, timeout) {
+                    Ok(description) if description.is_sonos() => DeviceInfo::from_description(ip, description),
+                    Ok(_) => return Err(DiscoveryError::NotSonos(ip)),
+                    Err(e) => {
+                        debug!("Couldn't fetch the description of {}: {}", ip, e);
+                        return Err(DiscoveryError::NotSonos(ip));
+                    }
+                }
+            }
+        };
+        device::resolve_hostnames(slice::from_mut(&mut device), &self.config);
+
+        Ok(device)
+    }
+
+    /// Address of the speaker of the room `room` (e.g. "Living Room", case-insensitive), `None` if no room of that
+    /// name was found.
+    ///
+    /// The room is looked up in the zone groups of `start_fast` first, a bonded set (stereo pair, home theater)
+    /// returns its main speaker. If the topology doesn't list the room (e.g. it's in another household or the
+    /// topology couldn't be fetched) the devices of `start_with_description` are matched by their room name, with
+    /// the descriptions cached by `set_description_ttl`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use sonos_discovery::Discover;
+    ///
+    /// match Discover::new().unwrap().find_by_room("Living Room").unwrap() {
+    ///     Some(ip) => println!("Living Room is {}", ip),
+    ///     None => println!("No speaker in the Living Room")
+    /// }
+    /// ```
+    pub fn find_by_room(&self, room: &str) -> Result<Option<IpAddr>> {
+        let room = room.trim();
+        let matches = |name: Option<&String>| name.is_some_and(|name| name.trim().eq_ignore_ascii_case(room));
+
+        let result = self.start_fast(None)?;
+        let member = result.grouped().into_iter()
+            .flat_map(|group| group.members)
+            .find(|member| {
+                member.role == MemberRole::Zone && member.ip.is_some() && matches(member.zone_name.as_ref())
+            });
+        if let Some(member) = member {
+            return Ok(member.ip);
+        }
+
+        debug!("The zone groups have no room {}, matching the device descriptions", room);
+        let devices = self.start_with_description(None, None)?;
+
+        Ok(devices.into_iter()
+            .find(|device| matches(device.description.as_ref().and_then(|description| description.room_name.as_ref())))
+            .map(|device| device.ip))
+    }
+
+    /// Near-instant discovery for cli tools: probes the speakers of the cache file at `path` (`DeviceCache`, e.g.
+    /// `DeviceCache::default_path()`) with unicast searches and only falls back to `start` if one of them doesn't
+    /// answer within a second.
+    ///
+    /// Speakers which joined since the last full discovery aren't found as long as all cached speakers answer.
+    /// The result of a full discovery is written to the cache, a missing or unreadable cache file and failed writes
+    /// are only logged. `timeout` applies to the full discovery.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use sonos_discovery::{DeviceCache, Discover};
+    ///
+    /// let path = DeviceCache::default_path().unwrap();
+    /// for ip in Discover::new().unwrap().start_cached(&path, None).unwrap() {
+    ///     println!("{}", ip);
+    /// }
+    /// ```
+    #[cfg(feature = "device-cache")]
+    pub fn start_cached(&self, path: &Path, timeout: Option<Duration>) -> Result<DiscoveryResult> {
+        let cache = DeviceCache::load(path).unwrap_or_else(|e| {
+            warn!("Couldn't read the device cache {}: {}", path.display(), e);
+            DeviceCache::default()
+        });
+
+        let ips = cache.ips();
+        if !ips.is_empty() {
+            let mut probe = self.clone();
+            probe.config.timeout = CACHE_PROBE_TIMEOUT;
+            match probe.probe(&ips) {
+                Ok(result) if ips.iter().all(|ip| result.contains(ip)) => return Ok(result),
+                Ok(result) => debug!("{} of {} cached devices answered, discovering all", result.unique_ips().len(), ips.len()),
+                Err(e) => debug!("Probing the cached devices failed, discovering all: {}", e)
+            }
+        }
+
+        let result = self.start(timeout, None)?;
+        if let Err(e) = DeviceCache::from_result(&result).write(path) {
+            warn!("Couldn't write the device cache {}: {}", path.display(), e);
+        }
+
+        Ok(result)
+    }
+
+    /// Runs `start` on a dedicated thread inside the network namespace `namespace` (see `enter_netns`),
+    /// the namespace of the calling thread isn't changed.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use sonos_discovery::Discover;
+    ///
+    /// let result = Discover::new().unwrap().start_in_netns("lan", None, None).unwrap();
+    /// for ip in result {
+    ///     println!("{}", ip);
+    /// }
+    /// ```
+    #[cfg(all(target_os = "linux", feature = "netns"))]
+    pub fn start_in_netns(&self, namespace: &str, timeout: Option<Duration>, device_count: Option<usize>) -> Result<DiscoveryResult> {
+        let discovery = self.clone();
+        let namespace = namespace.to_string();
+
+        thread::spawn(move || {
+            enter_netns(&namespace).map_err(DiscoveryError::Namespace)?;
+            discovery.start(timeout, device_count)
+        })
+            .join()
+            .unwrap_or_else(|panic| panic::resume_unwind(panic))
+    }
+
+    /// Discovers all devices (waits for the whole configured timeout) and fetches their device descriptions.
+    ///
+    /// Returns one record per device (devices answering from several addresses are merged), sorted by uuid.
+    /// Devices whose description can't be fetched are skipped with a logged warning.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use sonos_discovery::Discover;
+    ///
+    /// for record in Discover::new().unwrap().inventory().unwrap() {
+    ///     println!("{} {:?} {:?} {:?}", record.uuid, record.room_name, record.model_name, record.ips);
+    /// }
+    /// ```
+    pub fn inventory(&self) -> Result<Vec<InventoryRecord>> {
+        let mut config = self.config.clone();
+        config.device_count = None;
+
+        let engine = Engine::new(config, Arc::clone(&self.duplicate_log), self.descriptions.clone())?;
+        let (responses, stats) = engine.search()?;
+        let timeout = engine.fetch_timeout(inventory::FETCH_TIMEOUT, stats.elapsed);
+        let mut records = inventory::collect(&self.config.http_fetcher, self.descriptions.as_ref(), &responses, timeout,
+                                         self.config.max_requests);
+        records.sort_by(|a, b| a.uuid.cmp(&b.uuid));
+
+        Ok(records)
+    }
 }
@@ -0,0 +1,11 @@
+use std::fmt::Debug;
+use std::net::IpAddr;
+
+/// Called while a discovery is running, e.g. to react to a device before the timeout is reached.
+///
+/// Installed with `Discover::set_listener`.
+/// A single discovery can't tell that a device left, compare the results of two discoveries for that.
+pub trait DeviceListener: Debug + Send + Sync {
+    /// First response of a sonos device at `ip`, `uuid` is `None` if the response didn't contain one
+    fn found(&self, ip: IpAddr, uuid: Option<&str>);
+}
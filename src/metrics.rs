@@ -0,0 +1,116 @@
+use ssdp::SsdpParseError;
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// Counters of the discovery internals, e.g. for the dashboards of a long running service.
+///
+/// Installed with `Discover::set_metrics_sink`, every method does nothing by default.
+/// Called from the thread of the discovery while it's running, the methods should be cheap (e.g. an atomic add).
+pub trait MetricsSink: Debug + Send + Sync {
+    /// A search (or a resend or probe) was sent
+    fn packet_sent(&self) {}
+
+    /// A packet was received, whether it answers the search or not
+    fn response_received(&self) {}
+
+    /// A received packet doesn't answer the search or is from another household
+    fn response_rejected(&self) {}
+
+    /// Another response of a device which already answered
+    fn duplicate(&self) {}
+
+    /// A received packet isn't a well-formed ssdp message, the discovery still makes the best of it
+    fn parse_error(&self, _error: &SsdpParseError) {}
+
+    /// A discovery finished after `elapsed`, including the verification and the topology
+    fn discovery_finished(&self, _elapsed: Duration) {}
+}
+
+/// `MetricsSink` which adds up the counters of every discovery it's installed in
+///
+/// # Examples
+///
+/// ```
+/// use sonos_discovery::{CounterMetrics, Discover};
+/// use std::sync::Arc;
+///
+/// let metrics = Arc::new(CounterMetrics::new());
+/// let mut discovery = Discover::new().unwrap();
+/// discovery.set_metrics_sink(metrics.clone());
+/// assert_eq!(metrics.discoveries(), 0);
+/// ```
+#[derive(Debug, Default)]
+pub struct CounterMetrics {
+    packets_sent: AtomicUsize,
+    responses_received: AtomicUsize,
+    responses_rejected: AtomicUsize,
+    duplicates: AtomicUsize,
+    parse_errors: AtomicUsize,
+    discoveries: AtomicUsize,
+    /// Total duration of the discoveries in microseconds
+    duration: AtomicU64
+}
+
+impl CounterMetrics {
+    pub fn new() -> Self {
+        CounterMetrics::default()
+    }
+
+    pub fn packets_sent(&self) -> usize {
+        self.packets_sent.load(Ordering::Relaxed)
+    }
+
+    pub fn responses_received(&self) -> usize {
+        self.responses_received.load(Ordering::Relaxed)
+    }
+
+    pub fn responses_rejected(&self) -> usize {
+        self.responses_rejected.load(Ordering::Relaxed)
+    }
+
+    pub fn duplicates(&self) -> usize {
+        self.duplicates.load(Ordering::Relaxed)
+    }
+
+    pub fn parse_errors(&self) -> usize {
+        self.parse_errors.load(Ordering::Relaxed)
+    }
+
+    /// Number of finished discoveries
+    pub fn discoveries(&self) -> usize {
+        self.discoveries.load(Ordering::Relaxed)
+    }
+
+    /// Total duration of the finished discoveries
+    pub fn duration(&self) -> Duration {
+        Duration::from_micros(self.duration.load(Ordering::Relaxed))
+    }
+}
+
+impl MetricsSink for CounterMetrics {
+    fn packet_sent(&self) {
+        self.packets_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn response_received(&self) {
+        self.responses_received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn response_rejected(&self) {
+        self.responses_rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn duplicate(&self) {
+        self.duplicates.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn parse_error(&self, _error: &SsdpParseError) {
+        self.parse_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn discovery_finished(&self, elapsed: Duration) {
+        self.discoveries.fetch_add(1, Ordering::Relaxed);
+        self.duration.fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,776 @@
+use cancel::CancellationToken;
+use device::DeviceInfo;
+use device_set::DeviceSet;
+use gena::Subscription;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+use Discover;
+
+/// Scans in a row a device has to miss before it's removed, a single lost response doesn't count
+const MISSED_SCANS: u32 = 2;
+
+/// A device is probed to renew its advertisement in the last `1 / RENEWAL_SHARE` of its `max-age`
+const RENEWAL_SHARE: u32 = 10;
+
+/// Time the event thread waits for a topology event before it checks the subscription and the monitor again
+const EVENT_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Failed events in a row after which the event thread subscribes again, e.g. the listener itself broke
+const MAX_EVENT_FAILURES: u32 = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[non_exhaustive]
+/// Change of the devices seen by a `Monitor`
+pub enum MonitorEvent {
+    /// A device answered for the first time (or again after it was removed)
+    DeviceAdded(DeviceInfo),
+    /// A device didn't answer the last scans or its advertisement (`max-age`) ran out without a renewal, the info is
+    /// from its last answer
+    DeviceRemoved(DeviceInfo),
+    /// A known device answered with another `boot_id`, it restarted or rejoined the network and lost its
+    /// subscriptions (GENA), which have to be made again
+    DeviceRebooted(DeviceInfo),
+    /// A known device answered with another `config_id`, its description changed and should be fetched again
+    ConfigChanged(DeviceInfo),
+    /// A known device answered from another address (e.g. a new DHCP lease) than `old`, the one of its last answer
+    AddressChanged {
+        old: IpAddr,
+        new: DeviceInfo
+    }
+}
+
+/// Name of `Monitor` for long running services (e.g. home automation bridges) which hand out subscriptions
+pub type DiscoveryService = Monitor;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Current view of a `Monitor`, see `Monitor::snapshot`
+pub struct MonitorSnapshot {
+    /// Devices of the current view
+    pub devices: Vec<DeviceInfo>,
+    /// Time the last scan finished, `None` before the first one
+    pub last_scan: Option<SystemTime>,
+    /// Number of finished scans, failed ones included
+    pub scans: u64,
+    /// Error of the last scan if it failed, the view is kept from the scan before
+    pub last_error: Option<String>
+}
+
+/// Keeps a live view of the devices by running a discovery periodically on a background thread
+///
+/// Devices are identified by their uuid (or their address without one), a new address, `boot_id` or `config_id` of a
+/// known device is reported as `AddressChanged`, `DeviceRebooted` or `ConfigChanged`.
+/// A device is only valid for the `max-age` of its `CACHE-CONTROL` header: if it runs out before the next scan, the
+/// device is probed shortly before and removed if it didn't answer until then.
+/// Every `subscribe` hands out another receiver of the events, `snapshot` returns the view with the state of the
+/// scans. Dropping the monitor stops it like `shutdown`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use sonos_discovery::{Discover, Monitor, MonitorEvent};
+/// use std::time::Duration;
+///
+/// let monitor = Monitor::start(Discover::new().unwrap(), Duration::from_secs(60));
+/// for event in monitor.subscribe() {
+///     match event {
+///         MonitorEvent::DeviceAdded(device) => println!("+ {}", device.ip),
+///         MonitorEvent::DeviceRemoved(device) => println!("- {}", device.ip),
+///         MonitorEvent::DeviceRebooted(device) => println!("{} restarted", device.ip),
+///         _ => {}
+///     }
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Monitor {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+    /// Thread of the topology subscription, see `Monitor::start_with_events`
+    events: Option<JoinHandle<()>>
+}
+
+/// State shared with the scanning thread
+#[derive(Debug, Default)]
+struct Shared {
+    devices: Mutex<Vec<Tracked>>,
+    /// Time, number and error of the scans
+    scans: Mutex<Scans>,
+    subscribers: Mutex<Vec<mpsc::Sender<MonitorEvent>>>,
+    stopped: Mutex<bool>,
+    /// Cancelled by `stop`, ends a running scan within one receive
+    cancel: CancellationToken,
+    /// A topology event arrived, the next scan starts without waiting for the interval
+    rescan: AtomicBool,
+    /// Wakes the scanning thread from its wait between two scans
+    wake: Condvar
+}
+
+#[derive(Debug, Default)]
+struct Scans {
+    last: Option<SystemTime>,
+    count: u64,
+    last_error: Option<String>
+}
+
+/// Device of the current view
+#[derive(Debug)]
+struct Tracked {
+    key: String,
+    info: DeviceInfo,
+    /// Scans in a row the device didn't answer
+    missed: u32,
+    /// End of the `max-age` of its last answer, `None` if it didn't announce one
+    expires: Option<Instant>,
+    /// Time of the next probe to renew the advertisement, `None` once only the expiry is left
+    renew_at: Option<Instant>
+}
+
+impl Tracked {
+    /// Device seen at `now`
+    fn new(key: String, info: DeviceInfo, now: Instant) -> Self {
+        let mut device = Tracked {
+            key,
+            info,
+            missed: 0,
+            expires: None,
+            renew_at: None
+        };
+        device.renew(now);
+
+        device
+    }
+
+    /// Renews the advertisement at `now` for the `max-age` of `info`
+    fn renew(&mut self, now: Instant) {
+        // A max-age of 0 would be probed over and over again
+        let max_age = self.info.max_age().filter(|max_age| !max_age.is_zero());
+        // `checked_add`, a forged max-age too large for an `Instant` never runs out
+        self.expires = max_age.and_then(|max_age| now.checked_add(max_age));
+        self.renew_at = max_age.and_then(|max_age| now.checked_add(max_age - max_age / RENEWAL_SHARE));
+    }
+
+    fn is_expired(&self, now: Instant) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+}
+
+impl Monitor {
+    /// Starts scanning with `discovery` right away and then every `interval` after a scan finished
+    pub fn start(discovery: Discover, interval: Duration) -> Self {
+        Monitor::spawn(discovery, interval, false)
+    }
+
+    /// Like `start`, but also subscribes to the topology events (GENA) of one of the devices. A speaker joining or
+    /// leaving a group or the household starts a scan within seconds instead of after the `interval`.
+    ///
+    /// The subscription is renewed in the background. If it can't be made or renewed (e.g. a firewall blocks the
+    /// callback of the speaker or the speaker went away) the monitor keeps scanning every `interval` and subscribes
+    /// again at another device after the next scan.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use sonos_discovery::{Discover, Monitor};
+    /// use std::time::Duration;
+    ///
+    /// let monitor = Monitor::start_with_events(Discover::new().unwrap(), Duration::from_secs(300));
+    /// for event in monitor.subscribe() {
+    ///     println!("{:?}", event);
+    /// }
+    /// ```
+    pub fn start_with_events(discovery: Discover, interval: Duration) -> Self {
+        Monitor::spawn(discovery, interval, true)
+    }
+
+    fn spawn(discovery: Discover, interval: Duration, events: bool) -> Self {
+        let shared = Arc::new(Shared::default());
+        let thread = {
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || {
+                let clock = Arc::clone(&discovery.config().clock);
+                'scans: while !shared.is_stopped() {
+                    let scan = discovery.start_detailed_until(&shared.cancel);
+                    // A scan cut short by the shutdown would remove the devices which didn't answer yet
+                    if shared.is_stopped() {
+                        break;
+                    }
+                    let error = match scan {
+                        Ok(devices) => {
+                            shared.update(devices, clock.now());
+                            None
+                        }
+                        Err(e) => {
+                            warn!("Monitor scan failed: {}", e);
+                            Some(e.to_string())
+                        }
+                    };
+                    shared.finish_scan(error);
+
+                    // Advertisements running out before the next scan are renewed in between
+                    let next_scan = clock.now() + interval;
+                    while let Some(renewal) = shared.next_renewal().filter(|&renewal| renewal < next_scan) {
+                        if shared.wait(renewal.saturating_duration_since(clock.now())) {
+                            continue 'scans;
+                        }
+                        shared.renew(&discovery);
+                    }
+                    shared.wait(next_scan.saturating_duration_since(clock.now()));
+                }
+            })
+        };
+
+        let events = if events {
+            let shared = Arc::clone(&shared);
+            Some(thread::spawn(move || shared.watch_topology(interval)))
+        } else {
+            None
+        };
+
+        Monitor {
+            shared,
+            thread: Some(thread),
+            events
+        }
+    }
+
+    /// Receives every event from now on, the channel closes when the monitor stops
+    pub fn subscribe(&self) -> mpsc::Receiver<MonitorEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.shared.subscribers.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).push(sender);
+
+        receiver
+    }
+
+    /// Devices of the current view
+    pub fn devices(&self) -> Vec<DeviceInfo> {
+        self.shared.devices.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).iter()
+            .map(|device| device.info.clone())
+            .collect()
+    }
+
+    /// Devices of the current view by their uuid, e.g. to compare them with an earlier view (`DeviceSet::diff`)
+    pub fn device_set(&self) -> DeviceSet {
+        DeviceSet::from_devices(&self.devices())
+    }
+
+    /// Devices of the current view together with the state of the scans
+    pub fn snapshot(&self) -> MonitorSnapshot {
+        let scans = self.shared.scans.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        MonitorSnapshot {
+            devices: self.devices(),
+            last_scan: scans.last,
+            scans: scans.count,
+            last_error: scans.last_error.clone()
+        }
+    }
+
+    /// Stops scanning and waits for the background threads, a running scan is cancelled (within 500ms) and its
+    /// devices are dropped.
+    ///
+    /// No thread of the monitor is left afterwards, the sockets of the scans are closed and the channels of all
+    /// subscribers are closed.
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            *self.shared.stopped.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = true;
+            self.shared.cancel.cancel();
+            self.shared.wake.notify_all();
+            if thread.join().is_err() {
+                warn!("Monitor thread panicked");
+            }
+            if let Some(events) = self.events.take() {
+                if events.join().is_err() {
+                    warn!("Monitor event thread panicked");
+                }
+            }
+            self.shared.subscribers.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clear();
+        }
+    }
+}
+
+impl Drop for Monitor {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl Shared {
+    fn is_stopped(&self) -> bool {
+        *self.stopped.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Waits `interval`, until a topology event requests a scan or until the monitor is stopped.
+    /// Returns whether it didn't wait the whole `interval` because of either.
+    fn wait(&self, interval: Duration) -> bool {
+        let stopped = self.stopped.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let (stopped, _) = self.wake
+            .wait_timeout_while(stopped, interval, |stopped| !*stopped && !self.rescan.load(Ordering::SeqCst))
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        self.rescan.swap(false, Ordering::SeqCst) || *stopped
+    }
+
+    /// Waits `duration` or until the monitor is stopped, ignores requested scans
+    fn pause(&self, duration: Duration) {
+        let stopped = self.stopped.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let _ = self.wake.wait_timeout_while(stopped, duration, |stopped| !*stopped);
+    }
+
+    /// Starts the next scan right away
+    fn request_scan(&self) {
+        let _stopped = self.stopped.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        self.rescan.store(true, Ordering::SeqCst);
+        self.wake.notify_all();
+    }
+
+    /// Keeps a topology subscription at a device of the view and requests a scan for every change until the monitor
+    /// is stopped, without a device or subscription it tries again after `interval`
+    fn watch_topology(&self, interval: Duration) {
+        while !self.is_stopped() {
+            let device = self.devices.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).iter()
+                .map(|device| device.info.ip)
+                .next();
+            let mut subscription = match device.map(Subscription::subscribe) {
+                Some(Ok(subscription)) => subscription,
+                Some(Err(e)) => {
+                    warn!("Couldn't subscribe to the topology events, scanning every {:?}: {}", interval, e);
+                    self.pause(interval);
+                    continue;
+                }
+                None => {
+                    self.pause(interval);
+                    continue;
+                }
+            };
+            debug!("Subscribed to the topology events of {}", subscription.device());
+
+            let mut failures = 0;
+            while !self.is_stopped() {
+                if let Err(e) = subscription.renew_if_due() {
+                    warn!("Topology subscription at {} dropped, scanning every {:?}: {}",
+                          subscription.device(), interval, e);
+                    break;
+                }
+                match subscription.accept_event() {
+                    Ok(accepted) => {
+                        failures = 0;
+                        if accepted {
+                            self.request_scan();
+                        } else {
+                            self.pause(EVENT_POLL_INTERVAL);
+                        }
+                    }
+                    Err(e) => {
+                        debug!("Invalid topology event: {}", e);
+                        failures += 1;
+                        if failures >= MAX_EVENT_FAILURES {
+                            warn!("Topology events at {} failed {} times in a row, subscribing again",
+                                  subscription.device(), failures);
+                            break;
+                        }
+                        // An error which doesn't go away (e.g. of the listener) would spin
+                        self.pause(EVENT_POLL_INTERVAL);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Records the end of a scan, `error` if it failed
+    fn finish_scan(&self, error: Option<String>) {
+        let mut scans = self.scans.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        scans.last = Some(SystemTime::now());
+        scans.count += 1;
+        scans.last_error = error;
+    }
+
+    /// Earliest probe to renew the advertisement of a device of the view
+    fn next_renewal(&self) -> Option<Instant> {
+        self.devices.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).iter()
+            .filter_map(|device| device.renew_at)
+            .min()
+    }
+
+    /// Probes the devices whose advertisement runs out soon, the ones which didn't answer until it ran out are removed
+    fn renew(&self, discovery: &Discover) {
+        let clock = &discovery.config().clock;
+        let due = self.due(clock.now());
+        if due.is_empty() {
+            return;
+        }
+
+        let answered = discovery.probe(&due).map(|result| result.unique_ips()).unwrap_or_else(|e| {
+            warn!("Couldn't probe the devices to renew their advertisements: {}", e);
+            Vec::new()
+        });
+        self.renewed(&due, &answered, clock.now());
+    }
+
+    /// Addresses of the devices whose advertisement has to be renewed at `now`
+    fn due(&self, now: Instant) -> Vec<IpAddr> {
+        self.devices.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).iter()
+            .filter(|device| device.renew_at.is_some_and(|renew_at| renew_at <= now))
+            .map(|device| device.info.ip)
+            .collect()
+    }
+
+    /// Renews the `due` devices which `answered` the probe at `now`, the others are probed once more right before
+    /// their advertisement runs out and removed once it did
+    fn renewed(&self, due: &[IpAddr], answered: &[IpAddr], now: Instant) {
+        let mut events = Vec::new();
+        {
+            let mut devices = self.devices.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            for device in devices.iter_mut().filter(|device| due.contains(&device.info.ip)) {
+                if answered.contains(&device.info.ip) {
+                    device.renew(now);
+                } else {
+                    // One more probe right before it runs out
+                    device.renew_at = device.expires.filter(|&expires| expires > now);
+                }
+            }
+
+            let (removed, kept): (Vec<Tracked>, Vec<Tracked>) = devices.drain(..)
+                .partition(|device| device.is_expired(now));
+            *devices = kept;
+            events.extend(removed.into_iter().map(|device| MonitorEvent::DeviceRemoved(device.info)));
+        }
+
+        self.publish(events);
+    }
+
+    /// Merges the devices of a scan finished at `now` into the view
+    fn update(&self, scan: Vec<DeviceInfo>, now: Instant) {
+        let mut events = Vec::new();
+        {
+            let mut devices = self.devices.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            for device in devices.iter_mut() {
+                device.missed += 1;
+            }
+
+            for info in scan {
+                let key = info.uuid.clone().unwrap_or_else(|| info.ip.to_string());
+                match devices.iter_mut().find(|device| device.key == key) {
+                    Some(device) => {
+                        // Devices without the UPnP 1.1 headers can't tell, neither can a device seen without them
+                        let changed = |old: Option<u32>, new: Option<u32>| old.is_some() && new.is_some() && old != new;
+                        if device.info.ip != info.ip {
+                            events.push(MonitorEvent::AddressChanged {
+                                old: device.info.ip,
+                                new: info.clone()
+                            });
+                        }
+                        if changed(device.info.boot_id, info.boot_id) {
+                            events.push(MonitorEvent::DeviceRebooted(info.clone()));
+                        }
+                        if changed(device.info.config_id, info.config_id) {
+                            events.push(MonitorEvent::ConfigChanged(info.clone()));
+                        }
+                        device.info = info;
+                        device.missed = 0;
+                        device.renew(now);
+                    }
+                    None => {
+                        events.push(MonitorEvent::DeviceAdded(info.clone()));
+                        devices.push(Tracked::new(key, info, now));
+                    }
+                }
+            }
+
+            let (removed, kept): (Vec<Tracked>, Vec<Tracked>) = devices.drain(..)
+                .partition(|device| device.missed >= MISSED_SCANS || device.is_expired(now));
+            *devices = kept;
+            events.extend(removed.into_iter().map(|device| MonitorEvent::DeviceRemoved(device.info)));
+        }
+
+        self.publish(events);
+    }
+
+    /// Sends `events` to every subscriber, dropped receivers are unsubscribed
+    fn publish(&self, events: Vec<MonitorEvent>) {
+        if events.is_empty() {
+            return;
+        }
+
+        let mut subscribers = self.subscribers.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        subscribers.retain(|subscriber| events.iter().all(|event| subscriber.send(event.clone()).is_ok()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clock::{Clock, ManualClock};
+    use device::DeviceKind;
+
+    /// Speaker `uuid` at `ip` whose advertisement is valid for `max_age` seconds
+    fn device(uuid: &str, ip: &str, max_age: Option<u64>) -> DeviceInfo {
+        DeviceInfo {
+            ip: ip.parse().unwrap(),
+            location: None,
+            usn: None,
+            uuid: Some(uuid.to_string()),
+            mac: None,
+            serial_number: None,
+            server: None,
+            cache_control: max_age.map(|max_age| format!("max-age = {}", max_age)),
+            household: None,
+            description: None,
+            kind: DeviceKind::SonosZonePlayer,
+            hostname: None,
+            boot_id: None,
+            config_id: None
+        }
+    }
+
+    /// State of a monitor with a subscriber of its events
+    fn shared() -> (Shared, mpsc::Receiver<MonitorEvent>) {
+        let shared = Shared::default();
+        let (sender, receiver) = mpsc::channel();
+        shared.subscribers.lock().unwrap().push(sender);
+
+        (shared, receiver)
+    }
+
+    fn keys(shared: &Shared) -> Vec<String> {
+        shared.devices.lock().unwrap().iter().map(|device| device.key.clone()).collect()
+    }
+
+    #[test]
+    fn devices_are_removed_after_the_missed_scans() {
+        let clock = ManualClock::new();
+        let (shared, events) = shared();
+        let kitchen = device("RINCON_A", "192.168.1.10", None);
+
+        shared.update(vec![kitchen.clone()], clock.now());
+        assert_eq!(events.try_recv(), Ok(MonitorEvent::DeviceAdded(kitchen.clone())));
+        for _ in 1..MISSED_SCANS {
+            clock.advance(Duration::from_secs(60));
+            shared.update(Vec::new(), clock.now());
+            assert_eq!(keys(&shared), vec!["RINCON_A"]);
+        }
+        clock.advance(Duration::from_secs(60));
+        shared.update(Vec::new(), clock.now());
+        assert!(keys(&shared).is_empty());
+        assert_eq!(events.try_recv(), Ok(MonitorEvent::DeviceRemoved(kitchen)));
+        assert!(events.try_recv().is_err());
+    }
+
+    #[test]
+    fn an_answer_resets_the_missed_scans() {
+        let clock = ManualClock::new();
+        let (shared, events) = shared();
+        let kitchen = device("RINCON_A", "192.168.1.10", None);
+
+        shared.update(vec![kitchen.clone()], clock.now());
+        for _ in 0..MISSED_SCANS * 2 {
+            clock.advance(Duration::from_secs(60));
+            shared.update(Vec::new(), clock.now());
+            clock.advance(Duration::from_secs(60));
+            shared.update(vec![kitchen.clone()], clock.now());
+        }
+        assert_eq!(keys(&shared), vec!["RINCON_A"]);
+        assert_eq!(events.try_iter().count(), 1);
+    }
+
+    #[test]
+    fn expired_advertisements_are_removed_by_the_next_scan() {
+        let clock = ManualClock::new();
+        let (shared, events) = shared();
+        let kitchen = device("RINCON_A", "192.168.1.10", Some(60));
+        let bathroom = device("RINCON_B", "192.168.1.11", Some(1800));
+
+        shared.update(vec![kitchen.clone(), bathroom.clone()], clock.now());
+        // Missed a single scan only, but its max-age ran out
+        clock.advance(Duration::from_secs(60));
+        shared.update(vec![bathroom], clock.now());
+        assert_eq!(keys(&shared), vec!["RINCON_B"]);
+        assert_eq!(events.try_iter().last(), Some(MonitorEvent::DeviceRemoved(kitchen)));
+    }
+
+    #[test]
+    fn renewals_are_due_in_the_last_tenth_of_the_max_age() {
+        let clock = ManualClock::new();
+        let (shared, _events) = shared();
+        let kitchen = device("RINCON_A", "192.168.1.10", Some(1000));
+
+        shared.update(vec![kitchen.clone()], clock.now());
+        assert_eq!(shared.next_renewal(), Some(clock.now() + Duration::from_secs(900)));
+        clock.advance(Duration::from_secs(899));
+        assert!(shared.due(clock.now()).is_empty());
+        clock.advance(Duration::from_secs(1));
+        let due = shared.due(clock.now());
+        assert_eq!(due, vec![kitchen.ip]);
+
+        shared.renewed(&due, &due, clock.now());
+        assert!(shared.due(clock.now()).is_empty());
+        assert_eq!(shared.next_renewal(), Some(clock.now() + Duration::from_secs(900)));
+        assert_eq!(keys(&shared), vec!["RINCON_A"]);
+    }
+
+    #[test]
+    fn unanswered_renewals_are_retried_at_the_expiry() {
+        let clock = ManualClock::new();
+        let (shared, events) = shared();
+        let kitchen = device("RINCON_A", "192.168.1.10", Some(1000));
+
+        shared.update(vec![kitchen.clone()], clock.now());
+        let expires = clock.now() + Duration::from_secs(1000);
+        clock.advance(Duration::from_secs(900));
+        let due = shared.due(clock.now());
+        shared.renewed(&due, &[], clock.now());
+        assert_eq!(keys(&shared), vec!["RINCON_A"]);
+        assert_eq!(shared.next_renewal(), Some(expires));
+
+        clock.advance(Duration::from_secs(99));
+        assert!(shared.due(clock.now()).is_empty());
+        clock.advance(Duration::from_secs(1));
+        let due = shared.due(clock.now());
+        assert_eq!(due, vec![kitchen.ip]);
+        shared.renewed(&due, &[], clock.now());
+        assert!(keys(&shared).is_empty());
+        assert_eq!(shared.next_renewal(), None);
+        assert_eq!(events.try_iter().last(), Some(MonitorEvent::DeviceRemoved(kitchen)));
+    }
+
+    #[test]
+    fn a_scan_renews_a_device_waiting_for_its_expiry() {
+        let clock = ManualClock::new();
+        let (shared, events) = shared();
+        let kitchen = device("RINCON_A", "192.168.1.10", Some(1000));
+
+        shared.update(vec![kitchen.clone()], clock.now());
+        clock.advance(Duration::from_secs(900));
+        let due = shared.due(clock.now());
+        shared.renewed(&due, &[], clock.now());
+        // A rescan requested by a topology event before the last probe
+        clock.advance(Duration::from_secs(50));
+        shared.update(vec![kitchen], clock.now());
+        assert_eq!(shared.next_renewal(), Some(clock.now() + Duration::from_secs(900)));
+
+        clock.advance(Duration::from_secs(50));
+        assert!(shared.due(clock.now()).is_empty());
+        shared.renewed(&[], &[], clock.now());
+        assert_eq!(keys(&shared), vec!["RINCON_A"]);
+        assert_eq!(events.try_iter().count(), 1);
+    }
+
+    #[test]
+    fn zero_and_huge_max_ages_are_never_renewed() {
+        let clock = ManualClock::new();
+        let (shared, _events) = shared();
+        let kitchen = device("RINCON_A", "192.168.1.10", Some(0));
+        let bathroom = device("RINCON_B", "192.168.1.11", Some(u64::MAX));
+
+        shared.update(vec![kitchen.clone(), bathroom.clone()], clock.now());
+        assert_eq!(shared.next_renewal(), None);
+        clock.advance(Duration::from_secs(3600 * 24 * 365));
+        assert!(shared.due(clock.now()).is_empty());
+        // Only the missed scans remove them
+        shared.update(vec![kitchen, bathroom], clock.now());
+        assert_eq!(keys(&shared), vec!["RINCON_A", "RINCON_B"]);
+    }
+
+    #[test]
+    fn a_requested_scan_interrupts_the_wait() {
+        let shared = Arc::new(Shared::default());
+        let waiting = {
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || shared.wait(Duration::from_secs(600)))
+        };
+        thread::sleep(Duration::from_millis(50));
+        shared.request_scan();
+        assert!(waiting.join().unwrap());
+
+        // The request is used up
+        assert!(!shared.wait(Duration::from_millis(10)));
+        shared.request_scan();
+        assert!(shared.wait(Duration::from_secs(600)));
+    }
+
+    #[test]
+    fn changed_boot_and_config_ids_are_reported() {
+        let clock = ManualClock::new();
+        let (shared, events) = shared();
+        let mut kitchen = device("RINCON_A", "192.168.1.10", None);
+        kitchen.boot_id = Some(1);
+        kitchen.config_id = Some(1);
+
+        shared.update(vec![kitchen.clone()], clock.now());
+        assert_eq!(events.try_recv(), Ok(MonitorEvent::DeviceAdded(kitchen.clone())));
+        shared.update(vec![kitchen.clone()], clock.now());
+        assert!(events.try_recv().is_err());
+
+        kitchen.boot_id = Some(2);
+        shared.update(vec![kitchen.clone()], clock.now());
+        assert_eq!(events.try_recv(), Ok(MonitorEvent::DeviceRebooted(kitchen.clone())));
+        assert!(events.try_recv().is_err());
+
+        kitchen.config_id = Some(2);
+        shared.update(vec![kitchen.clone()], clock.now());
+        assert_eq!(events.try_recv(), Ok(MonitorEvent::ConfigChanged(kitchen.clone())));
+
+        kitchen.boot_id = Some(3);
+        kitchen.config_id = Some(3);
+        shared.update(vec![kitchen.clone()], clock.now());
+        assert_eq!(events.try_iter().collect::<Vec<_>>(),
+                   vec![MonitorEvent::DeviceRebooted(kitchen.clone()), MonitorEvent::ConfigChanged(kitchen.clone())]);
+    }
+
+    #[test]
+    fn changed_addresses_are_reported() {
+        let clock = ManualClock::new();
+        let (shared, events) = shared();
+        let kitchen = device("RINCON_A", "192.168.1.10", None);
+        let mut moved = device("RINCON_A", "192.168.1.30", None);
+        moved.boot_id = Some(2);
+
+        shared.update(vec![kitchen.clone()], clock.now());
+        assert_eq!(events.try_recv(), Ok(MonitorEvent::DeviceAdded(kitchen.clone())));
+        shared.update(vec![kitchen.clone()], clock.now());
+        assert!(events.try_recv().is_err());
+
+        shared.update(vec![moved.clone()], clock.now());
+        assert_eq!(events.try_recv(), Ok(MonitorEvent::AddressChanged {
+            old: kitchen.ip,
+            new: moved.clone()
+        }));
+        assert!(events.try_recv().is_err());
+        assert_eq!(shared.devices.lock().unwrap()[0].info.ip, moved.ip);
+
+        // A device without uuid is known by its address, another one is another device
+        let bathroom = DeviceInfo { uuid: None, ..device("", "192.168.1.11", None) };
+        let elsewhere = DeviceInfo { uuid: None, ..device("", "192.168.1.12", None) };
+        shared.update(vec![moved.clone(), bathroom.clone()], clock.now());
+        shared.update(vec![moved.clone(), elsewhere.clone()], clock.now());
+        assert_eq!(events.try_iter().collect::<Vec<_>>(),
+                   vec![MonitorEvent::DeviceAdded(bathroom), MonitorEvent::DeviceAdded(elsewhere)]);
+    }
+
+    #[test]
+    fn missing_boot_and_config_ids_are_no_change() {
+        let clock = ManualClock::new();
+        let (shared, events) = shared();
+        let mut kitchen = device("RINCON_A", "192.168.1.10", None);
+
+        shared.update(vec![kitchen.clone()], clock.now());
+        let added = MonitorEvent::DeviceAdded(kitchen.clone());
+        kitchen.boot_id = Some(1);
+        kitchen.config_id = Some(1);
+        // Seen without the UPnP 1.1 headers before
+        shared.update(vec![kitchen.clone()], clock.now());
+        kitchen.boot_id = None;
+        kitchen.config_id = None;
+        // and now
+        shared.update(vec![kitchen.clone()], clock.now());
+        assert_eq!(events.try_iter().collect::<Vec<_>>(), vec![added]);
+    }
+}
@@ -0,0 +1,26 @@
+use libc;
+use std::fs::File;
+use std::io::{Error, Result};
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+
+/// Moves the current thread into the network namespace `name`.
+///
+/// `name` is either a namespace created by `ip netns add` (`/run/netns/<name>`) or a path like `/proc/<pid>/ns/net`.
+/// Sockets created afterwards (every `Discover::start` creates a new one) and threads spawned by this thread belong
+/// to the namespace. Needs `CAP_SYS_ADMIN`.
+pub fn enter_netns(name: &str) -> Result<()> {
+    let path = if name.contains('/') {
+        PathBuf::from(name)
+    } else {
+        PathBuf::from("/run/netns").join(name)
+    };
+    let namespace = File::open(&path)?;
+
+    // The file descriptor stays valid until `namespace` is dropped at the end of the function
+    if unsafe { libc::setns(namespace.as_raw_fd(), libc::CLONE_NEWNET) } != 0 {
+        return Err(Error::last_os_error());
+    }
+
+    Ok(())
+}
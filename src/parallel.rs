@@ -0,0 +1,42 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Runs `work` for every job on at most `limit` threads, the results are in the order of the jobs.
+///
+/// A panicking job only fails its own result (like a joined thread), the other jobs still run.
+pub(crate) fn run_limited<T, R, F>(jobs: Vec<T>, limit: usize, work: F) -> Vec<thread::Result<R>>
+    where T: Send + 'static,
+          R: Send + 'static,
+          F: Fn(T) -> R + Send + Sync + 'static {
+    let count = jobs.len();
+    let queue = Arc::new(Mutex::new(jobs.into_iter().enumerate()));
+    let results: Arc<Mutex<Vec<Option<thread::Result<R>>>>> = Arc::new(Mutex::new((0..count).map(|_| None).collect()));
+    let work = Arc::new(work);
+
+    let workers: Vec<_> = (0..limit.max(1).min(count))
+        .map(|_| {
+            let queue = Arc::clone(&queue);
+            let results = Arc::clone(&results);
+            let work = Arc::clone(&work);
+            thread::spawn(move || loop {
+                let next = queue.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).next();
+                let (index, job) = match next {
+                    Some(next) => next,
+                    None => break
+                };
+
+                let result = panic::catch_unwind(AssertUnwindSafe(|| work(job)));
+                results.lock().unwrap_or_else(|poisoned| poisoned.into_inner())[index] = Some(result);
+            })
+        })
+        .collect();
+    for worker in workers {
+        let _ = worker.join();
+    }
+
+    let mut results = results.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    results.drain(..)
+        .map(|result| result.unwrap_or_else(|| Err(Box::new("Job didn't run"))))
+        .collect()
+}
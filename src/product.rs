@@ -0,0 +1,162 @@
+use std::fmt;
+
+/// A sonos product, see `product` and `DeviceInfo::product`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Product {
+    /// Model identifier of the device description (`modelNumber`, e.g. "S18")
+    pub model_number: &'static str,
+    /// Name as sold, with the generation if there are several (e.g. "One Gen 2")
+    pub name: &'static str,
+    /// Hardware generation of the product line, 1 for products with a single generation
+    pub generation: u8
+}
+
+/// Known model identifiers, the `Sub` and a few rare models report their product name instead
+const PRODUCTS: &[Product] = &[
+    Product { model_number: "ZP80", name: "ZonePlayer 80", generation: 1 },
+    Product { model_number: "ZP90", name: "Connect", generation: 1 },
+    Product { model_number: "ZP100", name: "ZonePlayer 100", generation: 1 },
+    Product { model_number: "ZP120", name: "Connect:Amp", generation: 1 },
+    Product { model_number: "S1", name: "Play:1", generation: 1 },
+    Product { model_number: "S3", name: "Play:3", generation: 1 },
+    Product { model_number: "S5", name: "Play:5", generation: 1 },
+    Product { model_number: "S6", name: "Play:5 Gen 2", generation: 2 },
+    Product { model_number: "S9", name: "Playbar", generation: 1 },
+    Product { model_number: "S11", name: "Playbase", generation: 1 },
+    Product { model_number: "S12", name: "Play:1", generation: 1 },
+    Product { model_number: "S13", name: "One", generation: 1 },
+    Product { model_number: "S14", name: "Beam", generation: 1 },
+    Product { model_number: "S15", name: "Connect Gen 2", generation: 2 },
+    Product { model_number: "S16", name: "Amp", generation: 1 },
+    Product { model_number: "S17", name: "Move", generation: 1 },
+    Product { model_number: "S18", name: "One Gen 2", generation: 2 },
+    Product { model_number: "S19", name: "Arc", generation: 1 },
+    Product { model_number: "S20", name: "Symfonisk Table Lamp", generation: 1 },
+    Product { model_number: "S21", name: "Symfonisk Bookshelf", generation: 1 },
+    Product { model_number: "S22", name: "One SL", generation: 1 },
+    Product { model_number: "S27", name: "Roam", generation: 1 },
+    Product { model_number: "S31", name: "Beam Gen 2", generation: 2 },
+    Product { model_number: "S39", name: "Era 100", generation: 1 },
+    Product { model_number: "S41", name: "Era 300", generation: 1 }
+];
+
+/// Product of the model identifier `model_number` (`DeviceDescription::model_number`, case insensitive),
+/// `None` for unknown models
+///
+/// # Examples
+///
+/// ```
+/// let product = sonos_discovery::product("S31").unwrap();
+/// assert_eq!((product.name, product.generation), ("Beam Gen 2", 2));
+/// ```
+pub fn product(model_number: &str) -> Option<&'static Product> {
+    let model_number = model_number.trim();
+    PRODUCTS.iter().find(|product| product.model_number.eq_ignore_ascii_case(model_number))
+}
+
+/// Generation of the sonos system a device runs, the apps and control apis of S1 and S2 aren't compatible
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[non_exhaustive]
+pub enum SonosSystem {
+    /// Firmware 11 and older, the last one for devices which can't run S2
+    S1,
+    /// Firmware 12 and newer
+    S2
+}
+
+impl fmt::Display for SonosSystem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            SonosSystem::S1 => "S1",
+            SonosSystem::S2 => "S2"
+        })
+    }
+}
+
+/// System of the display version of a firmware (e.g. "16.1"), S2 starts at 12.0
+pub(crate) fn system_of_display_version(version: &str) -> Option<SonosSystem> {
+    let major: u32 = version.trim().split('.').next()?.parse().ok()?;
+
+    Some(if major >= 12 { SonosSystem::S2 } else { SonosSystem::S1 })
+}
+
+/// System of the build in a `SERVER` header (`Linux UPnP/1.0 Sonos/70.3-35220 (ZPS9)`).
+/// Builds before 56 predate the split and builds 58 and newer are S2, `None` for the builds 56 and 57 which both
+/// systems used.
+#[cfg(feature = "net")]
+pub(crate) fn system_of_server(server: &str) -> Option<SonosSystem> {
+    let start = server.find("Sonos/")? + "Sonos/".len();
+    let build: u32 = server[start..].split(|c: char| !c.is_ascii_digit()).next()?.parse().ok()?;
+
+    match build {
+        0..=55 => Some(SonosSystem::S1),
+        56 | 57 => None,
+        _ => Some(SonosSystem::S2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn products() {
+        let cases = [("S18", Some(("One Gen 2", 2))),
+                     ("s13", Some(("One", 1))),
+                     (" S9 ", Some(("Playbar", 1))),
+                     ("ZP120", Some(("Connect:Amp", 1))),
+                     ("S41", Some(("Era 300", 1))),
+                     ("S99", None),
+                     ("Sub", None),
+                     ("", None)];
+        for &(model_number, expected) in &cases {
+            let found = product(model_number).map(|product| (product.name, product.generation));
+            assert_eq!(found, expected, "{}", model_number);
+        }
+    }
+
+    #[test]
+    fn model_numbers_are_unique() {
+        for (index, known) in PRODUCTS.iter().enumerate() {
+            assert_eq!(product(known.model_number), Some(known), "{}", known.model_number);
+            let duplicate = PRODUCTS[index + 1..].iter()
+                .any(|other| other.model_number.eq_ignore_ascii_case(known.model_number));
+            assert!(!duplicate, "{}", known.model_number);
+            assert!(known.generation >= 1);
+        }
+    }
+
+    #[test]
+    fn systems_of_display_versions() {
+        let cases = [("16.1", Some(SonosSystem::S2)),
+                     ("12.0", Some(SonosSystem::S2)),
+                     (" 12 ", Some(SonosSystem::S2)),
+                     ("11.12", Some(SonosSystem::S1)),
+                     ("9.3", Some(SonosSystem::S1)),
+                     ("", None),
+                     ("v16.1", None),
+                     ("-1.0", None)];
+        for &(version, system) in &cases {
+            assert_eq!(system_of_display_version(version), system, "{}", version);
+        }
+        assert_eq!(SonosSystem::S1.to_string(), "S1");
+        assert_eq!(SonosSystem::S2.to_string(), "S2");
+    }
+
+    #[cfg(feature = "net")]
+    #[test]
+    fn systems_of_servers() {
+        let cases = [("Linux UPnP/1.0 Sonos/79.1-56030 (ZPS18)", Some(SonosSystem::S2)),
+                     ("Linux UPnP/1.0 Sonos/58.1-77010 (ZPS12)", Some(SonosSystem::S2)),
+                     ("Linux UPnP/1.0 Sonos/57.19-79110 (ZPS9)", None),
+                     ("Linux UPnP/1.0 Sonos/56.0-76060 (ZPS9)", None),
+                     ("Linux UPnP/1.0 Sonos/55.1-25110 (ZPS9)", Some(SonosSystem::S1)),
+                     ("Linux UPnP/1.0 Sonos/70.3-35220 (ZPS9)", Some(SonosSystem::S2)),
+                     ("Linux UPnP/1.0 Sonos/ (ZPS9)", None),
+                     ("Linux/5.4 UPnP/1.0 MiniUPnPd/2.2", None),
+                     ("", None)];
+        for &(server, system) in &cases {
+            assert_eq!(system_of_server(server), system, "{}", server);
+        }
+    }
+}
@@ -0,0 +1,102 @@
+//! Python module `sonos_discovery` (pyo3), built with `maturin build --release` from `pyproject.toml`.
+//!
+//! ```python
+//! import sonos_discovery
+//!
+//! for device in sonos_discovery.discover(timeout=2, count=3):
+//!     print(device["ip"], device["uuid"])
+//! ```
+
+use pyo3::exceptions::{PyOSError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use pyo3::wrap_pyfunction;
+use std::time::Duration;
+use {DeviceInfo, Discover};
+
+/// Discovers the sonos devices for `timeout` seconds or until `count` devices answered.
+///
+/// Returns a dict for every device with the keys of the binary's json output (`ip`, `uuid`, `mac`, `household`,
+/// `location`, `usn`, `server`, `max_age`, `kind`, `system`, `hostname`), a missing header is `None`.
+/// `resolve_hostnames` looks up the hostname of every device (`Discover::set_resolve_hostnames`).
+/// Raises `OSError` if the discovery fails.
+#[pyfunction]
+#[pyo3(signature = (timeout = 5.0, count = None, resolve_hostnames = false))]
+fn discover(py: Python, timeout: f64, count: Option<usize>, resolve_hostnames: bool) -> PyResult<Vec<PyObject>> {
+    let timeout = Duration::try_from_secs_f64(timeout)
+        .map_err(|_| PyValueError::new_err(format!("Invalid timeout {}, has to be a positive number of seconds", timeout)))?;
+    // The search blocks for the whole timeout, other python threads keep running meanwhile
+    let mut discovery = Discover::default();
+    discovery.set_resolve_hostnames(resolve_hostnames);
+    let devices = py.allow_threads(|| discovery.start_detailed(Some(timeout), count))
+        .map_err(|e| PyOSError::new_err(e.to_string()))?;
+
+    devices.iter().map(|device| device_dict(py, device)).collect()
+}
+
+/// Dict of `device` returned by `discover`
+fn device_dict(py: Python, device: &DeviceInfo) -> PyResult<PyObject> {
+    let dict = PyDict::new(py);
+    dict.set_item("ip", device.ip.to_string())?;
+    dict.set_item("uuid", &device.uuid)?;
+    dict.set_item("mac", &device.mac)?;
+    dict.set_item("household", &device.household)?;
+    dict.set_item("location", &device.location)?;
+    dict.set_item("usn", &device.usn)?;
+    dict.set_item("server", &device.server)?;
+    dict.set_item("max_age", device.max_age().map(|max_age| max_age.as_secs()))?;
+    dict.set_item("kind", device.kind.to_string())?;
+    dict.set_item("system", device.system().map(|system| system.to_string()))?;
+    dict.set_item("hostname", &device.hostname)?;
+
+    Ok(dict.to_object(py))
+}
+
+#[pymodule]
+fn sonos_discovery(_py: Python, module: &PyModule) -> PyResult<()> {
+    // The macro `use`s the function, paths of a `use` start at the crate root in the 2015 edition
+    module.add_function(wrap_pyfunction!(python::discover, module)?)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use DeviceKind;
+
+    #[test]
+    fn devices_are_dicts_with_the_keys_of_the_json_output() {
+        let device = DeviceInfo {
+            ip: "192.168.1.10".parse().unwrap(),
+            location: Some("http://192.168.1.10:1400/xml/device_description.xml".to_string()),
+            usn: None,
+            uuid: Some("RINCON_000E58A0123401400".to_string()),
+            mac: Some("00:0E:58:A0:12:34".to_string()),
+            serial_number: Some("00-0E-58-A0-12-34".to_string()),
+            server: None,
+            cache_control: Some("max-age = 1800".to_string()),
+            household: None,
+            description: None,
+            kind: DeviceKind::SonosZonePlayer,
+            hostname: None,
+            boot_id: None,
+            config_id: None
+        };
+
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let dict = device_dict(py, &device).unwrap();
+            let dict: &PyDict = dict.downcast(py).unwrap();
+            let keys: Vec<String> = dict.keys().extract().unwrap();
+            assert_eq!(keys, ["ip", "uuid", "mac", "household", "location", "usn", "server", "max_age", "kind", "system",
+                              "hostname"]);
+            let item = |key: &str| dict.get_item(key).unwrap().unwrap();
+            assert_eq!(item("ip").extract::<String>().unwrap(), "192.168.1.10");
+            assert_eq!(item("mac").extract::<String>().unwrap(), "00:0E:58:A0:12:34");
+            assert_eq!(item("max_age").extract::<u64>().unwrap(), 1800);
+            assert_eq!(item("kind").extract::<String>().unwrap(), "sonos");
+            assert!(item("household").is_none());
+        });
+    }
+}
@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Token bucket of a single source
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    /// Messages dropped since the last allowed one
+    suppressed: u32
+}
+
+/// Token bucket per source address, limits how often a message about the same source is logged.
+/// Shared by all discoveries of a `Discover`, so the limit holds across the runs of a long monitor session.
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    /// Messages allowed in a burst
+    capacity: f64,
+    /// Time until one more message is allowed
+    refill: Duration,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>
+}
+
+impl RateLimiter {
+    pub(crate) fn new(capacity: u32, refill: Duration) -> Self {
+        RateLimiter {
+            capacity: f64::from(capacity),
+            refill,
+            buckets: Mutex::new(HashMap::new())
+        }
+    }
+
+    /// Takes a token of `source`.
+    /// Returns the number of suppressed messages since the last allowed one, `None` if this one is suppressed as well.
+    pub(crate) fn allow(&self, source: IpAddr, now: Instant) -> Option<u32> {
+        let mut buckets = self.buckets.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let capacity = self.capacity;
+        let refill = self.refill.as_secs() as f64 + f64::from(self.refill.subsec_nanos()) / 1e9;
+        let refilled = |bucket: &Bucket| {
+            let elapsed = now.duration_since(bucket.updated);
+            let elapsed = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+            (bucket.tokens + elapsed / refill).min(capacity)
+        };
+
+        // Full buckets behave like new ones, dropping them keeps the map small
+        buckets.retain(|_, bucket| bucket.suppressed > 0 || refilled(bucket) < capacity);
+
+        let bucket = buckets.entry(source).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+            suppressed: 0
+        });
+        bucket.tokens = refilled(bucket);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            let suppressed = bucket.suppressed;
+            bucket.suppressed = 0;
+            Some(suppressed)
+        } else {
+            bucket.suppressed += 1;
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn bursts_are_limited() {
+        let limiter = RateLimiter::new(3, Duration::from_secs(10));
+        let start = Instant::now();
+        let source = ip("192.168.1.20");
+
+        let allowed: Vec<Option<u32>> = (0..5).map(|_| limiter.allow(source, start)).collect();
+        assert_eq!(allowed, vec![Some(0), Some(0), Some(0), None, None]);
+
+        // One token after the refill, with the count of the messages dropped in the meantime
+        assert_eq!(limiter.allow(source, start + Duration::from_secs(9)), None);
+        assert_eq!(limiter.allow(source, start + Duration::from_secs(10)), Some(3));
+        assert_eq!(limiter.allow(source, start + Duration::from_secs(10)), None);
+        // Never more than a burst, however long it was quiet
+        let later = start + Duration::from_secs(3600);
+        let allowed: Vec<Option<u32>> = (0..4).map(|_| limiter.allow(source, later)).collect();
+        assert_eq!(allowed, vec![Some(1), Some(0), Some(0), None]);
+    }
+
+    #[test]
+    fn sources_have_buckets_of_their_own() {
+        let limiter = RateLimiter::new(1, Duration::from_secs(10));
+        let now = Instant::now();
+
+        assert_eq!(limiter.allow(ip("192.168.1.20"), now), Some(0));
+        assert_eq!(limiter.allow(ip("192.168.1.20"), now), None);
+        assert_eq!(limiter.allow(ip("192.168.1.21"), now), Some(0));
+        assert_eq!(limiter.allow(ip("fe80::1"), now), Some(0));
+        assert_eq!(limiter.allow(ip("192.168.1.21"), now), None);
+    }
+
+    #[test]
+    fn full_buckets_are_dropped() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(1));
+        let start = Instant::now();
+        for index in 0..100 {
+            limiter.allow(IpAddr::from([10, 0, 0, index]), start);
+        }
+        assert_eq!(limiter.buckets.lock().unwrap().len(), 100);
+
+        // Refilled buckets without suppressed messages are gone with the next message
+        limiter.allow(ip("192.168.1.20"), start + Duration::from_secs(2));
+        assert_eq!(limiter.buckets.lock().unwrap().len(), 1);
+
+        // Buckets with suppressed messages are kept until they were reported
+        let source = ip("192.168.1.21");
+        let later = start + Duration::from_secs(10);
+        for _ in 0..3 {
+            limiter.allow(source, later);
+        }
+        limiter.allow(ip("192.168.1.22"), later + Duration::from_secs(60));
+        assert!(limiter.buckets.lock().unwrap().contains_key(&source));
+        assert_eq!(limiter.allow(source, later + Duration::from_secs(60)), Some(1));
+    }
+
+    #[test]
+    fn limits_without_a_refill_time() {
+        let limiter = RateLimiter::new(1, Duration::from_secs(0));
+        let now = Instant::now();
+        assert!((0..10).all(|_| limiter.allow(ip("192.168.1.20"), now) == Some(0)));
+
+        let limiter = RateLimiter::new(0, Duration::from_secs(1));
+        assert_eq!(limiter.allow(ip("192.168.1.20"), now), None);
+        assert_eq!(limiter.allow(ip("192.168.1.20"), now + Duration::from_secs(5)), None);
+    }
+}
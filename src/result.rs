@@ -0,0 +1,568 @@
+use config::DiscoveryConfig;
+use device_set::DeviceSet;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::net::{IpAddr, SocketAddr};
+use std::ops::{Deref, Index};
+use std::slice::{self, SliceIndex};
+use std::time::{Duration, Instant, SystemTime};
+use std::vec;
+use topology::{MemberRole, ZoneGroup, ZoneGroupMember};
+use upnp::UpnpHeaders;
+
+#[derive(Debug, Clone)]
+/// Result of a single discovery (`Discover::start`)
+///
+/// Iterating over the result yields the addresses of the discovered devices, sorted and every device once.
+/// It also derefs to a slice of the addresses, so `result.len()`, `result[0]`, `result.iter()` etc. work as on the former `Vec<IpAddr>`.
+///
+/// Two results are equal if they found the same devices with the same uuids and households, the timing, statistics
+/// and configuration of the discoveries aren't compared.
+pub struct DiscoveryResult {
+    /// Addresses of the discovered devices, sorted and without duplicates
+    devices: Vec<IpAddr>,
+    /// Addresses of the responses in the order they were received, once for every answer of a device
+    responses: Vec<IpAddr>,
+    /// Unique ids (`RINCON_...`) of the devices from the `USN` header of their responses
+    uuids: HashMap<IpAddr, String>,
+    /// UPnP 1.1 headers of the first response of each device
+    upnp: HashMap<IpAddr, UpnpHeaders>,
+    /// Household ids (`X-RINCON-HOUSEHOLD` header) of the devices
+    households: HashMap<IpAddr, String>,
+    /// First and last response of each device
+    seen: HashMap<IpAddr, Sighting>,
+    /// Payloads of the responses of each device, only with `Discover::set_keep_raw`
+    raw: HashMap<IpAddr, Vec<RawResponse>>,
+    /// Payloads of the rejected responses, only with `Discover::set_keep_raw`
+    rejected: Vec<RawResponse>,
+    /// Zone groups of all households, empty without `Discover::set_topology`
+    groups: Vec<ZoneGroup>,
+    /// Statistics about the received responses
+    stats: DiscoveryStats,
+    /// Hints why the discovery might be incomplete
+    warnings: Vec<String>,
+    /// Effective configuration the discovery ran with
+    config: DiscoveryConfig
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+/// How the search message was sent
+pub enum SearchMethod {
+    /// To the multicast address (the default)
+    #[default]
+    Multicast,
+    /// To the broadcast fallback, sending to the multicast address failed
+    Broadcast,
+    /// Directly to the probed addresses (`Discover::probe`)
+    Unicast,
+    /// No search was sent, the devices announced themselves (`Discover::listen`)
+    Passive
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// When a device answered during a discovery, see `DiscoveryResult::seen`
+pub struct Sighting {
+    /// First response on the discovery's clock (`Discover::set_clock`), for measuring durations
+    pub first_seen: Instant,
+    /// First response as wall clock time, for display and storage
+    pub first_seen_at: SystemTime,
+    /// Last response on the discovery's clock, devices can answer more than once
+    pub last_seen: Instant,
+    /// Last response as wall clock time
+    pub last_seen_at: SystemTime,
+    /// Time from sending the search until the first response
+    pub latency: Duration
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+/// Received packet as it was sent, see `Discover::set_keep_raw`
+pub struct RawResponse {
+    /// Sender of the packet
+    pub addr: SocketAddr,
+    /// Payload, truncated to `DiscoveryConfig::receive_buffer_size`
+    pub data: Vec<u8>,
+    /// Wall clock time the packet was received
+    pub received_at: SystemTime
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+/// Statistics about a single discovery
+pub struct DiscoveryStats {
+    /// Number of received responses
+    pub responses: usize,
+    /// Number of responses which weren't sent by a sonos device
+    pub rejected: usize,
+    /// Number of sonos responses dropped by the `LOCATION` verification (`Discover::set_verify`)
+    pub unverified: usize,
+    /// Time from sending the search until the discovery stopped
+    pub elapsed: Duration,
+    /// Wall time of the whole discovery, including the verification and the topology
+    pub total: Duration,
+    /// Wall clock time the search was sent (the listening started for a passive discovery)
+    pub sent_at: Option<SystemTime>,
+    /// Number of additional responses of devices which had already answered
+    pub duplicates: usize,
+    /// How the search which produced the responses was sent
+    pub method: SearchMethod,
+    /// Time from sending the search until the first sonos device answered
+    pub first_response: Option<Duration>,
+    /// The discovery was stopped by its `CancellationToken`, the verification and topology were skipped
+    pub cancelled: bool,
+    /// Number of devices found by the sweep fallback (`Discover::set_sweep_fallback`) after nobody answered
+    pub swept: usize,
+    /// Number of times the socket was recreated after it failed (`Discover::set_recreate_socket`)
+    pub recoveries: usize
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+/// Timing of a single discovery, see `DiscoveryResult::report`
+pub struct DiscoveryReport {
+    /// Wall clock time the search was sent
+    pub sent_at: Option<SystemTime>,
+    /// Time from sending the search until the first response of every device, the fastest first
+    pub latencies: Vec<(IpAddr, Duration)>,
+    /// Timeout of the search
+    pub timeout: Duration,
+    /// Number of received responses
+    pub responses: usize,
+    /// Number of additional responses of devices which had already answered
+    pub duplicates: usize,
+    /// Number of responses which weren't sent by a sonos device
+    pub rejected: usize,
+    /// Time from sending the search until the discovery stopped
+    pub elapsed: Duration,
+    /// Wall time of the whole discovery, including the verification and the topology
+    pub total: Duration
+}
+
+impl DiscoveryResult {
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(responses: Vec<IpAddr>, uuids: HashMap<IpAddr, String>, upnp: HashMap<IpAddr, UpnpHeaders>,
+                      households: HashMap<IpAddr, String>, seen: HashMap<IpAddr, Sighting>,
+                      raw: HashMap<IpAddr, Vec<RawResponse>>, rejected: Vec<RawResponse>, groups: Vec<ZoneGroup>,
+                      stats: DiscoveryStats, warnings: Vec<String>, config: DiscoveryConfig) -> Self {
+        let mut devices = responses.clone();
+        devices.sort();
+        devices.dedup();
+
+        DiscoveryResult {
+            devices,
+            responses,
+            uuids,
+            upnp,
+            households,
+            seen,
+            raw,
+            rejected,
+            groups,
+            stats,
+            warnings,
+            config
+        }
+    }
+
+    /// Adds the members of the zone groups which didn't answer the search (`Discover::start_fast`)
+    pub(crate) fn add_group_members(&mut self) {
+        let members: Vec<(IpAddr, String)> = self.groups.iter()
+            .flat_map(|group| &group.members)
+            .filter_map(|member| member.ip.map(|ip| (ip, member.uuid.clone())))
+            .collect();
+        for (ip, uuid) in members {
+            if let Err(index) = self.devices.binary_search(&ip) {
+                self.devices.insert(index, ip);
+                self.uuids.insert(ip, uuid);
+            }
+        }
+    }
+
+    /// Adds the devices of the sweep fallback (`Discover::set_sweep_fallback`), they have no response
+    pub(crate) fn add_swept(&mut self, devices: Vec<(IpAddr, Option<String>)>) {
+        for (ip, uuid) in devices {
+            if let Err(index) = self.devices.binary_search(&ip) {
+                self.devices.insert(index, ip);
+                if let Some(uuid) = uuid {
+                    self.uuids.insert(ip, uuid);
+                }
+                self.stats.swept += 1;
+            }
+        }
+    }
+
+    /// Addresses of the discovered devices sorted, every device only once even if it answered several times
+    pub fn devices(&self) -> &[IpAddr] {
+        &self.devices
+    }
+
+    /// Addresses of the responses in the order they were received, a device which answered several times is listed
+    /// for every answer. Devices which didn't answer the search (`Discover::start_fast`, the sweep fallback) are
+    /// missing.
+    pub fn response_addrs(&self) -> &[IpAddr] {
+        &self.responses
+    }
+
+    /// Addresses of the discovered devices like `devices`, same as the return value of `Discover::start` in previous
+    /// versions
+    pub fn ips(&self) -> Vec<IpAddr> {
+        self.devices.clone()
+    }
+
+    /// Addresses of the discovered devices sorted, every device only once even if it answered several times
+    pub fn unique_ips(&self) -> Vec<IpAddr> {
+        self.devices.clone()
+    }
+
+    /// Unique id (`RINCON_...`) of the device at `ip`, `None` if its response had no usable `USN` header.
+    ///
+    /// The id stays the same when a device gets a new address (e.g. from dhcp).
+    pub fn uuid(&self, ip: &IpAddr) -> Option<&str> {
+        self.uuids.get(ip).map(String::as_str)
+    }
+
+    /// Household id (`X-RINCON-HOUSEHOLD` header, e.g. `Sonos_abc123`) of the device at `ip`, `None` if its
+    /// response had none
+    pub fn household(&self, ip: &IpAddr) -> Option<&str> {
+        self.households.get(ip).map(String::as_str)
+    }
+
+    /// Discovered devices grouped by their household (see `household`), sorted like `unique_ips`.
+    ///
+    /// Devices without a household id are grouped under `None`. To only discover one household use
+    /// `Discover::set_household`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use sonos_discovery::Discover;
+    ///
+    /// let result = Discover::new().unwrap().start(None, None).unwrap();
+    /// for (household, ips) in result.by_household() {
+    ///     println!("{}: {:?}", household.as_deref().unwrap_or("unknown household"), ips);
+    /// }
+    /// ```
+    pub fn by_household(&self) -> BTreeMap<Option<String>, Vec<IpAddr>> {
+        let mut households: BTreeMap<Option<String>, Vec<IpAddr>> = BTreeMap::new();
+        for ip in self.unique_ips() {
+            households.entry(self.households.get(&ip).cloned()).or_default().push(ip);
+        }
+
+        households
+    }
+
+    /// Devices by their uuid (or their address without one) to compare the result with another scan, see
+    /// `DeviceSet::diff`. A device which answered from several addresses is listed with the address of its first
+    /// response, a device without a response with its lowest address.
+    pub fn device_set(&self) -> DeviceSet {
+        let mut set = DeviceSet::new();
+        // Later insertions replace the address, the first response comes last
+        for ip in self.devices.iter().rev().chain(self.responses.iter().rev()) {
+            set.insert(self.uuid(ip).map_or_else(|| ip.to_string(), str::to_string), *ip);
+        }
+
+        set
+    }
+
+    /// First and last response of the device at `ip`, `None` if it wasn't discovered
+    pub fn seen(&self, ip: &IpAddr) -> Option<&Sighting> {
+        self.seen.get(ip)
+    }
+
+    /// Payloads of all responses of the device at `ip` in the order they were received,
+    /// empty without `Discover::set_keep_raw`
+    pub fn raw_responses(&self, ip: &IpAddr) -> &[RawResponse] {
+        self.raw.get(ip).map_or(&[], Vec::as_slice)
+    }
+
+    /// Payloads of the responses which were received but not accepted (e.g. from other devices or households),
+    /// empty without `Discover::set_keep_raw`
+    pub fn rejected_responses(&self) -> &[RawResponse] {
+        &self.rejected
+    }
+
+    /// Discovered devices organized by zone group, the coordinator of each group comes first.
+    ///
+    /// Needs `Discover::set_topology`, otherwise (or if the topology couldn't be fetched) every device is its own group.
+    /// Groups contain all their members, even those which didn't answer the search.
+    /// A device missing in the topology becomes a group of its own with its uuid (or its address) as id.
+    pub fn grouped(&self) -> Vec<ZoneGroup> {
+        let mut groups = self.groups.clone();
+
+        for ip in &self.devices {
+            let uuid = self.uuid(ip);
+            let grouped = groups.iter()
+                .flat_map(|group| &group.members)
+                .any(|member| member.ip == Some(*ip) || (uuid.is_some() && uuid == Some(member.uuid.as_str())));
+            if !grouped {
+                let id = uuid.map_or_else(|| ip.to_string(), str::to_string);
+                groups.push(ZoneGroup {
+                    id: id.clone(),
+                    members: vec![ZoneGroupMember {
+                        uuid: id,
+                        ip: Some(*ip),
+                        zone_name: None,
+                        role: MemberRole::Zone,
+                        bonded_to: None
+                    }]
+                });
+            }
+        }
+
+        groups
+    }
+
+    /// Coordinator of the zone group of the device at `ip` (the device itself if it coordinates the group), which has
+    /// to receive the transport commands (play, pause, volume of the group) of all members.
+    ///
+    /// `None` if the device isn't in the topology (`Discover::set_topology` or `Discover::start_fast`) or the
+    /// address of the coordinator is unknown.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use sonos_discovery::Discover;
+    ///
+    /// let mut discovery = Discover::new().unwrap();
+    /// discovery.set_topology(true);
+    /// let result = discovery.start(None, None).unwrap();
+    /// for ip in result.unique_ips() {
+    ///     match result.coordinator(&ip) {
+    ///         Some(coordinator) if coordinator == ip => {
+    ///             println!("{} coordinates {:?}", ip, result.group_members(&ip))
+    ///         }
+    ///         Some(coordinator) => println!("{} is grouped under {}", ip, coordinator),
+    ///         None => println!("{} isn't in the topology", ip)
+    ///     }
+    /// }
+    /// ```
+    pub fn coordinator(&self, ip: &IpAddr) -> Option<IpAddr> {
+        self.group_of(ip)?.coordinator().ip
+    }
+
+    /// Whether the device at `ip` coordinates its zone group, see `coordinator`
+    pub fn is_coordinator(&self, ip: &IpAddr) -> bool {
+        self.coordinator(ip) == Some(*ip)
+    }
+
+    /// Addresses of the coordinators of all zone groups of the topology, sorted
+    pub fn coordinators(&self) -> Vec<IpAddr> {
+        let mut coordinators: Vec<IpAddr> = self.groups.iter().filter_map(|group| group.coordinator().ip).collect();
+        coordinators.sort();
+        coordinators.dedup();
+
+        coordinators
+    }
+
+    /// Addresses of the other members of the zone group coordinated by the device at `coordinator`, in the order of
+    /// the topology. Empty if the device doesn't coordinate a group or plays on its own.
+    pub fn group_members(&self, coordinator: &IpAddr) -> Vec<IpAddr> {
+        match self.group_of(coordinator) {
+            Some(group) if group.coordinator().ip == Some(*coordinator) => {
+                group.members[1..].iter().filter_map(|member| member.ip).collect()
+            }
+            _ => Vec::new()
+        }
+    }
+
+    /// Zone group of the topology with the device at `ip` (by its address or uuid)
+    fn group_of(&self, ip: &IpAddr) -> Option<&ZoneGroup> {
+        let uuid = self.uuid(ip);
+        self.groups.iter().find(|group| {
+            group.members.iter()
+                .any(|member| member.ip == Some(*ip) || (uuid.is_some() && uuid == Some(member.uuid.as_str())))
+        })
+    }
+
+    /// UPnP 1.1 headers of the device at `ip`, e.g. the port for a unicast re-probe (`UpnpHeaders::search_addr`)
+    pub fn upnp_headers(&self, ip: &IpAddr) -> Option<&UpnpHeaders> {
+        self.upnp.get(ip)
+    }
+
+    /// Statistics about the received responses
+    pub fn stats(&self) -> &DiscoveryStats {
+        &self.stats
+    }
+
+    /// Timing of the discovery, e.g. to find devices answering at the edge of the timeout
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use sonos_discovery::Discover;
+    ///
+    /// let report = Discover::new().unwrap().start(None, None).unwrap().report();
+    /// for (ip, latency) in &report.latencies {
+    ///     println!("{} answered after {:?} of {:?}", ip, latency, report.timeout);
+    /// }
+    /// ```
+    pub fn report(&self) -> DiscoveryReport {
+        let mut latencies: Vec<(IpAddr, Duration)> = self.seen.iter()
+            .map(|(ip, sighting)| (*ip, sighting.latency))
+            .collect();
+        latencies.sort_by_key(|&(ip, latency)| (latency, ip));
+
+        DiscoveryReport {
+            sent_at: self.stats.sent_at,
+            latencies,
+            timeout: self.config.timeout,
+            responses: self.stats.responses,
+            duplicates: self.stats.duplicates,
+            rejected: self.stats.rejected,
+            elapsed: self.stats.elapsed,
+            total: self.stats.total
+        }
+    }
+
+    /// Hints why the discovery might be incomplete (e.g. no responses at all)
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    /// Effective configuration the discovery ran with (defaults and arguments of `start` applied)
+    pub fn config(&self) -> &DiscoveryConfig {
+        &self.config
+    }
+}
+
+impl PartialEq for DiscoveryResult {
+    fn eq(&self, other: &Self) -> bool {
+        self.devices == other.devices && self.devices.iter().all(|ip| {
+            self.uuid(ip) == other.uuid(ip) && self.household(ip) == other.household(ip)
+        })
+    }
+}
+
+impl Eq for DiscoveryResult {}
+
+impl Hash for DiscoveryResult {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for ip in &self.devices {
+            (ip, self.uuid(ip), self.household(ip)).hash(state);
+        }
+    }
+}
+
+impl IntoIterator for DiscoveryResult {
+    type Item = IpAddr;
+    type IntoIter = vec::IntoIter<IpAddr>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.devices.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a DiscoveryResult {
+    type Item = &'a IpAddr;
+    type IntoIter = slice::Iter<'a, IpAddr>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.devices.iter()
+    }
+}
+
+impl Deref for DiscoveryResult {
+    type Target = [IpAddr];
+
+    fn deref(&self) -> &[IpAddr] {
+        &self.devices
+    }
+}
+
+impl<I: SliceIndex<[IpAddr]>> Index<I> for DiscoveryResult {
+    type Output = I::Output;
+
+    fn index(&self, index: I) -> &Self::Output {
+        &self.devices[index]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use description::element;
+    use std::collections::hash_map::DefaultHasher;
+    use topology;
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    /// Result of the responses from `responses`, the devices at `uuids` with these uuids
+    fn result(responses: &[&str], uuids: &[(&str, &str)], stats: DiscoveryStats) -> DiscoveryResult {
+        DiscoveryResult::new(responses.iter().map(|&address| ip(address)).collect(),
+                             uuids.iter().map(|&(address, uuid)| (ip(address), uuid.to_string())).collect(),
+                             HashMap::new(), HashMap::new(), HashMap::new(), HashMap::new(), Vec::new(), Vec::new(),
+                             stats, Vec::new(), DiscoveryConfig::default())
+    }
+
+    #[test]
+    fn devices_are_sorted_and_unique() {
+        let mut result = result(&["192.168.1.12", "192.168.1.10", "192.168.1.12"], &[], DiscoveryStats::default());
+        result.add_swept(vec![(ip("192.168.1.11"), None), (ip("192.168.1.10"), None)]);
+
+        let sorted = vec![ip("192.168.1.10"), ip("192.168.1.11"), ip("192.168.1.12")];
+        assert_eq!(result.devices(), &sorted[..]);
+        assert_eq!(result.unique_ips(), sorted);
+        assert_eq!(result.clone().into_iter().collect::<Vec<_>>(), sorted);
+        assert_eq!(result.response_addrs(), &[ip("192.168.1.12"), ip("192.168.1.10"), ip("192.168.1.12")]);
+        assert_eq!(result.stats().swept, 1);
+    }
+
+    #[test]
+    fn device_set_keeps_the_address_of_the_first_response() {
+        let result = result(&["192.168.1.12", "192.168.1.10"],
+                            &[("192.168.1.10", "RINCON_A"), ("192.168.1.12", "RINCON_A")], DiscoveryStats::default());
+
+        assert_eq!(result.device_set().ip("RINCON_A"), Some(ip("192.168.1.12")));
+    }
+
+    #[test]
+    fn results_of_the_same_devices_are_equal() {
+        let stats = DiscoveryStats {
+            responses: 3,
+            ..DiscoveryStats::default()
+        };
+        let first = result(&["192.168.1.10", "192.168.1.11"], &[("192.168.1.10", "RINCON_A")], stats);
+        let second = result(&["192.168.1.11", "192.168.1.10", "192.168.1.10"], &[("192.168.1.10", "RINCON_A")],
+                            DiscoveryStats::default());
+        let other = result(&["192.168.1.10", "192.168.1.11"], &[("192.168.1.10", "RINCON_B")], stats);
+
+        assert_eq!(first, second);
+        assert_ne!(first, other);
+        let hash = |result: &DiscoveryResult| {
+            let mut hasher = DefaultHasher::new();
+            result.hash(&mut hasher);
+            hasher.finish()
+        };
+        assert_eq!(hash(&first), hash(&second));
+    }
+
+    #[test]
+    fn devices_are_grouped_by_the_topology() {
+        let state = element(include_str!("../tests/fixtures/zone_group_state.xml"), "ZoneGroupState").unwrap();
+        // The living room and the kitchen answered, as well as a device missing in the topology
+        let mut result = result(&["192.168.1.30", "192.168.1.20", "192.168.1.99"], &[("192.168.1.99", "RINCON_X")],
+                                DiscoveryStats::default());
+        result.groups = topology::parse(&state, true);
+
+        let groups = result.grouped();
+        assert_eq!(groups.len(), 4);
+        assert_eq!(groups[..3], result.groups[..]);
+        assert_eq!(groups[3].id, "RINCON_X");
+        assert_eq!(groups[3].members.len(), 1);
+        assert_eq!(groups[3].coordinator().ip, Some(ip("192.168.1.99")));
+
+        assert_eq!(result.coordinator(&ip("192.168.1.30")), Some(ip("192.168.1.20")));
+        assert!(result.is_coordinator(&ip("192.168.1.20")));
+        assert!(!result.is_coordinator(&ip("192.168.1.30")));
+        assert_eq!(result.coordinator(&ip("192.168.1.99")), None);
+        assert_eq!(result.coordinators(), vec![ip("192.168.1.20"), ip("192.168.1.40"), ip("192.168.1.50")]);
+        // Members in the order of the topology, the satellites of the coordinator first
+        assert_eq!(result.group_members(&ip("192.168.1.20")),
+                   vec![ip("192.168.1.21"), ip("192.168.1.22"), ip("192.168.1.23"), ip("192.168.1.30")]);
+        assert!(result.group_members(&ip("192.168.1.30")).is_empty());
+    }
+}
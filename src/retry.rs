@@ -0,0 +1,198 @@
+use clock::Clock;
+use std::collections::hash_map::RandomState;
+use std::fmt::Debug;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{Error, ErrorKind, Result};
+use std::time::Duration;
+
+/// Retries of a transient error (`is_transient`) before the `RetryPolicy` is asked
+const TRANSIENT_RETRIES: u32 = 3;
+/// Delay before the first retry of a transient error, doubled for every further one
+const TRANSIENT_DELAY: Duration = Duration::from_millis(20);
+
+/// Decides whether and when a failed network operation is retried.
+///
+/// Used for sending the search message, installed with `Discover::set_retry_policy`. Transient errors (an
+/// interrupted call, a network which is unreachable for a moment) are retried up to 3 times with a short backoff
+/// before the policy is asked.
+pub trait RetryPolicy: Debug + Send + Sync {
+    /// Delay before retry number `attempt` (starting at 1), `None` gives up and returns the last error
+    fn retry_delay(&self, attempt: u32) -> Option<Duration>;
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+/// Never retries, the first error is returned
+pub struct NoRetry;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Retries up to `retries` times with the same `delay` in between
+pub struct FixedRetry {
+    pub retries: u32,
+    pub delay: Duration
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Retries up to `retries` times, doubling the delay (starting at `initial`) up to `max`.
+///
+/// With `jitter` each delay is randomized between half and the full delay, so several hosts failing at the same time
+/// don't retry in lockstep.
+pub struct ExponentialBackoff {
+    pub retries: u32,
+    pub initial: Duration,
+    pub max: Duration,
+    pub jitter: bool,
+    /// Makes the jitter deterministic (the same seed always produces the same delays), e.g. to reproduce a bug.
+    /// `None` uses true randomness.
+    pub seed: Option<u64>
+}
+
+impl RetryPolicy for NoRetry {
+    fn retry_delay(&self, _attempt: u32) -> Option<Duration> {
+        None
+    }
+}
+
+impl RetryPolicy for FixedRetry {
+    fn retry_delay(&self, attempt: u32) -> Option<Duration> {
+        if attempt > self.retries {
+            return None;
+        }
+
+        Some(self.delay)
+    }
+}
+
+impl RetryPolicy for ExponentialBackoff {
+    fn retry_delay(&self, attempt: u32) -> Option<Duration> {
+        if attempt > self.retries {
+            return None;
+        }
+
+        let factor = 1u32.checked_shl(attempt - 1).unwrap_or(u32::MAX);
+        let delay = self.initial.checked_mul(factor).map_or(self.max, |delay| delay.min(self.max));
+        if !self.jitter {
+            return Some(delay);
+        }
+
+        let random = match self.seed {
+            Some(seed) => splitmix64(seed ^ u64::from(attempt)),
+            None => {
+                // `RandomState` is randomly keyed, good enough for jitter without depending on a rng crate
+                let mut hasher = RandomState::new().build_hasher();
+                hasher.write_u32(attempt);
+                hasher.finish()
+            }
+        } % 1_000;
+
+        let half = delay / 2;
+        Some(half + half.checked_mul(random as u32).map_or(half, |scaled| scaled / 1_000))
+    }
+}
+
+/// Deterministic 64 bit mix of `value` (http://xorshift.di.unimi.it/splitmix64.c)
+fn splitmix64(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Whether `error` of a socket is transient and worth retrying: an interrupted call, a full buffer or a network which
+/// is briefly unreachable (a wifi blip, an interface flap). Other errors (e.g. a denied permission) are fatal.
+pub(crate) fn is_transient(error: &Error) -> bool {
+    matches!(error.kind(),
+             ErrorKind::Interrupted | ErrorKind::WouldBlock | ErrorKind::TimedOut
+             | ErrorKind::NetworkUnreachable | ErrorKind::HostUnreachable | ErrorKind::NetworkDown
+             | ErrorKind::AddrNotAvailable | ErrorKind::ConnectionRefused | ErrorKind::ConnectionReset)
+}
+
+/// Delay before retry number `attempt` (starting at 1) of a transient error, `None` once they're used up
+pub(crate) fn transient_delay(attempt: u32) -> Option<Duration> {
+    if attempt > TRANSIENT_RETRIES {
+        return None;
+    }
+
+    Some(TRANSIENT_DELAY * (1 << (attempt - 1)))
+}
+
+/// Runs `operation` until it succeeds or `policy` gives up, transient errors (`is_transient`) are retried with
+/// `transient_delay` first
+pub(crate) fn retry<T, F: FnMut() -> Result<T>>(policy: &dyn RetryPolicy, clock: &dyn Clock, mut operation: F) -> Result<T> {
+    let mut attempt = 0;
+    let mut transient_attempt = 0;
+    loop {
+        match operation() {
+            Ok(value) => return Ok(value),
+            Err(e) => {
+                if is_transient(&e) {
+                    transient_attempt += 1;
+                    if let Some(delay) = transient_delay(transient_attempt) {
+                        debug!("Transient error ({}), retrying in {:?}", e, delay);
+                        clock.sleep(delay);
+                        continue;
+                    }
+                }
+                attempt += 1;
+                match policy.retry_delay(attempt) {
+                    Some(delay) => {
+                        debug!("Attempt {} failed ({}), retrying in {:?}", attempt, e, delay);
+                        clock.sleep(delay);
+                    }
+                    None => return Err(e)
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backoff(seed: Option<u64>) -> ExponentialBackoff {
+        ExponentialBackoff {
+            retries: 8,
+            initial: Duration::from_millis(100),
+            max: Duration::from_secs(2),
+            jitter: true,
+            seed
+        }
+    }
+
+    fn delays(policy: &dyn RetryPolicy) -> Vec<Duration> {
+        (1..).map_while(|attempt| policy.retry_delay(attempt)).collect()
+    }
+
+    #[test]
+    fn the_same_seed_gives_the_same_delays() {
+        let first = delays(&backoff(Some(42)));
+
+        assert_eq!(first.len(), 8);
+        assert_eq!(first, delays(&backoff(Some(42))));
+        assert_ne!(first, delays(&backoff(Some(43))));
+    }
+
+    #[test]
+    fn jittered_delays_stay_between_half_and_the_full_delay() {
+        let unjittered = delays(&ExponentialBackoff { jitter: false, ..backoff(None) });
+        assert_eq!(&unjittered[..5], &[100, 200, 400, 800, 1600].map(Duration::from_millis));
+        assert!(unjittered[5..].iter().all(|&delay| delay == Duration::from_secs(2)));
+
+        for seed in 0..100 {
+            for (delay, full) in delays(&backoff(Some(seed))).into_iter().zip(&unjittered) {
+                assert!(delay >= *full / 2 && delay <= *full, "{:?} of {:?} with seed {}", delay, full, seed);
+            }
+        }
+    }
+
+    #[test]
+    fn huge_attempts_are_capped_at_the_max() {
+        let policy = ExponentialBackoff {
+            retries: u32::MAX,
+            ..backoff(None)
+        };
+
+        assert!(policy.retry_delay(u32::MAX).is_some_and(|delay| delay <= policy.max));
+        assert_eq!(ExponentialBackoff { jitter: false, ..policy }.retry_delay(40), Some(policy.max));
+    }
+}
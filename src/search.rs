@@ -0,0 +1,80 @@
+use audit::Direction;
+use config::DiscoveryConfig;
+use engine::{self, Engine};
+use error::{DiscoveryError, Result};
+use result::SearchMethod;
+use ssdp::SsdpResponse;
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// Search socket for custom discovery loops, see `Discover::search_socket`
+///
+/// Sends the search (`send_search`) or any other message (`send_message`) and receives single packets
+/// (`recv_response`), e.g. interleaved with other io of the caller. Nothing is filtered or deduplicated, the audit
+/// sink and the metrics sink only see the packets. The socket is closed when the `SearchSocket` is dropped.
+///
+/// # Examples
+///
+/// ```no_run
+/// use sonos_discovery::{ssdp, Discover, SSDP_MULTICAST_V4, SSDP_PORT};
+/// use std::net::SocketAddr;
+/// use std::time::Duration;
+///
+/// let socket = Discover::new().unwrap().search_socket().unwrap();
+/// let group = SocketAddr::from((SSDP_MULTICAST_V4, SSDP_PORT));
+/// socket.send_message(ssdp::search_request(group, 2, "upnp:rootdevice").as_bytes(), group).unwrap();
+/// while let Some((source, response)) = socket.recv_response(Duration::from_millis(500)).unwrap() {
+///     println!("{} {:?}", source, response.usn());
+/// }
+/// ```
+pub struct SearchSocket {
+    engine: Engine
+}
+
+impl SearchSocket {
+    pub(crate) fn new(engine: Engine) -> Self {
+        SearchSocket {
+            engine
+        }
+    }
+
+    /// Sends the search of the configuration like `Discover::start` (retries, broadcast fallback, additional
+    /// multicast groups), returns how it was sent
+    pub fn send_search(&self) -> Result<SearchMethod> {
+        self.engine.send_search()
+    }
+
+    /// Sends `message` (e.g. a search of `ssdp::search_request` with another target or MX) to `address`,
+    /// with the retry policy of the configuration
+    pub fn send_message(&self, message: &[u8], address: SocketAddr) -> Result<()> {
+        self.engine.send_to(message, address)
+    }
+
+    /// Waits up to `timeout` for the next packet and parses it, `None` if none arrived in time.
+    ///
+    /// Every packet is returned, also those which don't answer the search (e.g. announcements of other devices).
+    pub fn recv_response(&self, timeout: Duration) -> Result<Option<(SocketAddr, SsdpResponse)>> {
+        let (addr, data) = match self.engine.try_receive(timeout).map_err(DiscoveryError::Receive)? {
+            Some(packet) => packet,
+            None => return Ok(None)
+        };
+        engine::audit(self.config(), Direction::Received, addr, &data);
+        if let Some(ref sink) = self.config().metrics_sink {
+            sink.response_received();
+        }
+
+        Ok(Some((addr, SsdpResponse::from_bytes(data))))
+    }
+
+    /// Configuration the socket was created with
+    pub fn config(&self) -> &DiscoveryConfig {
+        self.engine.config()
+    }
+}
+
+impl fmt::Debug for SearchSocket {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SearchSocket").finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,214 @@
+use inventory::InventoryRecord;
+use serde::{Deserialize, Serialize};
+use serde_json;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Result, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Version of the snapshot files, only changes for breaking changes of the format
+pub const SNAPSHOT_SCHEMA_VERSION: u32 = 1;
+
+/// File name prefix of snapshots, followed by the unix time they were taken
+const FILE_PREFIX: &str = "inventory-";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// Inventory (`Discover::inventory`) at a point in time, stored as json file (`inventory-<unix time>.json`)
+pub struct Snapshot {
+    pub schema_version: u32,
+    /// Unix time the snapshot was taken
+    pub taken: u64,
+    pub records: Vec<InventoryRecord>
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// Changes between two snapshots, devices are matched by uuid
+pub struct SnapshotDiff {
+    /// Devices only in the newer snapshot
+    pub added: Vec<InventoryRecord>,
+    /// Devices only in the older snapshot
+    pub removed: Vec<InventoryRecord>,
+    /// Devices in both snapshots with different records (older, newer), e.g. new firmware or addresses
+    pub changed: Vec<(InventoryRecord, InventoryRecord)>
+}
+
+impl Snapshot {
+    /// Snapshot of `records` taken now
+    pub fn new(records: Vec<InventoryRecord>) -> Self {
+        let taken = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+
+        Snapshot {
+            schema_version: SNAPSHOT_SCHEMA_VERSION,
+            taken,
+            records
+        }
+    }
+
+    /// Writes the snapshot to `directory` (created if missing), returns the path of the file
+    pub fn write(&self, directory: &Path) -> Result<PathBuf> {
+        fs::create_dir_all(directory)?;
+        let path = directory.join(format!("{}{}.json", FILE_PREFIX, self.taken));
+        // Write to a temporary file first, readers never see a half-written snapshot
+        let temporary = directory.join(format!(".{}{}.json.tmp", FILE_PREFIX, self.taken));
+
+        let mut writer = BufWriter::new(File::create(&temporary)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.flush()?;
+        fs::rename(&temporary, &path)?;
+
+        Ok(path)
+    }
+
+    /// Reads a snapshot file
+    pub fn load(path: &Path) -> Result<Self> {
+        let snapshot: Snapshot = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+
+        if snapshot.schema_version > SNAPSHOT_SCHEMA_VERSION {
+            return Err(Error::new(ErrorKind::InvalidData,
+                                  format!("Unsupported snapshot schema version {}", snapshot.schema_version)));
+        }
+
+        Ok(snapshot)
+    }
+
+    /// Paths of all snapshots in `directory`, oldest first
+    pub fn list(directory: &Path) -> Result<Vec<PathBuf>> {
+        let mut snapshots: Vec<(u64, PathBuf)> = Vec::new();
+        for entry in fs::read_dir(directory)? {
+            let path = entry?.path();
+            let taken = path.file_name()
+                .and_then(|name| name.to_str())
+                .filter(|name| name.starts_with(FILE_PREFIX) && name.ends_with(".json"))
+                .and_then(|name| name[FILE_PREFIX.len()..name.len() - ".json".len()].parse().ok());
+
+            if let Some(taken) = taken {
+                snapshots.push((taken, path));
+            }
+        }
+        snapshots.sort();
+
+        Ok(snapshots.into_iter().map(|(_, path)| path).collect())
+    }
+
+    /// Changes from `self` to the newer snapshot `newer`
+    pub fn diff(&self, newer: &Snapshot) -> SnapshotDiff {
+        let mut diff = SnapshotDiff::default();
+
+        for record in &newer.records {
+            match self.records.iter().find(|old| old.uuid == record.uuid) {
+                None => diff.added.push(record.clone()),
+                Some(old) if old != record => diff.changed.push((old.clone(), record.clone())),
+                Some(_) => {}
+            }
+        }
+        diff.removed = self.records.iter()
+            .filter(|old| !newer.records.iter().any(|record| record.uuid == old.uuid))
+            .cloned()
+            .collect();
+
+        diff
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    /// Record of a device answering from `ips` with firmware `version`
+    pub(crate) fn record(uuid: &str, version: &str, ips: &[&str]) -> InventoryRecord {
+        InventoryRecord {
+            uuid: uuid.to_string(),
+            serial_number: None,
+            model_name: Some("Sonos One".to_string()),
+            model_number: Some("S18".to_string()),
+            software_version: Some(version.to_string()),
+            room_name: Some("Kitchen".to_string()),
+            ips: ips.iter().map(|ip| ip.parse().unwrap()).collect(),
+            household: None
+        }
+    }
+
+    /// Snapshot of `records` taken at `taken`
+    pub(crate) fn snapshot(taken: u64, records: Vec<InventoryRecord>) -> Snapshot {
+        Snapshot {
+            schema_version: SNAPSHOT_SCHEMA_VERSION,
+            taken,
+            records
+        }
+    }
+
+    /// Empty directory of the test `name`, removed before the test
+    pub(crate) fn directory(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("sonos_discovery_{}_{}", name, process::id()));
+        let _ = fs::remove_dir_all(&path);
+        path
+    }
+
+    #[test]
+    fn diffs() {
+        let a = record("RINCON_A", "79.1-56030", &["192.168.1.20"]);
+        let b = record("RINCON_B", "79.1-56030", &["192.168.1.21"]);
+        let moved = record("RINCON_B", "79.1-56030", &["192.168.1.31"]);
+        let c = record("RINCON_C", "79.1-56030", &["192.168.1.22"]);
+
+        let cases = [(vec![a.clone(), b.clone()], vec![b.clone(), a.clone()], SnapshotDiff::default()),
+                     (vec![], vec![], SnapshotDiff::default()),
+                     (vec![a.clone(), b.clone()], vec![moved.clone(), c.clone()], SnapshotDiff {
+                         added: vec![c.clone()],
+                         removed: vec![a.clone()],
+                         changed: vec![(b.clone(), moved.clone())]
+                     }),
+                     (vec![], vec![a.clone()], SnapshotDiff {
+                         added: vec![a.clone()],
+                         ..SnapshotDiff::default()
+                     })];
+        for (older, newer, expected) in cases.iter() {
+            assert_eq!(&snapshot(1, older.clone()).diff(&snapshot(2, newer.clone())), expected,
+                       "{:?} -> {:?}", older, newer);
+        }
+    }
+
+    #[test]
+    fn snapshots_are_written_and_listed_oldest_first() {
+        let path = directory("snapshots");
+        let records = vec![record("RINCON_A", "79.1-56030", &["192.168.1.20", "fe80::1"])];
+        for &taken in &[1_700_000_100, 999, 1_700_000_000] {
+            snapshot(taken, records.clone()).write(&path.join("nested")).unwrap();
+        }
+        // Not snapshots
+        for name in &["inventory-.json", "inventory-12.json.tmp", "inventory-abc.json", "notes.json"] {
+            fs::write(path.join("nested").join(name), "{}").unwrap();
+        }
+
+        let listed = Snapshot::list(&path.join("nested")).unwrap();
+        let names: Vec<_> = listed.iter().map(|path| path.file_name().unwrap().to_str().unwrap()).collect();
+        assert_eq!(names, vec!["inventory-999.json", "inventory-1700000000.json", "inventory-1700000100.json"]);
+        assert_eq!(Snapshot::load(&listed[0]).unwrap(), snapshot(999, records));
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn newer_schemas_are_rejected() {
+        let path = directory("snapshot_schema");
+        let mut newer = snapshot(1, vec![]);
+        newer.schema_version = SNAPSHOT_SCHEMA_VERSION + 1;
+        let file = newer.write(&path).unwrap();
+        assert_eq!(Snapshot::load(&file).unwrap_err().kind(), ErrorKind::InvalidData);
+
+        fs::write(&file, "{\"schema_version\": 1}").unwrap();
+        assert_eq!(Snapshot::load(&file).unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(Snapshot::load(&path.join("missing.json")).unwrap_err().kind(), ErrorKind::NotFound);
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn new_snapshots_are_taken_now() {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let taken = Snapshot::new(vec![]).taken;
+        assert!(taken >= now && taken <= now + 1, "{} {}", taken, now);
+    }
+}
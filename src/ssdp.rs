@@ -0,0 +1,430 @@
+//! Parser of ssdp packets (search responses and `NOTIFY` announcements), independent of the discovery.
+//!
+//! # Examples
+//!
+//! ```
+//! use sonos_discovery::ssdp::SsdpResponse;
+//! use std::time::Duration;
+//!
+//! let response = SsdpResponse::parse(b"HTTP/1.1 200 OK\r\n\
+//!                                      CACHE-CONTROL: max-age = 1800\r\n\
+//!                                      LOCATION: http://192.168.1.20:1400/xml/device_description.xml\r\n\
+//!                                      ST: urn:schemas-upnp-org:device:ZonePlayer:1\r\n\
+//!                                      USN: uuid:RINCON_000E58000000000000::urn:schemas-upnp-org:device:ZonePlayer:1\r\n\
+//!                                      \r\n");
+//! assert!(response.is_search_response());
+//! assert_eq!(response.uuid(), Some("RINCON_000E58000000000000"));
+//! assert_eq!(response.max_age(), Some(Duration::from_secs(1800)));
+//! ```
+
+use std::error;
+use std::fmt;
+use std::net::SocketAddr;
+use std::str;
+use std::time::Duration;
+
+/// Search request (`M-SEARCH`) for `search_target` sent to `host`, devices may wait up to `mx` seconds before
+/// answering. The request can't have leading/trailing whitespaces (\s).
+///
+/// # Message
+/// ```text
+/// M-SEARCH * HTTP/1.1
+/// HOST: <host>
+/// MAN: "ssdp:discover"
+/// MX: <mx>
+/// ST: <search_target>
+/// ```
+///
+/// # Examples
+///
+/// ```
+/// use sonos_discovery::ssdp::search_request;
+/// use sonos_discovery::upnp::{SSDP_MULTICAST_V4, SSDP_PORT, ZONE_PLAYER};
+/// use std::net::SocketAddr;
+///
+/// let request = search_request(SocketAddr::new(SSDP_MULTICAST_V4.into(), SSDP_PORT), 1, ZONE_PLAYER);
+/// assert!(request.starts_with("M-SEARCH * HTTP/1.1\nHOST: 239.255.255.250:1900\n"));
+/// ```
+pub fn search_request(host: SocketAddr, mx: u32, search_target: &str) -> String {
+    format!(r#"M-SEARCH * HTTP/1.1
+HOST: {}
+MAN: "ssdp:discover"
+MX: {}
+ST: {}"#, host, mx, search_target)
+}
+
+/// Why a packet isn't a well-formed ssdp message, see `SsdpResponse::try_parse`
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SsdpParseError {
+    /// The packet is empty
+    Empty,
+    /// The packet isn't valid utf-8 from the byte `valid_up_to` on
+    InvalidUtf8 {
+        valid_up_to: usize
+    },
+    /// The first line is neither a response (`HTTP/1.x <status>`) nor a request (`<method> * HTTP/1.x`)
+    InvalidStatusLine(String),
+    /// The line `line` (the status line is 1) isn't empty and has no `:`
+    InvalidHeader {
+        line: usize
+    }
+}
+
+impl fmt::Display for SsdpParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SsdpParseError::Empty => f.write_str("Empty packet"),
+            SsdpParseError::InvalidUtf8 { valid_up_to } => write!(f, "Invalid utf-8 after {} bytes", valid_up_to),
+            SsdpParseError::InvalidStatusLine(ref line) => write!(f, "Invalid status line: {:?}", line),
+            SsdpParseError::InvalidHeader { line } => write!(f, "Invalid header in line {}", line)
+        }
+    }
+}
+
+impl error::Error for SsdpParseError {}
+
+/// Start and end of a part of the packet
+type Span = (usize, usize);
+
+/// Status line and headers of a ssdp packet
+///
+/// Owns the packet and keeps the positions of the status line and headers, parsing only allocates the list of these
+/// positions and the accessors borrow from the packet.
+#[derive(Clone, PartialEq, Eq)]
+pub struct SsdpResponse {
+    data: Box<[u8]>,
+    status_line: Span,
+    /// Names and values in the order of the packet, the first one wins if a header is repeated
+    headers: Vec<(Span, Span)>
+}
+
+impl SsdpResponse {
+    /// Parses a copy of `data`, lines without a `:` or with invalid utf-8 are skipped. Never fails, whether the packet
+    /// is a response is up to `is_search_response` and `is_notify`.
+    pub fn parse(data: &[u8]) -> Self {
+        SsdpResponse::from_bytes(data.into())
+    }
+
+    /// Like `parse`, but takes the packet without copying it
+    pub fn from_bytes(data: Box<[u8]>) -> Self {
+        SsdpResponse::from_bytes_checked(data).0
+    }
+
+    /// Like `from_bytes`, together with the result of `try_parse` found in the same pass over the packet
+    pub(crate) fn from_bytes_checked(data: Box<[u8]>) -> (Self, Result<(), SsdpParseError>) {
+        // Spans only have to be checked one by one if the packet as a whole isn't valid utf-8
+        let utf8 = str::from_utf8(&data).map(|_| ()).map_err(|e| SsdpParseError::InvalidUtf8 {
+            valid_up_to: e.valid_up_to()
+        });
+        let is_text = |span| utf8.is_ok() || is_text(&data, span);
+
+        let mut lines = lines(&data);
+        let status_line = lines.next()
+            .map(|line| trim(&data, line))
+            .filter(|&line| is_text(line))
+            .unwrap_or_default();
+        let mut headers = Vec::new();
+        let mut invalid_header = None;
+        for (index, (start, end)) in lines.enumerate() {
+            let colon = match data[start..end].iter().position(|&byte| byte == b':') {
+                Some(colon) => start + colon,
+                None => {
+                    let (start, end) = trim(&data, (start, end));
+                    if start < end && invalid_header.is_none() {
+                        // Line 1 is the status line
+                        invalid_header = Some(index + 2);
+                    }
+                    continue;
+                }
+            };
+            let name = trim(&data, (start, colon));
+            let value = trim(&data, (colon + 1, end));
+            if is_text(name) && is_text(value) {
+                headers.push((name, value));
+            }
+        }
+
+        let response = SsdpResponse {
+            data,
+            status_line,
+            headers
+        };
+        let result = if response.data.is_empty() {
+            Err(SsdpParseError::Empty)
+        } else if let Err(e) = utf8 {
+            Err(e)
+        } else if !is_status_line(response.status_line()) {
+            Err(SsdpParseError::InvalidStatusLine(response.status_line().to_string()))
+        } else if let Some(line) = invalid_header {
+            Err(SsdpParseError::InvalidHeader { line })
+        } else {
+            Ok(())
+        };
+
+        (response, result)
+    }
+
+    /// Like `parse`, but fails for packets which aren't well-formed ssdp messages instead of making the best of them
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sonos_discovery::ssdp::{SsdpParseError, SsdpResponse};
+    ///
+    /// assert_eq!(SsdpResponse::try_parse(b"HTTP/1.1 200 OK\r\nST\r\n"), Err(SsdpParseError::InvalidHeader { line: 2 }));
+    /// ```
+    pub fn try_parse(data: &[u8]) -> Result<Self, SsdpParseError> {
+        let (response, result) = SsdpResponse::from_bytes_checked(data.into());
+
+        result.map(|()| response)
+    }
+
+    /// The packet as it was received
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// First line, e.g. `HTTP/1.1 200 OK` for a search response or `NOTIFY * HTTP/1.1` for an announcement
+    pub fn status_line(&self) -> &str {
+        self.text(self.status_line)
+    }
+
+    /// Names and values of the headers in the order of the packet
+    pub fn headers(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.headers.iter().map(move |&(name, value)| (self.text(name), self.text(value)))
+    }
+
+    /// Whether the status line is a successful search response (`HTTP/1.0 200` or `HTTP/1.1 200`)
+    pub fn is_search_response(&self) -> bool {
+        let mut status = self.status_line().split_whitespace();
+        status.next().is_some_and(|version| version.starts_with("HTTP/1.")) && status.next() == Some("200")
+    }
+
+    /// Whether the packet is an announcement (`NOTIFY * HTTP/1.1`)
+    pub fn is_notify(&self) -> bool {
+        self.status_line().starts_with("NOTIFY ")
+    }
+
+    /// Value of the header `name` (case insensitive)
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers().find(|&(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value)
+    }
+
+    /// `LOCATION`, url of the device description
+    pub fn location(&self) -> Option<&str> {
+        self.header("LOCATION")
+    }
+
+    /// `USN`, unique service name (`uuid:RINCON_...::urn:...`)
+    pub fn usn(&self) -> Option<&str> {
+        self.header("USN")
+    }
+
+    /// `ST` of a search response, the search target it answers
+    pub fn st(&self) -> Option<&str> {
+        self.header("ST")
+    }
+
+    /// `NT` of an announcement, the announced notification type
+    pub fn nt(&self) -> Option<&str> {
+        self.header("NT")
+    }
+
+    /// `CACHE-CONTROL`, e.g. `max-age = 1800`
+    pub fn cache_control(&self) -> Option<&str> {
+        self.header("CACHE-CONTROL")
+    }
+
+    /// Unique id of the device (`RINCON_...`) from the `USN` header, `None` if it's missing or empty
+    pub fn uuid(&self) -> Option<&str> {
+        let uuid = self.usn()?.trim_start_matches("uuid:").split("::").next()?;
+
+        if uuid.is_empty() {
+            None
+        } else {
+            Some(uuid)
+        }
+    }
+
+    /// Time the response is valid from the `max-age` directive of `CACHE-CONTROL`
+    pub fn max_age(&self) -> Option<Duration> {
+        self.cache_control()?
+            .split(',')
+            .filter_map(|directive| {
+                let mut parts = directive.splitn(2, '=');
+                match (parts.next(), parts.next()) {
+                    (Some(name), Some(value)) if name.trim().eq_ignore_ascii_case("max-age") => value.trim().parse().ok(),
+                    _ => None
+                }
+            })
+            .next()
+            .map(Duration::from_secs)
+    }
+
+    /// Part `span` of the packet, `from_bytes` only keeps spans of valid utf-8
+    fn text(&self, (start, end): Span) -> &str {
+        str::from_utf8(&self.data[start..end]).unwrap_or_default()
+    }
+}
+
+impl fmt::Debug for SsdpResponse {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SsdpResponse")
+            .field("status_line", &self.status_line())
+            .field("headers", &self.headers().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+/// Whether `line` is the status line of a response (`HTTP/1.x <status>`) or a request (`<method> * HTTP/1.x`)
+fn is_status_line(line: &str) -> bool {
+    let mut parts = line.split_whitespace();
+    match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(version), Some(status), _, _) if version.starts_with("HTTP/1.") => status.parse::<u16>().is_ok(),
+        (Some(_), Some(target), Some(version), None) => target == "*" && version.starts_with("HTTP/1."),
+        _ => false
+    }
+}
+
+/// Spans of the lines of `data`, including the `\r` of a `\r\n` line ending
+fn lines<'a>(data: &'a [u8]) -> impl Iterator<Item = Span> + 'a {
+    let mut start = 0;
+    data.split(|&byte| byte == b'\n').map(move |line| {
+        let span = (start, start + line.len());
+        start = span.1 + 1;
+        span
+    })
+}
+
+/// `span` without the leading and trailing ascii whitespace
+fn trim(data: &[u8], (mut start, mut end): Span) -> Span {
+    while start < end && data[start].is_ascii_whitespace() {
+        start += 1;
+    }
+    while end > start && data[end - 1].is_ascii_whitespace() {
+        end -= 1;
+    }
+
+    (start, end)
+}
+
+/// Whether `span` is valid utf-8
+fn is_text(data: &[u8], (start, end): Span) -> bool {
+    str::from_utf8(&data[start..end]).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RESPONSE: &[u8] = b"HTTP/1.1 200 OK\r\n\
+                              CACHE-CONTROL: max-age = 1800\r\n\
+                              LOCATION: http://192.168.1.20:1400/xml/device_description.xml\r\n\
+                              ST: urn:schemas-upnp-org:device:ZonePlayer:1\r\n\
+                              USN: uuid:RINCON_000E58000000000000::urn:schemas-upnp-org:device:ZonePlayer:1\r\n\
+                              X-RINCON-HOUSEHOLD: Sonos_test\r\n\
+                              \r\n";
+
+    #[test]
+    fn responses() {
+        let response = SsdpResponse::try_parse(RESPONSE).unwrap();
+        assert_eq!(response.status_line(), "HTTP/1.1 200 OK");
+        assert!(response.is_search_response());
+        assert!(!response.is_notify());
+        assert_eq!(response.location(), Some("http://192.168.1.20:1400/xml/device_description.xml"));
+        assert_eq!(response.st(), Some("urn:schemas-upnp-org:device:ZonePlayer:1"));
+        assert_eq!(response.nt(), None);
+        assert_eq!(response.uuid(), Some("RINCON_000E58000000000000"));
+        assert_eq!(response.max_age(), Some(Duration::from_secs(1800)));
+        assert_eq!(response.headers().count(), 5);
+        assert_eq!(response.as_bytes(), RESPONSE);
+        assert_eq!(response, SsdpResponse::parse(RESPONSE));
+    }
+
+    #[test]
+    fn announcements() {
+        let response = SsdpResponse::try_parse(b"NOTIFY * HTTP/1.1\nNT: upnp:rootdevice\nNTS: ssdp:alive\n").unwrap();
+        assert!(response.is_notify());
+        assert!(!response.is_search_response());
+        assert_eq!(response.nt(), Some("upnp:rootdevice"));
+        assert_eq!(response.header("NTS"), Some("ssdp:alive"));
+    }
+
+    #[test]
+    fn headers_are_case_insensitive() {
+        let response = SsdpResponse::parse(b"HTTP/1.1 200 OK\r\nlocation: http://a/\r\nSt:urn:a\r\ncache-control: \
+                                             no-cache, MAX-AGE=60\r\n");
+        assert_eq!(response.location(), Some("http://a/"));
+        assert_eq!(response.header("LoCaTiOn"), Some("http://a/"));
+        assert_eq!(response.st(), Some("urn:a"));
+        assert_eq!(response.max_age(), Some(Duration::from_secs(60)));
+        assert_eq!(response.header("USN"), None);
+    }
+
+    #[test]
+    fn the_first_of_duplicate_headers_wins() {
+        let response = SsdpResponse::try_parse(b"HTTP/1.1 200 OK\r\nST: urn:first\r\nst: urn:second\r\n").unwrap();
+        assert_eq!(response.st(), Some("urn:first"));
+        assert_eq!(response.headers().collect::<Vec<_>>(), vec![("ST", "urn:first"), ("st", "urn:second")]);
+    }
+
+    #[test]
+    fn values_keep_their_colons() {
+        let response = SsdpResponse::parse(b"HTTP/1.1 200 OK\r\nUSN: uuid:RINCON_A::urn:a:b\r\nEXT:\r\n");
+        assert_eq!(response.usn(), Some("uuid:RINCON_A::urn:a:b"));
+        assert_eq!(response.header("EXT"), Some(""));
+        assert_eq!(response.uuid(), Some("RINCON_A"));
+        assert_eq!(SsdpResponse::parse(b"HTTP/1.1 200 OK\r\nUSN: uuid:\r\n").uuid(), None);
+    }
+
+    #[test]
+    fn truncated_responses() {
+        for length in 0..RESPONSE.len() {
+            // Never panics, whatever is left of the packet
+            let response = SsdpResponse::parse(&RESPONSE[..length]);
+            assert!(response.headers().count() <= 5);
+        }
+
+        // Cut off in the middle of the LOCATION
+        let end = RESPONSE.windows(4).position(|window| window == b"/xml").unwrap();
+        let response = SsdpResponse::try_parse(&RESPONSE[..end]).unwrap();
+        assert_eq!(response.max_age(), Some(Duration::from_secs(1800)));
+        assert_eq!(response.location(), Some("http://192.168.1.20:1400"));
+        assert_eq!(SsdpResponse::try_parse(&RESPONSE[..20]), Err(SsdpParseError::InvalidHeader { line: 2 }));
+        assert_eq!(SsdpResponse::try_parse(&RESPONSE[..8]),
+                   Err(SsdpParseError::InvalidStatusLine("HTTP/1.1".to_string())));
+        assert_eq!(SsdpResponse::try_parse(b""), Err(SsdpParseError::Empty));
+        assert_eq!(SsdpResponse::parse(b"").status_line(), "");
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        let data = b"HTTP/1.1 200 OK\r\nSERVER: Linux \xff\xfe\r\nST: urn:a\r\n";
+        assert_eq!(SsdpResponse::try_parse(data), Err(SsdpParseError::InvalidUtf8 { valid_up_to: 31 }));
+
+        // Only the invalid header is left out
+        let response = SsdpResponse::parse(data);
+        assert_eq!(response.header("SERVER"), None);
+        assert_eq!(response.st(), Some("urn:a"));
+        assert_eq!(SsdpResponse::parse(b"\xffHTTP/1.1 200 OK\r\nST: urn:a\r\n").status_line(), "");
+    }
+
+    #[test]
+    fn status_lines() {
+        for line in &["HTTP/1.0 200 OK", "HTTP/1.1 404", "M-SEARCH * HTTP/1.1", "NOTIFY * HTTP/1.0"] {
+            assert!(is_status_line(line), "{}", line);
+        }
+        for line in &["", "HTTP/1.1", "HTTP/1.1 OK", "HTTP/2 200 OK", "NOTIFY / HTTP/1.1", "NOTIFY * HTTP/1.1 x"] {
+            assert!(!is_status_line(line), "{}", line);
+        }
+        assert!(!SsdpResponse::parse(b"HTTP/1.1 404 Not Found\r\n").is_search_response());
+    }
+
+    #[test]
+    fn invalid_headers_are_reported_by_their_line() {
+        let data = b"HTTP/1.1 200 OK\r\nST: urn:a\r\n   \r\ngarbage\r\nLOCATION: http://a/\r\n";
+        assert_eq!(SsdpResponse::try_parse(data), Err(SsdpParseError::InvalidHeader { line: 4 }));
+        assert_eq!(SsdpResponse::parse(data).location(), Some("http://a/"));
+    }
+}
@@ -0,0 +1,140 @@
+use config::DiscoveryConfig;
+use description::{self, DescriptionCache, DeviceDescription, DEVICE_PORT};
+use error::DiscoveryError;
+use parallel::run_limited;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use transport;
+
+/// Time a host gets to accept the connection to port 1400
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(300);
+
+/// Smallest prefix length of a swept network, a /20 already has 4094 hosts
+pub const MIN_SWEEP_PREFIX: u8 = 20;
+
+/// Ipv4 network swept for devices by `Discover::set_sweep_fallback`, e.g. `192.168.1.0/24`
+///
+/// # Examples
+///
+/// ```
+/// use sonos_discovery::SweepNetwork;
+///
+/// let network: SweepNetwork = "192.168.1.0/24".parse().unwrap();
+/// assert_eq!(network.hosts().len(), 254);
+/// assert!("10.0.0.0/8".parse::<SweepNetwork>().is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SweepNetwork {
+    address: Ipv4Addr,
+    prefix_len: u8
+}
+
+impl SweepNetwork {
+    /// Network of `address` with `prefix_len` bits, `None` if it's too large (shorter than `MIN_SWEEP_PREFIX`) or
+    /// longer than 32 bits
+    pub fn new(address: Ipv4Addr, prefix_len: u8) -> Option<Self> {
+        if !(MIN_SWEEP_PREFIX..=32).contains(&prefix_len) {
+            return None;
+        }
+
+        Some(SweepNetwork {
+            address: Ipv4Addr::from(u32::from(address) & mask(prefix_len)),
+            prefix_len
+        })
+    }
+
+    /// Address of the network, the host bits are cleared
+    pub fn address(&self) -> Ipv4Addr {
+        self.address
+    }
+
+    /// Number of network bits
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    /// Addresses of the hosts, without the network and the broadcast address (unless the network is a /31 or /32)
+    pub fn hosts(&self) -> Vec<Ipv4Addr> {
+        let network = u32::from(self.address);
+        let broadcast = network | !mask(self.prefix_len);
+        let (first, last) = if self.prefix_len >= 31 { (network, broadcast) } else { (network + 1, broadcast - 1) };
+
+        (first..=last).map(Ipv4Addr::from).collect()
+    }
+}
+
+impl FromStr for SweepNetwork {
+    type Err = DiscoveryError;
+
+    /// `address/prefix`, e.g. `192.168.1.0/24`
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || DiscoveryError::InvalidAddress(
+            format!("Invalid network {}, expected an ipv4 network of at least /{} like 192.168.1.0/24", value,
+                    MIN_SWEEP_PREFIX));
+        let mut parts = value.trim().splitn(2, '/');
+        let address = parts.next().and_then(|address| address.parse().ok()).ok_or_else(invalid)?;
+        let prefix_len = parts.next().and_then(|prefix_len| prefix_len.parse().ok()).ok_or_else(invalid)?;
+
+        SweepNetwork::new(address, prefix_len).ok_or_else(invalid)
+    }
+}
+
+impl fmt::Display for SweepNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix_len)
+    }
+}
+
+/// Netmask of `prefix_len` bits, at least `MIN_SWEEP_PREFIX`
+fn mask(prefix_len: u8) -> u32 {
+    u32::MAX << (32 - u32::from(prefix_len))
+}
+
+/// Network swept by `config`: `sweep_network`, otherwise the /24 of the search's interface (`multicast_if`,
+/// `interface` or `best_interface`). `None` if no interface is known.
+pub(crate) fn network(config: &DiscoveryConfig) -> Option<SweepNetwork> {
+    if config.sweep_network.is_some() {
+        return config.sweep_network;
+    }
+
+    let interface = config.multicast_if.or(config.interface)
+        .or_else(|| transport::best_interface().ok().flatten().map(|choice| choice.address))?;
+    SweepNetwork::new(interface, 24)
+}
+
+/// Sonos devices of `network`: the hosts accepting a connection to port 1400 (with at most `sweep_concurrency`
+/// connections at a time) whose description is the one of a sonos device, sorted by the address
+pub(crate) fn sweep(config: &DiscoveryConfig, network: SweepNetwork, cache: Option<&Arc<DescriptionCache>>,
+                    timeout: Duration) -> Vec<(IpAddr, DeviceDescription)> {
+    let hosts: Vec<IpAddr> = network.hosts().into_iter().map(IpAddr::V4).collect();
+    let connects = run_limited(hosts.clone(), config.sweep_concurrency, |ip| {
+        TcpStream::connect_timeout(&SocketAddr::new(ip, DEVICE_PORT), CONNECT_TIMEOUT).is_ok()
+    });
+    let open: Vec<IpAddr> = hosts.into_iter().zip(connects)
+        .filter_map(|(ip, open)| if let Ok(true) = open { Some(ip) } else { None })
+        .collect();
+    debug!("{} hosts of {} accept connections to port {}", open.len(), network, DEVICE_PORT);
+
+    let fetcher = Arc::clone(&config.http_fetcher);
+    let cache = cache.cloned();
+    let fetches = run_limited(open.clone(), config.max_requests, move |ip|
+        description::fetch(&*fetcher, cache.as_deref(), None, ip, timeout));
+
+    open.into_iter().zip(fetches)
+        .filter_map(|(ip, fetch)| match fetch {
+            Ok(Ok(description)) if description.is_sonos() => Some((ip, description)),
+            Ok(Ok(_)) => {
+                debug!("{} serves port {}, but isn't a sonos device", ip, DEVICE_PORT);
+                None
+            }
+            Ok(Err(e)) => {
+                debug!("Couldn't fetch the description of {}: {}", ip, e);
+                None
+            }
+            Err(_) => None
+        })
+        .collect()
+}
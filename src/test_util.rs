@@ -0,0 +1,195 @@
+//! Fake sonos speaker for tests without hardware, needs the `test-util` feature.
+//!
+//! A `MockResponder` answers every `M-SEARCH` sent to its local udp socket with a canned response of a sonos
+//! speaker. The discovery sends its search to the responder instead of the multicast address
+//! (`Discover::with_address`).
+
+use std::io::{self, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Time the responder waits for a search before it checks whether it was stopped
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Answers searches like a sonos speaker until it's dropped
+///
+/// Every responder is one device, several devices need several responders on different addresses
+/// (e.g. `127.0.0.2`, `127.0.0.3` on linux).
+///
+/// # Examples
+///
+/// ```
+/// use sonos_discovery::Discover;
+/// use sonos_discovery::test_util::MockResponder;
+/// use std::time::Duration;
+///
+/// let responder = MockResponder::builder()
+///     .uuid("RINCON_000E58000000000000")
+///     .delay(Duration::from_millis(50))
+///     .start()
+///     .unwrap();
+///
+/// let result = Discover::with_address(responder.addr()).unwrap().start(None, Some(1)).unwrap();
+/// assert_eq!(result.uuid(&responder.addr().ip()), Some("RINCON_000E58000000000000"));
+/// ```
+#[derive(Debug)]
+pub struct MockResponder {
+    addr: SocketAddr,
+    stopped: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>
+}
+
+/// Configures a `MockResponder`, see `MockResponder::builder`
+#[derive(Debug, Clone)]
+pub struct MockResponderBuilder {
+    bind_addr: SocketAddr,
+    uuid: String,
+    household: Option<String>,
+    delay: Duration,
+    duplicates: u32,
+    malformed: bool,
+    response: Option<Vec<u8>>
+}
+
+impl MockResponder {
+    /// Responder on a free port of `127.0.0.1` answering `RINCON_000E58000000000000` in the household `Sonos_test`
+    pub fn builder() -> MockResponderBuilder {
+        MockResponderBuilder {
+            bind_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0),
+            uuid: "RINCON_000E58000000000000".to_string(),
+            household: Some("Sonos_test".to_string()),
+            delay: Duration::default(),
+            duplicates: 0,
+            malformed: false,
+            response: None
+        }
+    }
+
+    /// Address the searches have to be sent to, also the address of the device in the results
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    fn stop(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            self.stopped.store(true, Ordering::SeqCst);
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for MockResponder {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl MockResponderBuilder {
+    /// Local address the responder listens on (default: `127.0.0.1:0`, a free port)
+    pub fn bind_addr(mut self, address: SocketAddr) -> Self {
+        self.bind_addr = address;
+        self
+    }
+
+    /// Unique id in the `USN` header
+    pub fn uuid(mut self, uuid: &str) -> Self {
+        self.uuid = uuid.to_string();
+        self
+    }
+
+    /// `X-RINCON-HOUSEHOLD` header, `None` leaves it out
+    pub fn household(mut self, household: Option<&str>) -> Self {
+        self.household = household.map(str::to_string);
+        self
+    }
+
+    /// Time between receiving a search and answering it, e.g. to test timeouts (default: none)
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Additional copies of every answer, devices answering more than once are common (default: 0)
+    pub fn duplicates(mut self, duplicates: u32) -> Self {
+        self.duplicates = duplicates;
+        self
+    }
+
+    /// Sends a packet which isn't a valid response before every answer (default: false)
+    pub fn malformed(mut self, malformed: bool) -> Self {
+        self.malformed = malformed;
+        self
+    }
+
+    /// Answers with exactly `response` instead of the canned sonos response
+    pub fn response(mut self, response: &[u8]) -> Self {
+        self.response = Some(response.to_vec());
+        self
+    }
+
+    /// Binds the socket and answers searches on a background thread until the responder is dropped
+    pub fn start(self) -> io::Result<MockResponder> {
+        let socket = UdpSocket::bind(self.bind_addr)?;
+        socket.set_read_timeout(Some(POLL_INTERVAL))?;
+        let addr = socket.local_addr()?;
+        let response = self.response.clone().unwrap_or_else(|| self.sonos_response(addr.ip()));
+
+        let stopped = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stopped = Arc::clone(&stopped);
+            thread::spawn(move || {
+                let mut buffer = [0; 1024];
+                while !stopped.load(Ordering::SeqCst) {
+                    let (length, peer) = match socket.recv_from(&mut buffer) {
+                        Ok(packet) => packet,
+                        Err(ref e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => continue,
+                        Err(e) => {
+                            warn!("Mock responder couldn't receive: {}", e);
+                            continue;
+                        }
+                    };
+                    if !buffer[..length].starts_with(b"M-SEARCH ") {
+                        continue;
+                    }
+
+                    thread::sleep(self.delay);
+                    if self.malformed {
+                        let _ = socket.send_to(b"HTTP/1.1 200 OK\r\nST\r\n\xff\xfe", peer);
+                    }
+                    for _ in 0..=self.duplicates {
+                        if let Err(e) = socket.send_to(&response, peer) {
+                            warn!("Mock responder couldn't answer {}: {}", peer, e);
+                        }
+                    }
+                }
+            })
+        };
+
+        Ok(MockResponder {
+            addr,
+            stopped,
+            thread: Some(thread)
+        })
+    }
+
+    /// Search response of a sonos speaker at `ip`
+    fn sonos_response(&self, ip: IpAddr) -> Vec<u8> {
+        let mut response = format!("HTTP/1.1 200 OK\r\n\
+                                    CACHE-CONTROL: max-age = 1800\r\n\
+                                    EXT:\r\n\
+                                    LOCATION: http://{}:1400/xml/device_description.xml\r\n\
+                                    SERVER: Linux UPnP/1.0 Sonos/70.3-35220 (ZPS9)\r\n\
+                                    ST: urn:schemas-upnp-org:device:ZonePlayer:1\r\n\
+                                    USN: uuid:{}::urn:schemas-upnp-org:device:ZonePlayer:1\r\n",
+                                   ip, self.uuid);
+        if let Some(ref household) = self.household {
+            response.push_str(&format!("X-RINCON-HOUSEHOLD: {}\r\n", household));
+        }
+        response.push_str("\r\n");
+
+        response.into_bytes()
+    }
+}
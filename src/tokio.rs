@@ -0,0 +1,186 @@
+//! Discovery for tokio applications, needs the `tokio` feature.
+//!
+//! The search runs on tokio's `UdpSocket` and doesn't block a thread of the runtime.
+
+use config::DiscoveryConfig;
+use engine::{self, Collector};
+use error::{DiscoveryError, Result};
+use ratelimit::RateLimiter;
+use result::DiscoveryResult;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio_runtime::io::ReadBuf;
+use tokio_runtime::net::UdpSocket;
+use tokio_runtime::time::{self, Instant, Sleep};
+use transport;
+
+/// Async variant of `sonos_discovery::Discover`
+///
+/// Configured like the blocking `Discover`, which is converted with `From`. Differences:
+///
+/// - the sockets are always udp sockets, the transport (`set_transport`) isn't used
+/// - the retry policy (`set_retry_policy`) isn't used, a failed send fails the discovery (or uses the broadcast fallback)
+/// - the search is only sent once, `set_resends` is ignored
+/// - the http requests after the search (`set_verify`, `set_topology`) are skipped with a warning in the result
+///
+/// # Examples
+///
+/// ```no_run,edition2018
+/// use sonos_discovery::tokio::Discover;
+///
+/// # async fn run() {
+/// let result = Discover::new().start(None, Some(3)).await.unwrap();
+/// for ip in result {
+///     println!("{}", ip);
+/// }
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Discover {
+    discovery: ::Discover
+}
+
+impl Discover {
+    /// Creates a new `Discover` with the default configuration (see `DiscoveryConfig`)
+    pub fn new() -> Self {
+        Discover::with_config(DiscoveryConfig::default())
+    }
+
+    /// Creates a new `Discover` with a complete configuration.
+    pub fn with_config(config: DiscoveryConfig) -> Self {
+        Discover {
+            discovery: ::Discover::with_config(config)
+        }
+    }
+
+    /// Configuration used by every `start`
+    pub fn config(&self) -> &DiscoveryConfig {
+        self.discovery.config()
+    }
+
+    /// Start discovering devices, the returned future has to be polled inside the tokio runtime.
+    ///
+    /// `timeout` and `device_count` override the configured values for this run.
+    pub fn start(&self, timeout: Option<Duration>, device_count: Option<usize>) -> Start {
+        let mut config = self.discovery.config.clone();
+        config.timeout = timeout.unwrap_or(config.timeout);
+        config.device_count = device_count.or(config.device_count);
+
+        Start {
+            config: Some(config),
+            duplicate_log: Arc::clone(&self.discovery.duplicate_log),
+            search: None
+        }
+    }
+}
+
+impl Default for Discover {
+    fn default() -> Self {
+        Discover::new()
+    }
+}
+
+impl From<::Discover> for Discover {
+    fn from(discovery: ::Discover) -> Self {
+        Discover {
+            discovery
+        }
+    }
+}
+
+/// Future of a running discovery, see `Discover::start`
+#[derive(Debug)]
+pub struct Start {
+    /// Configuration of the run until the search is sent
+    config: Option<DiscoveryConfig>,
+    duplicate_log: Arc<RateLimiter>,
+    search: Option<Search>
+}
+
+/// Sent search, created by the first poll so the socket is registered with the polling runtime
+struct Search {
+    socket: UdpSocket,
+    collector: Collector,
+    /// Wakes the future to check the timeout if no packet arrives
+    sleep: Pin<Box<Sleep>>,
+    buffer: Box<[u8]>
+}
+
+impl fmt::Debug for Search {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Search")
+            .field("socket", &self.socket)
+            .finish()
+    }
+}
+
+impl Future for Start {
+    type Output = Result<DiscoveryResult>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = &mut *self;
+        if let Some(config) = this.config.take() {
+            match Search::send(config, Arc::clone(&this.duplicate_log)) {
+                Ok(search) => this.search = Some(search),
+                Err(e) => return Poll::Ready(Err(e))
+            }
+        }
+        let search = match this.search {
+            Some(ref mut search) => search,
+            None => panic!("`Start` polled after completion")
+        };
+
+        loop {
+            if search.collector.finished() {
+                let search = this.search.take().expect("search is running");
+                return Poll::Ready(Ok(search.finish()));
+            }
+
+            let mut buffer = ReadBuf::new(&mut search.buffer);
+            // Receive errors are ignored like in the blocking discovery, the timer retries the receive
+            if let Poll::Ready(Ok(addr)) = search.socket.poll_recv_from(cx, &mut buffer) {
+                let data: Box<[u8]> = buffer.filled().into();
+                search.collector.receive(addr, data);
+                continue;
+            }
+
+            if search.sleep.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            search.sleep.as_mut().reset(Instant::now() + search.collector.receive_timeout());
+        }
+    }
+}
+
+impl Search {
+    /// Sends the search message like `Engine::send_search`, without retries
+    fn send(mut config: DiscoveryConfig, duplicate_log: Arc<RateLimiter>) -> Result<Self> {
+        transport::select_interface(&mut config);
+        let socket = transport::search_socket(&config)?;
+
+        let time = config.clock.now();
+        let method = engine::send_async_search(&config, &socket)?;
+        socket.set_nonblocking(true).map_err(DiscoveryError::SocketCreate)?;
+
+        let buffer = vec![0; config.receive_buffer_size].into_boxed_slice();
+        // Otherwise the collector would wait for resends which never come
+        config.resends = 0;
+        let collector = Collector::new(config, duplicate_log, method, time);
+        let wait = collector.receive_timeout();
+
+        Ok(Search {
+            socket: UdpSocket::from_std(socket).map_err(DiscoveryError::SocketCreate)?,
+            collector,
+            sleep: Box::pin(time::sleep(wait)),
+            buffer
+        })
+    }
+
+    fn finish(self) -> DiscoveryResult {
+        engine::async_result(self.collector)
+    }
+}
@@ -0,0 +1,358 @@
+use description::{element, unescape, DEVICE_PORT};
+use fetcher::{parse_http_url, HttpFetcher};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::io::{Error, ErrorKind, Result};
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+/// Control url of the `ZoneGroupTopology` service
+const CONTROL_PATH: &str = "/ZoneGroupTopology/Control";
+
+const SOAP_ACTION: &str = "\"urn:schemas-upnp-org:service:ZoneGroupTopology:1#GetZoneGroupState\"";
+
+const SOAP_BODY: &str = concat!(
+    r#"<?xml version="1.0" encoding="utf-8"?>"#,
+    r#"<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/" s:encodingStyle="http://schemas.xmlsoap.org/soap/encoding/">"#,
+    r#"<s:Body><u:GetZoneGroupState xmlns:u="urn:schemas-upnp-org:service:ZoneGroupTopology:1"/></s:Body>"#,
+    r#"</s:Envelope>"#);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+/// Speaker of a zone group
+pub struct ZoneGroupMember {
+    /// Unique id (`RINCON_...`)
+    pub uuid: String,
+    /// Address from the member's location, `None` if it couldn't be parsed
+    pub ip: Option<IpAddr>,
+    /// Room name (e.g. "Kitchen")
+    pub zone_name: Option<String>,
+    /// Whether the speaker is a zone of its own or bonded to another one
+    pub role: MemberRole,
+    /// Uuid of the speaker a bonded speaker belongs to, e.g. the soundbar of a sub or the left speaker of a stereo pair
+    pub bonded_to: Option<String>
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[non_exhaustive]
+/// Role of a `ZoneGroupMember` in its room
+pub enum MemberRole {
+    /// Visible speaker, a room of its own or the main speaker of a bonded set
+    #[default]
+    Zone,
+    /// Sub bonded to a speaker (`SW` channel)
+    Subwoofer,
+    /// Rear speaker of a home theater (`LR`/`RR` channels)
+    Surround,
+    /// Second, invisible speaker of a stereo pair
+    StereoPair,
+    /// Any other invisible speaker
+    Satellite
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+/// Speakers playing the same audio, see `DiscoveryResult::grouped`
+pub struct ZoneGroup {
+    /// Id of the group (`RINCON_...:123`)
+    pub id: String,
+    /// Members of the group, the coordinator (which controls the playback) is always first
+    pub members: Vec<ZoneGroupMember>
+}
+
+impl ZoneGroup {
+    /// Speaker controlling the playback of the group
+    pub fn coordinator(&self) -> &ZoneGroupMember {
+        &self.members[0]
+    }
+}
+
+/// Fetches the zone groups of the household of the device at `ip` (`GetZoneGroupState`), with the satellites of the
+/// members (subs, surrounds) if `include_invisible`
+pub(crate) fn fetch(fetcher: &dyn HttpFetcher, ip: IpAddr, timeout: Duration, include_invisible: bool)
+                    -> Result<Vec<ZoneGroup>> {
+    if timeout == Duration::from_secs(0) {
+        return Err(Error::new(ErrorKind::TimedOut, "Time budget of the discovery is used up"));
+    }
+
+    let url = format!("http://{}{}", SocketAddr::new(ip, DEVICE_PORT), CONTROL_PATH);
+    let headers = [("Content-Type", "text/xml; charset=\"utf-8\""), ("SOAPACTION", SOAP_ACTION)];
+    let response = fetcher.post(&url, &headers, SOAP_BODY, timeout)?;
+
+    // The state is an escaped xml document inside the soap response
+    let state = element(&response, "ZoneGroupState")
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Response has no ZoneGroupState"))?;
+
+    Ok(parse(&state, include_invisible))
+}
+
+/// Parses the `ZoneGroup` elements of a zone group state, groups without a known coordinator are skipped.
+/// The satellites nested in their member (subs, surrounds) follow it if `include_invisible`.
+pub(crate) fn parse(state: &str, include_invisible: bool) -> Vec<ZoneGroup> {
+    let mut groups = Vec::new();
+
+    for group in state.split("<ZoneGroup ").skip(1) {
+        let group = group.split("</ZoneGroup>").next().unwrap_or(group);
+        let (id, coordinator) = match (attribute(group, "ID"), attribute(group, "Coordinator")) {
+            (Some(id), Some(coordinator)) => (id, coordinator),
+            _ => continue
+        };
+
+        // Satellites (surrounds, subs) are nested in their member and not listed separately
+        let mut members = Vec::new();
+        for member in group.split("<ZoneGroupMember ").skip(1) {
+            let parsed = match parse_member(member, None) {
+                Some(parsed) => parsed,
+                None => continue
+            };
+            let satellites: Vec<ZoneGroupMember> = if include_invisible {
+                member.split("<Satellite ").skip(1)
+                    .filter_map(|satellite| parse_member(satellite, Some(&parsed)))
+                    .collect()
+            } else {
+                Vec::new()
+            };
+            members.push((parsed, satellites));
+        }
+
+        // The satellites of the coordinator move with it
+        match members.iter().position(|(member, _)| member.uuid == coordinator) {
+            Some(index) => {
+                let coordinator = members.remove(index);
+                members.insert(0, coordinator);
+            }
+            None => continue
+        }
+
+        groups.push(ZoneGroup {
+            id,
+            members: members.into_iter()
+                .flat_map(|(member, satellites)| std::iter::once(member).chain(satellites))
+                .collect()
+        });
+    }
+
+    groups
+}
+
+/// Member (or the satellite of `parent`) with the attributes of the first tag in `tag`
+fn parse_member(tag: &str, parent: Option<&ZoneGroupMember>) -> Option<ZoneGroupMember> {
+    let uuid = attribute(tag, "UUID")?;
+    let invisible = attribute(tag, "Invisible").as_deref() == Some("1");
+    // `RINCON_A:LF,RF;RINCON_B:SW`, channels of every speaker of the bonded set
+    let channel_map = attribute(tag, "HTSatChanMapSet").or_else(|| attribute(tag, "ChannelMapSet"));
+    let channels: Vec<(String, String)> = channel_map.as_deref().unwrap_or_default().split(';')
+        .filter_map(|entry| {
+            let mut parts = entry.splitn(2, ':');
+            match (parts.next(), parts.next()) {
+                (Some(uuid), Some(channels)) => Some((uuid.to_string(), channels.to_string())),
+                _ => None
+            }
+        })
+        .collect();
+    let own_channels = channels.iter().find(|(member, _)| *member == uuid).map(|(_, channels)| channels.as_str());
+
+    let role = match own_channels {
+        _ if parent.is_none() && !invisible => MemberRole::Zone,
+        Some(channels) if channels.contains("SW") => MemberRole::Subwoofer,
+        Some(channels) if channels.contains("LR") || channels.contains("RR") => MemberRole::Surround,
+        Some(_) if attribute(tag, "ChannelMapSet").is_some() => MemberRole::StereoPair,
+        _ => MemberRole::Satellite
+    };
+    let bonded_to = match parent {
+        Some(parent) => Some(parent.uuid.clone()),
+        // The main speaker is listed first in the channel map
+        None if role != MemberRole::Zone => channels.into_iter().map(|(member, _)| member).find(|member| *member != uuid),
+        None => None
+    };
+
+    Some(ZoneGroupMember {
+        ip: attribute(tag, "Location")
+            .and_then(|location| parse_http_url(&location))
+            .map(|(address, _)| address.ip()),
+        zone_name: attribute(tag, "ZoneName"),
+        uuid,
+        role,
+        bonded_to
+    })
+}
+
+/// Value of the attribute `name` of the first tag in `tag`
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let tag = &tag[..tag.find('>').unwrap_or(tag.len())];
+    let needle = format!(" {}=\"", name);
+    // The first attribute directly follows the element name, which was split off already
+    let start = if tag.starts_with(&needle[1..]) {
+        needle.len() - 1
+    } else {
+        tag.find(&needle)? + needle.len()
+    };
+    let end = start + tag[start..].find('"')?;
+
+    Some(unescape(&tag[start..end]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fake_fetcher::FakeFetcher;
+
+    /// `GetZoneGroupState` response of a household with a home theater grouped with the kitchen, a stereo pair and a
+    /// room with an escaped name
+    const RESPONSE: &str = include_str!("../tests/fixtures/zone_group_state.xml");
+
+    const URL: &str = "http://192.168.1.20:1400/ZoneGroupTopology/Control";
+
+    /// Fetcher answering the topology requests to 192.168.1.20 with `response`
+    fn fetcher(response: &str) -> FakeFetcher {
+        let fetcher = FakeFetcher::default();
+        fetcher.answer(URL, response);
+
+        fetcher
+    }
+
+    /// Zone groups of the fixture
+    fn groups(include_invisible: bool) -> Vec<ZoneGroup> {
+        fetch(&fetcher(RESPONSE), "192.168.1.20".parse().unwrap(), Duration::from_secs(1), include_invisible).unwrap()
+    }
+
+    /// Uuid, role and uuid of the speaker it's bonded to of every member of `group`
+    fn members(group: &ZoneGroup) -> Vec<(&str, MemberRole, Option<&str>)> {
+        group.members.iter().map(|member| (member.uuid.as_str(), member.role, member.bonded_to.as_deref())).collect()
+    }
+
+    #[test]
+    fn groups_have_their_coordinator_first() {
+        let groups = groups(false);
+        assert_eq!(groups.iter().map(|group| group.id.as_str()).collect::<Vec<_>>(),
+                   vec!["RINCON_000E58A0000001400:3102303617", "RINCON_000E58B0000001400:1475909442",
+                        "RINCON_000E58D0000001400:861545420"]);
+
+        // The kitchen is listed before the coordinator
+        let living_room = &groups[0];
+        assert_eq!(members(living_room), vec![("RINCON_000E58A0000001400", MemberRole::Zone, None),
+                                              ("RINCON_000E58C0000001400", MemberRole::Zone, None)]);
+        assert_eq!(living_room.coordinator(), &ZoneGroupMember {
+            uuid: "RINCON_000E58A0000001400".to_string(),
+            ip: Some("192.168.1.20".parse().unwrap()),
+            zone_name: Some("Living Room".to_string()),
+            role: MemberRole::Zone,
+            bonded_to: None
+        });
+        assert_eq!(living_room.members[1].zone_name.as_deref(), Some("Kitchen"));
+        assert_eq!(living_room.members[1].ip, Some("192.168.1.30".parse().unwrap()));
+    }
+
+    #[test]
+    fn stereo_pairs_are_bonded_to_their_main_speaker() {
+        for include_invisible in [false, true] {
+            // The second speaker is a member of its own and answers the search, it's always listed
+            assert_eq!(members(&groups(include_invisible)[1]),
+                       vec![("RINCON_000E58B0000001400", MemberRole::Zone, None),
+                            ("RINCON_000E58B0000011400", MemberRole::StereoPair, Some("RINCON_000E58B0000001400"))]);
+        }
+    }
+
+    #[test]
+    fn satellites_follow_their_member() {
+        let groups = groups(true);
+        let living_room = &groups[0];
+        let bonded = Some("RINCON_000E58A0000001400");
+        assert_eq!(members(living_room), vec![("RINCON_000E58A0000001400", MemberRole::Zone, None),
+                                              ("RINCON_000E58A0000011400", MemberRole::Subwoofer, bonded),
+                                              ("RINCON_000E58A0000021400", MemberRole::Surround, bonded),
+                                              ("RINCON_000E58A0000031400", MemberRole::Surround, bonded),
+                                              ("RINCON_000E58C0000001400", MemberRole::Zone, None)]);
+        assert_eq!(living_room.members[1].ip, Some("192.168.1.21".parse().unwrap()));
+        assert_eq!(living_room.members[1].zone_name.as_deref(), Some("Living Room"));
+        assert_eq!(groups.iter().map(|group| group.members.len()).sum::<usize>(), 8);
+    }
+
+    #[test]
+    fn names_are_unescaped() {
+        assert_eq!(groups(false)[2].coordinator().zone_name.as_deref(), Some("Tom & Jerry's Room"));
+    }
+
+    #[test]
+    fn requests() {
+        let fetcher = fetcher(RESPONSE);
+        fetch(&fetcher, "192.168.1.20".parse().unwrap(), Duration::from_secs(1), false).unwrap();
+        let requests = fetcher.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!((requests[0].method, requests[0].url.as_str()), ("POST", URL));
+        assert!(requests[0].headers.contains(&("SOAPACTION".to_string(), SOAP_ACTION.to_string())));
+        assert_eq!(requests[0].body.as_deref(), Some(SOAP_BODY));
+    }
+
+    #[test]
+    fn failed_requests() {
+        let ip = "192.168.1.20".parse().unwrap();
+        let timeout = Duration::from_secs(1);
+
+        let budget_used_up = fetcher(RESPONSE);
+        let error = fetch(&budget_used_up, ip, Duration::from_secs(0), false).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::TimedOut);
+        assert!(budget_used_up.requests().is_empty());
+
+        let fault = fetcher("<s:Envelope><s:Body><s:Fault/></s:Body></s:Envelope>");
+        assert_eq!(fetch(&fault, ip, timeout, false).unwrap_err().kind(), ErrorKind::InvalidData);
+
+        let unreachable = FakeFetcher::default();
+        assert_eq!(fetch(&unreachable, ip, timeout, false).unwrap_err().kind(), ErrorKind::ConnectionRefused);
+    }
+
+    #[test]
+    fn malformed_states() {
+        // No groups at all
+        assert_eq!(parse("", false), Vec::new());
+        assert_eq!(parse("<ZoneGroupState><ZoneGroups></ZoneGroups></ZoneGroupState>", false), Vec::new());
+        assert_eq!(parse("not xml <<<\"", false), Vec::new());
+
+        // Groups without an id or coordinator, or whose coordinator isn't a member, are skipped
+        let member = r#"<ZoneGroupMember UUID="RINCON_A" ZoneName="Kitchen"/>"#;
+        assert_eq!(parse(&format!(r#"<ZoneGroup Coordinator="RINCON_A">{}</ZoneGroup>"#, member), false), Vec::new());
+        assert_eq!(parse(&format!(r#"<ZoneGroup ID="RINCON_A:1">{}</ZoneGroup>"#, member), false), Vec::new());
+        assert_eq!(parse(&format!(r#"<ZoneGroup Coordinator="RINCON_B" ID="RINCON_B:1">{}</ZoneGroup>"#, member),
+                         false), Vec::new());
+
+        // Members without an uuid, unterminated attributes and groups and unknown locations
+        let groups = parse(r#"<ZoneGroup Coordinator="RINCON_A" ID="RINCON_A:1"><ZoneGroupMember ZoneName="X"/>
+                              <ZoneGroupMember UUID="RINCON_A" Location="ftp://a/" ZoneName="Kitchen/>
+                              <ZoneGroupMember UUID="RINCON_B" Location="http://192.168.1.30/" ChannelMapSet="x"/>"#,
+                           false);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].members, vec![ZoneGroupMember {
+            uuid: "RINCON_A".to_string(),
+            ip: None,
+            zone_name: None,
+            role: MemberRole::Zone,
+            bonded_to: None
+        }, ZoneGroupMember {
+            uuid: "RINCON_B".to_string(),
+            ip: Some("192.168.1.30".parse().unwrap()),
+            zone_name: None,
+            role: MemberRole::Zone,
+            bonded_to: None
+        }]);
+
+        // Satellites outside the channel map of their member
+        let groups = parse(r#"<ZoneGroup Coordinator="RINCON_A" ID="RINCON_A:1"><ZoneGroupMember UUID="RINCON_A">
+                              <Satellite UUID="RINCON_S" Invisible="1"/></ZoneGroupMember></ZoneGroup>"#, true);
+        assert_eq!(members(&groups[0]), vec![("RINCON_A", MemberRole::Zone, None),
+                                             ("RINCON_S", MemberRole::Satellite, Some("RINCON_A"))]);
+    }
+
+    #[test]
+    fn attributes() {
+        let tag = r#"UUID="RINCON_A" ZoneName="A &amp; B" Empty="" Broken="x>y" Invisible="1"/>"#;
+        assert_eq!(attribute(tag, "UUID").as_deref(), Some("RINCON_A"));
+        assert_eq!(attribute(tag, "ZoneName").as_deref(), Some("A & B"));
+        assert_eq!(attribute(tag, "Empty").as_deref(), Some(""));
+        assert_eq!(attribute(tag, "Name"), None);
+        // Only the first tag counts, even if a `>` is inside a value
+        assert_eq!(attribute(tag, "Broken"), None);
+        assert_eq!(attribute(tag, "Invisible"), None);
+        assert_eq!(attribute("UUID=\"RINCON_A", "UUID"), None);
+    }
+}
@@ -0,0 +1,416 @@
+//! Creation of the udp sockets with `socket2`, the options std doesn't offer have to be set before binding.
+
+use config::DiscoveryConfig;
+use if_addrs::{self, IfAddr};
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
+use error::{DiscoveryError, Result};
+use std::fmt::Debug;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::time::Duration;
+
+/// Socket of a single discovery, sending the search and receiving the answers
+///
+/// Implemented for `UdpSocket`, own implementations (e.g. an in-memory network for tests) are created by a
+/// `TransportFactory`.
+pub trait Transport: Debug + Send {
+    /// Sends `data` to `address`, returns the number of bytes sent
+    fn send_to(&self, data: &[u8], address: SocketAddr) -> io::Result<usize>;
+
+    /// Waits up to `timeout` (never zero) for a packet, a timeout is an error of the kind `WouldBlock` or `TimedOut`
+    fn recv_from(&self, buffer: &mut [u8], timeout: Duration) -> io::Result<(usize, SocketAddr)>;
+
+    /// Allows sending to a broadcast address, for `Discover::set_broadcast_fallback`
+    fn set_broadcast(&self, enabled: bool) -> io::Result<()>;
+
+    /// Sends the following multicast packets out of the ipv4 interface with the address `interface`,
+    /// for `Discover::set_all_interfaces`
+    fn set_multicast_if_v4(&self, interface: &Ipv4Addr) -> io::Result<()>;
+}
+
+impl Transport for UdpSocket {
+    fn send_to(&self, data: &[u8], address: SocketAddr) -> io::Result<usize> {
+        UdpSocket::send_to(self, data, address)
+    }
+
+    fn recv_from(&self, buffer: &mut [u8], timeout: Duration) -> io::Result<(usize, SocketAddr)> {
+        self.set_read_timeout(Some(timeout))?;
+        UdpSocket::recv_from(self, buffer)
+    }
+
+    fn set_broadcast(&self, enabled: bool) -> io::Result<()> {
+        UdpSocket::set_broadcast(self, enabled)
+    }
+
+    fn set_multicast_if_v4(&self, interface: &Ipv4Addr) -> io::Result<()> {
+        SockRef::from(self).set_multicast_if_v4(interface)
+    }
+}
+
+/// Creates the `Transport` of every discovery, installed with `Discover::set_transport`
+///
+/// # Examples
+///
+/// ```
+/// use sonos_discovery::{Discover, DiscoveryConfig, DiscoveryError, Transport, TransportFactory, UdpTransport};
+/// use std::sync::Arc;
+///
+/// /// Logs every socket before using the default udp sockets
+/// #[derive(Debug)]
+/// struct LoggingTransport;
+///
+/// impl TransportFactory for LoggingTransport {
+///     fn search(&self, config: &DiscoveryConfig) -> Result<Box<dyn Transport>, DiscoveryError> {
+///         println!("searching on {}", config.multicast_addr);
+///         UdpTransport.search(config)
+///     }
+///
+///     fn passive(&self, config: &DiscoveryConfig) -> Result<Box<dyn Transport>, DiscoveryError> {
+///         UdpTransport.passive(config)
+///     }
+/// }
+///
+/// let mut discovery = Discover::new().unwrap();
+/// discovery.set_transport(Arc::new(LoggingTransport));
+/// ```
+pub trait TransportFactory: Debug + Send + Sync {
+    /// Transport sending the search of `config`, answers are sent back to it
+    fn search(&self, config: &DiscoveryConfig) -> Result<Box<dyn Transport>>;
+
+    /// Transport receiving the announcements sent to the multicast address of `config`, see `Discover::listen`
+    fn passive(&self, config: &DiscoveryConfig) -> Result<Box<dyn Transport>>;
+}
+
+/// Default `TransportFactory`, udp sockets of the configured interface, bind address and multicast address
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UdpTransport;
+
+impl TransportFactory for UdpTransport {
+    fn search(&self, config: &DiscoveryConfig) -> Result<Box<dyn Transport>> {
+        Ok(Box::new(search_socket(config)?))
+    }
+
+    fn passive(&self, config: &DiscoveryConfig) -> Result<Box<dyn Transport>> {
+        Ok(Box::new(passive_socket(config)?))
+    }
+}
+
+/// Socket sending the search of `config` (and receiving the answers) from the configured interface or bind address
+pub(crate) fn search_socket(config: &DiscoveryConfig) -> Result<UdpSocket> {
+    check(config)?;
+    let socket = socket(config).map_err(DiscoveryError::SocketCreate)?;
+    bind_device(&socket, config)?;
+    let mut address = match (config.bind_addr, config.interface) {
+        (Some(address), _) => address,
+        (None, Some(interface)) => SocketAddr::new(IpAddr::V4(interface), 0),
+        // Bound explicitly, windows can't receive on a socket which was never bound
+        (None, None) => unspecified(config.multicast_addr, 0)
+    };
+    if let Some(port) = config.source_port {
+        address.set_port(port);
+    }
+    socket.bind(&address.into()).map_err(|e| match config.source_port {
+        Some(port) => DiscoveryError::SourcePort(port, e),
+        None => DiscoveryError::SocketCreate(e)
+    })?;
+
+    Ok(socket.into())
+}
+
+/// Socket receiving the announcements sent to the multicast address of `config`, see `Discover::listen`
+pub(crate) fn passive_socket(config: &DiscoveryConfig) -> Result<UdpSocket> {
+    check(config)?;
+    let socket = socket(config).map_err(DiscoveryError::SocketCreate)?;
+    bind_device(&socket, config)?;
+    joined_passive_socket(socket, config).map_err(DiscoveryError::SocketCreate)
+}
+
+fn joined_passive_socket(socket: Socket, config: &DiscoveryConfig) -> io::Result<UdpSocket> {
+    // Other ssdp listeners of the host (e.g. a media server) use the port as well, `reuse_port` also sets SO_REUSEPORT
+    socket.set_reuse_address(true)?;
+    let group = config.multicast_addr;
+    socket.bind(&config.bind_addr.unwrap_or_else(|| unspecified(group, group.port())).into())?;
+    match group.ip() {
+        IpAddr::V4(address) => socket.join_multicast_v4(&address, &config.interface.unwrap_or(Ipv4Addr::UNSPECIFIED))?,
+        // Interface 0 is the default interface
+        IpAddr::V6(address) => socket.join_multicast_v6(&address, 0)?
+    }
+
+    Ok(socket.into())
+}
+
+/// Sends the search with `send` out of every interface of `config` (`all_interfaces`, `interface_name`), or once if
+/// the search isn't sent out of all interfaces. Fails only if it couldn't be sent out of any interface.
+pub(crate) fn send_multicast<F>(socket: &dyn Transport, config: &DiscoveryConfig, mut send: F) -> Result<()>
+    where F: FnMut() -> io::Result<usize>
+{
+    if !sends_on_all_interfaces(config) {
+        return send().map(|_| ()).map_err(DiscoveryError::Send);
+    }
+
+    let mut sent = false;
+    let mut last_error = None;
+    for interface in search_interfaces(config)? {
+        match socket.set_multicast_if_v4(&interface).and_then(|()| send()) {
+            Ok(_) => {
+                debug!("Sent the search out of {}", interface);
+                sent = true;
+            }
+            Err(e) => {
+                warn!("Sending the search out of {} failed: {}", interface, e);
+                last_error = Some(e);
+            }
+        }
+    }
+
+    match last_error {
+        Some(e) if !sent => Err(DiscoveryError::Send(e)),
+        _ => Ok(())
+    }
+}
+
+/// Address and netmask of a local interface, see `local_networks`
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct LocalNetwork {
+    address: IpAddr,
+    netmask: IpAddr
+}
+
+impl LocalNetwork {
+    /// Whether `ip` is in the subnet of the interface
+    pub(crate) fn contains(&self, ip: IpAddr) -> bool {
+        match (self.address, self.netmask, ip) {
+            (IpAddr::V4(address), IpAddr::V4(netmask), IpAddr::V4(ip)) =>
+                u32::from(address) & u32::from(netmask) == u32::from(ip) & u32::from(netmask),
+            (IpAddr::V6(address), IpAddr::V6(netmask), IpAddr::V6(ip)) =>
+                u128::from(address) & u128::from(netmask) == u128::from(ip) & u128::from(netmask),
+            _ => false
+        }
+    }
+}
+
+/// Subnets of the interfaces the search of `config` is sent out of: the configured interface (`multicast_if`,
+/// `interface`, `interface_name`, `bind_device`), otherwise every interface including the loopback, the os chooses
+/// one of them
+pub(crate) fn local_networks(config: &DiscoveryConfig) -> io::Result<Vec<LocalNetwork>> {
+    let interface = config.multicast_if.or(config.interface).map(IpAddr::V4);
+    let interface_name = config.interface_name.as_deref().or_else(|| bound_device(config));
+    let networks = if_addrs::get_if_addrs()?.into_iter()
+        .map(|iface| {
+            let (address, netmask) = match iface.addr {
+                IfAddr::V4(address) => (IpAddr::V4(address.ip), IpAddr::V4(address.netmask)),
+                IfAddr::V6(address) => (IpAddr::V6(address.ip), IpAddr::V6(address.netmask))
+            };
+            (iface.name, LocalNetwork { address, netmask })
+        })
+        .filter(|&(ref name, network)| match (interface, interface_name) {
+            (Some(address), _) => network.address == address,
+            (None, Some(interface_name)) => name == interface_name,
+            (None, None) => true
+        })
+        .map(|(_, network)| network)
+        .collect();
+
+    Ok(networks)
+}
+
+/// Interface chosen by `best_interface`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterfaceChoice {
+    /// Name of the interface (e.g. `eth0`)
+    pub name: String,
+    /// Its local ipv4 address, the value for `Discover::set_interface`
+    pub address: Ipv4Addr,
+    /// Number of interfaces which qualified, with more than one the choice could be the wrong network
+    pub candidates: usize
+}
+
+/// Name prefixes of tunnels, VPNs and virtual bridges, which don't lead to the speakers and mostly can't multicast
+const VIRTUAL_INTERFACES: &[&str] = &[
+    "tun", "tap", "wg", "utun", "ppp", "ipsec", "gpd", "tailscale", "zt", "docker", "br-", "veth", "virbr", "vboxnet",
+    "vmnet", "lxc", "lxd", "cni", "flannel", "cali", "vpn"
+];
+
+/// Interface the speakers are most likely reachable on: an ipv4 address of a private network (RFC 1918) on an
+/// interface which isn't a loopback, tunnel, VPN or virtual bridge. `192.168.0.0/16` is preferred over `10.0.0.0/8`
+/// over `172.16.0.0/12` (used by containers), then the name decides. `None` if no interface qualifies.
+///
+/// `Discover::set_auto_interface` uses it for the search, `best_interface` shows the choice beforehand to override it
+/// with `Discover::set_interface`.
+///
+/// # Examples
+///
+/// ```
+/// use sonos_discovery::{best_interface, Discover};
+///
+/// let mut discovery = Discover::new().unwrap();
+/// if let Some(choice) = best_interface().unwrap() {
+///     println!("Searching on {} ({})", choice.name, choice.address);
+///     discovery.set_interface(choice.address);
+/// }
+/// ```
+pub fn best_interface() -> io::Result<Option<InterfaceChoice>> {
+    let mut candidates: Vec<(String, Ipv4Addr)> = if_addrs::get_if_addrs()?.into_iter()
+        .filter(|interface| !interface.is_loopback())
+        .filter(|interface| {
+            let name = interface.name.to_ascii_lowercase();
+            !VIRTUAL_INTERFACES.iter().any(|prefix| name.starts_with(prefix))
+        })
+        .filter_map(|interface| match interface.addr {
+            IfAddr::V4(address) if address.ip.is_private() => Some((interface.name, address.ip)),
+            _ => None
+        })
+        .collect();
+    candidates.sort_by(|a, b| (private_rank(a.1), &a.0, a.1).cmp(&(private_rank(b.1), &b.0, b.1)));
+
+    let count = candidates.len();
+    Ok(candidates.into_iter().next().map(|(name, address)| InterfaceChoice { name, address, candidates: count }))
+}
+
+/// Order of the private networks for `best_interface`, the most common for home networks first
+fn private_rank(address: Ipv4Addr) -> u8 {
+    match address.octets() {
+        [192, 168, ..] => 0,
+        [10, ..] => 1,
+        _ => 2
+    }
+}
+
+/// Sets the interface of `config` to `best_interface` with `auto_interface`, unless the interface or the bind address
+/// is configured already. Keeps the default route if no interface qualifies.
+pub(crate) fn select_interface(config: &mut DiscoveryConfig) {
+    if !config.auto_interface || config.multicast_addr.is_ipv6() || config.interface.is_some()
+        || config.multicast_if.is_some() || config.bind_addr.is_some() || bound_device(config).is_some()
+        || sends_on_all_interfaces(config) {
+        return;
+    }
+
+    match best_interface() {
+        Ok(Some(choice)) => {
+            info!("Searching on {} ({}), chosen from {} private interfaces", choice.name, choice.address,
+                  choice.candidates);
+            config.interface = Some(choice.address);
+        }
+        Ok(None) => warn!("No private network interface found, the search uses the default route"),
+        Err(e) => warn!("Couldn't list the interfaces, the search uses the default route: {}", e)
+    }
+}
+
+fn sends_on_all_interfaces(config: &DiscoveryConfig) -> bool {
+    config.interface.is_none() && config.multicast_if.is_none()
+        && (config.all_interfaces || config.interface_name.is_some())
+}
+
+/// Local addresses of the ipv4 interfaces the search is sent out of, loopbacks are skipped
+fn search_interfaces(config: &DiscoveryConfig) -> Result<Vec<Ipv4Addr>> {
+    let mut addresses: Vec<Ipv4Addr> = if_addrs::get_if_addrs()?.into_iter()
+        .filter(|interface| !interface.is_loopback())
+        .filter(|interface| match config.interface_name {
+            Some(ref name) => interface.name == *name,
+            None => true
+        })
+        .filter_map(|interface| match interface.addr {
+            IfAddr::V4(address) => Some(address.ip),
+            IfAddr::V6(_) => None
+        })
+        .collect();
+    // An interface with several addresses only needs the search once per address
+    addresses.sort();
+    addresses.dedup();
+
+    if addresses.is_empty() {
+        let message = match config.interface_name {
+            Some(ref name) => format!("No ipv4 interface named {} found", name),
+            None => "No ipv4 interface found".to_string()
+        };
+        return Err(DiscoveryError::InterfaceNotFound(message));
+    }
+
+    Ok(addresses)
+}
+
+/// Rejects the addresses of `config` which don't fit the family of the multicast address
+fn check(config: &DiscoveryConfig) -> Result<()> {
+    if let Some(address) = config.bind_addr {
+        if address.is_ipv4() != config.multicast_addr.is_ipv4() {
+            return Err(DiscoveryError::InvalidAddress(
+                format!("The bind address {} isn't of the family of {}", address, config.multicast_addr)));
+        }
+    }
+    let other_family = |group: &&SocketAddr| group.is_ipv4() != config.multicast_addr.is_ipv4();
+    if let Some(group) = config.additional_multicast_addrs.iter().find(other_family) {
+        return Err(DiscoveryError::InvalidAddress(
+            format!("The multicast group {} isn't of the family of {}", group, config.multicast_addr)));
+    }
+    if config.multicast_addr.is_ipv6()
+        && (config.interface.is_some() || config.multicast_if.is_some() || sends_on_all_interfaces(config)) {
+        return Err(DiscoveryError::InvalidAddress("The interface can only be set for ipv4 discoveries".to_string()));
+    }
+
+    Ok(())
+}
+
+/// Socket of the address family of the multicast address, sending from the configured interface
+fn socket(config: &DiscoveryConfig) -> io::Result<Socket> {
+    let socket = if config.multicast_addr.is_ipv6() {
+        let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+        // Same limit as the TTL of ipv4, only matters for FF05::C
+        socket.set_multicast_hops_v6(config.multicast_ttl)?;
+        socket
+    } else {
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+        // UPnP 1.0 needs a TTL of 4
+        socket.set_multicast_ttl_v4(config.multicast_ttl)?;
+        socket
+    };
+    if let Some(ref interface) = config.multicast_if.or(config.interface) {
+        socket.set_multicast_if_v4(interface)?;
+    }
+    if config.multicast_addr.is_ipv6() {
+        socket.set_multicast_loop_v6(config.multicast_loop)?;
+    } else {
+        socket.set_multicast_loop_v4(config.multicast_loop)?;
+    }
+    if config.reuse_port {
+        socket.set_reuse_address(true)?;
+        // Windows only has SO_REUSEADDR, which already allows sharing the port
+        #[cfg(unix)]
+        socket.set_reuse_port(true)?;
+    }
+
+    Ok(socket)
+}
+
+/// Binds `socket` to the interface of `DiscoveryConfig::bind_device` (`SO_BINDTODEVICE`)
+#[cfg(target_os = "linux")]
+fn bind_device(socket: &Socket, config: &DiscoveryConfig) -> Result<()> {
+    match config.bind_device {
+        Some(ref device) => socket.bind_device(Some(device.as_bytes()))
+            .map_err(|e| DiscoveryError::BindDevice(device.clone(), e)),
+        None => Ok(())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bind_device(_socket: &Socket, _config: &DiscoveryConfig) -> Result<()> {
+    Ok(())
+}
+
+/// Interface of `DiscoveryConfig::bind_device`
+#[cfg(target_os = "linux")]
+fn bound_device(config: &DiscoveryConfig) -> Option<&str> {
+    config.bind_device.as_deref()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bound_device(_config: &DiscoveryConfig) -> Option<&str> {
+    None
+}
+
+/// Unspecified address of the family of `address` with `port`
+fn unspecified(address: SocketAddr, port: u16) -> SocketAddr {
+    match address {
+        SocketAddr::V4(_) => SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port),
+        SocketAddr::V6(_) => SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), port)
+    }
+}
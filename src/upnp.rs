@@ -0,0 +1,411 @@
+//! UPnP headers and constants of ssdp, and the parser of whole device description documents (`Description`)
+
+use description::element;
+use fetcher::HttpFetcher;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::io::{Error, ErrorKind, Result};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+/// Search target of sonos speakers
+pub const ZONE_PLAYER: &str = "urn:schemas-upnp-org:device:ZonePlayer:1";
+/// Port of ssdp, used for unicast searches if a device doesn't announce `SEARCHPORT.UPNP.ORG`
+pub const SSDP_PORT: u16 = 1900;
+/// Ipv4 multicast address of ssdp (`239.255.255.250`), the default of every discovery
+pub const SSDP_MULTICAST_V4: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 250);
+/// Link-local ipv6 multicast address of ssdp (`FF02::C`), reaches the devices on the same link
+pub const SSDP_LINK_LOCAL_V6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xc);
+/// Site-local ipv6 multicast address of ssdp (`FF05::C`), needs multicast routing between the links
+pub const SSDP_SITE_LOCAL_V6: Ipv6Addr = Ipv6Addr::new(0xff05, 0, 0, 0, 0, 0, 0, 0xc);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+/// UPnP 1.1 headers of a search response, all `None` for UPnP 1.0 devices
+pub struct UpnpHeaders {
+    /// `BOOTID.UPNP.ORG`, increased every time the device (re)joins the network
+    pub boot_id: Option<u32>,
+    /// `CONFIGID.UPNP.ORG`, changes with the device description
+    pub config_id: Option<u32>,
+    /// `SEARCHPORT.UPNP.ORG`, the device listens for unicast searches on this port instead of 1900
+    pub search_port: Option<u16>
+}
+
+impl UpnpHeaders {
+    /// Parses the headers with `header` (a case insensitive lookup), invalid values are ignored
+    pub(crate) fn parse<'a, F: Fn(&str) -> Option<&'a str>>(header: F) -> Self {
+        UpnpHeaders {
+            boot_id: header("BOOTID.UPNP.ORG").and_then(|value| value.parse().ok()),
+            config_id: header("CONFIGID.UPNP.ORG").and_then(|value| value.parse().ok()),
+            // Only 49152-65535 is allowed, anything else has to be ignored (UPnP device architecture 1.1, 1.3.2)
+            search_port: header("SEARCHPORT.UPNP.ORG")
+                .and_then(|value| value.parse().ok())
+                .filter(|&port| port >= 49_152)
+        }
+    }
+
+    /// Address a unicast search (re-probe) of the device at `ip` has to be sent to
+    pub fn search_addr(&self, ip: IpAddr) -> SocketAddr {
+        SocketAddr::new(ip, self.search_port.unwrap_or(SSDP_PORT))
+    }
+}
+
+/// Whole UPnP device description document (the `LOCATION` of a search response) with the embedded devices and
+/// their services, e.g. to find the control urls of the `MediaRenderer` of a speaker
+///
+/// `DeviceDescription` only has the fields of the root device which the discovery needs.
+///
+/// # Examples
+///
+/// ```no_run
+/// use sonos_discovery::TcpFetcher;
+/// use sonos_discovery::upnp::Description;
+/// use std::time::Duration;
+///
+/// let description = Description::fetch(&TcpFetcher, "http://192.168.1.20:1400/xml/device_description.xml",
+///                                      Duration::from_secs(2)).unwrap();
+/// if let Some(service) = description.find_service("urn:schemas-upnp-org:service:AVTransport:1") {
+///     println!("{:?}", description.url(service.control_url.as_deref().unwrap_or_default()));
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Description {
+    /// Url the document was fetched from, the relative urls of the services are resolved against it
+    pub location: Option<String>,
+    /// `URLBase` of UPnP 1.0 documents, takes precedence over `location`
+    pub url_base: Option<String>,
+    /// Root device, `ZonePlayer` for sonos speakers
+    pub device: Device
+}
+
+/// Device of a `Description`, the root device or an embedded one
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Device {
+    /// e.g. `urn:schemas-upnp-org:device:MediaRenderer:1`
+    pub device_type: Option<String>,
+    pub friendly_name: Option<String>,
+    pub manufacturer: Option<String>,
+    pub model_name: Option<String>,
+    pub model_number: Option<String>,
+    /// `uuid:RINCON_...`, embedded devices of sonos speakers append `_MR` or `_MS`
+    pub udn: Option<String>,
+    pub services: Vec<Service>,
+    /// Embedded devices (`deviceList`), e.g. `MediaRenderer` and `MediaServer` of a speaker
+    pub devices: Vec<Device>
+}
+
+/// Service of a `Device`, the urls are relative to the document unless they're absolute
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Service {
+    /// e.g. `urn:schemas-upnp-org:service:AVTransport:1`
+    pub service_type: Option<String>,
+    /// e.g. `urn:upnp-org:serviceId:AVTransport`
+    pub service_id: Option<String>,
+    /// Url of the soap actions
+    pub control_url: Option<String>,
+    /// Url of the GENA subscriptions
+    pub event_sub_url: Option<String>,
+    /// Url of the service description (actions and state variables)
+    pub scpd_url: Option<String>
+}
+
+impl Description {
+    /// Parses a device description document fetched from `location`, fails if it has no root device
+    pub fn parse(xml: &str, location: Option<&str>) -> Result<Self> {
+        let root = section(xml, "device")
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Device description without a device"))?
+            .0;
+
+        Ok(Description {
+            location: location.map(str::to_string),
+            url_base: element(&without(xml, "device"), "URLBase"),
+            device: Device::parse(root)
+        })
+    }
+
+    /// Downloads and parses the description at `location` with `fetcher`, e.g. `TcpFetcher`
+    pub fn fetch(fetcher: &dyn HttpFetcher, location: &str, timeout: Duration) -> Result<Self> {
+        Description::parse(&fetcher.get(location, timeout)?, Some(location))
+    }
+
+    /// The root device and all embedded devices, parents before their children
+    pub fn devices(&self) -> Vec<&Device> {
+        let mut devices = vec![&self.device];
+        let mut index = 0;
+        while index < devices.len() {
+            let embedded = &devices[index].devices;
+            devices.extend(embedded.iter());
+            index += 1;
+        }
+
+        devices
+    }
+
+    /// First service of the type `service_type` (ignoring case) in any device of the tree
+    pub fn find_service(&self, service_type: &str) -> Option<&Service> {
+        self.devices().into_iter()
+            .flat_map(|device| device.services.iter())
+            .find(|service| service.service_type.as_ref().is_some_and(|own| own.eq_ignore_ascii_case(service_type)))
+    }
+
+    /// Absolute url of the url `relative` of a service, resolved against `url_base` or `location`.
+    /// `None` if it's relative and neither is known.
+    pub fn url(&self, relative: &str) -> Option<String> {
+        if relative.starts_with("http://") || relative.starts_with("https://") {
+            return Some(relative.to_string());
+        }
+
+        let base = self.url_base.as_ref().or(self.location.as_ref())?;
+        // Scheme and authority of the base, the path of the document doesn't matter for absolute paths
+        let authority_end = base.find("://").map(|scheme| scheme + 3)
+            .map(|start| base[start..].find('/').map_or(base.len(), |end| start + end))?;
+        let path = if relative.starts_with('/') {
+            relative.to_string()
+        } else {
+            format!("/{}", relative)
+        };
+
+        Some(format!("{}{}", &base[..authority_end], path))
+    }
+}
+
+impl Device {
+    fn parse(xml: &str) -> Self {
+        // The fields of the device come before or after its embedded devices, which have the same elements
+        let own = without(&without(xml, "deviceList"), "serviceList");
+        let services = section(&without(xml, "deviceList"), "serviceList")
+            .map(|(list, _)| sections(list, "service").into_iter().map(Service::parse).collect())
+            .unwrap_or_default();
+        let devices = section(xml, "deviceList")
+            .map(|(list, _)| sections(list, "device").into_iter().map(Device::parse).collect())
+            .unwrap_or_default();
+
+        Device {
+            device_type: element(&own, "deviceType"),
+            friendly_name: element(&own, "friendlyName"),
+            manufacturer: element(&own, "manufacturer"),
+            model_name: element(&own, "modelName"),
+            model_number: element(&own, "modelNumber"),
+            udn: element(&own, "UDN"),
+            services,
+            devices
+        }
+    }
+}
+
+impl Service {
+    fn parse(xml: &str) -> Self {
+        Service {
+            service_type: element(xml, "serviceType"),
+            service_id: element(xml, "serviceId"),
+            control_url: element(xml, "controlURL"),
+            event_sub_url: element(xml, "eventSubURL"),
+            scpd_url: element(xml, "SCPDURL")
+        }
+    }
+}
+
+/// Content of the first `<name>` element and the text after it, nested elements of the same name are skipped over
+fn section<'a>(xml: &'a str, name: &str) -> Option<(&'a str, &'a str)> {
+    let start_tag = format!("<{}>", name);
+    let end_tag = format!("</{}>", name);
+
+    let start = xml.find(&start_tag)? + start_tag.len();
+    let mut depth = 1;
+    let mut position = start;
+    loop {
+        let end = position + xml[position..].find(&end_tag)?;
+        match xml[position..end].find(&start_tag) {
+            Some(nested) => {
+                depth += 1;
+                position += nested + start_tag.len();
+            }
+            None if depth == 1 => return Some((&xml[start..end], &xml[end + end_tag.len()..])),
+            None => {
+                depth -= 1;
+                position = end + end_tag.len();
+            }
+        }
+    }
+}
+
+/// Contents of all `<name>` elements on the outermost level of `xml`
+fn sections<'a>(mut xml: &'a str, name: &str) -> Vec<&'a str> {
+    let mut contents = Vec::new();
+    while let Some((content, rest)) = section(xml, name) {
+        contents.push(content);
+        xml = rest;
+    }
+
+    contents
+}
+
+/// `xml` without the `<name>` elements on its outermost level
+fn without(xml: &str, name: &str) -> String {
+    let start_tag = format!("<{}>", name);
+    let mut remaining = xml;
+    let mut text = String::new();
+    while let Some((_, rest)) = section(remaining, name) {
+        let start = remaining.find(&start_tag).unwrap_or_default();
+        text.push_str(&remaining[..start]);
+        remaining = rest;
+    }
+    text.push_str(remaining);
+
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fake_fetcher::FakeFetcher;
+
+    const LOCATION: &str = "http://192.168.1.20:1400/xml/device_description.xml";
+
+    /// Value of the header `name` in `headers`
+    fn lookup<'a>(headers: &[(&str, &'a str)], name: &str) -> Option<&'a str> {
+        headers.iter().find(|&&(key, _)| key == name).map(|&(_, value)| value)
+    }
+
+    /// Description of a Sonos One with its media server and renderer
+    fn description() -> Description {
+        Description::parse(include_str!("../tests/fixtures/device_description.xml"), Some(LOCATION)).unwrap()
+    }
+
+    #[test]
+    fn upnp_headers() {
+        let headers = [("BOOTID.UPNP.ORG", "84"), ("CONFIGID.UPNP.ORG", "2097369"), ("SEARCHPORT.UPNP.ORG", "49153")];
+        let upnp = UpnpHeaders::parse(|name| lookup(&headers, name));
+        assert_eq!(upnp, UpnpHeaders {
+            boot_id: Some(84),
+            config_id: Some(2_097_369),
+            search_port: Some(49_153)
+        });
+        assert_eq!(upnp.search_addr("192.168.1.20".parse().unwrap()), "192.168.1.20:49153".parse().unwrap());
+
+        // UPnP 1.0 devices, invalid values and search ports outside of the allowed range
+        let upnp = UpnpHeaders::parse(|_| None);
+        assert_eq!(upnp, UpnpHeaders::default());
+        assert_eq!(upnp.search_addr("192.168.1.20".parse().unwrap()), "192.168.1.20:1900".parse().unwrap());
+        for &(boot_id, port) in &[("-1", "1900"), ("x", "49151"), ("4294967296", "65536"), ("", "")] {
+            let headers = [("BOOTID.UPNP.ORG", boot_id), ("SEARCHPORT.UPNP.ORG", port)];
+            let upnp = UpnpHeaders::parse(|name| lookup(&headers, name));
+            assert_eq!(upnp, UpnpHeaders::default(), "{} {}", boot_id, port);
+        }
+    }
+
+    #[test]
+    fn root_devices() {
+        let description = description();
+        assert_eq!(description.location.as_deref(), Some(LOCATION));
+        assert_eq!(description.url_base, None);
+
+        let device = &description.device;
+        assert_eq!(device.device_type.as_deref(), Some(ZONE_PLAYER));
+        assert_eq!(device.friendly_name.as_deref(), Some("192.168.1.20 - Sonos One - RINCON_000E58A0000001400"));
+        assert_eq!(device.manufacturer.as_deref(), Some("Sonos, Inc."));
+        assert_eq!(device.model_name.as_deref(), Some("Sonos One"));
+        assert_eq!(device.model_number.as_deref(), Some("S18"));
+        assert_eq!(device.udn.as_deref(), Some("uuid:RINCON_000E58A0000001400"));
+        // Only its own services, not the ones of the embedded devices
+        assert_eq!(device.services.iter().map(|service| service.service_id.as_deref().unwrap()).collect::<Vec<_>>(),
+                   vec!["urn:upnp-org:serviceId:AlarmClock", "urn:upnp-org:serviceId:DeviceProperties",
+                        "urn:upnp-org:serviceId:ZoneGroupTopology"]);
+        assert_eq!(device.services[2], Service {
+            service_type: Some("urn:schemas-upnp-org:service:ZoneGroupTopology:1".to_string()),
+            service_id: Some("urn:upnp-org:serviceId:ZoneGroupTopology".to_string()),
+            control_url: Some("/ZoneGroupTopology/Control".to_string()),
+            event_sub_url: Some("/ZoneGroupTopology/Event".to_string()),
+            scpd_url: Some("/xml/ZoneGroupTopology1.xml".to_string())
+        });
+    }
+
+    #[test]
+    fn embedded_devices() {
+        let description = description();
+        let devices = description.devices();
+        assert_eq!(devices.iter().map(|device| device.udn.as_deref().unwrap()).collect::<Vec<_>>(),
+                   vec!["uuid:RINCON_000E58A0000001400", "uuid:RINCON_000E58A0000001400_MS",
+                        "uuid:RINCON_000E58A0000001400_MR"]);
+
+        let renderer = devices[2];
+        assert_eq!(renderer.device_type.as_deref(), Some("urn:schemas-upnp-org:device:MediaRenderer:1"));
+        assert_eq!(renderer.friendly_name.as_deref(),
+                   Some("Tom & Jerry's Room - Sonos One Media Renderer - RINCON_000E58A0000001400"));
+        assert_eq!(renderer.services.len(), 3);
+        assert!(renderer.devices.is_empty());
+        assert_eq!(devices[1].services.len(), 2);
+    }
+
+    #[test]
+    fn services_are_found_in_every_device() {
+        let description = description();
+        let transport = description.find_service("URN:SCHEMAS-UPNP-ORG:SERVICE:AVTRANSPORT:1").unwrap();
+        assert_eq!(transport.control_url.as_deref(), Some("/MediaRenderer/AVTransport/Control"));
+        assert_eq!(description.find_service("urn:schemas-upnp-org:service:ContentDirectory:1").unwrap().scpd_url
+                       .as_deref(), Some("/xml/ContentDirectory1.xml"));
+        assert_eq!(description.find_service("urn:schemas-upnp-org:service:GroupRenderingControl:1"), None);
+    }
+
+    #[test]
+    fn urls_are_resolved() {
+        let mut description = description();
+        assert_eq!(description.url("/MediaRenderer/AVTransport/Control").as_deref(),
+                   Some("http://192.168.1.20:1400/MediaRenderer/AVTransport/Control"));
+        assert_eq!(description.url("AVTransport/Control").as_deref(),
+                   Some("http://192.168.1.20:1400/AVTransport/Control"));
+        assert_eq!(description.url("http://192.168.1.21:1400/Control").as_deref(),
+                   Some("http://192.168.1.21:1400/Control"));
+
+        description.url_base = Some("http://192.168.1.30:1400".to_string());
+        assert_eq!(description.url("/Control").as_deref(), Some("http://192.168.1.30:1400/Control"));
+        description.url_base = Some("not a url".to_string());
+        assert_eq!(description.url("/Control"), None);
+        description.url_base = None;
+        description.location = None;
+        assert_eq!(description.url("/Control"), None);
+    }
+
+    #[test]
+    fn url_bases() {
+        let xml = "<root><URLBase>http://192.168.1.40:1400/</URLBase><device><deviceType>urn:a</deviceType>\
+                   <serviceList><service><controlURL>Control</controlURL></service></serviceList></device></root>";
+        let description = Description::parse(xml, None).unwrap();
+        assert_eq!(description.url_base.as_deref(), Some("http://192.168.1.40:1400/"));
+        assert_eq!(description.url("Control").as_deref(), Some("http://192.168.1.40:1400/Control"));
+
+        // An `URLBase` of an embedded device doesn't count
+        let xml = "<root><device><URLBase>http://a/</URLBase></device></root>";
+        assert_eq!(Description::parse(xml, None).unwrap().url_base, None);
+    }
+
+    #[test]
+    fn invalid_documents() {
+        // Unterminated root or embedded devices as well
+        for xml in &["", "<root></root>", "<root><device>", "<html><body>Not found</body></html>",
+                     "<device><deviceList><device><UDN>uuid:a</UDN></deviceList></device>"] {
+            let error = Description::parse(xml, Some(LOCATION)).unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidData, "{}", xml);
+        }
+
+        // Empty elements and lists
+        let description = Description::parse("<device><friendlyName> </friendlyName><UDN></UDN><serviceList>\
+                                              </serviceList><deviceList/></device>", None).unwrap();
+        assert_eq!(description.device, Device::default());
+    }
+
+    #[test]
+    fn descriptions_are_fetched() {
+        let fetcher = FakeFetcher::default();
+        fetcher.answer(LOCATION, include_str!("../tests/fixtures/device_description.xml"));
+        assert_eq!(Description::fetch(&fetcher, LOCATION, Duration::from_secs(1)).unwrap(), description());
+        assert_eq!(fetcher.requests()[0].url, LOCATION);
+
+        let error = Description::fetch(&fetcher, "http://192.168.1.21:1400/xml/device_description.xml",
+                                       Duration::from_secs(1)).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::ConnectionRefused);
+    }
+}
@@ -0,0 +1,67 @@
+//! Async discoveries on async-io's reactor against the fake speaker of `test_util`, needs the `async-io` and
+//! `test-util` features.
+
+extern crate async_io;
+extern crate sonos_discovery;
+
+use sonos_discovery::async_io::Discover;
+use sonos_discovery::test_util::MockResponder;
+use std::time::{Duration, Instant};
+
+/// Long enough for an answer on loopback, the discoveries wait for the whole timeout to see every duplicate
+const TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Async discovery of the speaker at `responder`
+fn discovery(responder: &MockResponder) -> Discover {
+    Discover::from(sonos_discovery::Discover::with_address(responder.addr()).unwrap())
+}
+
+#[test]
+fn finds_the_responder() {
+    let responder = MockResponder::builder().uuid("RINCON_000E58A0123401400").duplicates(1).start().unwrap();
+
+    let result = async_io::block_on(discovery(&responder).start(Some(TIMEOUT), None)).unwrap();
+    let ip = responder.addr().ip();
+    assert_eq!(result.unique_ips(), vec![ip]);
+    assert_eq!(result.uuid(&ip), Some("RINCON_000E58A0123401400"));
+    assert_eq!(result.household(&ip), Some("Sonos_test"));
+    assert_eq!(result.stats().responses, 2);
+    assert_eq!(result.stats().duplicates, 1);
+}
+
+#[test]
+fn finishes_with_the_device_count() {
+    let responder = MockResponder::builder().start().unwrap();
+
+    let start = Instant::now();
+    let result = async_io::block_on(discovery(&responder).start(Some(Duration::from_secs(10)), Some(1))).unwrap();
+    assert_eq!(result.unique_ips(), vec![responder.addr().ip()]);
+    assert!(start.elapsed() < Duration::from_secs(5), "{:?}", start.elapsed());
+}
+
+#[test]
+fn answers_after_the_timeout_are_missed() {
+    let responder = MockResponder::builder().delay(Duration::from_millis(300)).malformed(true).start().unwrap();
+    let discovery = discovery(&responder);
+
+    let start = Instant::now();
+    assert!(async_io::block_on(discovery.start(Some(Duration::from_millis(100)), None)).unwrap().unique_ips()
+        .is_empty());
+    assert!(start.elapsed() < Duration::from_millis(300), "{:?}", start.elapsed());
+
+    let result = async_io::block_on(discovery.start(Some(Duration::from_secs(2)), Some(1))).unwrap();
+    assert_eq!(result.unique_ips(), vec![responder.addr().ip()]);
+    assert_eq!(result.stats().rejected, 1);
+}
+
+#[test]
+fn http_requests_are_skipped_with_a_warning() {
+    let responder = MockResponder::builder().start().unwrap();
+    let mut blocking = sonos_discovery::Discover::with_address(responder.addr()).unwrap();
+    blocking.set_verify(true);
+
+    let result = async_io::block_on(Discover::from(blocking).start(Some(TIMEOUT), None)).unwrap();
+    assert_eq!(result.unique_ips(), vec![responder.addr().ip()]);
+    let warning = "The async discovery doesn't verify the devices or fetch the topology";
+    assert!(result.warnings().iter().any(|w| w == warning), "{:?}", result.warnings());
+}
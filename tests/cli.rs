@@ -0,0 +1,62 @@
+//! Exit statuses of the binary, needs the `cli` and `test-util` features. The discoveries only search on loopback.
+
+extern crate sonos_discovery;
+
+use sonos_discovery::test_util::MockResponder;
+use std::process::{Command, Output};
+
+/// Runs the binary with `args`, without a config file or defaults from the environment
+fn run(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_sonos_discovery"))
+        .args(args)
+        .env("XDG_CONFIG_HOME", env!("CARGO_TARGET_TMPDIR"))
+        .env_remove("SONOS_DISCOVERY_TIMEOUT")
+        .env_remove("SONOS_DISCOVERY_INTERFACE")
+        .env_remove("SONOS_DISCOVERY_TARGET")
+        .output()
+        .unwrap()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn invalid_arguments_exit_with_2() {
+    for (args, message) in &[(&["--bogus"][..], "Unknown argument: --bogus"),
+                             (&["--timeout"], "--timeout needs a value"),
+                             (&["--interval", "99999999999999999h"], "Invalid duration: 99999999999999999h"),
+                             (&["--format", "json", "--interval", "5s"], "--format json is a single discovery")] {
+        let output = run(args);
+        assert_eq!(output.status.code(), Some(2), "{:?}", args);
+        assert!(stderr(&output).starts_with(message), "{}", stderr(&output));
+        assert!(stderr(&output).contains("Usage: sonos_discovery"));
+    }
+}
+
+#[test]
+fn failed_discoveries_exit_with_1() {
+    // Not an address of this host
+    let output = run(&["--bind", "192.0.2.1", "--timeout", "200ms"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).starts_with("The discovery failed"), "{}", stderr(&output));
+}
+
+#[test]
+fn too_few_devices_exit_with_3() {
+    let output = run(&["--bind", "127.0.0.1", "--timeout", "200ms"]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(stderr(&output).contains("Found 0 devices, expected at least 1"), "{}", stderr(&output));
+
+    assert_eq!(run(&["--bind", "127.0.0.1", "--timeout", "200ms", "--min-devices", "0"]).status.code(), Some(0));
+}
+
+#[test]
+fn found_devices_exit_with_0() {
+    let responder = MockResponder::builder().start().unwrap();
+    let address = responder.addr().to_string();
+
+    let output = run(&["--bind", "127.0.0.1", "--multicast-group", &address, "--timeout", "500ms"]);
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("127.0.0.1\n"));
+}
@@ -0,0 +1,97 @@
+//! Discoveries against the fake speaker of `test_util`, needs the `test-util` feature.
+
+extern crate sonos_discovery;
+
+use sonos_discovery::test_util::MockResponder;
+use sonos_discovery::{Discover, Monitor, MonitorEvent};
+use std::time::Duration;
+
+/// Long enough for an answer on loopback, the discoveries wait for the whole timeout to see every duplicate
+const TIMEOUT: Duration = Duration::from_millis(500);
+
+#[test]
+fn finds_the_responder() {
+    let responder = MockResponder::builder().uuid("RINCON_000E58A0123401400").start().unwrap();
+
+    let result = Discover::with_address(responder.addr()).unwrap().start(Some(TIMEOUT), None).unwrap();
+    let ip = responder.addr().ip();
+    assert_eq!(result.unique_ips(), vec![ip]);
+    assert_eq!(result.uuid(&ip), Some("RINCON_000E58A0123401400"));
+    assert_eq!(result.household(&ip), Some("Sonos_test"));
+    assert_eq!(result.stats().responses, 1);
+    assert_eq!(result.stats().duplicates, 0);
+    assert_eq!(result.stats().rejected, 0);
+}
+
+#[test]
+fn answers_after_the_timeout_are_missed() {
+    let responder = MockResponder::builder().delay(Duration::from_millis(300)).start().unwrap();
+    let discovery = Discover::with_address(responder.addr()).unwrap();
+
+    assert!(discovery.start(Some(Duration::from_millis(100)), None).unwrap().unique_ips().is_empty());
+    // The answer to the first search is late, but not the one of the second
+    let result = discovery.start(Some(Duration::from_secs(2)), Some(1)).unwrap();
+    assert_eq!(result.unique_ips(), vec![responder.addr().ip()]);
+    assert!(result.stats().first_response.is_some_and(|latency| latency >= Duration::from_millis(300)));
+}
+
+#[test]
+fn counts_the_duplicates() {
+    let responder = MockResponder::builder().duplicates(2).start().unwrap();
+
+    let result = Discover::with_address(responder.addr()).unwrap().start(Some(TIMEOUT), None).unwrap();
+    assert_eq!(result.unique_ips(), vec![responder.addr().ip()]);
+    assert_eq!(result.stats().responses, 3);
+    assert_eq!(result.stats().duplicates, 2);
+    assert_eq!(result.response_addrs().len(), 3);
+}
+
+#[test]
+fn rejects_malformed_packets() {
+    let responder = MockResponder::builder().malformed(true).start().unwrap();
+
+    let result = Discover::with_address(responder.addr()).unwrap().start(Some(TIMEOUT), None).unwrap();
+    let ip = responder.addr().ip();
+    assert_eq!(result.unique_ips(), vec![ip]);
+    assert_eq!(result.uuid(&ip), Some("RINCON_000E58000000000000"));
+    assert_eq!(result.stats().responses, 2);
+    assert_eq!(result.stats().rejected, 1);
+}
+
+#[test]
+fn filters_the_household() {
+    let responder = MockResponder::builder().household(Some("Sonos_other")).start().unwrap();
+    let mut discovery = Discover::with_address(responder.addr()).unwrap();
+    discovery.set_household("Sonos_test");
+
+    let result = discovery.start(Some(TIMEOUT), None).unwrap();
+    assert!(result.unique_ips().is_empty());
+    assert_eq!(result.stats().rejected, 1);
+
+    let responder = MockResponder::builder().household(None).start().unwrap();
+    let result = Discover::with_address(responder.addr()).unwrap().start(Some(TIMEOUT), None).unwrap();
+    assert_eq!(result.household(&responder.addr().ip()), None);
+}
+
+#[test]
+fn the_monitor_reports_the_responder() {
+    let responder = MockResponder::builder().start().unwrap();
+    let mut discovery = Discover::with_address(responder.addr()).unwrap();
+    discovery.set_timeout(TIMEOUT);
+
+    let monitor = Monitor::start(discovery, Duration::from_secs(600));
+    let events = monitor.subscribe();
+    match events.recv_timeout(Duration::from_secs(5)).unwrap() {
+        MonitorEvent::DeviceAdded(device) => {
+            assert_eq!(device.ip, responder.addr().ip());
+            assert_eq!(device.uuid.as_deref(), Some("RINCON_000E58000000000000"));
+            assert_eq!(device.max_age(), Some(Duration::from_secs(1800)));
+        }
+        event => panic!("Unexpected event {:?}", event)
+    }
+    assert_eq!(monitor.device_set().ip("RINCON_000E58000000000000"), Some(responder.addr().ip()));
+
+    // The subscription closes with the monitor
+    monitor.shutdown();
+    assert!(events.recv().is_err());
+}